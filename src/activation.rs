@@ -0,0 +1,430 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+/// Written next to the instance lock: the port the running instance
+/// listens on and the token a second launch must present.
+const ADDR_FILE: &str = "instance.addr";
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Requests read at once; launches past this are turned away.
+const MAX_CLIENTS: usize = 8;
+
+/// Starts accepting activations from later launches on a loopback port.
+/// `on_open` gets the forwarded arguments; an empty list just asks for
+/// the window to be raised. Each request is read on its own thread, so a
+/// client that stalls doesn't hold up the others.
+pub fn listen(dir: &Path, on_open: impl Fn(Vec<String>) + Send + Sync + 'static) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let mut raw = [0u8; 16];
+    getrandom::getrandom(&mut raw).map_err(|e| io::Error::other(e.to_string()))?;
+    let token: String = raw.iter().map(|b| format!("{:02x}", b)).collect();
+    std::fs::write(
+        dir.join(ADDR_FILE),
+        format!("{} {}\n", listener.local_addr()?.port(), token),
+    )?;
+    std::thread::Builder::new()
+        .name("activation".into())
+        .spawn(move || {
+            let token: Arc<str> = token.into();
+            let on_open = Arc::new(on_open);
+            let clients = Arc::new(AtomicUsize::new(0));
+            for stream in listener.incoming().flatten() {
+                if clients.fetch_add(1, Ordering::Relaxed) >= MAX_CLIENTS {
+                    clients.fetch_sub(1, Ordering::Relaxed);
+                    eprintln!("activation: too many launches at once");
+                    continue;
+                }
+                let (token, on_open, clients) = (token.clone(), on_open.clone(), clients.clone());
+                let spawned = std::thread::Builder::new()
+                    .name("activation-client".into())
+                    .spawn(move || {
+                        match read_request(stream, &token) {
+                            Ok(Some(items)) => on_open(items),
+                            Ok(None) => {
+                                eprintln!("activation: rejected a request with a bad token")
+                            }
+                            Err(e) => eprintln!("activation: {}", e),
+                        }
+                        clients.fetch_sub(1, Ordering::Relaxed);
+                    });
+                if let Err(e) = spawned {
+                    eprintln!("activation: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// One request: the token, then one argument per line.
+fn read_request(stream: TcpStream, token: &str) -> io::Result<Option<Vec<String>>> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut lines = BufReader::new(stream).lines();
+    if lines.next().transpose()?.as_deref() != Some(token) {
+        return Ok(None);
+    }
+    Ok(Some(lines.collect::<io::Result<_>>()?))
+}
+
+/// Hands `items` to the instance running on the user path `dir`.
+pub fn forward(dir: &Path, items: &[String]) -> io::Result<()> {
+    let text = std::fs::read_to_string(dir.join(ADDR_FILE))?;
+    let (port, token) = text
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, ADDR_FILE))?;
+    let port: u16 = port
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, ADDR_FILE))?;
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut msg = format!("{}\n", token);
+    for item in items {
+        msg.push_str(&item.replace(['\r', '\n'], ""));
+        msg.push('\n');
+    }
+    stream.write_all(msg.as_bytes())
+}
+
+/// Registers this executable as the handler for `pob://` links. On macOS
+/// that is the app bundle it runs from, and links arrive as Apple Events,
+/// which `listen_for_links` picks up, rather than as arguments.
+pub fn register_url_handler() -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let exe = exe.to_string_lossy();
+    if cfg!(windows) {
+        let key = r"HKCU\Software\Classes\pob";
+        let command = format!("\"{}\" \"%1\"", exe);
+        run(
+            "reg",
+            &["add", key, "/ve", "/d", "URL:Path of Building", "/f"],
+        )?;
+        run("reg", &["add", key, "/v", "URL Protocol", "/d", "", "/f"])?;
+        let shell = format!(r"{}\shell\open\command", key);
+        run("reg", &["add", &shell, "/ve", "/d", &command, "/f"])
+    } else if cfg!(target_os = "macos") {
+        let bundle = app_bundle(Path::new(&*exe))
+            .ok_or("pob:// links need the app bundle; run it from inside the .app")?;
+        let plist = bundle.join("Contents/Info.plist");
+        let plist = plist.to_string_lossy();
+        // LaunchServices only routes a scheme to bundles that declare it.
+        // Signed bundles should ship with it, as the edit breaks the seal.
+        let declared = output(
+            "/usr/libexec/PlistBuddy",
+            &["-c", "Print :CFBundleURLTypes", &plist],
+        )
+        .is_ok_and(|out| out.lines().any(|l| l.trim() == "pob"));
+        if !declared {
+            run(
+                "plutil",
+                &[
+                    "-replace",
+                    "CFBundleURLTypes",
+                    "-json",
+                    r#"[{"CFBundleURLName":"Path of Building build","CFBundleURLSchemes":["pob"]}]"#,
+                    &plist,
+                ],
+            )?;
+        }
+        let id = output(
+            "/usr/libexec/PlistBuddy",
+            &["-c", "Print :CFBundleIdentifier", &plist],
+        )
+        .map_err(|_| format!("{} has no CFBundleIdentifier", plist))?;
+        set_default_handler(&bundle, id.trim())
+    } else {
+        let apps = dirs::data_dir()
+            .ok_or("no data directory")?
+            .join("applications");
+        std::fs::create_dir_all(&apps).map_err(|e| e.to_string())?;
+        let desktop = format!(
+            "[Desktop Entry]\nType=Application\nName=Path of Building\nExec=\"{}\" %u\nNoDisplay=true\nMimeType=x-scheme-handler/pob;\n",
+            exe
+        );
+        std::fs::write(apps.join("pob-runtime.desktop"), desktop).map_err(|e| e.to_string())?;
+        run(
+            "xdg-mime",
+            &["default", "pob-runtime.desktop", "x-scheme-handler/pob"],
+        )
+    }
+}
+
+/// The `.app` bundle an executable at `Contents/MacOS/` inside one is in.
+fn app_bundle(exe: &Path) -> Option<PathBuf> {
+    let macos = exe.parent()?;
+    let contents = macos.parent()?;
+    let bundle = contents.parent()?;
+    (macos.file_name()? == "MacOS"
+        && contents.file_name()? == "Contents"
+        && bundle.extension()? == "app")
+        .then(|| bundle.to_path_buf())
+}
+
+#[cfg(target_os = "macos")]
+pub use mac::listen_for_links;
+#[cfg(target_os = "macos")]
+use mac::set_default_handler;
+
+#[cfg(not(target_os = "macos"))]
+fn set_default_handler(_bundle: &Path, _id: &str) -> Result<(), String> {
+    Err("LaunchServices is only on macOS".into())
+}
+
+#[cfg(target_os = "macos")]
+mod mac {
+    use std::ffi::{CStr, c_char, c_void};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::OnceLock;
+
+    type CFTypeRef = *const c_void;
+    type Id = *mut c_void;
+    type Sel = *const c_void;
+
+    const UTF8: u32 = 0x0800_0100;
+    /// kInternetEventClass and kAEGetURL, which are the same code.
+    const GET_URL: u32 = u32::from_be_bytes(*b"GURL");
+    const DIRECT_OBJECT: u32 = u32::from_be_bytes(*b"----");
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    unsafe extern "C" {
+        fn CFStringCreateWithBytes(
+            alloc: CFTypeRef,
+            bytes: *const u8,
+            len: isize,
+            encoding: u32,
+            external: u8,
+        ) -> CFTypeRef;
+        fn CFURLCreateFromFileSystemRepresentation(
+            alloc: CFTypeRef,
+            path: *const u8,
+            len: isize,
+            is_dir: u8,
+        ) -> CFTypeRef;
+        fn CFRelease(cf: CFTypeRef);
+    }
+
+    #[link(name = "CoreServices", kind = "framework")]
+    unsafe extern "C" {
+        fn LSRegisterURL(url: CFTypeRef, update: u8) -> i32;
+        fn LSSetDefaultHandlerForURLScheme(scheme: CFTypeRef, bundle_id: CFTypeRef) -> i32;
+    }
+
+    #[link(name = "Foundation", kind = "framework")]
+    unsafe extern "C" {}
+
+    #[link(name = "objc")]
+    unsafe extern "C" {
+        fn objc_getClass(name: *const c_char) -> Id;
+        fn objc_allocateClassPair(superclass: Id, name: *const c_char, extra: usize) -> Id;
+        fn objc_registerClassPair(class: Id);
+        fn class_addMethod(class: Id, name: Sel, imp: *const c_void, types: *const c_char) -> u8;
+        fn sel_registerName(name: *const c_char) -> Sel;
+        fn objc_msgSend();
+    }
+
+    fn cf_string(s: &str) -> CFTypeRef {
+        unsafe { CFStringCreateWithBytes(std::ptr::null(), s.as_ptr(), s.len() as isize, UTF8, 0) }
+    }
+
+    /// Has LaunchServices read the bundle's scheme and send `pob://` links
+    /// to the app with identifier `id`.
+    pub fn set_default_handler(bundle: &Path, id: &str) -> Result<(), String> {
+        let path = bundle.as_os_str().as_bytes();
+        unsafe {
+            let url = CFURLCreateFromFileSystemRepresentation(
+                std::ptr::null(),
+                path.as_ptr(),
+                path.len() as isize,
+                1,
+            );
+            if url.is_null() {
+                return Err(format!("{}: not a bundle path", bundle.display()));
+            }
+            let status = LSRegisterURL(url, 1);
+            CFRelease(url);
+            if status != 0 {
+                return Err(format!("LSRegisterURL failed ({})", status));
+            }
+            let (scheme, id) = (cf_string("pob"), cf_string(id));
+            let status = LSSetDefaultHandlerForURLScheme(scheme, id);
+            CFRelease(scheme);
+            CFRelease(id);
+            match status {
+                0 => Ok(()),
+                e => Err(format!("LSSetDefaultHandlerForURLScheme failed ({})", e)),
+            }
+        }
+    }
+
+    /// `[receiver selector]` for selectors that take no arguments.
+    unsafe fn send(receiver: Id, selector: &CStr) -> Id {
+        unsafe {
+            let f = std::mem::transmute::<
+                unsafe extern "C" fn(),
+                unsafe extern "C" fn(Id, Sel) -> Id,
+            >(objc_msgSend);
+            f(receiver, sel_registerName(selector.as_ptr()))
+        }
+    }
+
+    static ON_LINK: OnceLock<Box<dyn Fn(Vec<String>) + Send + Sync>> = OnceLock::new();
+
+    /// `handleURLEvent:withReplyEvent:`, passing the event's URL along.
+    extern "C" fn handle_url(_this: Id, _cmd: Sel, event: Id, _reply: Id) {
+        let url = unsafe {
+            let param = std::mem::transmute::<
+                unsafe extern "C" fn(),
+                unsafe extern "C" fn(Id, Sel, u32) -> Id,
+            >(objc_msgSend);
+            let descriptor = param(
+                event,
+                sel_registerName(c"paramDescriptorForKeyword:".as_ptr()),
+                DIRECT_OBJECT,
+            );
+            if descriptor.is_null() {
+                return;
+            }
+            let string = send(descriptor, c"stringValue");
+            if string.is_null() {
+                return;
+            }
+            let utf8 = send(string, c"UTF8String") as *const c_char;
+            if utf8.is_null() {
+                return;
+            }
+            CStr::from_ptr(utf8).to_string_lossy().into_owned()
+        };
+        if let Some(on_open) = ON_LINK.get() {
+            on_open(vec![url]);
+        }
+    }
+
+    /// Passes `pob://` links macOS opens the app with to `on_open`, the way
+    /// `listen` passes along later launches' arguments.
+    pub fn listen_for_links(on_open: impl Fn(Vec<String>) + Send + Sync + 'static) {
+        if ON_LINK.set(Box::new(on_open)).is_err() {
+            return;
+        }
+        unsafe {
+            let class = objc_allocateClassPair(
+                objc_getClass(c"NSObject".as_ptr()),
+                c"PobLinkHandler".as_ptr(),
+                0,
+            );
+            if class.is_null() {
+                eprintln!("activation: couldn't make the link handler class");
+                return;
+            }
+            let handle = sel_registerName(c"handleURLEvent:withReplyEvent:".as_ptr());
+            let imp = handle_url as extern "C" fn(Id, Sel, Id, Id);
+            class_addMethod(class, handle, imp as *const c_void, c"v@:@@".as_ptr());
+            objc_registerClassPair(class);
+            // Kept for the life of the process; the manager doesn't retain it.
+            let handler = send(send(class, c"alloc"), c"init");
+            let manager = send(
+                objc_getClass(c"NSAppleEventManager".as_ptr()),
+                c"sharedAppleEventManager",
+            );
+            let set_handler = std::mem::transmute::<
+                unsafe extern "C" fn(),
+                unsafe extern "C" fn(Id, Sel, Id, Sel, u32, u32),
+            >(objc_msgSend);
+            set_handler(
+                manager,
+                sel_registerName(c"setEventHandler:andSelector:forEventClass:andEventID:".as_ptr()),
+                handler,
+                handle,
+                GET_URL,
+                GET_URL,
+            );
+        }
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", program, status))
+    }
+}
+
+/// Runs `program` for its standard output.
+fn output(program: &str, args: &[&str]) -> Result<String, String> {
+    let out = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if out.status.success() {
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    } else {
+        Err(format!("{} exited with {}", program, out.status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn forwards_arguments_to_the_listener() {
+        let dir = std::env::temp_dir().join(format!("pob-activation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (tx, rx) = mpsc::channel();
+        listen(&dir, move |items| tx.send(items).unwrap()).unwrap();
+
+        let items = vec!["pob://pobbin/abc".to_string(), "Build\n.xml".to_string()];
+        forward(&dir, &items).unwrap();
+        assert_eq!(
+            rx.recv_timeout(TIMEOUT).unwrap(),
+            vec!["pob://pobbin/abc", "Build.xml"]
+        );
+
+        // A client without the token is ignored.
+        let text = std::fs::read_to_string(dir.join(ADDR_FILE)).unwrap();
+        let port: u16 = text.split(' ').next().unwrap().parse().unwrap();
+        // Nor does one that never finishes hold up the next launch.
+        let _stalled = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let started = std::time::Instant::now();
+        forward(&dir, &["next".to_string()]).unwrap();
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), vec!["next"]);
+        assert!(started.elapsed() < TIMEOUT);
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        stream.write_all(b"guess\nitem\n").unwrap();
+        drop(stream);
+        forward(&dir, &[]).unwrap();
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), Vec::<String>::new());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn app_bundles_are_found_from_the_executable() {
+        assert_eq!(
+            app_bundle(Path::new(
+                "/Applications/Path of Building.app/Contents/MacOS/pob-runtime-rs"
+            )),
+            Some(PathBuf::from("/Applications/Path of Building.app"))
+        );
+        assert_eq!(app_bundle(Path::new("/usr/local/bin/pob-runtime-rs")), None);
+        assert_eq!(
+            app_bundle(Path::new("/Applications/Tools/Contents/MacOS/pob")),
+            None
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use zip::ZipArchive;
+
+use crate::vfs::{FoundFile, Provider, search_options};
+
+/// The most a read reserves up front; bigger entries grow as they're read.
+const MAX_PREALLOC: u64 = 1 << 20;
+
+/// Splits `tree.zip:/Assets/foo.dds` into the archive path and the entry
+/// name inside it. Plain paths give None.
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    let at = path.to_ascii_lowercase().find(".zip:")? + 4;
+    Some((&path[..at], path[at + 1..].trim_start_matches(['/', '\\'])))
+}
+
+/// Entry names are matched case-insensitively with forward slashes, as
+/// PoB was written against Windows paths.
+pub fn normalize(name: &str) -> String {
+    name.replace('\\', "/")
+        .trim_matches('/')
+        .to_ascii_lowercase()
+}
+
+struct Entry {
+    /// Name as stored, without a trailing slash.
+    name: String,
+    index: usize,
+    size: u64,
+}
+
+/// An indexed zip file. Mounted on its own it serves its entries as a
+/// directory tree.
+pub struct ArchiveIndex {
+    zip: ZipArchive<BufReader<File>>,
+    /// Normalized file name to entry.
+    files: HashMap<String, Entry>,
+    /// Directories, including ones only implied by file names.
+    dirs: BTreeSet<String>,
+    modified: Option<SystemTime>,
+}
+
+impl ArchiveIndex {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let modified = file.metadata().and_then(|m| m.modified()).ok();
+        let mut zip = ZipArchive::new(BufReader::new(file)).map_err(io::Error::other)?;
+        let mut files = HashMap::new();
+        let mut dirs = BTreeSet::new();
+        for index in 0..zip.len() {
+            let entry = zip.by_index_raw(index).map_err(io::Error::other)?;
+            let name = entry.name().map_err(io::Error::other)?.replace('\\', "/");
+            let name = name.trim_matches('/').to_string();
+            let mut parent = name.as_str();
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                dirs.insert(dir.to_string());
+                parent = dir;
+            }
+            if entry.is_dir() {
+                dirs.insert(name);
+            } else {
+                let size = entry.size();
+                files.insert(normalize(&name), Entry { name, index, size });
+            }
+        }
+        Ok(Self {
+            zip,
+            files,
+            dirs,
+            modified,
+        })
+    }
+
+    fn read(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let entry = self
+            .files
+            .get(&normalize(name))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, name.to_string()))?;
+        let mut data = Vec::with_capacity(entry.size.min(MAX_PREALLOC) as usize);
+        self.zip
+            .by_index(entry.index)
+            .map_err(io::Error::other)?
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Entries matching a wildcard `pattern` of slash-separated names.
+    fn search(&self, pattern: &str, dirs: bool) -> Vec<FoundFile> {
+        let Ok(pattern) = glob::Pattern::new(&pattern.replace('\\', "/")) else {
+            return Vec::new();
+        };
+        let options = search_options();
+        let leaf = |name: &str| name.rsplit('/').next().unwrap_or(name).to_string();
+        let mut found: Vec<FoundFile> = if dirs {
+            self.dirs
+                .iter()
+                .filter(|d| pattern.matches_with(d, options))
+                .map(|d| FoundFile {
+                    name: leaf(d),
+                    size: 0,
+                    modified: self.modified,
+                })
+                .collect()
+        } else {
+            self.files
+                .values()
+                .filter(|e| pattern.matches_with(&e.name, options))
+                .map(|e| FoundFile {
+                    name: leaf(&e.name),
+                    size: e.size,
+                    modified: self.modified,
+                })
+                .collect()
+        };
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        found
+    }
+}
+
+impl Provider for ArchiveIndex {
+    fn read(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        ArchiveIndex::read(self, path)
+    }
+
+    fn search(&mut self, spec: &str, dirs: bool) -> Vec<FoundFile> {
+        ArchiveIndex::search(self, spec, dirs)
+    }
+}
+
+/// A directory on disk. Paths into a zip inside it, such as
+/// `tree.zip:/Assets/foo.png`, read the archive's entry; each archive is
+/// indexed once, the first time it is used or at startup by `scan`.
+pub struct Archives {
+    /// Relative paths are taken from here.
+    base: PathBuf,
+    /// Keyed by canonical path; None records an archive that failed to open.
+    indexes: HashMap<PathBuf, Option<ArchiveIndex>>,
+}
+
+impl Archives {
+    pub fn new(base: PathBuf) -> Self {
+        Self {
+            base,
+            indexes: HashMap::new(),
+        }
+    }
+
+    /// Indexes every `.zip` directly inside the directory.
+    pub fn scan(&mut self) {
+        let Ok(dir) = std::fs::read_dir(&self.base) else {
+            return;
+        };
+        for entry in dir.flatten() {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+            {
+                self.index(&path).ok();
+            }
+        }
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.base.join(path)
+    }
+
+    fn index(&mut self, path: &Path) -> io::Result<&mut ArchiveIndex> {
+        let key = path.canonicalize()?;
+        let index = self.indexes.entry(key).or_insert_with_key(|key| {
+            ArchiveIndex::open(key)
+                .inspect_err(|e| eprintln!("archive {}: {}", key.display(), e))
+                .ok()
+        });
+        index
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, path.display().to_string()))
+    }
+}
+
+impl Provider for Archives {
+    fn read(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        match split(path) {
+            Some((archive, name)) => {
+                let archive = self.resolve(archive);
+                self.index(&archive)?.read(name)
+            }
+            None => std::fs::read(self.resolve(path)),
+        }
+    }
+
+    fn search(&mut self, spec: &str, dirs: bool) -> Vec<FoundFile> {
+        let Some((archive, pattern)) = split(spec) else {
+            let spec = self.resolve(spec);
+            let Ok(paths) = glob::glob_with(&spec.to_string_lossy(), search_options()) else {
+                return Vec::new();
+            };
+            return paths
+                .flatten()
+                .filter_map(|path| {
+                    let meta = path.metadata().ok()?;
+                    let name = path.file_name()?.to_string_lossy().into_owned();
+                    (meta.is_dir() == dirs).then(|| FoundFile {
+                        name,
+                        size: meta.len(),
+                        modified: meta.modified().ok(),
+                    })
+                })
+                .collect();
+        };
+        let archive = self.resolve(archive);
+        self.index(&archive)
+            .map(|index| index.search(pattern, dirs))
+            .unwrap_or_default()
+    }
+
+    fn host_path(&self, path: &str) -> Option<PathBuf> {
+        split(path).is_none().then(|| self.resolve(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn reads_and_searches_inside_archives() {
+        let dir = std::env::temp_dir().join(format!("pob-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(dir.join("Tree.zip")).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("Assets/Foo.png", options).unwrap();
+        zip.write_all(b"png bytes").unwrap();
+        zip.start_file("Assets/Sub/bar.png", options).unwrap();
+        zip.write_all(b"x").unwrap();
+        zip.start_file("data.json", options).unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.finish().unwrap();
+        std::fs::write(dir.join("plain.txt"), "plain").unwrap();
+
+        assert_eq!(
+            split("tree.zip:/Assets/foo.dds"),
+            Some(("tree.zip", "Assets/foo.dds"))
+        );
+        assert_eq!(split("Assets/foo.dds"), None);
+
+        let mut archives = Archives::new(dir.clone());
+        archives.scan();
+        assert_eq!(archives.indexes.len(), 1);
+        assert_eq!(
+            archives.read("Tree.zip:/assets/foo.PNG").unwrap(),
+            b"png bytes"
+        );
+        assert_eq!(archives.read("Tree.zip:\\data.json").unwrap(), b"{}");
+        assert!(archives.read("Tree.zip:/missing.png").is_err());
+        assert_eq!(archives.read("plain.txt").unwrap(), b"plain");
+        assert_eq!(archives.host_path("plain.txt"), Some(dir.join("plain.txt")));
+        assert_eq!(archives.host_path("Tree.zip:/data.json"), None);
+
+        let found = archives.search("Tree.zip:/Assets/*.PNG", false);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].name.as_str(), found[0].size), ("Foo.png", 9));
+        let found = archives.search("Tree.zip:/Assets/*", true);
+        assert_eq!(found[0].name, "Sub");
+        let found = archives.search(&format!("{}/*.txt", dir.display()), false);
+        assert_eq!(found[0].name, "plain.txt");
+
+        // Mounted on its own, a zip is a directory tree.
+        let mut zip = ArchiveIndex::open(&dir.join("Tree.zip")).unwrap();
+        assert_eq!(
+            Provider::read(&mut zip, "ASSETS/sub/bar.png").unwrap(),
+            b"x"
+        );
+        assert_eq!(Provider::search(&mut zip, "*", true)[0].name, "Assets");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
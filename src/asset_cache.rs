@@ -0,0 +1,194 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use mlua::prelude::*;
+
+/// Leads a cached image blob: `MAGIC`, width and height as little-endian
+/// u32, then the RGBA pixels, ready to upload without decoding.
+pub const MAGIC: &[u8; 8] = b"PoBRGBA\0";
+
+/// Generated tree assets kept between runs, one directory per tree version
+/// and DPI scale so regenerating at another scale doesn't evict the first.
+#[derive(Clone)]
+pub struct AssetCache {
+    root: PathBuf,
+    dpi_scale: Arc<Mutex<f32>>,
+}
+
+impl AssetCache {
+    pub fn new(root: PathBuf, dpi_scale: Arc<Mutex<f32>>) -> Self {
+        Self { root, dpi_scale }
+    }
+
+    /// Where `name` is cached for `version` at the current scale. None
+    /// for names that would leave the cache directory.
+    pub fn path(&self, version: &str, name: &str) -> Option<PathBuf> {
+        let inside = |p: &str| {
+            !p.is_empty()
+                && Path::new(p)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+        };
+        if !inside(version) || !inside(name) {
+            return None;
+        }
+        let scale = *self.dpi_scale.lock().unwrap();
+        let key = format!("{}@{}x", version, (scale * 100.0).round() / 100.0);
+        Some(self.root.join(key).join(name))
+    }
+
+    fn resolve(&self, version: &str, name: &str) -> io::Result<PathBuf> {
+        self.path(version, name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}/{}: not a cache name", version, name),
+            )
+        })
+    }
+
+    pub fn load(&self, version: &str, name: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.resolve(version, name)?)
+    }
+
+    /// Writes through a temporary file, so a crash mid-write never leaves a
+    /// truncated asset behind.
+    pub fn store(&self, version: &str, name: &str, data: &[u8]) -> io::Result<PathBuf> {
+        let path = self.resolve(version, name)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let part = path.with_extension("part");
+        std::fs::write(&part, data)?;
+        std::fs::rename(&part, &path)?;
+        Ok(path)
+    }
+
+    /// Stores RGBA pixels as an image blob.
+    pub fn store_image(
+        &self,
+        version: &str,
+        name: &str,
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+    ) -> io::Result<PathBuf> {
+        if rgba.len() != width as usize * height as usize * 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}x{} image needs {} bytes",
+                    width,
+                    height,
+                    width as u64 * height as u64 * 4
+                ),
+            ));
+        }
+        let mut data = Vec::with_capacity(16 + rgba.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(rgba);
+        self.store(version, name, &data)
+    }
+}
+
+/// Splits an image blob into width, height and pixels.
+pub fn decode_image(data: &[u8]) -> Option<(u32, u32, &[u8])> {
+    let rest = data.strip_prefix(MAGIC)?;
+    let width = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+    let height = u32::from_le_bytes(rest.get(4..8)?.try_into().ok()?);
+    let rgba = rest.get(8..)?;
+    (rgba.len() == width as usize * height as usize * 4).then_some((width, height, rgba))
+}
+
+/// Registers `GetAssetCachePath(version, name)`,
+/// `LoadCachedAsset(version, name)`, `SaveCachedAsset(version, name, data)`
+/// and `SaveCachedImage(version, name, width, height, rgba)`. Saves return
+/// the cached file's path, which image handles can `Load`.
+pub fn register(lua: &Lua, cache: AssetCache) -> LuaResult<()> {
+    let g = lua.globals();
+    let c = cache.clone();
+    g.set(
+        "GetAssetCachePath",
+        lua.create_function(move |_, (version, name): (String, String)| {
+            Ok(c.path(&version, &name)
+                .map(|p| p.to_string_lossy().into_owned()))
+        })?,
+    )?;
+    let c = cache.clone();
+    g.set(
+        "LoadCachedAsset",
+        lua.create_function(move |lua, (version, name): (String, String)| {
+            match c.load(&version, &name) {
+                Ok(data) => lua.create_string(&data)?.into_lua_multi(lua),
+                Err(e) => (LuaValue::Nil, e.to_string()).into_lua_multi(lua),
+            }
+        })?,
+    )?;
+    let c = cache.clone();
+    g.set(
+        "SaveCachedAsset",
+        lua.create_function(
+            move |lua, (version, name, data): (String, String, LuaString)| match c.store(
+                &version,
+                &name,
+                data.as_bytes(),
+            ) {
+                Ok(path) => path.to_string_lossy().into_lua_multi(lua),
+                Err(e) => (LuaValue::Nil, e.to_string()).into_lua_multi(lua),
+            },
+        )?,
+    )?;
+    g.set(
+        "SaveCachedImage",
+        lua.create_function(
+            move |lua, (version, name, width, height, rgba): (String, String, u32, u32, LuaString)| {
+                match cache.store_image(&version, &name, width, height, rgba.as_bytes()) {
+                    Ok(path) => path.to_string_lossy().into_lua_multi(lua),
+                    Err(e) => (LuaValue::Nil, e.to_string()).into_lua_multi(lua),
+                }
+            },
+        )?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assets_are_keyed_by_tree_version_and_scale() {
+        let dir = std::env::temp_dir().join(format!("pob-assets-{}", std::process::id()));
+        let scale = Arc::new(Mutex::new(1.0));
+        let lua = Lua::new();
+        register(&lua, AssetCache::new(dir.clone(), scale.clone())).unwrap();
+        let (saved, loaded, escape): (String, String, Option<String>) = lua
+            .load(
+                r#"
+                local path = SaveCachedImage("3_25", "skills.rgba", 1, 2, "\1\2\3\4\5\6\7\8")
+                assert(not SaveCachedImage("3_25", "bad.rgba", 2, 2, "\0"))
+                return path, LoadCachedAsset("3_25", "skills.rgba"), GetAssetCachePath("3_25", "../x")
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(saved, dir.join("3_25@1x/skills.rgba").to_string_lossy());
+        assert_eq!(
+            decode_image(loaded.as_bytes()),
+            Some((1, 2, &[1, 2, 3, 4, 5, 6, 7, 8][..]))
+        );
+        assert_eq!(escape, None);
+
+        *scale.lock().unwrap() = 1.25;
+        let missing: Option<String> = lua
+            .load(r#"return LoadCachedAsset("3_25", "skills.rgba")"#)
+            .eval()
+            .unwrap();
+        assert_eq!(missing, None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
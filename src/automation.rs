@@ -0,0 +1,244 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use mlua::prelude::*;
+
+use crate::host_thread::HostEvent;
+
+/// What the script asked for since the host last looked.
+#[derive(Default)]
+struct Requests {
+    events: Vec<HostEvent>,
+    screenshots: Vec<PathBuf>,
+}
+
+/// The `Automation` helpers written in Lua. Waits yield back to the host,
+/// which resumes the script once per frame.
+const HELPERS: &str = r#"
+local A = ...
+
+local function lookup(obj, name)
+    if type(obj) ~= "table" then
+        return nil
+    end
+    return obj.controls and obj.controls[name] or obj[name]
+end
+
+-- Dotted names resolve through `controls` tables, first from the current
+-- mode (main.modes[main.mode]) and then from main itself.
+function A.Find(path)
+    local roots = { main }
+    local mode = main.modes and main.modes[main.mode]
+    if mode then
+        table.insert(roots, 1, mode)
+    end
+    for _, root in ipairs(roots) do
+        local obj = root
+        for name in path:gmatch("[^.]+") do
+            obj = lookup(obj, name)
+        end
+        if obj ~= nil and obj ~= root then
+            return obj
+        end
+    end
+end
+
+function A.WaitFrames(n)
+    for _ = 1, n or 1 do
+        coroutine.yield()
+    end
+end
+
+function A.WaitFor(cond, seconds, what)
+    local deadline = GetTime() + (seconds or 10) * 1000
+    while not cond() do
+        if GetTime() > deadline then
+            error("timed out waiting for " .. (what or "condition"), 2)
+        end
+        coroutine.yield()
+    end
+end
+
+function A.Click(path, button)
+    local control = A.Find(path) or error("no control " .. path, 2)
+    if control.IsShown and not control:IsShown() then
+        error(path .. " is hidden", 2)
+    end
+    local x, y = control:GetPos()
+    local w, h = control:GetSize()
+    button = button or "LEFTBUTTON"
+    A.MoveTo(x + w / 2, y + h / 2)
+    A.KeyDown(button)
+    A.KeyUp(button)
+    coroutine.yield()
+end
+
+function A.Press(key)
+    A.KeyDown(key)
+    A.KeyUp(key)
+    coroutine.yield()
+end
+
+function A.Type(text)
+    for ch in text:gmatch("[%z\1-\127\194-\244][\128-\191]*") do
+        A.Char(ch)
+    end
+    coroutine.yield()
+end
+
+-- Captures the next frame. The second wait means the renderer has taken
+-- that frame, so the file exists even if the script ends right after.
+function A.Screenshot(path)
+    A.QueueScreenshot(path)
+    A.WaitFrames(2)
+end
+"#;
+
+/// A Lua test script driving the UI through synthesized input. It runs as
+/// a coroutine in the main state, so it can inspect PoB directly.
+pub struct Script<'lua> {
+    thread: LuaThread<'lua>,
+    requests: Arc<Mutex<Requests>>,
+}
+
+impl<'lua> Script<'lua> {
+    /// Loads the script at `path` and registers the `Automation` table.
+    /// Relative screenshot paths are taken from the script's directory.
+    pub fn load(lua: &'lua Lua, path: &Path) -> LuaResult<Self> {
+        let code = std::fs::read_to_string(path)
+            .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path.display(), e)))?;
+        let base = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let requests: Arc<Mutex<Requests>> = Arc::default();
+        let api = lua.create_table()?;
+        let queue = |event: fn(String) -> HostEvent| {
+            let requests = requests.clone();
+            lua.create_function(move |_, arg: String| {
+                requests.lock().unwrap().events.push(event(arg));
+                Ok(())
+            })
+        };
+        api.set(
+            "KeyDown",
+            queue(|key| HostEvent::KeyDown {
+                key,
+                double_click: false,
+            })?,
+        )?;
+        api.set("KeyUp", queue(|key| HostEvent::KeyUp { key })?)?;
+        api.set("Char", queue(HostEvent::Char)?)?;
+        let r = requests.clone();
+        api.set(
+            "MoveTo",
+            lua.create_function(move |_, (x, y): (f32, f32)| {
+                r.lock().unwrap().events.push(HostEvent::MouseMove { x, y });
+                Ok(())
+            })?,
+        )?;
+        let r = requests.clone();
+        api.set(
+            "QueueScreenshot",
+            lua.create_function(move |_, path: String| {
+                r.lock().unwrap().screenshots.push(base.join(path));
+                Ok(())
+            })?,
+        )?;
+        lua.load(HELPERS)
+            .set_name("automation")
+            .call::<_, ()>(api.clone())?;
+        lua.globals().set("Automation", api)?;
+
+        let func = lua
+            .load(&code)
+            .set_name(path.to_string_lossy())
+            .into_function()?;
+        Ok(Self {
+            thread: lua.create_thread(func)?,
+            requests,
+        })
+    }
+
+    /// Runs the script up to its next wait; false once it has returned.
+    pub fn resume(&self) -> LuaResult<bool> {
+        self.thread.resume::<_, ()>(())?;
+        Ok(self.thread.status() == LuaThreadStatus::Resumable)
+    }
+
+    /// Input queued since the last call, for this frame's dispatch.
+    pub fn take_events(&self) -> Vec<HostEvent> {
+        std::mem::take(&mut self.requests.lock().unwrap().events)
+    }
+
+    /// Screenshots to capture from this frame.
+    pub fn take_screenshots(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.requests.lock().unwrap().screenshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_clicks_named_controls_and_waits() {
+        let dir = std::env::temp_dir().join(format!("pob-automation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("import.lua");
+        std::fs::write(
+            &path,
+            r#"
+            Automation.Click("import")
+            Automation.Type("aé")
+            Automation.WaitFor(function() return main.imported end, 1, "import")
+            Automation.Screenshot("tree.png")
+            assert(Automation.Find("missing") == nil)
+            Automation.Click("missing")
+            "#,
+        )
+        .unwrap();
+        let lua = Lua::new();
+        lua.load(
+            r#"
+            function GetTime() return 0 end
+            local button = {
+                GetPos = function() return 100, 40 end,
+                GetSize = function() return 60, 20 end,
+            }
+            main = { mode = "BUILD", modes = { BUILD = { controls = { import = button } } } }
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let script = Script::load(&lua, &path).unwrap();
+
+        assert!(script.resume().unwrap());
+        assert_eq!(
+            script.take_events(),
+            vec![
+                HostEvent::MouseMove { x: 130.0, y: 50.0 },
+                HostEvent::KeyDown {
+                    key: "LEFTBUTTON".into(),
+                    double_click: false
+                },
+                HostEvent::KeyUp {
+                    key: "LEFTBUTTON".into()
+                },
+            ]
+        );
+        assert!(script.resume().unwrap());
+        assert_eq!(
+            script.take_events(),
+            vec![HostEvent::Char("a".into()), HostEvent::Char("é".into())]
+        );
+        // Still waiting for the import.
+        assert!(script.resume().unwrap());
+        lua.load("main.imported = true").exec().unwrap();
+        assert!(script.resume().unwrap());
+        assert_eq!(script.take_screenshots(), vec![dir.join("tree.png")]);
+        assert!(script.resume().unwrap());
+        let err = script.resume().unwrap_err().to_string();
+        assert!(err.contains("no control missing"), "{}", err);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+use crate::settings::BackupSettings;
+
+/// Present while a session runs; finding it at startup means the last
+/// session crashed.
+const MARKER: &str = "session.active";
+const SUFFIX: &str = ".xml.gz";
+
+/// Periodic gzip snapshots of the open build in a rotating directory.
+pub struct Backups {
+    dir: PathBuf,
+    interval: Duration,
+    keep: usize,
+    last_run: Instant,
+    /// Hash of the last saved XML, so an unchanged build isn't saved again.
+    last_hash: Option<u64>,
+    crashed: bool,
+}
+
+impl Backups {
+    /// None when backups are turned off or `dir` can't be created.
+    pub fn new(dir: PathBuf, settings: &BackupSettings) -> Option<Self> {
+        if settings.enabled == Some(false) {
+            return None;
+        }
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            eprintln!("backups: {}: {}", dir.display(), e);
+            return None;
+        }
+        let marker = dir.join(MARKER);
+        let crashed = marker.exists();
+        std::fs::write(&marker, "").ok();
+        Some(Self {
+            dir,
+            interval: Duration::from_secs(settings.interval_mins.unwrap_or(5).max(1) * 60),
+            keep: settings.keep.unwrap_or(10).max(1),
+            last_run: Instant::now(),
+            last_hash: None,
+            crashed,
+        })
+    }
+
+    /// Whether the previous session ended without `finish`.
+    pub fn crashed(&self) -> bool {
+        self.crashed
+    }
+
+    /// True once per interval; the next interval starts counting now.
+    pub fn due(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last_run) < self.interval {
+            return false;
+        }
+        self.last_run = now;
+        true
+    }
+
+    /// Writes a snapshot of `xml` unless it matches the last one, then
+    /// deletes the oldest snapshots beyond the configured count.
+    pub fn save(&mut self, name: &str, xml: &str) -> io::Result<Option<PathBuf>> {
+        let mut hasher = DefaultHasher::new();
+        xml.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.last_hash == Some(hash) {
+            return Ok(None);
+        }
+        let stamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let safe: String = name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || " -_()".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = self.dir.join(format!("{}-{}{}", stamp, safe, SUFFIX));
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(xml.as_bytes())?;
+        std::fs::write(&path, gz.finish()?)?;
+        self.last_hash = Some(hash);
+
+        let snapshots = list(&self.dir);
+        let excess = snapshots.len().saturating_sub(self.keep);
+        for (_, old) in &snapshots[..excess] {
+            std::fs::remove_file(old).ok();
+        }
+        Ok(Some(path))
+    }
+
+    /// The newest snapshot as (build name, XML).
+    pub fn latest(&self) -> Option<(String, String)> {
+        let (_, path) = list(&self.dir).pop()?;
+        let mut xml = String::new();
+        GzDecoder::new(std::fs::File::open(&path).ok()?)
+            .read_to_string(&mut xml)
+            .ok()?;
+        let file = path.file_name()?.to_string_lossy();
+        let name = file
+            .strip_suffix(SUFFIX)
+            .and_then(|f| f.split_once('-'))
+            .map_or("Recovered build", |(_, name)| name)
+            .to_string();
+        Some((name, xml))
+    }
+
+    /// Marks the session as closed cleanly.
+    pub fn finish(self) {
+        std::fs::remove_file(self.dir.join(MARKER)).ok();
+    }
+}
+
+/// Snapshots in `dir`, oldest first.
+fn list(dir: &Path) -> Vec<(u128, PathBuf)> {
+    let mut out: Vec<(u128, PathBuf)> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let stamp = name.strip_suffix(SUFFIX)?.split('-').next()?.parse().ok()?;
+            Some((stamp, entry.path()))
+        })
+        .collect();
+    out.sort();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_snapshots_and_detects_crashes() {
+        let dir = std::env::temp_dir().join(format!("pob-backup-{}", std::process::id()));
+        let settings = BackupSettings {
+            keep: Some(2),
+            ..BackupSettings::default()
+        };
+        let mut backups = Backups::new(dir.clone(), &settings).unwrap();
+        assert!(!backups.crashed());
+        assert!(!backups.due(Instant::now()));
+        assert!(backups.due(Instant::now() + Duration::from_secs(300)));
+
+        assert!(backups.save("Witch", "<a/>").unwrap().is_some());
+        assert!(backups.save("Witch", "<a/>").unwrap().is_none());
+        std::thread::sleep(Duration::from_millis(2));
+        backups.save("Witch", "<b/>").unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        backups.save("Big/Bad: Duelist", "<c/>").unwrap();
+        assert_eq!(list(&dir).len(), 2);
+        assert_eq!(
+            backups.latest(),
+            Some(("Big_Bad_ Duelist".to_string(), "<c/>".to_string()))
+        );
+
+        // Not finished: the next session sees a crash.
+        let again = Backups::new(dir.clone(), &settings).unwrap();
+        assert!(again.crashed());
+        again.finish();
+        assert!(!Backups::new(dir.clone(), &settings).unwrap().crashed());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+use crate::headless::Headless;
+use crate::layout::Layout;
+
+/// How long to keep running frames while a build loads or downloads.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Timings and Lua heap use of the calc passes.
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub load: Duration,
+    /// Milliseconds per pass.
+    pub passes: Vec<f64>,
+    /// Bytes allocated by each pass, measured with the collector stopped.
+    pub allocated: Vec<usize>,
+    /// Lua heap after a full collection at the end.
+    pub heap: usize,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sorted = self.passes.clone();
+        sorted.sort_by(f64::total_cmp);
+        let n = sorted.len().max(1) as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let median = sorted.get(sorted.len() / 2).copied().unwrap_or(0.0);
+        let alloc = self.allocated.iter().sum::<usize>() as f64 / n / 1024.0;
+        writeln!(f, "load:    {:.1} ms", self.load.as_secs_f64() * 1000.0)?;
+        writeln!(
+            f,
+            "passes:  {} | min {:.2} ms | median {:.2} ms | mean {:.2} ms | max {:.2} ms",
+            sorted.len(),
+            sorted.first().copied().unwrap_or(0.0),
+            median,
+            mean,
+            sorted.last().copied().unwrap_or(0.0),
+        )?;
+        writeln!(f, "alloc:   {:.1} KiB per pass", alloc)?;
+        write!(
+            f,
+            "heap:    {:.1} MiB",
+            self.heap as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+/// Loads `build` (a build code, file or `pob://` link) without a window
+/// and times `passes` full calc passes over it.
+pub fn run(layout: Layout, build: &str, passes: u32) -> LuaResult<Report> {
+    let start = Instant::now();
+    let state = Headless::start(layout)?;
+    state.load(build, start + LOAD_TIMEOUT)?;
+    let load = start.elapsed();
+    let host = &state.host;
+
+    let pass: LuaFunction = host
+        .lua
+        .load("main.modes.BUILD.calcsTab:BuildOutput()")
+        .into_function()?;
+    let mut report = Report {
+        load,
+        passes: Vec::new(),
+        allocated: Vec::new(),
+        heap: 0,
+    };
+    for _ in 0..passes.max(1) {
+        host.lua.gc_collect()?;
+        host.lua.gc_stop();
+        let before = host.lua.used_memory();
+        let t = Instant::now();
+        pass.call::<_, ()>(())?;
+        report.passes.push(t.elapsed().as_secs_f64() * 1000.0);
+        report
+            .allocated
+            .push(host.lua.used_memory().saturating_sub(before));
+        host.lua.gc_restart();
+    }
+    host.lua.gc_collect()?;
+    report.heap = host.lua.used_memory();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_summarizes_passes() {
+        let report = Report {
+            load: Duration::from_millis(1500),
+            passes: vec![30.0, 10.0, 20.0],
+            allocated: vec![1024, 2048, 3072],
+            heap: 3 * 1024 * 1024,
+        };
+        assert_eq!(
+            report.to_string(),
+            "load:    1500.0 ms\n\
+             passes:  3 | min 10.00 ms | median 20.00 ms | mean 20.00 ms | max 30.00 ms\n\
+             alloc:   2.0 KiB per pass\n\
+             heap:    3.0 MiB"
+        );
+    }
+}
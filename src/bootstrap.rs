@@ -0,0 +1,238 @@
+use std::{
+    io::{self, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use rfd::{MessageButtons, MessageLevel};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::dialogs;
+use crate::layout::Layout;
+use crate::net::NetSettings;
+use crate::platform::{HttpClient, HttpOptions, has_display};
+use crate::settings::NetworkSettings;
+
+/// The PoB editions that can be downloaded, by fork name.
+const EDITIONS: &[(&str, &str, &str)] = &[
+    (
+        "poe1",
+        "Path of Building Community",
+        "PathOfBuildingCommunity/PathOfBuilding",
+    ),
+    (
+        "poe2",
+        "Path of Building for PoE2",
+        "PathOfBuildingCommunity/PathOfBuilding-PoE2",
+    ),
+];
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+    /// `sha256:<hex>`, published by GitHub for each upload.
+    digest: Option<String>,
+}
+
+/// Where downloaded sources live. Shared by all profiles.
+fn sources_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_default()
+        .join("PathOfBuilding")
+        .join("Sources")
+}
+
+/// A layout for sources extracted to `dir`, either a checkout with `src`
+/// and `runtime` or a portable install with both merged at the top.
+fn locate(dir: &Path, fork: &str) -> Option<Layout> {
+    let (script_dir, runtime_dir) = if dir.join("src/Launch.lua").is_file() {
+        (dir.join("src"), dir.join("runtime"))
+    } else if dir.join("Launch.lua").is_file() {
+        (dir.to_path_buf(), dir.to_path_buf())
+    } else {
+        return None;
+    };
+    Some(Layout {
+        fork: fork.to_string(),
+        tree_dir: script_dir.join("TreeData"),
+        script_dir,
+        runtime_dir,
+    })
+}
+
+/// Checks `data` against a GitHub `sha256:<hex>` digest and unpacks it.
+fn install(data: &[u8], digest: &str, dir: &Path, fork: &str) -> Result<Layout, String> {
+    let expected = digest
+        .strip_prefix("sha256:")
+        .ok_or_else(|| format!("unsupported checksum {}", digest))?;
+    let actual = format!("{:x}", Sha256::digest(data));
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "checksum mismatch: expected {}, got {}",
+            expected, actual
+        ));
+    }
+    let mut zip = zip::ZipArchive::new(io::Cursor::new(data)).map_err(|e| e.to_string())?;
+    let part = dir.with_extension("part");
+    std::fs::remove_dir_all(&part).ok();
+    zip.extract_unwrapped_root_dir(&part, zip::read::root_dir_common_filter)
+        .map_err(|e| e.to_string())?;
+    if locate(&part, fork).is_none() {
+        std::fs::remove_dir_all(&part).ok();
+        return Err("the release has no Launch.lua".into());
+    }
+    std::fs::remove_dir_all(dir).ok();
+    std::fs::rename(&part, dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    locate(dir, fork).ok_or_else(|| "the release has no Launch.lua".into())
+}
+
+/// GETs `url` through the proxy the environment names, failing on an
+/// HTTP error status.
+fn get(url: &str) -> Result<Vec<u8>, String> {
+    let options = HttpOptions {
+        proxy: NetSettings::new(&NetworkSettings::default())
+            .proxy_for(url)
+            .map(String::from),
+        ..HttpOptions::default()
+    };
+    let headers = [(
+        "User-Agent".to_string(),
+        concat!("pob-runtime/", env!("CARGO_PKG_VERSION")).to_string(),
+    )];
+    let mut response = HttpClient::new(&options)
+        .and_then(|client| client.send("GET", url, &headers, None))
+        .map_err(|e| e.to_string())?;
+    if response.status >= 400 {
+        return Err(format!("{}: status code {}", response.url, response.status));
+    }
+    let mut data = Vec::new();
+    response
+        .body
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+/// Fetches the latest release of `repo` from GitHub.
+fn download(repo: &str) -> Result<(String, Vec<u8>, String), String> {
+    let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
+    let release: Release = serde_json::from_slice(&get(&url)?).map_err(|e| e.to_string())?;
+    let zips = || release.assets.iter().filter(|a| a.name.ends_with(".zip"));
+    let asset = zips()
+        .find(|a| a.name.ends_with("Portable.zip"))
+        .or_else(|| zips().next())
+        .ok_or_else(|| format!("{} {} has no zip to download", repo, release.tag_name))?;
+    let digest = asset.digest.clone().ok_or_else(|| {
+        format!(
+            "{} {}: {} has no published checksum",
+            repo, release.tag_name, asset.name
+        )
+    })?;
+    println!("downloading {}", asset.browser_download_url);
+    let data = get(&asset.browser_download_url)?;
+    Ok((release.tag_name.clone(), data, digest))
+}
+
+/// Asks on the terminal, or in a message box when launched without one.
+/// With neither, e.g. in a script, the answer is no; `bootstrap` downloads
+/// without asking.
+fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return has_display()
+            && dialogs::message_box(
+                MessageLevel::Info,
+                "Path of Building",
+                question,
+                MessageButtons::YesNo,
+            );
+    }
+    print!("{} [Y/n] ", question);
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok();
+    matches!(answer.trim(), "" | "y" | "Y" | "yes")
+}
+
+/// Sources for `fork` (default `poe1`) from the data directory, used when
+/// there is no checkout to run. Offers to download the latest release if
+/// none was downloaded yet; `update` downloads it without asking.
+pub fn sources(fork: Option<&str>, update: bool) -> Result<Layout, String> {
+    let fork = fork.unwrap_or("poe1");
+    let Some(&(_, title, repo)) = EDITIONS.iter().find(|e| e.0 == fork) else {
+        let names: Vec<_> = EDITIONS.iter().map(|e| e.0).collect();
+        return Err(format!(
+            "no sources for {} (downloadable: {})",
+            fork,
+            names.join(", ")
+        ));
+    };
+    let dir = sources_dir().join(fork);
+    if !update && let Some(layout) = locate(&dir, fork) {
+        return Ok(layout);
+    }
+    if !update
+        && !confirm(&format!(
+            "No PathOfBuilding sources found. Download {} into {}?",
+            title,
+            dir.display()
+        ))
+    {
+        return Err(format!(
+            "no PathOfBuilding sources; run `bootstrap{}` to download {}",
+            if fork == "poe1" { "" } else { " --fork poe2" },
+            title
+        ));
+    }
+    std::fs::create_dir_all(sources_dir()).map_err(|e| e.to_string())?;
+    let (tag, data, digest) = download(repo).map_err(|e| format!("{}: {}", repo, e))?;
+    let layout =
+        install(&data, &digest, &dir, fork).map_err(|e| format!("{} {}: {}", repo, tag, e))?;
+    println!("installed {} {} into {}", title, tag, dir.display());
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    #[test]
+    fn verifies_and_unpacks_release_archives() {
+        let mut zip = ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, text) in [
+            ("PathOfBuilding-2.50.0/src/Launch.lua", "launch"),
+            ("PathOfBuilding-2.50.0/runtime/lua/dkjson.lua", "json"),
+        ] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        let data = zip.finish().unwrap().into_inner();
+        let digest = format!("sha256:{:x}", Sha256::digest(&data));
+
+        let dir = std::env::temp_dir().join(format!("pob-bootstrap-{}", std::process::id()));
+        let target = dir.join("poe1");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("stale.lua"), "old").unwrap();
+        let bad = format!("sha256:{}", "0".repeat(64));
+        let err = install(&data, &bad, &target, "poe1").unwrap_err();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+        assert!(target.join("stale.lua").exists());
+
+        let layout = install(&data, &digest, &target, "poe1").unwrap();
+        assert_eq!(layout.script_dir, target.join("src"));
+        assert_eq!(layout.tree_dir, target.join("src/TreeData"));
+        assert_eq!(
+            std::fs::read_to_string(layout.runtime_dir.join("lua/dkjson.lua")).unwrap(),
+            "json"
+        );
+        assert!(!target.join("stale.lua").exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,97 @@
+use mlua::prelude::*;
+
+/// What a build summary reports, as keys of PoB's `mainOutput`.
+pub const SUMMARY_STATS: &[&str] = &[
+    "CombinedDPS",
+    "TotalDPS",
+    "TotalDot",
+    "Life",
+    "EnergyShield",
+    "Mana",
+    "TotalEHP",
+    "Armour",
+    "Evasion",
+    "FireResist",
+    "ColdResist",
+    "LightningResist",
+    "ChaosResist",
+];
+
+/// The main calc output of the open build: every `SUMMARY_STATS` value
+/// PoB computed, in that order. None when no build is open.
+pub fn summary(lua: &Lua) -> LuaResult<Option<Vec<(&'static str, f64)>>> {
+    let output: Option<LuaTable> = lua
+        .load(
+            r#"
+            local build = main and main.mode == "BUILD" and main.modes and main.modes.BUILD
+            return build and build.calcsTab and build.calcsTab.mainOutput
+            "#,
+        )
+        .eval()?;
+    let Some(output) = output else {
+        return Ok(None);
+    };
+    let mut stats = Vec::with_capacity(SUMMARY_STATS.len());
+    for &name in SUMMARY_STATS {
+        if let Some(v) = output.get::<_, Option<f64>>(name)? {
+            stats.push((name, v));
+        }
+    }
+    Ok(Some(stats))
+}
+
+/// `summary` as a JSON object.
+pub fn summary_json(stats: &[(&str, f64)]) -> serde_json::Value {
+    stats
+        .iter()
+        .map(|&(name, v)| (name.to_string(), serde_json::json!(v)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// The open build as a build code, the way PoB's Import/Export tab writes
+/// it. None when no build is open.
+pub fn export_code(lua: &Lua) -> LuaResult<Option<String>> {
+    lua.load(
+        r#"
+        local build = main and main.mode == "BUILD" and main.modes and main.modes.BUILD
+        if build and build.SaveDB then
+            return (common.base64.encode(Deflate(build:SaveDB("code"))):gsub("+", "-"):gsub("/", "_"))
+        end
+        "#,
+    )
+    .eval()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_reads_main_output_in_order() {
+        let lua = Lua::new();
+        assert_eq!(summary(&lua).unwrap(), None);
+        lua.load(
+            r#"
+            main = { mode = "BUILD", modes = { BUILD = { calcsTab = { mainOutput = {
+                Life = 4200, CombinedDPS = 1.5e6, FireResist = 75, Unrelated = 1,
+            } } } } }
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let stats = summary(&lua).unwrap().unwrap();
+        assert_eq!(
+            stats,
+            [
+                ("CombinedDPS", 1.5e6),
+                ("Life", 4200.0),
+                ("FireResist", 75.0)
+            ]
+        );
+        assert_eq!(
+            summary_json(&stats).to_string(),
+            r#"{"CombinedDPS":1500000.0,"Life":4200.0,"FireResist":75.0}"#
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+
+use mlua::prelude::*;
+use rfd::{MessageButtons, MessageLevel};
+
+use crate::dialogs;
+
+/// What happens when a main object callback like OnFrame raises an error.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorPolicy {
+    /// Carry on without a word.
+    Ignore,
+    /// Print every error and carry on.
+    Log,
+    /// Print each distinct error the first time it's raised.
+    Once,
+    /// Also ask, once per distinct error, whether to keep going.
+    Prompt,
+    /// Stop the host, as every error used to.
+    Crash,
+}
+
+impl ErrorPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ignore" => Some(Self::Ignore),
+            "log" => Some(Self::Log),
+            "once" => Some(Self::Once),
+            "prompt" => Some(Self::Prompt),
+            "crash" => Some(Self::Crash),
+            _ => None,
+        }
+    }
+}
+
+/// Applies the policy, remembering the errors already reported by their
+/// message and traceback, so one raised every frame is reported once.
+pub struct CallbackErrors {
+    policy: ErrorPolicy,
+    seen: HashSet<String>,
+}
+
+impl CallbackErrors {
+    pub fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            policy,
+            seen: HashSet::new(),
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: ErrorPolicy) {
+        self.policy = policy;
+    }
+
+    /// Ok to carry on after `callback` raised `error`; the error back when
+    /// the host should stop.
+    pub fn handle(&mut self, callback: &str, error: LuaError) -> LuaResult<()> {
+        let text = error.to_string();
+        let first = self.policy != ErrorPolicy::Log && self.seen.insert(text.clone());
+        match self.policy {
+            ErrorPolicy::Ignore => {}
+            ErrorPolicy::Crash => return Err(error),
+            ErrorPolicy::Log => eprintln!("{}: {}", callback, text),
+            ErrorPolicy::Once | ErrorPolicy::Prompt if !first => {}
+            ErrorPolicy::Once => eprintln!("{}: {}", callback, text),
+            ErrorPolicy::Prompt => {
+                eprintln!("{}: {}", callback, text);
+                let go_on = dialogs::message_box(
+                    MessageLevel::Error,
+                    &format!("Error in {}", callback),
+                    &format!("{}\n\nKeep running?", text),
+                    MessageButtons::YesNo,
+                );
+                if !go_on {
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_swallow_report_or_pass_errors_on() {
+        let error = || LuaError::RuntimeError("boom\nstack traceback: OnFrame".into());
+        let mut errors = CallbackErrors::new(ErrorPolicy::Once);
+        assert!(errors.handle("OnFrame", error()).is_ok());
+        assert!(!errors.seen.insert(error().to_string()));
+
+        errors.set_policy(ErrorPolicy::Crash);
+        assert!(errors.handle("OnMouseMove", error()).is_err());
+        errors.set_policy(ErrorPolicy::Ignore);
+        assert!(errors.handle("OnMouseMove", error()).is_ok());
+        assert_eq!(ErrorPolicy::parse("Prompt"), Some(ErrorPolicy::Prompt));
+        assert_eq!(ErrorPolicy::parse("panic"), None);
+    }
+}
@@ -0,0 +1,146 @@
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+
+/// Bumped when the callbacks the host makes change in a way scripts can
+/// tell apart; 2 added callback targets.
+pub const CALLBACK_API_VERSION: u32 = 2;
+
+/// Whether a target hears a callback before or after the main object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Before,
+    After,
+}
+
+impl Stage {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "before" => Some(Self::Before),
+            "after" => Some(Self::After),
+            _ => None,
+        }
+    }
+}
+
+/// Tables besides the main object that get its callbacks (OnFrame,
+/// OnKeyDown and the rest), so tools can hook in without wrapping PoB's
+/// methods. They nest around the main object like a stack: the newest
+/// Before target runs first and the newest After target runs last.
+#[derive(Default)]
+pub struct CallbackTargets {
+    before: Vec<LuaRegistryKey>,
+    after: Vec<LuaRegistryKey>,
+}
+
+pub type SharedTargets = Arc<Mutex<CallbackTargets>>;
+
+impl CallbackTargets {
+    /// Adds `obj`, moving it if it was already a target.
+    pub fn add(&mut self, lua: &Lua, obj: LuaTable, stage: Stage) -> LuaResult<()> {
+        self.remove(lua, &obj)?;
+        let key = lua.create_registry_value(obj)?;
+        match stage {
+            Stage::Before => self.before.insert(0, key),
+            Stage::After => self.after.push(key),
+        }
+        Ok(())
+    }
+
+    /// False if `obj` wasn't a target.
+    pub fn remove(&mut self, lua: &Lua, obj: &LuaTable) -> LuaResult<bool> {
+        for list in [&mut self.before, &mut self.after] {
+            for i in 0..list.len() {
+                if lua.registry_value::<LuaTable>(&list[i])? == *obj {
+                    lua.remove_registry_value(list.remove(i))?;
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Every table to call, in order, with `main` between the stages.
+    pub fn in_order<'lua>(
+        &self,
+        lua: &'lua Lua,
+        main: Option<LuaTable<'lua>>,
+    ) -> LuaResult<Vec<LuaTable<'lua>>> {
+        let mut tables = Vec::with_capacity(self.before.len() + self.after.len() + 1);
+        for key in &self.before {
+            tables.push(lua.registry_value(key)?);
+        }
+        tables.extend(main);
+        for key in &self.after {
+            tables.push(lua.registry_value(key)?);
+        }
+        Ok(tables)
+    }
+}
+
+/// Registers `AddCallbackTarget(obj, ["before" | "after"])`,
+/// `RemoveCallbackTarget(obj) -> wasTarget` and `GetCallbackAPIVersion()`.
+/// Targets are called like the main object, with themselves as `self`.
+pub fn register(lua: &Lua, targets: SharedTargets) -> LuaResult<()> {
+    let g = lua.globals();
+    let t = targets.clone();
+    g.set(
+        "AddCallbackTarget",
+        lua.create_function(move |lua, (obj, stage): (LuaTable, Option<String>)| {
+            let stage = match stage.as_deref() {
+                None => Stage::After,
+                Some(name) => Stage::parse(name).ok_or_else(|| {
+                    LuaError::RuntimeError(format!("unknown callback stage \"{}\"", name))
+                })?,
+            };
+            t.lock().unwrap().add(lua, obj, stage)
+        })?,
+    )?;
+    g.set(
+        "RemoveCallbackTarget",
+        lua.create_function(move |lua, obj: LuaTable| targets.lock().unwrap().remove(lua, &obj))?,
+    )?;
+    g.set(
+        "GetCallbackAPIVersion",
+        lua.create_function(|_, ()| Ok(CALLBACK_API_VERSION))?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_nest_around_the_main_object() {
+        let lua = Lua::new();
+        let targets: SharedTargets = Arc::default();
+        register(&lua, targets.clone()).unwrap();
+        let main: LuaTable = lua
+            .load(
+                r#"
+                local function named(name) return { name = name } end
+                local main, overlay, automation, log = named("main"), named("overlay"),
+                    named("automation"), named("log")
+                AddCallbackTarget(overlay, "before")
+                AddCallbackTarget(automation, "before")
+                AddCallbackTarget(log)
+                AddCallbackTarget(overlay, "after")
+                assert(RemoveCallbackTarget(log) and not RemoveCallbackTarget(log))
+                assert(not pcall(AddCallbackTarget, log, "during"))
+                assert(GetCallbackAPIVersion() >= 2)
+                return main
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let names: Vec<String> = targets
+            .lock()
+            .unwrap()
+            .in_order(&lua, Some(main))
+            .unwrap()
+            .iter()
+            .map(|t| t.get("name").unwrap())
+            .collect();
+        assert_eq!(names, ["automation", "main", "overlay"]);
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{
+        Arc, Mutex,
+        mpsc::{Receiver, TryRecvError, channel},
+    },
+    time::{Duration, Instant},
+};
+
+use mlua::prelude::*;
+
+use crate::graphics::{
+    DrawCmd, DrawItem, DrawQueue, PixelFormat, TextureCmd, TextureQueue, TextureUploadCmd,
+};
+use crate::host_thread::HostEvent;
+use crate::platform::{MonitorInfo, WindowMode};
+use crate::texture_ids::{SharedTextureIds, TextureLease};
+
+/// Darkens the grab outside the selection.
+const SHADE: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
+const OUTLINE: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+
+/// How long the window gets to leave the screen before it is grabbed.
+const HIDE_DELAY: Duration = Duration::from_millis(250);
+
+/// How long the OCR command gets before it is killed.
+const OCR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// RGBA pixels taken from the screen.
+#[derive(Clone, Debug, PartialEq)]
+struct Grab {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Grab {
+    /// The pixels between two corners, in either order, kept inside the grab.
+    fn crop(&self, a: [u32; 2], b: [u32; 2]) -> Grab {
+        let [x0, x1] = [a[0].min(b[0]), a[0].max(b[0]).min(self.width)];
+        let [y0, y1] = [a[1].min(b[1]), a[1].max(b[1]).min(self.height)];
+        let (width, height) = (x1.saturating_sub(x0), y1.saturating_sub(y0));
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in y0..y0 + height {
+            let row = ((y * self.width + x0) * 4) as usize;
+            pixels.extend_from_slice(&self.pixels[row..row + (width * 4) as usize]);
+        }
+        Grab {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Grabs the monitor at `point`, or the primary one.
+#[cfg(feature = "capture")]
+fn grab_screen(point: Option<[i32; 2]>) -> Result<Grab, String> {
+    let monitor = match point.map(|[x, y]| xcap::Monitor::from_point(x, y)) {
+        Some(Ok(monitor)) => monitor,
+        _ => {
+            let monitors = xcap::Monitor::all().map_err(|e| e.to_string())?;
+            monitors
+                .iter()
+                .find(|m| m.is_primary().unwrap_or(false))
+                .or(monitors.first())
+                .ok_or("no monitor to capture")?
+                .clone()
+        }
+    };
+    let image = monitor.capture_image().map_err(|e| e.to_string())?;
+    Ok(Grab {
+        width: image.width(),
+        height: image.height(),
+        pixels: image.into_raw(),
+    })
+}
+
+#[cfg(not(feature = "capture"))]
+fn grab_screen(_: Option<[i32; 2]>) -> Result<Grab, String> {
+    Err("this build has no screen capture".into())
+}
+
+/// Runs the configured OCR command with the region as PNG on stdin and
+/// returns what it printed.
+fn recognize(command: &[String], grab: &Grab) -> Result<String, String> {
+    let image = image::RgbaImage::from_raw(grab.width, grab.height, grab.pixels.clone())
+        .ok_or("bad region size")?;
+    let mut png = Vec::new();
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .map_err(|e| e.to_string())?;
+    run_ocr(command, png, OCR_TIMEOUT)
+}
+
+/// Feeds `input` to `command` and collects its stdout, each on its own
+/// thread so neither pipe can fill up and stall the other. The command is
+/// killed if it hasn't finished by `timeout`.
+fn run_ocr(command: &[String], input: Vec<u8>, timeout: Duration) -> Result<String, String> {
+    let (program, args) = command.split_first().ok_or("empty OCR command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    let mut stdin = child.stdin.take().unwrap();
+    // A command that exits without reading it all closes the pipe, which
+    // isn't an error here.
+    std::thread::spawn(move || stdin.write_all(&input).ok());
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        stdout.read_to_end(&mut out).map(|_| out)
+    });
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(_) => break,
+            None if Instant::now() >= deadline => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!("{}: timed out", program));
+            }
+            None => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+    let out = reader
+        .join()
+        .map_err(|_| "OCR output reader panicked")?
+        .map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// A screen grab shown full screen while the user drags out a region.
+struct Pick {
+    grab: Grab,
+    texture: TextureLease,
+    callback: LuaRegistryKey,
+    cursor: [f32; 2],
+    from: Option<[f32; 2]>,
+    /// The window mode to go back to once a region is picked.
+    mode: WindowMode,
+}
+
+/// A grab being taken on its own thread while the window is hidden.
+struct Grabbing {
+    grab: Receiver<Result<Grab, String>>,
+    callback: LuaRegistryKey,
+}
+
+/// A picked region waiting for OCR before it goes to its callback.
+struct Picked {
+    grab: Grab,
+    callback: LuaRegistryKey,
+    text: Option<Receiver<Option<String>>>,
+}
+
+/// `CaptureScreenRegion`: grabs the monitor the window is on, with the
+/// window hidden, lets the user select part of it in the window's own
+/// overlay and hands the pixels to Lua.
+#[derive(Clone)]
+pub struct RegionCapture {
+    pub ids: SharedTextureIds,
+    pub queue: TextureQueue,
+    pub window_mode: Arc<Mutex<WindowMode>>,
+    pub screen_size: Arc<Mutex<[u32; 2]>>,
+    pub monitors: Arc<Mutex<Vec<MonitorInfo>>>,
+    pub hide_for_grab: Arc<Mutex<bool>>,
+    /// Program and arguments reading a PNG on stdin and printing its text.
+    pub ocr_command: Option<Vec<String>>,
+    grabbing: Arc<Mutex<Option<Grabbing>>>,
+    pick: Arc<Mutex<Option<Pick>>>,
+    picked: Arc<Mutex<Vec<Picked>>>,
+}
+
+impl RegionCapture {
+    pub fn new(
+        ids: SharedTextureIds,
+        queue: TextureQueue,
+        window_mode: Arc<Mutex<WindowMode>>,
+        screen_size: Arc<Mutex<[u32; 2]>>,
+        monitors: Arc<Mutex<Vec<MonitorInfo>>>,
+        hide_for_grab: Arc<Mutex<bool>>,
+        ocr_command: Option<Vec<String>>,
+    ) -> Self {
+        Self {
+            ids,
+            queue,
+            window_mode,
+            screen_size,
+            monitors,
+            hide_for_grab,
+            ocr_command,
+            grabbing: Arc::default(),
+            pick: Arc::default(),
+            picked: Arc::default(),
+        }
+    }
+
+    /// True while a region is being selected; input and drawing then
+    /// belong to the overlay instead of Lua.
+    pub fn selecting(&self) -> bool {
+        self.pick.lock().unwrap().is_some()
+    }
+
+    fn start(&self, lua: &Lua, callback: LuaFunction) -> LuaResult<Result<(), String>> {
+        if self.selecting() || self.grabbing.lock().unwrap().is_some() {
+            return Ok(Err("a region is already being picked".into()));
+        }
+        if !cfg!(feature = "capture") {
+            return Ok(grab_screen(None).map(|_| ()));
+        }
+        // The middle of the monitor the window, and so the overlay, is on.
+        let point = self
+            .monitors
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|m| m.current)
+            .map(|m| {
+                [
+                    m.position[0] + m.size[0] as i32 / 2,
+                    m.position[1] + m.size[1] as i32 / 2,
+                ]
+            });
+        let (tx, rx) = channel();
+        let spawned = std::thread::Builder::new()
+            .name("screen grab".into())
+            .spawn(move || {
+                std::thread::sleep(HIDE_DELAY);
+                tx.send(grab_screen(point)).ok();
+            });
+        if let Err(e) = spawned {
+            return Ok(Err(e.to_string()));
+        }
+        *self.hide_for_grab.lock().unwrap() = true;
+        *self.grabbing.lock().unwrap() = Some(Grabbing {
+            grab: rx,
+            callback: lua.create_registry_value(callback)?,
+        });
+        Ok(Ok(()))
+    }
+
+    /// Shows the window again, now as the overlay over `grab`.
+    fn show(&self, grab: Grab, callback: LuaRegistryKey) {
+        let texture = TextureLease::new(self.ids.clone(), self.queue.clone(), |_| {});
+        self.queue
+            .lock()
+            .unwrap()
+            .push(TextureCmd::Upload(TextureUploadCmd {
+                id: texture.id(),
+                pixels: grab.pixels.clone().into(),
+                format: PixelFormat::Rgba8,
+                width: grab.width,
+                height: grab.height,
+                premultiplied: false,
+            }));
+        let mode = std::mem::replace(
+            &mut *self.window_mode.lock().unwrap(),
+            WindowMode::Borderless,
+        );
+        *self.pick.lock().unwrap() = Some(Pick {
+            grab,
+            texture,
+            callback,
+            cursor: [0.0; 2],
+            from: None,
+            mode,
+        });
+    }
+
+    /// Grab pixels per UI unit across and down.
+    fn scale(&self, grab: &Grab) -> [f32; 2] {
+        let [w, h] = *self.screen_size.lock().unwrap();
+        [
+            grab.width as f32 / w.max(1) as f32,
+            grab.height as f32 / h.max(1) as f32,
+        ]
+    }
+
+    /// Drag with the left button to select, Escape to cancel.
+    pub fn event(&self, event: &HostEvent) {
+        let mut guard = self.pick.lock().unwrap();
+        let Some(pick) = guard.as_mut() else {
+            return;
+        };
+        match event {
+            HostEvent::MouseMove { x, y } => pick.cursor = [*x, *y],
+            HostEvent::KeyDown { key, .. } if key == "LEFTBUTTON" => pick.from = Some(pick.cursor),
+            HostEvent::KeyUp { key } if key == "LEFTBUTTON" => {
+                let Some(from) = pick.from else {
+                    return;
+                };
+                let pick = guard.take().unwrap();
+                let [sx, sy] = self.scale(&pick.grab);
+                let at = |p: [f32; 2]| [(p[0] * sx).max(0.0) as u32, (p[1] * sy).max(0.0) as u32];
+                let region = pick.grab.crop(at(from), at(pick.cursor));
+                self.finish(pick.mode, pick.callback, Some(region));
+            }
+            HostEvent::KeyDown { key, .. } if key == "ESCAPE" => {
+                let pick = guard.take().unwrap();
+                self.finish(pick.mode, pick.callback, None);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&self, mode: WindowMode, callback: LuaRegistryKey, region: Option<Grab>) {
+        *self.window_mode.lock().unwrap() = mode;
+        let grab = region.unwrap_or(Grab {
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        });
+        let text = match &self.ocr_command {
+            Some(command) if grab.width > 0 && grab.height > 0 => {
+                let (tx, rx) = channel();
+                let (command, image) = (command.clone(), grab.clone());
+                std::thread::spawn(move || {
+                    let text = recognize(&command, &image)
+                        .map_err(|e| eprintln!("OCR: {}", e))
+                        .ok();
+                    tx.send(text).ok();
+                });
+                Some(rx)
+            }
+            _ => None,
+        };
+        self.picked.lock().unwrap().push(Picked {
+            grab,
+            callback,
+            text,
+        });
+    }
+
+    /// Draws the grab, shaded outside the selection, in place of a frame.
+    pub fn draw(&self, queue: &DrawQueue) {
+        let guard = self.pick.lock().unwrap();
+        let Some(pick) = guard.as_ref() else {
+            return;
+        };
+        let [w, h] = self.screen_size.lock().unwrap().map(|v| v as f32);
+        let rect = |x: f32, y: f32, w: f32, h: f32, color, texture_id| {
+            DrawItem::Rect(DrawCmd {
+                x,
+                y,
+                w,
+                h,
+                color,
+                texture_id,
+                uv: [0.0, 0.0, 1.0, 1.0],
+                clip: None,
+                corners: None,
+            })
+        };
+        let mut items = vec![rect(0.0, 0.0, w, h, [1.0; 4], pick.texture.id())];
+        let [x0, y0, x1, y1] = match pick.from {
+            Some(from) => [
+                from[0].min(pick.cursor[0]),
+                from[1].min(pick.cursor[1]),
+                from[0].max(pick.cursor[0]),
+                from[1].max(pick.cursor[1]),
+            ],
+            None => [
+                pick.cursor[0],
+                pick.cursor[1],
+                pick.cursor[0],
+                pick.cursor[1],
+            ],
+        };
+        items.extend([
+            rect(0.0, 0.0, w, y0, SHADE, 0),
+            rect(0.0, y1, w, h - y1, SHADE, 0),
+            rect(0.0, y0, x0, y1 - y0, SHADE, 0),
+            rect(x1, y0, w - x1, y1 - y0, SHADE, 0),
+        ]);
+        if pick.from.is_some() {
+            items.extend([
+                rect(x0, y0 - 1.0, x1 - x0, 1.0, OUTLINE, 0),
+                rect(x0, y1, x1 - x0, 1.0, OUTLINE, 0),
+                rect(x0 - 1.0, y0, 1.0, y1 - y0, OUTLINE, 0),
+                rect(x1, y0, 1.0, y1 - y0, OUTLINE, 0),
+            ]);
+        }
+        queue.lock().unwrap().extend(items);
+    }
+
+    /// Starts the overlay once the grab is in, and hands picked regions
+    /// whose OCR is done to their callbacks, as (RGBA pixels, width,
+    /// height, text), or nil when cancelled or the grab failed.
+    pub fn pump(&self, lua: &Lua) -> LuaResult<()> {
+        let grabbed = {
+            let mut grabbing = self.grabbing.lock().unwrap();
+            match grabbing.as_ref().map(|g| g.grab.try_recv()) {
+                None | Some(Err(TryRecvError::Empty)) => None,
+                Some(result) => Some((result, grabbing.take().unwrap().callback)),
+            }
+        };
+        if let Some((result, callback)) = grabbed {
+            *self.hide_for_grab.lock().unwrap() = false;
+            match result.unwrap_or_else(|_| Err("the screen grab stopped".into())) {
+                Ok(grab) => self.show(grab, callback),
+                Err(e) => {
+                    eprintln!("CaptureScreenRegion: {}", e);
+                    let mode = *self.window_mode.lock().unwrap();
+                    self.finish(mode, callback, None);
+                }
+            }
+        }
+        let mut ready = Vec::new();
+        {
+            let mut picked = self.picked.lock().unwrap();
+            let mut i = 0;
+            while i < picked.len() {
+                let text = match &picked[i].text {
+                    None => None,
+                    Some(rx) => match rx.try_recv() {
+                        Err(TryRecvError::Empty) => {
+                            i += 1;
+                            continue;
+                        }
+                        answer => answer.ok().flatten(),
+                    },
+                };
+                ready.push((picked.remove(i), text));
+            }
+        }
+        for (Picked { grab, callback, .. }, text) in ready {
+            let f: LuaFunction = lua.registry_value(&callback)?;
+            lua.remove_registry_value(callback)?;
+            let result = if grab.width == 0 || grab.height == 0 {
+                f.call::<_, ()>(())
+            } else {
+                let pixels = lua.create_string(&grab.pixels)?;
+                f.call::<_, ()>((pixels, grab.width, grab.height, text))
+            };
+            if let Err(e) = result {
+                eprintln!("CaptureScreenRegion callback: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers `CaptureScreenRegion(callback) -> started, [error]`.
+    pub fn register(&self, lua: &Lua) -> LuaResult<()> {
+        let capture = self.clone();
+        lua.globals().set(
+            "CaptureScreenRegion",
+            lua.create_function(move |lua, callback: LuaFunction| {
+                Ok(match capture.start(lua, callback)? {
+                    Ok(()) => (true, None),
+                    Err(e) => (false, Some(e)),
+                })
+            })?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crops_between_corners_in_any_order() {
+        // 4x3 image whose red channel is the pixel index.
+        let grab = Grab {
+            width: 4,
+            height: 3,
+            pixels: (0..12u8).flat_map(|i| [i, 0, 0, 255]).collect(),
+        };
+        let region = grab.crop([3, 2], [1, 0]);
+        assert_eq!((region.width, region.height), (2, 2));
+        let red: Vec<u8> = region.pixels.chunks(4).map(|p| p[0]).collect();
+        assert_eq!(red, [1, 2, 5, 6]);
+        // Corners past the edge are clamped.
+        assert_eq!(grab.crop([2, 1], [9, 9]).pixels.len(), 2 * 2 * 4);
+        assert_eq!(grab.crop([1, 1], [1, 2]).width, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ocr_pipes_dont_stall_and_slow_commands_are_killed() {
+        let sh = |script: &str| vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+        // Writes more than a pipe holds before reading any of its input.
+        let out = run_ocr(
+            &sh("head -c 1000000 /dev/zero | tr '\\0' x; cat >/dev/null"),
+            vec![0; 1_000_000],
+            Duration::from_secs(20),
+        )
+        .unwrap();
+        assert_eq!(out.len(), 1_000_000);
+
+        let started = Instant::now();
+        let err =
+            run_ocr(&sh("exec sleep 10"), Vec::new(), Duration::from_millis(200)).unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,194 @@
+/// The longest `--timeout` taken, in seconds.
+const MAX_TIMEOUT: u64 = 24 * 60 * 60;
+
+/// Command line options.
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    /// `--fork NAME`: which install from `pob.toml` to run.
+    pub fork: Option<String>,
+    /// `--profile NAME`: a separate user path, so several instances can
+    /// run side by side.
+    pub profile: Option<String>,
+    /// `--register-url-handler`: make this executable open `pob://` links.
+    pub register_url_handler: bool,
+    /// `bench BUILD`: time calc passes over a build without a window.
+    pub bench: bool,
+    /// `pack OUTPUT`: write a standalone executable with the scripts
+    /// embedded.
+    pub pack: bool,
+    /// `diff BUILD_A BUILD_B`: print how two builds' main stats differ.
+    pub diff: bool,
+    /// `bootstrap`: download the latest PoB release for `--fork`, then
+    /// launch it.
+    pub bootstrap: bool,
+    /// `--passes N`: calc passes a benchmark runs.
+    pub passes: Option<u32>,
+    /// `--record FILE`: log window input for a later `--replay`.
+    pub record: Option<String>,
+    /// `--replay FILE`: feed a recording back instead of live input.
+    pub replay: Option<String>,
+    /// `--automate SCRIPT`: drive the UI from a Lua test script, then exit.
+    pub automate: Option<String>,
+    /// `--shader-dir DIR`: rebuild the pipelines whenever `shader.wgsl` or
+    /// `post.wgsl` in DIR changes, for working on the shaders.
+    pub shader_dir: Option<String>,
+    /// `--render-dump FILE`: draw a frame saved with Ctrl+Shift+D instead
+    /// of running the scripts.
+    pub render_dump: Option<String>,
+    /// `--gpu-debug`: turn on wgpu's validation and record an API trace
+    /// under the user path, for GPU bug reports.
+    pub gpu_debug: bool,
+    /// `--serve`: answer build stats over HTTP instead of opening a window.
+    pub serve: bool,
+    /// `--listen ADDR`: where `--serve` listens.
+    pub listen: Option<String>,
+    /// `--workers N`: headless Lua states `--serve` keeps ready.
+    pub workers: Option<usize>,
+    /// `--timeout SECS`: how long `--serve` spends on one request, or
+    /// `diff` on loading a build; at most a day.
+    pub timeout: Option<u64>,
+    /// Build files and `pob://` links to open.
+    pub open: Vec<String>,
+}
+
+impl Args {
+    /// Parses the arguments after the program name. Options take their
+    /// value as the next argument or after `=`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut out = Self::default();
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(String::as_str) {
+            Some("bench") => out.bench = true,
+            Some("pack") => out.pack = true,
+            Some("bootstrap") => out.bootstrap = true,
+            Some("diff") => out.diff = true,
+            _ => {}
+        }
+        if out.bench || out.pack || out.bootstrap || out.diff {
+            args.next();
+        }
+        let (mut passes, mut workers, mut timeout) = (None, None, None);
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let slot = match flag {
+                "--fork" => &mut out.fork,
+                "--profile" => &mut out.profile,
+                "--passes" => &mut passes,
+                "--listen" => &mut out.listen,
+                "--workers" => &mut workers,
+                "--timeout" => &mut timeout,
+                "--record" => &mut out.record,
+                "--replay" => &mut out.replay,
+                "--automate" => &mut out.automate,
+                "--shader-dir" => &mut out.shader_dir,
+                "--render-dump" => &mut out.render_dump,
+                "--register-url-handler" => {
+                    out.register_url_handler = true;
+                    continue;
+                }
+                "--gpu-debug" => {
+                    out.gpu_debug = true;
+                    continue;
+                }
+                "--serve" => {
+                    out.serve = true;
+                    continue;
+                }
+                _ if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                _ => {
+                    out.open.push(arg);
+                    continue;
+                }
+            };
+            let value = inline
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))?;
+            *slot = Some(value);
+        }
+        if let Some(n) = passes {
+            out.passes = Some(
+                n.parse()
+                    .map_err(|_| format!("--passes {}: not a count", n))?,
+            );
+        }
+        if let Some(n) = workers {
+            out.workers = Some(
+                n.parse()
+                    .map_err(|_| format!("--workers {}: not a count", n))?,
+            );
+        }
+        if let Some(n) = timeout {
+            let secs: u64 = n
+                .parse()
+                .map_err(|_| format!("--timeout {}: not a number of seconds", n))?;
+            out.timeout = Some(secs.min(MAX_TIMEOUT));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_options() {
+        let parse = |a: &[&str]| Args::parse(a.iter().map(|s| s.to_string()));
+        assert_eq!(
+            parse(&[
+                "--fork",
+                "poe2",
+                "pob://pobbin/x",
+                "--profile=alt",
+                "--replay",
+                "bug.jsonl",
+                "--shader-dir=src"
+            ])
+            .unwrap(),
+            Args {
+                fork: Some("poe2".into()),
+                profile: Some("alt".into()),
+                register_url_handler: false,
+                bench: false,
+                pack: false,
+                bootstrap: false,
+                diff: false,
+                passes: None,
+                record: None,
+                replay: Some("bug.jsonl".into()),
+                automate: None,
+                shader_dir: Some("src".into()),
+                render_dump: None,
+                gpu_debug: false,
+                serve: false,
+                listen: None,
+                workers: None,
+                timeout: None,
+                open: vec!["pob://pobbin/x".into()],
+            }
+        );
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+        let bench = parse(&["bench", "code", "--passes", "50"]).unwrap();
+        assert!(bench.bench);
+        assert_eq!((bench.passes, bench.open), (Some(50), vec!["code".into()]));
+        assert!(parse(&["bench", "--passes=many"]).is_err());
+        let pack = parse(&["pack", "dist/pob"]).unwrap();
+        assert!(pack.pack && !pack.bench && pack.open == ["dist/pob"]);
+        let bootstrap = parse(&["bootstrap", "--fork", "poe2"]).unwrap();
+        assert!(bootstrap.bootstrap && bootstrap.open.is_empty());
+        let diff = parse(&["diff", "codeA", "b.xml"]).unwrap();
+        assert!(diff.diff && diff.open == ["codeA", "b.xml"]);
+        assert!(parse(&["--gpu-debug", "build.xml"]).unwrap().gpu_debug);
+        let serve = parse(&["--serve", "--listen=0.0.0.0:80", "--workers", "4"]).unwrap();
+        assert!(serve.serve && serve.listen.as_deref() == Some("0.0.0.0:80"));
+        assert_eq!((serve.workers, serve.timeout), (Some(4), None));
+        assert!(parse(&["--serve", "--timeout=soon"]).is_err());
+        let forever = parse(&["--serve", "--timeout", &u64::MAX.to_string()]).unwrap();
+        assert_eq!(forever.timeout, Some(MAX_TIMEOUT));
+        assert!(parse(&["--fork"]).is_err());
+        assert!(parse(&["--frok=x"]).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use std::time::{Duration, Instant};
+
+use crate::platform::SystemClipboard;
+
+/// How often a watched clipboard is read.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The OS clipboard, backed by a process-local copy so Copy and Paste keep
+/// working where there is none (headless servers, some Wayland sessions).
+pub struct Clipboard {
+    os: Option<SystemClipboard>,
+    local: String,
+    /// The text last copied or seen by `changed_text`.
+    seen: Option<String>,
+}
+
+impl Clipboard {
+    /// Connects to the OS clipboard, falling back to the local copy alone.
+    pub fn open() -> Self {
+        let os = SystemClipboard::open()
+            .inspect_err(|e| eprintln!("no system clipboard, copies stay in this process: {}", e))
+            .ok();
+        Self {
+            os,
+            local: String::new(),
+            seen: None,
+        }
+    }
+
+    pub fn set_text(&mut self, text: String) {
+        if let Some(os) = &mut self.os
+            && let Err(e) = os.set_text(&text)
+        {
+            eprintln!("clipboard copy: {}", e);
+        }
+        self.seen = Some(text.clone());
+        self.local = text;
+    }
+
+    /// The clipboard's text if something other than this process changed
+    /// it since the last call. The first call only takes note of it.
+    pub fn changed_text(&mut self) -> Option<String> {
+        let text = self.get_text();
+        let first = self.seen.is_none();
+        if self.seen.as_ref() == Some(&text) {
+            return None;
+        }
+        self.seen = Some(text.clone());
+        (!first).then_some(text)
+    }
+
+    /// The OS clipboard's text, or the local copy where there is no OS
+    /// clipboard. Empty if the OS one can't be read, rather than a copy
+    /// the user may have replaced since.
+    pub fn get_text(&mut self) -> String {
+        let Some(os) = &mut self.os else {
+            return self.local.clone();
+        };
+        os.get_text().unwrap_or_else(|e| {
+            eprintln!("clipboard paste: {}", e);
+            String::new()
+        })
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(os) = &mut self.os {
+            os.clear();
+        }
+        self.local.clear();
+        self.seen = Some(String::new());
+    }
+}
+
+/// When the clipboard is watched for copied items.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchMode {
+    Off,
+    /// Only while the window has focus.
+    Focused,
+    /// Also in the background, e.g. while the game has focus.
+    Global,
+}
+
+impl WatchMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "focused" => Some(Self::Focused),
+            "global" => Some(Self::Global),
+            _ => None,
+        }
+    }
+}
+
+/// Paces reads of a watched clipboard.
+pub struct ClipboardWatch {
+    pub mode: WatchMode,
+    polled: Option<Instant>,
+}
+
+impl ClipboardWatch {
+    pub fn new(mode: WatchMode) -> Self {
+        Self { mode, polled: None }
+    }
+
+    /// Whether to read the clipboard now.
+    pub fn due(&mut self, focused: bool, now: Instant) -> bool {
+        let wanted = match self.mode {
+            WatchMode::Off => false,
+            WatchMode::Focused => focused,
+            WatchMode::Global => true,
+        };
+        if !wanted || self.polled.is_some_and(|t| now - t < WATCH_INTERVAL) {
+            return false;
+        }
+        self.polled = Some(now);
+        true
+    }
+}
+
+/// Whether `text` looks like an item copied from the game with Ctrl+C.
+pub fn is_item_text(text: &str) -> bool {
+    let first = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    (first.starts_with("Item Class: ") || first.starts_with("Rarity: "))
+        && text.lines().any(|l| l.trim() == "--------")
+}
+
+/// Line ending convention for text crossing the OS clipboard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NewlineMode {
+    /// Copy writes `\n`; Paste normalizes to `\n`.
+    Lf,
+    /// Copy writes `\r\n` as Windows PoB does; Paste normalizes to `\n`.
+    Crlf,
+    /// Text passes through untouched in both directions.
+    Raw,
+}
+
+impl NewlineMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "LF" => Some(Self::Lf),
+            "CRLF" => Some(Self::Crlf),
+            "RAW" => Some(Self::Raw),
+            _ => None,
+        }
+    }
+
+    pub fn platform_default() -> Self {
+        if cfg!(windows) { Self::Crlf } else { Self::Lf }
+    }
+
+    /// Text as it should be written to the OS clipboard. Only line endings
+    /// change, so `^` colour escapes survive the round trip.
+    pub fn for_copy(self, text: &str) -> String {
+        match self {
+            Self::Lf => to_lf(text),
+            Self::Crlf => to_lf(text).replace('\n', "\r\n"),
+            Self::Raw => text.to_string(),
+        }
+    }
+
+    /// Text as Lua should see it after Paste.
+    pub fn for_paste(self, text: &str) -> String {
+        match self {
+            Self::Lf | Self::Crlf => to_lf(text),
+            Self::Raw => text.to_string(),
+        }
+    }
+}
+
+fn to_lf(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newlines_normalize_and_escapes_round_trip() {
+        let item = "^xE05030Rarity: Unique\r\nStarforge\n^7Infernal Sword\r";
+        let copied = NewlineMode::Crlf.for_copy(item);
+        assert_eq!(
+            copied,
+            "^xE05030Rarity: Unique\r\nStarforge\r\n^7Infernal Sword\r\n"
+        );
+        assert_eq!(
+            NewlineMode::Crlf.for_paste(&copied),
+            "^xE05030Rarity: Unique\nStarforge\n^7Infernal Sword\n"
+        );
+        assert_eq!(NewlineMode::Raw.for_paste(item), item);
+        assert_eq!(NewlineMode::parse("crlf"), Some(NewlineMode::Crlf));
+    }
+
+    #[test]
+    fn items_are_recognised_and_watching_waits_for_focus_and_the_interval() {
+        assert!(is_item_text(
+            "Item Class: Two Hand Swords\r\nRarity: Unique\r\nStarforge\r\n--------\r\nQuality: +20%"
+        ));
+        assert!(!is_item_text("Rarity: Unique\nno separator"));
+        let mut watch = ClipboardWatch::new(WatchMode::Focused);
+        let now = Instant::now();
+        assert!(!watch.due(false, now));
+        assert!(watch.due(true, now));
+        assert!(!watch.due(true, now + Duration::from_millis(100)));
+        assert!(watch.due(true, now + WATCH_INTERVAL));
+    }
+
+    #[test]
+    fn local_clipboard_stands_in_without_an_os_one() {
+        let mut clipboard = Clipboard {
+            os: None,
+            local: String::new(),
+            seen: None,
+        };
+        clipboard.set_text("Rarity: Rare".into());
+        assert_eq!(clipboard.get_text(), "Rarity: Rare");
+        clipboard.clear();
+        assert_eq!(clipboard.get_text(), "");
+    }
+
+    #[test]
+    fn only_copies_from_elsewhere_count_as_changes() {
+        let mut clipboard = Clipboard {
+            os: None,
+            local: String::new(),
+            seen: None,
+        };
+        clipboard.set_text("Rarity: Rare".into());
+        assert_eq!(clipboard.changed_text(), None);
+        clipboard.local = "Rarity: Magic".into();
+        assert_eq!(clipboard.changed_text().as_deref(), Some("Rarity: Magic"));
+        assert_eq!(clipboard.changed_text(), None);
+        clipboard.clear();
+        assert_eq!(clipboard.changed_text(), None);
+    }
+}
@@ -0,0 +1,176 @@
+use base64::{
+    Engine,
+    alphabet::{STANDARD, URL_SAFE},
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+};
+use md5::Md5;
+use mlua::prelude::*;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+const PADDED: GeneralPurposeConfig = GeneralPurposeConfig::new()
+    .with_encode_padding(true)
+    .with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const UNPADDED: GeneralPurposeConfig = GeneralPurposeConfig::new()
+    .with_encode_padding(false)
+    .with_decode_padding_mode(DecodePaddingMode::Indifferent);
+
+/// Registers `native.base64` (the lbase64 API `common.base64` uses) and
+/// `native.hash` / `native.sha1` so build codes and account import don't
+/// run in pure Lua.
+pub fn register(lua: &Lua) -> LuaResult<()> {
+    let base64 = lua.create_table()?;
+    // Encoders are descriptor tables; only the two alphabets build codes and
+    // the trade/OAuth APIs use are supported.
+    base64.set(
+        "makeencoder",
+        lua.create_function(
+            |lua, (s62, s63, spad): (Option<String>, Option<String>, Option<String>)| {
+                let t = lua.create_table()?;
+                t.set(
+                    "urlsafe",
+                    s62.as_deref() == Some("-") && s63.as_deref() == Some("_"),
+                )?;
+                t.set("pad", spad.as_deref() != Some(""))?;
+                Ok(t)
+            },
+        )?,
+    )?;
+    base64.set("makedecoder", base64.get::<_, LuaFunction>("makeencoder")?)?;
+    base64.set(
+        "encode",
+        lua.create_function(|_, (data, encoder): (LuaString, Option<LuaTable>)| {
+            let (urlsafe, pad) = options(encoder.as_ref())?;
+            Ok(engine(urlsafe, pad).encode(data.as_bytes()))
+        })?,
+    )?;
+    base64.set(
+        "decode",
+        lua.create_function(|lua, (data, _decoder): (LuaString, Option<LuaTable>)| {
+            match decode(data.as_bytes()) {
+                Ok(bytes) => lua.create_string(&bytes)?.into_lua_multi(lua),
+                Err(e) => (LuaValue::Nil, e).into_lua_multi(lua),
+            }
+        })?,
+    )?;
+
+    let hash = lua.create_table()?;
+    hash.set("sha1", digest_fn::<Sha1>(lua)?)?;
+    hash.set("sha256", digest_fn::<Sha256>(lua)?)?;
+    hash.set("md5", digest_fn::<Md5>(lua)?)?;
+
+    // The kikito sha1 module PoB bundles is called directly for a hex digest.
+    let sha1 = lua.create_table()?;
+    sha1.set("sha1", digest_fn::<Sha1>(lua)?)?;
+    sha1.set(
+        "binary",
+        lua.create_function(|lua, data: LuaString| {
+            lua.create_string(Sha1::digest(data.as_bytes()))
+        })?,
+    )?;
+    let call = lua.create_table()?;
+    call.set(
+        "__call",
+        lua.create_function(|_, (_, data): (LuaValue, LuaString)| {
+            Ok(hex(&Sha1::digest(data.as_bytes())))
+        })?,
+    )?;
+    sha1.set_metatable(Some(call));
+
+    let preload: LuaTable = lua
+        .globals()
+        .get::<_, LuaTable>("package")?
+        .get("preload")?;
+    for (name, module) in [
+        ("native.base64", base64),
+        ("native.hash", hash),
+        ("native.sha1", sha1),
+    ] {
+        lua.set_named_registry_value(name, module)?;
+        preload.set(
+            name,
+            lua.create_function(move |lua, _: LuaMultiValue| {
+                lua.named_registry_value::<LuaTable>(name)
+            })?,
+        )?;
+    }
+    Ok(())
+}
+
+fn options(encoder: Option<&LuaTable>) -> LuaResult<(bool, bool)> {
+    match encoder {
+        Some(t) => Ok((
+            t.get::<_, Option<bool>>("urlsafe")?.unwrap_or(false),
+            t.get::<_, Option<bool>>("pad")?.unwrap_or(true),
+        )),
+        None => Ok((false, true)),
+    }
+}
+
+fn engine(urlsafe: bool, pad: bool) -> GeneralPurpose {
+    let alphabet = if urlsafe { &URL_SAFE } else { &STANDARD };
+    GeneralPurpose::new(alphabet, if pad { PADDED } else { UNPADDED })
+}
+
+/// Decodes either alphabet with or without padding, skipping whitespace, so
+/// build codes pasted from forums and pastebin raw pages both work.
+fn decode(data: &[u8]) -> Result<Vec<u8>, String> {
+    let clean: Vec<u8> = data
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let urlsafe = clean.iter().any(|&b| b == b'-' || b == b'_');
+    engine(urlsafe, true)
+        .decode(&clean)
+        .map_err(|e| e.to_string())
+}
+
+/// `fn(data, [raw])`: lowercase hex digest, or the raw bytes when `raw`.
+fn digest_fn<D: Digest>(lua: &Lua) -> LuaResult<LuaFunction<'_>> {
+    lua.create_function(|lua, (data, raw): (LuaString, Option<bool>)| {
+        let digest = D::digest(data.as_bytes());
+        if raw.unwrap_or(false) {
+            lua.create_string(&digest[..])?.into_lua(lua)
+        } else {
+            hex(&digest).into_lua(lua)
+        }
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_and_digests_match_reference_values() {
+        let lua = Lua::new();
+        register(&lua).unwrap();
+        let (b64, url, back, sha1, sha256, md5): (String, String, bool, String, String, String) =
+            lua.load(
+                r#"
+                local base64 = require("native.base64")
+                local hash = require("native.hash")
+                local sha1 = require("native.sha1")
+                local url = base64.encode("\251\255", base64.makeencoder("-", "_", ""))
+                return base64.encode("PoB"), url, base64.decode(url) == "\251\255", sha1("abc"),
+                    hash.sha256("abc"), hash.md5("")
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(b64, "UG9C");
+        assert_eq!(url, "-_8");
+        assert!(back);
+        assert_eq!(sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(md5, "d41d8cd98f00b204e9800998ecf8427e");
+    }
+}
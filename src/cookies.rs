@@ -0,0 +1,300 @@
+use std::time::{Duration, SystemTime};
+
+/// One stored cookie. Values are credentials (POESESSID), so the Debug
+/// output leaves them out.
+#[derive(Clone, PartialEq)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Lowercase, without a leading dot.
+    domain: String,
+    /// Set without a Domain attribute: only sent to that exact host.
+    host_only: bool,
+    path: String,
+    secure: bool,
+    expires: Option<SystemTime>,
+}
+
+impl std::fmt::Debug for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cookie")
+            .field("name", &self.name)
+            .field("domain", &self.domain)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cookie {
+    fn matches(&self, url: &RequestUrl, now: SystemTime) -> bool {
+        if self.expires.is_some_and(|t| t <= now) || (self.secure && !url.secure) {
+            return false;
+        }
+        let domain_ok = url.host == self.domain
+            || (!self.host_only && url.host.ends_with(&format!(".{}", self.domain)));
+        let path_ok = url.path == self.path
+            || (url.path.starts_with(&self.path)
+                && (self.path.ends_with('/') || url.path[self.path.len()..].starts_with('/')));
+        domain_ok && path_ok
+    }
+}
+
+struct RequestUrl {
+    host: String,
+    path: String,
+    secure: bool,
+}
+
+impl RequestUrl {
+    fn parse(url: &str) -> Self {
+        let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let authority = &rest[..end];
+        let authority = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+        let host = match authority.rsplit_once(':') {
+            Some((h, port)) if port.chars().all(|c| c.is_ascii_digit()) => h,
+            _ => authority,
+        };
+        let path = rest[end..].split(['?', '#']).next().unwrap_or("");
+        Self {
+            host: host.to_ascii_lowercase(),
+            path: if path.starts_with('/') {
+                path.to_string()
+            } else {
+                "/".to_string()
+            },
+            secure: scheme.eq_ignore_ascii_case("https"),
+        }
+    }
+
+    /// RFC 6265 default-path: the directory of the request path.
+    fn default_path(&self) -> String {
+        match self.path.rfind('/') {
+            Some(0) | None => "/".to_string(),
+            Some(i) => self.path[..i].to_string(),
+        }
+    }
+}
+
+/// A header added to every request whose host matches.
+#[derive(Clone, PartialEq)]
+struct HeaderPreset {
+    /// Host or `.domain` suffix; empty matches every host.
+    host: String,
+    name: String,
+    value: String,
+}
+
+impl std::fmt::Debug for HeaderPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeaderPreset")
+            .field("host", &self.host)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Cookies and header presets kept for the lifetime of the runtime and
+/// shared by every Lua state, so a POESESSID set by the account import
+/// reaches the subscripts doing the downloads.
+#[derive(Debug, Default)]
+pub struct Session {
+    cookies: Vec<Cookie>,
+    headers: Vec<HeaderPreset>,
+}
+
+impl Session {
+    /// Sets a cookie for `domain` and its subdomains, or removes it when
+    /// `value` is None.
+    pub fn set_cookie(&mut self, domain: &str, name: &str, value: Option<&str>) {
+        let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+        self.cookies
+            .retain(|c| !(c.name == name && c.domain == domain && c.path == "/"));
+        if let Some(value) = value {
+            self.cookies.push(Cookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain,
+                host_only: false,
+                path: "/".to_string(),
+                secure: false,
+                expires: None,
+            });
+        }
+    }
+
+    /// Drops all cookies, or just those for `domain` and its subdomains.
+    pub fn clear_cookies(&mut self, domain: Option<&str>) {
+        match domain {
+            None => self.cookies.clear(),
+            Some(domain) => {
+                let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+                let suffix = format!(".{}", domain);
+                self.cookies
+                    .retain(|c| c.domain != domain && !c.domain.ends_with(&suffix));
+            }
+        }
+    }
+
+    /// The `Cookie` header value for a request to `url`, longest paths first.
+    pub fn cookie_header(&mut self, url: &str, now: SystemTime) -> Option<String> {
+        self.cookies.retain(|c| c.expires.is_none_or(|t| t > now));
+        let url = RequestUrl::parse(url);
+        let mut matching: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|c| c.matches(&url, now))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        Some(
+            matching
+                .iter()
+                .map(|c| format!("{}={}", c.name, c.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    /// Applies a `Set-Cookie` header received from `url`.
+    pub fn store_set_cookie(&mut self, url: &str, header: &str, now: SystemTime) {
+        let url = RequestUrl::parse(url);
+        let mut parts = header.split(';');
+        let Some((name, value)) = parts.next().and_then(|p| p.split_once('=')) else {
+            return;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: url.host.clone(),
+            host_only: true,
+            path: url.default_path(),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attr in parts {
+            let (key, val) = attr.split_once('=').unwrap_or((attr, ""));
+            let val = val.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !val.is_empty() => {
+                    let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                    // A server may only widen a cookie to its own parent domains.
+                    if url.host != domain && !url.host.ends_with(&format!(".{}", domain)) {
+                        return;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = val.parse::<i64>().ok(),
+                "expires" => {
+                    if let Ok(t) = httpdate::parse_http_date(val) {
+                        cookie.expires = Some(t);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires; zero or negative deletes.
+        if let Some(secs) = max_age {
+            cookie.expires = Some(if secs <= 0 {
+                SystemTime::UNIX_EPOCH
+            } else {
+                now + Duration::from_secs(secs as u64)
+            });
+        }
+        self.cookies.retain(|c| {
+            !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+        });
+        if cookie.expires.is_none_or(|t| t > now) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// Sets a header preset for `host` (empty for every host), or removes
+    /// it when `value` is None.
+    pub fn set_header(&mut self, host: &str, name: &str, value: Option<&str>) {
+        let host = host.to_ascii_lowercase();
+        self.headers
+            .retain(|h| !(h.host == host && h.name.eq_ignore_ascii_case(name)));
+        if let Some(value) = value {
+            self.headers.push(HeaderPreset {
+                host,
+                name: name.to_string(),
+                value: value.to_string(),
+            });
+        }
+    }
+
+    pub fn clear_headers(&mut self, host: Option<&str>) {
+        match host {
+            None => self.headers.clear(),
+            Some(host) => {
+                let host = host.to_ascii_lowercase();
+                self.headers.retain(|h| h.host != host);
+            }
+        }
+    }
+
+    /// Preset headers that apply to `host`, as (name, value).
+    pub fn headers_for(&self, host: &str) -> Vec<(String, String)> {
+        let host = host.to_ascii_lowercase();
+        self.headers
+            .iter()
+            .filter(|h| {
+                h.host.is_empty()
+                    || h.host == host
+                    || (h.host.starts_with('.') && host.ends_with(&h.host))
+            })
+            .map(|h| (h.name.clone(), h.value.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_cookies_by_domain_path_and_expiry() {
+        let now = SystemTime::now();
+        let mut session = Session::default();
+        session.set_cookie("pathofexile.com", "POESESSID", Some("secret"));
+        session.store_set_cookie(
+            "https://www.pathofexile.com/character-window/get-characters",
+            "cf=1; Path=/character-window; Secure; HttpOnly",
+            now,
+        );
+        session.store_set_cookie("https://evil.example/", "x=1; Domain=pathofexile.com", now);
+        session.store_set_cookie("https://www.pathofexile.com/", "gone=1; Max-Age=10", now);
+
+        assert_eq!(
+            session
+                .cookie_header("https://www.pathofexile.com/character-window/x", now)
+                .as_deref(),
+            Some("cf=1; POESESSID=secret; gone=1")
+        );
+        assert_eq!(
+            session
+                .cookie_header(
+                    "http://pathofexile.com/trade",
+                    now + Duration::from_secs(20)
+                )
+                .as_deref(),
+            Some("POESESSID=secret")
+        );
+        assert_eq!(session.cookie_header("https://poe.ninja/", now), None);
+        assert!(!format!("{:?}", session).contains("secret"));
+
+        session.clear_cookies(Some("pathofexile.com"));
+        assert!(session.cookies.is_empty());
+    }
+}
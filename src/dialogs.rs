@@ -0,0 +1,324 @@
+use std::sync::{
+    Arc, Mutex,
+    mpsc::{Receiver, TryRecvError, channel},
+};
+
+use mlua::prelude::*;
+use rfd::{AsyncFileDialog, AsyncMessageDialog, MessageButtons, MessageDialogResult, MessageLevel};
+
+/// The paths a dialog ended with; empty when it was cancelled.
+type Answer = Vec<String>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Open,
+    OpenMany,
+    Save,
+    Folder,
+}
+
+/// The options table the dialog functions take.
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    title: Option<String>,
+    directory: Option<String>,
+    file_name: Option<String>,
+    /// (name, extensions) pairs, e.g. ("Builds", ["xml"]).
+    filters: Vec<(String, Vec<String>)>,
+    multiple: bool,
+}
+
+impl Options {
+    fn from_lua(t: Option<LuaTable>) -> LuaResult<Self> {
+        let Some(t) = t else {
+            return Ok(Self::default());
+        };
+        let mut filters = Vec::new();
+        if let Some(list) = t.get::<_, Option<LuaTable>>("filters")? {
+            for filter in list.sequence_values::<LuaTable>() {
+                let filter = filter?;
+                let exts: Vec<String> = filter.get("extensions")?;
+                let exts = exts
+                    .into_iter()
+                    .map(|e| e.trim_start_matches('.').to_string())
+                    .collect();
+                filters.push((filter.get("name")?, exts));
+            }
+        }
+        Ok(Self {
+            title: t.get("title")?,
+            directory: t.get("directory")?,
+            file_name: t.get("fileName")?,
+            filters,
+            multiple: t.get::<_, Option<bool>>("multiple")?.unwrap_or(false),
+        })
+    }
+
+    fn dialog(&self) -> AsyncFileDialog {
+        let mut dialog = AsyncFileDialog::new();
+        if let Some(title) = &self.title {
+            dialog = dialog.set_title(title);
+        }
+        if let Some(dir) = &self.directory {
+            dialog = dialog.set_directory(dir);
+        }
+        if let Some(name) = &self.file_name {
+            dialog = dialog.set_file_name(name);
+        }
+        for (name, exts) in &self.filters {
+            dialog = dialog.add_filter(name, exts);
+        }
+        dialog
+    }
+}
+
+/// Shows a dialog and waits for it; run off the host thread.
+fn show(kind: Kind, options: &Options) -> Answer {
+    let dialog = options.dialog();
+    let files = pollster::block_on(async move {
+        match kind {
+            Kind::Open => dialog.pick_file().await.map(|f| vec![f]),
+            Kind::OpenMany => dialog.pick_files().await,
+            Kind::Save => dialog.save_file().await.map(|f| vec![f]),
+            Kind::Folder => dialog.pick_folder().await.map(|f| vec![f]),
+        }
+    });
+    files
+        .unwrap_or_default()
+        .iter()
+        .map(|f| f.path().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Shows a native message box and waits for it, so it works when the Lua
+/// UI can't draw. Returns whether it was answered with OK or Yes.
+pub fn message_box(level: MessageLevel, title: &str, text: &str, buttons: MessageButtons) -> bool {
+    let dialog = AsyncMessageDialog::new()
+        .set_level(level)
+        .set_title(title)
+        .set_description(text)
+        .set_buttons(buttons);
+    matches!(
+        pollster::block_on(dialog.show()),
+        MessageDialogResult::Ok | MessageDialogResult::Yes
+    )
+}
+
+fn message_level(name: &str) -> Option<MessageLevel> {
+    match name.to_ascii_lowercase().as_str() {
+        "info" => Some(MessageLevel::Info),
+        "warning" => Some(MessageLevel::Warning),
+        "error" => Some(MessageLevel::Error),
+        _ => None,
+    }
+}
+
+fn message_buttons(name: &str) -> Option<MessageButtons> {
+    match name.to_ascii_lowercase().as_str() {
+        "ok" => Some(MessageButtons::Ok),
+        "okcancel" => Some(MessageButtons::OkCancel),
+        "yesno" => Some(MessageButtons::YesNo),
+        _ => None,
+    }
+}
+
+struct Pending {
+    kind: Kind,
+    callback: LuaRegistryKey,
+    answer: Receiver<Answer>,
+}
+
+/// Native file dialogs that are open, each with the Lua callback its
+/// answer goes to. Dialogs run on their own threads so frames keep coming.
+#[derive(Clone, Default)]
+pub struct Dialogs(Arc<Mutex<Vec<Pending>>>);
+
+impl Dialogs {
+    fn open(
+        &self,
+        lua: &Lua,
+        kind: Kind,
+        options: Options,
+        callback: LuaFunction,
+    ) -> LuaResult<()> {
+        let (tx, answer) = channel();
+        std::thread::Builder::new()
+            .name("file-dialog".into())
+            .spawn(move || tx.send(show(kind, &options)))
+            .map_err(LuaError::external)?;
+        self.0.lock().unwrap().push(Pending {
+            kind,
+            callback: lua.create_registry_value(callback)?,
+            answer,
+        });
+        Ok(())
+    }
+
+    /// Calls back for dialogs that were closed since the last call: with
+    /// the path, a list of paths for `multiple`, or nil when cancelled.
+    pub fn pump(&self, lua: &Lua) -> LuaResult<()> {
+        let mut done = Vec::new();
+        {
+            let mut pending = self.0.lock().unwrap();
+            let mut i = 0;
+            while i < pending.len() {
+                match pending[i].answer.try_recv() {
+                    Err(TryRecvError::Empty) => i += 1,
+                    answer => done.push((pending.remove(i), answer.unwrap_or_default())),
+                }
+            }
+        }
+        for (Pending { kind, callback, .. }, paths) in done {
+            let f: LuaFunction = lua.registry_value(&callback)?;
+            lua.remove_registry_value(callback)?;
+            let arg = match (kind, paths.is_empty()) {
+                (_, true) => LuaNil,
+                (Kind::OpenMany, false) => LuaValue::Table(lua.create_sequence_from(paths)?),
+                (_, false) => LuaValue::String(lua.create_string(&paths[0])?),
+            };
+            if let Err(e) = f.call::<_, ()>(arg) {
+                eprintln!("file dialog callback: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Registers `OpenFileDialog`, `SaveFileDialog` and `PickFolder`, which
+/// take an options table and a callback and return straight away, and
+/// `MessageBox`.
+pub fn register(lua: &Lua, dialogs: Dialogs) -> LuaResult<()> {
+    let g = lua.globals();
+    // MessageBox(text, [title], ["info"|"warning"|"error"],
+    // ["ok"|"okcancel"|"yesno"]) -> true for OK or Yes. Frames stop
+    // until it is answered.
+    g.set(
+        "MessageBox",
+        lua.create_function(
+            |_,
+             (text, title, level, buttons): (
+                String,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+            )| {
+                let level = match level {
+                    Some(name) => message_level(&name).ok_or_else(|| {
+                        LuaError::RuntimeError(format!("unknown message level \"{}\"", name))
+                    })?,
+                    None => MessageLevel::Info,
+                };
+                let buttons = match buttons {
+                    Some(name) => message_buttons(&name).ok_or_else(|| {
+                        LuaError::RuntimeError(format!("unknown message buttons \"{}\"", name))
+                    })?,
+                    None => MessageButtons::Ok,
+                };
+                let title = title.unwrap_or_else(|| "Path of Building".into());
+                Ok(message_box(level, &title, &text, buttons))
+            },
+        )?,
+    )?;
+    let d = dialogs.clone();
+    g.set(
+        "OpenFileDialog",
+        lua.create_function(move |lua, (t, f): (Option<LuaTable>, LuaFunction)| {
+            let options = Options::from_lua(t)?;
+            let kind = if options.multiple {
+                Kind::OpenMany
+            } else {
+                Kind::Open
+            };
+            d.open(lua, kind, options, f)
+        })?,
+    )?;
+    let d = dialogs.clone();
+    g.set(
+        "SaveFileDialog",
+        lua.create_function(move |lua, (t, f): (Option<LuaTable>, LuaFunction)| {
+            d.open(lua, Kind::Save, Options::from_lua(t)?, f)
+        })?,
+    )?;
+    g.set(
+        "PickFolder",
+        lua.create_function(move |lua, (t, f): (Option<LuaTable>, LuaFunction)| {
+            dialogs.open(lua, Kind::Folder, Options::from_lua(t)?, f)
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_parse_and_answers_reach_callbacks() {
+        let lua = Lua::new();
+        let t: LuaTable = lua
+            .load(
+                r#"return { title = "Import", multiple = true,
+                    filters = { { name = "Builds", extensions = { ".xml", "txt" } } } }"#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            Options::from_lua(Some(t)).unwrap(),
+            Options {
+                title: Some("Import".into()),
+                filters: vec![("Builds".into(), vec!["xml".into(), "txt".into()])],
+                multiple: true,
+                ..Options::default()
+            }
+        );
+
+        let dialogs = Dialogs::default();
+        let answer = |kind, name: &str, paths: Answer| {
+            let f: LuaFunction = lua
+                .load(format!("return function(p) {} = p or false end", name))
+                .eval()
+                .unwrap();
+            let (tx, answer) = channel();
+            tx.send(paths).unwrap();
+            dialogs.0.lock().unwrap().push(Pending {
+                kind,
+                callback: lua.create_registry_value(f).unwrap(),
+                answer,
+            });
+        };
+        answer(Kind::Save, "saved", vec!["/builds/Witch.xml".into()]);
+        answer(
+            Kind::OpenMany,
+            "opened",
+            vec!["a.xml".into(), "b.xml".into()],
+        );
+        answer(Kind::Folder, "folder", vec![]);
+        // A dialog still open stays pending.
+        let (_tx, open) = channel();
+        dialogs.0.lock().unwrap().push(Pending {
+            kind: Kind::Open,
+            callback: lua.create_registry_value(LuaNil).unwrap(),
+            answer: open,
+        });
+        dialogs.pump(&lua).unwrap();
+        let (saved, second, folder): (String, String, bool) =
+            lua.load("return saved, opened[2], folder").eval().unwrap();
+        assert_eq!(
+            (saved.as_str(), second.as_str(), folder),
+            ("/builds/Witch.xml", "b.xml", false)
+        );
+        assert_eq!(dialogs.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn message_box_levels_and_buttons_parse_by_name() {
+        assert!(matches!(
+            message_level("Warning"),
+            Some(MessageLevel::Warning)
+        ));
+        assert!(matches!(
+            message_buttons("yesno"),
+            Some(MessageButtons::YesNo)
+        ));
+        assert!(message_buttons("maybe").is_none());
+    }
+}
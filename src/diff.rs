@@ -0,0 +1,125 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::build_stats::SUMMARY_STATS;
+use crate::headless::{JobError, StatePool, Summary};
+use crate::layout::Layout;
+
+/// Loads builds `a` and `b` side by side on two headless states and
+/// returns their summaries.
+pub fn run(
+    layout: &Layout,
+    a: &str,
+    b: &str,
+    timeout: Duration,
+) -> Result<(Summary, Summary), String> {
+    let pool = StatePool::spawn(layout, 2).map_err(|e| e.to_string())?;
+    let (a, b) = std::thread::scope(|s| {
+        let a = s.spawn(|| pool.summarize(a, timeout));
+        let b = pool.summarize(b, timeout);
+        (a.join().unwrap(), b)
+    });
+    let fail = |which, e: JobError| format!("build {}: {}", which, e);
+    Ok((a.map_err(|e| fail("A", e))?, b.map_err(|e| fail("B", e))?))
+}
+
+/// The stats of `a` and `b` and how `b` differs, one row per stat either
+/// build has.
+pub fn table(a: &Summary, b: &Summary) -> String {
+    let get = |s: &Summary, name| s.iter().find(|&&(n, _)| n == name).map(|&(_, v)| v);
+    let mut out = format!(
+        "{:<16} {:>14} {:>14} {:>24}\n",
+        "stat", "build A", "build B", "delta"
+    );
+    for &name in SUMMARY_STATS {
+        let (va, vb) = (get(a, name), get(b, name));
+        if va.is_none() && vb.is_none() {
+            continue;
+        }
+        let cell = |v: Option<f64>| v.map_or("-".to_string(), number);
+        let delta = match (va, vb) {
+            (Some(va), Some(vb)) if va == vb => "=".to_string(),
+            (Some(va), Some(vb)) if va != 0.0 => format!(
+                "{}{} ({:+.1}%)",
+                if vb > va { "+" } else { "" },
+                number(vb - va),
+                (vb - va) / va.abs() * 100.0
+            ),
+            (Some(va), Some(vb)) => {
+                format!("{}{}", if vb > va { "+" } else { "" }, number(vb - va))
+            }
+            _ => String::new(),
+        };
+        writeln!(
+            out,
+            "{:<16} {:>14} {:>14} {:>24}",
+            name,
+            cell(va),
+            cell(vb),
+            delta
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Whole numbers with thousands separators; small fractions keep a decimal.
+fn number(v: f64) -> String {
+    if v.fract() != 0.0 && v.abs() < 100.0 {
+        return format!("{:.1}", v);
+    }
+    let digits = format!("{:.0}", v.abs());
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    if v < 0.0 { format!("-{}", out) } else { out }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_lines_up_deltas_for_stats_either_build_has() {
+        let a = vec![
+            ("CombinedDPS", 1_250_000.0),
+            ("Life", 4200.0),
+            ("FireResist", 75.0),
+        ];
+        let b = vec![
+            ("CombinedDPS", 1_000_000.0),
+            ("Life", 4200.0),
+            ("TotalEHP", 38_500.4),
+            ("FireResist", 76.5),
+        ];
+        let table = table(&a, &b);
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(rows[0], ["stat", "build", "A", "build", "B", "delta"]);
+        assert_eq!(
+            rows[1],
+            [
+                "CombinedDPS",
+                "1,250,000",
+                "1,000,000",
+                "-250,000",
+                "(-20.0%)"
+            ]
+        );
+        assert_eq!(rows[2], ["Life", "4,200", "4,200", "="]);
+        assert_eq!(rows[3], ["TotalEHP", "-", "38,500"]);
+        assert_eq!(rows[4], ["FireResist", "75", "76.5", "+1.5", "(+2.0%)"]);
+        assert_eq!(rows.len(), 5);
+        assert!(
+            table
+                .lines()
+                .all(|l| l.len() == table.lines().next().unwrap().len())
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use mlua::prelude::*;
+
+/// How often the watched directory is rescanned.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size and modification time of every file under a directory.
+pub type Snapshot = HashMap<PathBuf, (u64, Option<SystemTime>)>;
+
+/// Whether some file differs between the snapshots other than under a
+/// path PoB changed itself.
+fn changed_outside(prev: &Snapshot, now: &Snapshot, own: &HashMap<PathBuf, Instant>) -> bool {
+    let gone = prev.keys().filter(|p| !now.contains_key(*p));
+    let new = now.iter().filter(|(p, v)| prev.get(*p) != Some(*v));
+    gone.chain(new.map(|(p, _)| p))
+        .any(|p| !own.keys().any(|o| p.starts_with(o)))
+}
+
+/// Walks `dir` recursively. A missing directory is an empty snapshot.
+pub fn snapshot(dir: &Path) -> Snapshot {
+    let mut out = Snapshot::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else {
+                out.insert(entry.path(), (meta.len(), meta.modified().ok()));
+            }
+        }
+    }
+    out
+}
+
+/// Watches one directory tree from a background thread by polling, which
+/// also catches changes on network and cloud-sync mounts that don't
+/// deliver native change events. The thread ends once the watcher is
+/// dropped.
+pub struct DirWatcher {
+    shared: Arc<Watched>,
+}
+
+#[derive(Default)]
+struct Watched {
+    dir: Mutex<Option<PathBuf>>,
+    changed: AtomicBool,
+    /// Files and folders PoB itself wrote, moved or removed, and when.
+    own: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+impl DirWatcher {
+    pub fn spawn() -> Self {
+        let shared = Arc::<Watched>::default();
+        let w = Arc::downgrade(&shared);
+        std::thread::Builder::new()
+            .name("dir-watch".into())
+            .spawn(move || {
+                let mut last: Option<(PathBuf, Snapshot)> = None;
+                let mut last_poll = Instant::now();
+                loop {
+                    std::thread::sleep(POLL_INTERVAL);
+                    let Some(w) = w.upgrade() else {
+                        return;
+                    };
+                    let Some(dir) = w.dir.lock().unwrap().clone() else {
+                        continue;
+                    };
+                    let polled = Instant::now();
+                    let now = snapshot(&dir);
+                    // Writes noted since the previous poll began may have
+                    // finished after it, so they count for this one too.
+                    let mut own = w.own.lock().unwrap();
+                    own.retain(|_, at| *at >= last_poll);
+                    // A newly watched directory starts from its current contents.
+                    if let Some((prev_dir, prev)) = &last
+                        && *prev_dir == dir
+                        && changed_outside(prev, &now, &own)
+                    {
+                        w.changed.store(true, Ordering::Relaxed);
+                    }
+                    drop(own);
+                    last = Some((dir, now));
+                    last_poll = polled;
+                }
+            })
+            .expect("failed to spawn dir watch thread");
+        Self { shared }
+    }
+
+    /// Switches to watching `dir`; a no-op if it is already watched.
+    /// Returns whether it switched.
+    pub fn watch(&self, dir: &Path) -> bool {
+        let mut current = self.shared.dir.lock().unwrap();
+        if current.as_deref() == Some(dir) {
+            return false;
+        }
+        *current = Some(dir.to_path_buf());
+        true
+    }
+
+    /// True once for each batch of changes seen since the last call.
+    pub fn take_changed(&self) -> bool {
+        self.shared.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Wraps `io.open` for writing, `os.remove` and `os.rename` so the files
+/// they touch count as PoB's own changes to `watcher`'s directory.
+pub fn register(lua: &Lua, watcher: &DirWatcher) -> LuaResult<()> {
+    let w = Arc::downgrade(&watcher.shared);
+    let own = lua.create_function(move |_, path: String| {
+        if let Some(w) = w.upgrade() {
+            w.own.lock().unwrap().insert(path.into(), Instant::now());
+        }
+        Ok(())
+    })?;
+    lua.load(
+        r#"
+        local noteOwn = ...
+        local function own(path)
+            if type(path) == "string" then
+                noteOwn(path)
+            end
+        end
+        local open, remove, rename = io.open, os.remove, os.rename
+        function io.open(path, mode)
+            if mode and mode:find("[wa+]") then
+                own(path)
+            end
+            return open(path, mode)
+        end
+        function os.remove(path)
+            own(path)
+            return remove(path)
+        end
+        function os.rename(from, to)
+            own(from)
+            own(to)
+            return rename(from, to)
+        end
+        "#,
+    )
+    .set_name("dir_watch")
+    .call::<_, ()>(own)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_sees_nested_edits() {
+        let dir = std::env::temp_dir().join(format!("pob-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("League")).unwrap();
+        std::fs::write(dir.join("Witch.xml"), "a").unwrap();
+        let before = snapshot(&dir);
+        assert_eq!(before.len(), 1);
+
+        std::fs::write(dir.join("League/Duelist.xml"), "b").unwrap();
+        let after = snapshot(&dir);
+        assert_ne!(before, after);
+        assert_eq!(after.len(), 2);
+        std::fs::write(dir.join("Witch.xml"), "longer").unwrap();
+        assert_ne!(after, snapshot(&dir));
+
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(snapshot(&dir).is_empty());
+    }
+
+    #[test]
+    fn pobs_own_saves_are_not_external_changes() {
+        let dir = std::env::temp_dir().join(format!("pob-own-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Old")).unwrap();
+        std::fs::write(dir.join("Old/Ranger.xml"), "a").unwrap();
+        let watcher = DirWatcher::spawn();
+        let lua = Lua::new();
+        register(&lua, &watcher).unwrap();
+        lua.globals().set("dir", dir.to_str().unwrap()).unwrap();
+        let before = snapshot(&dir);
+        lua.load(
+            r#"
+            local f = io.open(dir .. "/Witch.xml", "w")
+            f:write("<PathOfBuilding/>")
+            f:close()
+            os.rename(dir .. "/Old", dir .. "/New")
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let after = snapshot(&dir);
+        assert_eq!(after.len(), 2);
+        let own = watcher.shared.own.lock().unwrap().clone();
+        assert!(!changed_outside(&before, &after, &own));
+
+        // Another program's edit still counts.
+        std::fs::write(dir.join("Duelist.xml"), "b").unwrap();
+        assert!(changed_outside(&after, &snapshot(&dir), &own));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
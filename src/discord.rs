@@ -0,0 +1,315 @@
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mlua::prelude::*;
+use serde_json::{Value, json};
+
+use crate::settings::DiscordSettings;
+
+/// How long to wait before trying a Discord that wasn't running again.
+const RETRY: Duration = Duration::from_secs(15);
+
+/// How long Discord gets to take a message and answer it before the
+/// connection is given up on.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Discord IPC opcodes.
+const HANDSHAKE: u32 = 0;
+const FRAME: u32 = 1;
+
+/// The largest answer taken from Discord; its replies are a few hundred
+/// bytes.
+const MAX_PAYLOAD: u32 = 64 * 1024;
+
+/// Details and state lines of the activity; None clears it.
+type Activity = Option<(String, Option<String>)>;
+
+/// One IPC message: opcode and payload length, little endian, then JSON.
+fn frame(op: u32, payload: &Value) -> Vec<u8> {
+    let body = payload.to_string();
+    let mut out = Vec::with_capacity(8 + body.len());
+    out.extend_from_slice(&op.to_le_bytes());
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body.as_bytes());
+    out
+}
+
+fn set_activity(activity: &Activity, started: u64, nonce: u64) -> Value {
+    let activity = activity.as_ref().map(|(details, state)| {
+        let mut a = json!({ "details": details, "timestamps": { "start": started } });
+        if let Some(state) = state {
+            a["state"] = json!(state);
+        }
+        a
+    });
+    json!({
+        "cmd": "SET_ACTIVITY",
+        "args": { "pid": std::process::id(), "activity": activity },
+        "nonce": nonce.to_string(),
+    })
+}
+
+/// A connection to Discord that sends a frame and returns the answer, or
+/// fails after `IO_TIMEOUT`.
+trait Pipe: Send {
+    fn exchange(&mut self, frame: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// Writes `frame` and reads one answer's payload.
+fn exchange(stream: &mut (impl Read + Write), frame: &[u8]) -> io::Result<Vec<u8>> {
+    stream.write_all(frame)?;
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if len > MAX_PAYLOAD {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} byte answer", len),
+        ));
+    }
+    let mut body = vec![0; len as usize];
+    stream.read_exact(&mut body)?;
+    Ok(body)
+}
+
+#[cfg(unix)]
+impl Pipe for std::os::unix::net::UnixStream {
+    fn exchange(&mut self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        exchange(self, frame)
+    }
+}
+
+/// A named pipe, which std can't time out, driven from its own thread. A
+/// Discord that stops answering strands only that thread, which ends once
+/// Discord closes the pipe.
+#[cfg(not(unix))]
+struct PipeThread {
+    frames: Sender<Vec<u8>>,
+    answers: Receiver<io::Result<Vec<u8>>>,
+}
+
+#[cfg(not(unix))]
+impl PipeThread {
+    fn spawn(mut pipe: std::fs::File) -> io::Result<Self> {
+        let (frames, rx) = channel::<Vec<u8>>();
+        let (tx, answers) = channel();
+        std::thread::Builder::new()
+            .name("discord pipe".into())
+            .spawn(move || {
+                for frame in rx {
+                    if tx.send(exchange(&mut pipe, &frame)).is_err() {
+                        return;
+                    }
+                }
+            })?;
+        Ok(Self { frames, answers })
+    }
+}
+
+#[cfg(not(unix))]
+impl Pipe for PipeThread {
+    fn exchange(&mut self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        self.frames
+            .send(frame.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        match self.answers.recv_timeout(IO_TIMEOUT) {
+            Ok(answer) => answer,
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::from(io::ErrorKind::TimedOut)),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        }
+    }
+}
+
+/// Discord listens on the first free of ten numbered pipes or sockets.
+fn pipes() -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = if cfg!(windows) {
+        vec![r"\\.\pipe".into()]
+    } else {
+        let mut base: Vec<_> = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+            .iter()
+            .filter_map(|v| std::env::var_os(v).map(PathBuf::from))
+            .collect();
+        base.push("/tmp".into());
+        // Flatpak and Snap installs put the socket in a subdirectory.
+        base.iter()
+            .flat_map(|b| {
+                [
+                    b.clone(),
+                    b.join("app/com.discordapp.Discord"),
+                    b.join("snap.discord"),
+                ]
+            })
+            .collect()
+    };
+    dirs.iter()
+        .flat_map(|d| (0..10).map(move |i| d.join(format!("discord-ipc-{}", i))))
+        .collect()
+}
+
+#[cfg(unix)]
+fn open(path: &Path) -> io::Result<Box<dyn Pipe>> {
+    let socket = std::os::unix::net::UnixStream::connect(path)?;
+    socket.set_read_timeout(Some(IO_TIMEOUT))?;
+    socket.set_write_timeout(Some(IO_TIMEOUT))?;
+    Ok(Box::new(socket))
+}
+
+#[cfg(not(unix))]
+fn open(path: &Path) -> io::Result<Box<dyn Pipe>> {
+    let pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    Ok(Box::new(PipeThread::spawn(pipe)?))
+}
+
+fn send(pipe: &mut dyn Pipe, op: u32, payload: &Value) -> io::Result<Value> {
+    let body = pipe.exchange(&frame(op, payload))?;
+    serde_json::from_slice(&body).map_err(io::Error::other)
+}
+
+/// The first of `paths` that opens and takes the handshake; a pipe held
+/// by something other than Discord doesn't stop the search.
+fn connect(paths: &[PathBuf], client_id: &str) -> io::Result<Box<dyn Pipe>> {
+    let mut last = io::Error::from(io::ErrorKind::NotFound);
+    for path in paths {
+        let handshake = open(path).and_then(|mut pipe| {
+            send(
+                &mut *pipe,
+                HANDSHAKE,
+                &json!({ "v": 1, "client_id": client_id }),
+            )?;
+            Ok(pipe)
+        });
+        match handshake {
+            Ok(pipe) => return Ok(pipe),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// Keeps Discord showing the latest activity, connecting when Discord is
+/// running and again whenever the connection drops.
+fn run(client_id: String, updates: Receiver<Activity>) {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (mut pipe, mut current, mut dirty, mut nonce) = (None, None, false, 0);
+    loop {
+        match updates.recv_timeout(RETRY) {
+            Ok(activity) => {
+                // Only the newest of a burst of updates matters.
+                current = updates.try_iter().last().unwrap_or(activity);
+                dirty = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if !dirty {
+            continue;
+        }
+        if pipe.is_none() {
+            pipe = connect(&pipes(), &client_id).ok();
+        }
+        if let Some(p) = &mut pipe {
+            nonce += 1;
+            match send(&mut **p, FRAME, &set_activity(&current, started, nonce)) {
+                Ok(_) => dirty = false,
+                Err(_) => pipe = None,
+            }
+        }
+    }
+}
+
+/// Registers `SetRichPresence(details, [state])`; nil details clears the
+/// activity. It does nothing unless enabled in the settings with an
+/// application id.
+pub fn register(lua: &Lua, settings: &DiscordSettings) -> LuaResult<()> {
+    let updates: Option<Sender<Activity>> = match (&settings.enabled, &settings.client_id) {
+        (Some(true), Some(id)) => {
+            let (tx, rx) = channel();
+            let id = id.clone();
+            std::thread::Builder::new()
+                .name("discord".into())
+                .spawn(move || run(id, rx))
+                .map_err(LuaError::external)?;
+            Some(tx)
+        }
+        (Some(true), None) => {
+            eprintln!("discord: enabled without a client_id");
+            None
+        }
+        _ => None,
+    };
+    lua.globals().set(
+        "SetRichPresence",
+        lua.create_function(
+            move |_, (details, state): (Option<String>, Option<String>)| {
+                if let Some(tx) = &updates {
+                    tx.send(details.map(|d| (d, state))).ok();
+                }
+                Ok(())
+            },
+        )?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_carry_op_length_and_activity() {
+        let activity = Some(("Witch - Righteous Fire".to_string(), None));
+        let payload = set_activity(&activity, 1700000000, 3);
+        assert_eq!(
+            payload["args"]["activity"],
+            json!({ "details": "Witch - Righteous Fire", "timestamps": { "start": 1700000000 } })
+        );
+        assert_eq!(payload["nonce"], "3");
+        assert_eq!(set_activity(&None, 0, 4)["args"]["activity"], Value::Null);
+
+        let bytes = frame(FRAME, &json!({ "a": 1 }));
+        assert_eq!(&bytes[..4], &1u32.to_le_bytes());
+        assert_eq!(&bytes[4..8], &7u32.to_le_bytes());
+        assert_eq!(&bytes[8..], br#"{"a":1}"#);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connect_skips_pipes_that_fail_the_handshake() {
+        use std::os::unix::net::UnixListener;
+        let dir = std::env::temp_dir().join(format!("pob-discord-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|i| dir.join(format!("discord-ipc-{}", i)))
+            .collect();
+        // 0 isn't there, 1 answers with an oversized frame, 2 is Discord.
+        let bad = UnixListener::bind(&paths[1]).unwrap();
+        let good = UnixListener::bind(&paths[2]).unwrap();
+        std::thread::spawn(move || {
+            let (mut s, _) = bad.accept().unwrap();
+            let mut header = [0; 8];
+            s.read_exact(&mut header).unwrap();
+            s.write_all(&frame(FRAME, &json!({}))[..4]).unwrap();
+            s.write_all(&(MAX_PAYLOAD + 1).to_le_bytes()).unwrap();
+        });
+        std::thread::spawn(move || {
+            let (mut s, _) = good.accept().unwrap();
+            let mut header = [0; 8];
+            s.read_exact(&mut header).unwrap();
+            let mut body = vec![0; u32::from_le_bytes(header[4..].try_into().unwrap()) as usize];
+            s.read_exact(&mut body).unwrap();
+            s.write_all(&frame(FRAME, &json!({ "evt": "READY" })))
+                .unwrap();
+        });
+
+        assert!(connect(&paths, "123").is_ok());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+
+/// PoB's display scaling option: a percentage of the UI's size, or 0 to
+/// leave it to the user's `ui_zoom`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DpiOverride {
+    pub percent: u32,
+    /// Set when Lua changed it, until the window thread rescales.
+    pub changed: bool,
+}
+
+pub type SharedDpiOverride = Arc<Mutex<DpiOverride>>;
+
+impl DpiOverride {
+    /// The UI zoom this override gives, `ui_zoom` when there is none.
+    pub fn zoom(&self, ui_zoom: f32) -> f32 {
+        match self.percent {
+            0 => ui_zoom,
+            p => p as f32 / 100.0,
+        }
+    }
+}
+
+/// Writes `percent` to `[display] dpi_override_percent` in the settings
+/// file at `path`, keeping the rest of it, comments included. 0 removes it.
+pub fn persist(path: &Path, percent: u32) -> Result<(), String> {
+    set_display(
+        path,
+        "dpi_override_percent",
+        (percent != 0).then(|| toml_edit::value(percent as i64)),
+    )
+}
+
+/// Writes the user's UI zoom to `[display] ui_zoom` the same way; 1
+/// removes it.
+pub fn persist_zoom(path: &Path, zoom: f32) -> Result<(), String> {
+    // Zoom steps in tenths; the f32 would print as 1.2000000476837158.
+    let zoom = (zoom as f64 * 10.0).round() / 10.0;
+    set_display(
+        path,
+        "ui_zoom",
+        (zoom != 1.0).then(|| toml_edit::value(zoom)),
+    )
+}
+
+fn set_display(path: &Path, key: &str, value: Option<toml_edit::Item>) -> Result<(), String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut doc: toml_edit::DocumentMut = text.parse().map_err(|e| format!("{}", e))?;
+    let display = doc
+        .entry("display")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or("[display] is not a table")?;
+    match value {
+        Some(value) => display.insert(key, value),
+        None => display.remove(key),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, doc.to_string()).map_err(|e| e.to_string())
+}
+
+/// Registers `GetDPIScaleOverridePercent()` and
+/// `SetDPIScaleOverridePercent(percent)`. Changes are saved to the
+/// settings file at `path`, if given, so the next launch starts at that
+/// scale.
+pub fn register(lua: &Lua, dpi: SharedDpiOverride, path: Option<PathBuf>) -> LuaResult<()> {
+    let g = lua.globals();
+    let d = dpi.clone();
+    g.set(
+        "GetDPIScaleOverridePercent",
+        lua.create_function(move |_, ()| Ok(d.lock().unwrap().percent))?,
+    )?;
+    g.set(
+        "SetDPIScaleOverridePercent",
+        lua.create_function(move |_, percent: Option<f64>| {
+            let percent = match percent.unwrap_or(0.0).round() {
+                p if p <= 0.0 => 0,
+                p => p.clamp(50.0, 300.0) as u32,
+            };
+            let mut dpi = dpi.lock().unwrap();
+            if dpi.percent != percent {
+                *dpi = DpiOverride {
+                    percent,
+                    changed: true,
+                };
+                if let Some(path) = &path
+                    && let Err(e) = persist(path, percent)
+                {
+                    eprintln!("{}: {}", path.display(), e);
+                }
+            }
+            Ok(())
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn override_round_trips_through_the_settings_file() {
+        let dir = std::env::temp_dir().join(format!("pob-dpi-{}", std::process::id()));
+        let path = dir.join("runtime.toml");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "# mine\n[display]\nui_zoom = 1.5\n").unwrap();
+
+        let lua = Lua::new();
+        let dpi = SharedDpiOverride::default();
+        register(&lua, dpi.clone(), Some(path.clone())).unwrap();
+        lua.load("SetDPIScaleOverridePercent(125)").exec().unwrap();
+        let percent: u32 = lua
+            .load("return GetDPIScaleOverridePercent()")
+            .eval()
+            .unwrap();
+        assert_eq!(percent, 125);
+        assert!(dpi.lock().unwrap().changed);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# mine\n"));
+        let settings = Settings::parse(&text).unwrap();
+        assert_eq!(settings.display.ui_zoom, Some(1.5));
+        assert_eq!(settings.display.dpi_override_percent, Some(125));
+        let restored = DpiOverride {
+            percent: 125,
+            changed: false,
+        };
+        assert_eq!(restored.zoom(1.5), 1.25);
+
+        lua.load("SetDPIScaleOverridePercent(0)").exec().unwrap();
+        let settings = Settings::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(settings.display.dpi_override_percent, None);
+        assert_eq!(dpi.lock().unwrap().zoom(1.5), 1.5);
+
+        persist_zoom(&path, 1.2).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("ui_zoom = 1.2\n"), "{}", text);
+        persist_zoom(&path, 1.0).unwrap();
+        let settings = Settings::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(settings.display.ui_zoom, None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::archive::ArchiveIndex;
+use crate::layout::Layout;
+use crate::vfs::{FoundFile, Provider};
+
+type SharedIndex = Arc<Mutex<ArchiveIndex>>;
+
+/// The zip `pack` appended to this executable, if there is one.
+fn archive() -> Option<SharedIndex> {
+    static ARCHIVE: OnceLock<Option<SharedIndex>> = OnceLock::new();
+    ARCHIVE
+        .get_or_init(|| {
+            let exe = std::env::current_exe().ok()?;
+            let index = ArchiveIndex::open(&exe).ok()?;
+            Some(Arc::new(Mutex::new(index)))
+        })
+        .clone()
+}
+
+/// One top-level directory of a zip, served as a tree of its own.
+pub struct Subtree {
+    zip: SharedIndex,
+    prefix: String,
+}
+
+impl Subtree {
+    fn path(&self, path: &str) -> String {
+        format!("{}/{}", self.prefix, path)
+    }
+}
+
+impl Provider for Subtree {
+    fn read(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        self.zip.lock().unwrap().read(&self.path(path))
+    }
+
+    fn search(&mut self, spec: &str, dirs: bool) -> Vec<FoundFile> {
+        Provider::search(&mut *self.zip.lock().unwrap(), &self.path(spec), dirs)
+    }
+}
+
+/// The embedded `src` or `runtime` tree, when running standalone.
+pub fn subtree(prefix: &str) -> Option<Subtree> {
+    Some(Subtree {
+        zip: archive()?,
+        prefix: prefix.to_string(),
+    })
+}
+
+/// Writes a standalone executable to `output`: this executable followed by
+/// a zip of the layout's `src` and `runtime` trees. The tree directory goes
+/// in as `src/TreeData` wherever the layout keeps it. Returns the number of
+/// files packed.
+pub fn pack(layout: &Layout, output: &Path) -> io::Result<usize> {
+    let exe = std::env::current_exe()?;
+    std::fs::copy(&exe, output)?;
+    let mut file = File::options().write(true).open(output)?;
+    file.seek(SeekFrom::End(0))?;
+    let mut zip = ZipWriter::new(file);
+    let mut count = 0;
+    let tree_inside = layout.tree_dir.starts_with(&layout.script_dir);
+    let mut trees = vec![
+        ("src".to_string(), layout.script_dir.clone()),
+        ("runtime".to_string(), layout.runtime_dir.clone()),
+    ];
+    if !tree_inside {
+        trees.push(("src/TreeData".to_string(), layout.tree_dir.clone()));
+    }
+    for (prefix, dir) in trees {
+        let mut pending = vec![(prefix, dir)];
+        while let Some((name, dir)) = pending.pop() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(&dir)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?
+                .flatten()
+                .map(|e| e.path())
+                .collect();
+            entries.sort();
+            for path in entries {
+                let leaf = path.file_name().unwrap_or_default().to_string_lossy();
+                if leaf.starts_with(".git") || (!tree_inside && path == layout.tree_dir) {
+                    continue;
+                }
+                let entry = format!("{}/{}", name, leaf);
+                if path.is_dir() {
+                    pending.push((entry, path));
+                } else {
+                    zip.start_file(entry, SimpleFileOptions::default())
+                        .map_err(io::Error::other)?;
+                    io::copy(&mut File::open(&path)?, &mut zip)?;
+                    count += 1;
+                }
+            }
+        }
+    }
+    zip.finish().map_err(io::Error::other)?.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_trees_read_back_from_behind_the_executable() {
+        let dir = std::env::temp_dir().join(format!("pob-embed-{}", std::process::id()));
+        let layout = Layout::standard(&dir);
+        std::fs::create_dir_all(layout.script_dir.join("Modules")).unwrap();
+        std::fs::create_dir_all(layout.runtime_dir.join("lua")).unwrap();
+        std::fs::create_dir_all(layout.script_dir.join(".git")).unwrap();
+        std::fs::write(layout.script_dir.join("Launch.lua"), "launch").unwrap();
+        std::fs::write(layout.script_dir.join("Modules/Main.lua"), "main").unwrap();
+        std::fs::write(layout.script_dir.join(".git/HEAD"), "x").unwrap();
+        std::fs::write(layout.runtime_dir.join("lua/dkjson.lua"), "json").unwrap();
+
+        let output = dir.join("standalone");
+        assert_eq!(pack(&layout, &output).unwrap(), 3);
+        let index = ArchiveIndex::open(&output).unwrap();
+        let mut src = Subtree {
+            zip: Arc::new(Mutex::new(index)),
+            prefix: "src".into(),
+        };
+        assert_eq!(src.read("modules/main.lua").unwrap(), b"main");
+        assert!(src.read(".git/HEAD").is_err());
+        let found = src.search("*.lua", false);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "Launch.lua");
+        let mut runtime = Subtree {
+            zip: src.zip.clone(),
+            prefix: "runtime".into(),
+        };
+        assert_eq!(runtime.read("lua/dkjson.lua").unwrap(), b"json");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::settings::TextSettings;
+
+/// A SimpleGraphic colour escape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Escape {
+    /// `^0` to `^9`.
+    Digit(u8),
+    /// `^xRRGGBB`, or `^X`.
+    Hex([u8; 3]),
+    /// A name from the palette, like `^ORANGE` in some forks.
+    Named([f32; 3]),
+}
+
+impl Escape {
+    /// The escape at the start of `s` and its length in bytes. As in
+    /// SimpleGraphic, a `^` that doesn't start one (the last byte, `^^`, a
+    /// `^x` without six hex digits, an unknown name) is kept as a literal
+    /// caret.
+    pub fn at(s: &[u8], palette: &Palette) -> Option<(Self, usize)> {
+        match s {
+            [b'^', d @ b'0'..=b'9', ..] => Some((Self::Digit(d - b'0'), 2)),
+            [b'^', b'x' | b'X', hex @ ..] if hex.len() >= 6 => {
+                let hex = std::str::from_utf8(&hex[..6]).ok()?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                let rgb = u32::from_str_radix(hex, 16).ok()?.to_be_bytes();
+                Some((Self::Hex([rgb[1], rgb[2], rgb[3]]), 8))
+            }
+            [b'^', name @ ..] => palette
+                .names
+                .iter()
+                .filter(|(n, _)| name.starts_with(n.as_bytes()))
+                .max_by_key(|(n, _)| n.len())
+                .map(|(n, &rgb)| (Self::Named(rgb), 1 + n.len())),
+            _ => None,
+        }
+    }
+
+    pub fn color(self, palette: &Palette, alpha: f32) -> [f32; 4] {
+        let [r, g, b] = match self {
+            Self::Hex(rgb) => rgb.map(|c| c as f32 / 255.0),
+            Self::Digit(d) => palette.digits[d as usize],
+            Self::Named(rgb) => rgb,
+        };
+        [r, g, b, alpha]
+    }
+}
+
+/// SimpleGraphic's colours for `^0` to `^9`.
+const OFFICIAL: [[f32; 3]; 10] = [
+    [0.0, 0.0, 0.0], // black
+    [1.0, 0.0, 0.0], // red
+    [0.0, 1.0, 0.0], // green
+    [0.0, 0.0, 1.0], // blue
+    [1.0, 1.0, 0.0], // yellow
+    [1.0, 0.0, 1.0], // magenta
+    [0.0, 1.0, 1.0], // cyan
+    [1.0, 1.0, 1.0], // white
+    [0.7, 0.7, 0.7], // gray
+    [0.4, 0.4, 0.4], // dark gray
+];
+
+/// `RRGGBB`, with or without a leading `#`.
+pub fn hex_color(s: &str) -> Option<[f32; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?.to_be_bytes();
+    Some([rgb[1], rgb[2], rgb[3]].map(|c| c as f32 / 255.0))
+}
+
+/// The colours escapes stand for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    digits: [[f32; 3]; 10],
+    /// Colours of the named escapes; the longest name matching wins.
+    names: HashMap<String, [f32; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            digits: OFFICIAL,
+            names: HashMap::new(),
+        }
+    }
+}
+
+/// Whether `^name` can be an escape: a letter, then letters, digits or
+/// underscores.
+pub fn valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+impl Palette {
+    /// The official palette with the digits the user themed replaced.
+    pub fn from_settings(settings: &TextSettings) -> Self {
+        let mut palette = Self::default();
+        for (digit, color) in settings.digit_colors.iter().flatten() {
+            match (digit.parse::<usize>(), hex_color(color)) {
+                (Ok(d @ 0..=9), Some(rgb)) => palette.digits[d] = rgb,
+                _ => eprintln!(
+                    "text: digit colour {} = {:?} isn't 0-9 = RRGGBB",
+                    digit, color
+                ),
+            }
+        }
+        for (name, color) in settings.named_colors.iter().flatten() {
+            match hex_color(color) {
+                Some(rgb) if valid_name(name) => palette.set_name(name, Some(rgb)),
+                _ => eprintln!(
+                    "text: named colour {} = {:?} isn't NAME = RRGGBB",
+                    name, color
+                ),
+            }
+        }
+        palette
+    }
+
+    /// Adds, replaces or (with None) removes a named escape.
+    pub fn set_name(&mut self, name: &str, rgb: Option<[f32; 3]>) {
+        match rgb {
+            Some(rgb) => self.names.insert(name.to_string(), rgb),
+            None => self.names.remove(name),
+        };
+    }
+}
+
+/// Read when drawing text on the window thread and measuring it on the
+/// Lua one, and extended by RegisterColorEscape.
+pub type SharedPalette = Arc<Mutex<Palette>>;
+
+/// A run of a text between escapes, with its byte offset, or an escape.
+#[derive(Debug, PartialEq)]
+pub enum Piece<'a> {
+    Text(usize, &'a str),
+    Escape(Escape),
+}
+
+/// Splits `text` into runs and escapes, the one parse both drawing and
+/// measuring go by.
+pub fn pieces<'a>(text: &'a str, palette: &Palette) -> impl Iterator<Item = Piece<'a>> {
+    let bytes = text.as_bytes();
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        if at >= bytes.len() {
+            return None;
+        }
+        if let Some((escape, len)) = Escape::at(&bytes[at..], palette) {
+            at += len;
+            return Some(Piece::Escape(escape));
+        }
+        let start = at;
+        at += 1;
+        while at < bytes.len()
+            && !(bytes[at] == b'^' && Escape::at(&bytes[at..], palette).is_some())
+        {
+            at += 1;
+        }
+        // Escapes are ASCII, so runs end on character boundaries.
+        Some(Piece::Text(start, &text[start..at]))
+    })
+}
+
+/// Runs of `text` with the colour each is drawn in, starting from
+/// `default_color`. Escapes keep its alpha.
+pub fn color_spans<'a>(
+    text: &'a str,
+    default_color: [f32; 4],
+    palette: &Palette,
+) -> Vec<(&'a str, [f32; 4])> {
+    let mut color = default_color;
+    let mut spans = Vec::new();
+    for piece in pieces(text, palette) {
+        match piece {
+            Piece::Text(_, run) => spans.push((run, color)),
+            Piece::Escape(e) => color = e.color(palette, default_color[3]),
+        }
+    }
+    spans
+}
+
+/// `text` without its escapes, plus where each byte of the result came
+/// from in `text`, ending with `text.len()` for the end of the text.
+pub fn strip_mapped(text: &str, palette: &Palette) -> (String, Vec<usize>) {
+    let mut out = String::with_capacity(text.len());
+    let mut map = Vec::with_capacity(text.len() + 1);
+    for piece in pieces(text, palette) {
+        if let Piece::Text(at, run) = piece {
+            out.push_str(run);
+            map.extend(at..at + run.len());
+        }
+    }
+    map.push(text.len());
+    (out, map)
+}
+
+pub fn strip(text: &str, palette: &Palette) -> String {
+    strip_mapped(text, palette).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drawing_and_stripping_agree_on_edge_cases() {
+        let cases = [
+            ("^7Fire ^x33FF88Trap", "Fire Trap"),
+            ("^XFFFFFFcaps", "caps"),
+            // Not escapes: a trailing caret, a doubled one, short or bad hex.
+            ("100%^", "100%^"),
+            ("a^^1b", "a^b"),
+            ("^x12zz", "^x12zz"),
+            ("^x12345", "^x12345"),
+            ("^y", "^y"),
+            ("ünï^2cödé", "ünïcödé"),
+        ];
+        let palette = Palette::default();
+        for (text, stripped) in cases {
+            assert_eq!(strip(text, &palette), stripped, "{:?}", text);
+            let drawn: String = color_spans(text, [1.0; 4], &palette)
+                .iter()
+                .map(|s| s.0)
+                .collect();
+            assert_eq!(drawn, stripped, "{:?}", text);
+        }
+
+        let spans = color_spans("^^1x^x0000FF", [1.0, 1.0, 1.0, 0.5], &palette);
+        assert_eq!(
+            spans,
+            [("^", [1.0, 1.0, 1.0, 0.5]), ("x", [1.0, 0.0, 0.0, 0.5])]
+        );
+        let (_, map) = strip_mapped("a^^1b", &palette);
+        assert_eq!(map, [0, 1, 4, 5]);
+    }
+
+    #[test]
+    fn palette_themes_digits_and_adds_names() {
+        let official = Palette::default();
+        assert_eq!(Escape::Digit(5).color(&official, 1.0), [1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(Escape::Digit(9).color(&official, 0.5), [0.4, 0.4, 0.4, 0.5]);
+        let settings = TextSettings {
+            digit_colors: Some(
+                [("6", "#FF8000"), ("12", "FFFFFF"), ("1", "red")]
+                    .map(|(d, c)| (d.to_string(), c.to_string()))
+                    .into(),
+            ),
+            named_colors: Some(
+                [
+                    ("ORANGE", "FF8000"),
+                    ("ORANGE_DARK", "804000"),
+                    ("9lives", "FFFFFF"),
+                ]
+                .map(|(n, c)| (n.to_string(), c.to_string()))
+                .into(),
+            ),
+            shaping: None,
+            compat_metrics: None,
+            fonts: None,
+        };
+        let themed = Palette::from_settings(&settings);
+        assert_eq!(
+            Escape::Digit(6).color(&themed, 1.0),
+            [1.0, 128.0 / 255.0, 0.0, 1.0]
+        );
+        // Bad entries leave the official colours.
+        assert_eq!(themed.digits[1], OFFICIAL[1]);
+        assert!(!themed.names.contains_key("9lives"));
+
+        // The longest name wins; unknown names stay visible.
+        let spans = color_spans("^ORANGEa^ORANGE_DARKb^PURPLEc", [1.0; 4], &themed);
+        assert_eq!(spans[0], ("a", [1.0, 128.0 / 255.0, 0.0, 1.0]));
+        assert_eq!(
+            spans[1],
+            ("b^PURPLEc", [128.0 / 255.0, 64.0 / 255.0, 0.0, 1.0])
+        );
+        let mut themed = themed;
+        themed.set_name("ORANGE_DARK", None);
+        assert_eq!(strip("^ORANGE_DARKb", &themed), "_DARKb");
+        assert_eq!(strip("^ORANGE_DARKb", &Palette::default()), "^ORANGE_DARKb");
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::settings::{FontMetricSettings, TextSettings};
+
+/// How text in one of PoB's fonts ("VAR", "VAR BOLD", "FIXED") is sized
+/// and measured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontMetrics {
+    /// Line spacing as a multiple of the size.
+    pub line_height: f32,
+    /// What measured widths are multiplied by.
+    pub width_scale: f32,
+    /// Sizes rounded to whole pixels before shaping.
+    pub round_size: bool,
+    /// Glyph advances rounded to whole pixels when measuring.
+    pub hinting: bool,
+}
+
+impl Default for FontMetrics {
+    fn default() -> Self {
+        Self {
+            line_height: 1.2,
+            width_scale: 1.0,
+            round_size: false,
+            hinting: false,
+        }
+    }
+}
+
+impl FontMetrics {
+    /// SimpleGraphic draws only whole pixel heights, its lines are the
+    /// height apart, and its hinted glyphs advance by whole pixels.
+    const COMPAT: Self = Self {
+        line_height: 1.0,
+        width_scale: 1.0,
+        round_size: true,
+        hinting: true,
+    };
+
+    /// These metrics with the ones `settings` gives replaced.
+    fn with(mut self, settings: &FontMetricSettings) -> Self {
+        if let Some(v) = settings.line_height {
+            self.line_height = v.clamp(0.5, 3.0);
+        }
+        if let Some(v) = settings.width_scale {
+            self.width_scale = v.clamp(0.5, 2.0);
+        }
+        if let Some(v) = settings.round_size {
+            self.round_size = v;
+        }
+        if let Some(v) = settings.hinting {
+            self.hinting = v;
+        }
+        self
+    }
+
+    pub fn size(&self, size: f32) -> f32 {
+        match self.round_size {
+            true => size.round().max(1.0),
+            false => size,
+        }
+    }
+
+    /// Distance between the tops of lines at `size`.
+    pub fn line_spacing(&self, size: f32) -> f32 {
+        self.size(size) * self.line_height
+    }
+}
+
+/// The metrics of each font, as set in `[text]`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FontMetricsTable {
+    base: FontMetrics,
+    fonts: HashMap<String, FontMetrics>,
+}
+
+pub type SharedFontMetrics = Arc<FontMetricsTable>;
+
+impl FontMetricsTable {
+    /// `compat_metrics` starts every font from SimpleGraphic's metrics;
+    /// `fonts` then adjusts them by name.
+    pub fn from_settings(settings: &TextSettings) -> Self {
+        let base = match settings.compat_metrics {
+            Some(true) => FontMetrics::COMPAT,
+            _ => FontMetrics::default(),
+        };
+        let fonts = settings
+            .fonts
+            .iter()
+            .flatten()
+            .map(|(name, font)| (name.to_ascii_uppercase(), base.with(font)))
+            .collect();
+        Self { base, fonts }
+    }
+
+    pub fn get(&self, font: &str) -> FontMetrics {
+        match self.fonts.get(font) {
+            Some(metrics) => *metrics,
+            None => self.base,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compat_mode_rounds_sizes_and_fonts_override_it() {
+        let settings: TextSettings = toml::from_str(
+            r#"
+            compat_metrics = true
+            [fonts.var]
+            width_scale = 1.04
+            [fonts.FIXED]
+            line_height = 9.0
+            round_size = false
+            hinting = false
+            "#,
+        )
+        .unwrap();
+        let table = FontMetricsTable::from_settings(&settings);
+        let var = table.get("VAR");
+        assert_eq!((var.width_scale, var.size(14.4)), (1.04, 14.0));
+        assert_eq!(var.line_spacing(15.6), 16.0);
+        let fixed = table.get("FIXED");
+        assert_eq!((fixed.line_height, fixed.size(14.4)), (3.0, 14.4));
+        assert!(var.hinting && !fixed.hinting);
+        assert_eq!(table.get("VAR BOLD"), FontMetrics::COMPAT);
+        assert_eq!(
+            FontMetricsTable::default().get("VAR"),
+            FontMetrics::default()
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::graphics::{DrawItem, PixelFormat, Renderer, TargetPass, TextureCmd, TextureUploadCmd};
+use crate::host_thread::Frame;
+
+/// Bumped whenever the draw item layout changes.
+const VERSION: u32 = 1;
+
+/// Side of the squares in the stand-in for a dumped texture.
+const CHECKER: u32 = 8;
+
+/// A texture a dumped frame draws with. The pixels stay with the user.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextureInfo {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TargetInfo {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Everything one frame drew, for bug reports about rendering. Written on
+/// Ctrl+Shift+D and drawn again with `--render-dump`.
+#[derive(Serialize, Deserialize)]
+pub struct FrameDump {
+    pub version: u32,
+    /// The window in physical pixels, and the UI zoom it was drawn at.
+    pub size: [u32; 2],
+    pub zoom: f32,
+    pub targets: Vec<TargetPass>,
+    pub items: Vec<DrawItem>,
+    pub textures: Vec<TextureInfo>,
+    pub render_targets: Vec<TargetInfo>,
+}
+
+fn texture_ids(items: &[DrawItem], ids: &mut BTreeSet<u32>) {
+    for item in items {
+        match item {
+            DrawItem::Rect(cmd) => ids.insert(cmd.texture_id),
+            DrawItem::Quad(cmd) => ids.insert(cmd.texture_id),
+            _ => continue,
+        };
+    }
+}
+
+/// A grey checkerboard the size of a texture that wasn't dumped.
+fn placeholder(info: &TextureInfo) -> TextureUploadCmd {
+    let pixels: Vec<u8> = (0..info.height)
+        .flat_map(|y| (0..info.width).map(move |x| (x / CHECKER + y / CHECKER) % 2))
+        .flat_map(|odd| {
+            if odd == 1 {
+                [96, 96, 96, 255]
+            } else {
+                [160, 160, 160, 255]
+            }
+        })
+        .collect();
+    TextureUploadCmd {
+        id: info.id,
+        pixels: pixels.into(),
+        format: PixelFormat::Rgba8,
+        width: info.width,
+        height: info.height,
+        premultiplied: false,
+    }
+}
+
+impl FrameDump {
+    /// Records a frame whose texture commands have been applied but whose
+    /// target passes are still to be drawn.
+    pub fn capture(frame: &Frame, renderer: &Renderer, size: [u32; 2], zoom: f32) -> Self {
+        let mut ids = BTreeSet::new();
+        texture_ids(&frame.items, &mut ids);
+        for target in &frame.targets {
+            texture_ids(&target.items, &mut ids);
+            ids.insert(target.id);
+        }
+        let mut textures = Vec::new();
+        let mut render_targets = Vec::new();
+        for id in ids {
+            if let Some((width, height)) = renderer.target_size(id) {
+                render_targets.push(TargetInfo { id, width, height });
+            } else if let Some((width, height, format)) = renderer.texture_info(id) {
+                textures.push(TextureInfo {
+                    id,
+                    width,
+                    height,
+                    format,
+                });
+            }
+        }
+        Self {
+            version: VERSION,
+            size,
+            zoom,
+            targets: frame.targets.clone(),
+            items: frame.items.clone(),
+            textures,
+            render_targets,
+        }
+    }
+
+    /// Writes the dump into `dir`, named by the time, and returns the path.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, String> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let path = dir.join(format!("frame-{}.json", secs));
+        let json = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        std::fs::write(&path, json).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        let dump: Self = serde_json::from_slice(&data).map_err(|e| e.to_string())?;
+        if dump.version != VERSION {
+            return Err(format!(
+                "frame dump version {}, expected {}",
+                dump.version, VERSION
+            ));
+        }
+        Ok(dump)
+    }
+
+    /// The frame to draw for the dump. Textures become checkerboards of
+    /// their size, and render targets start out cleared, so only what
+    /// the target passes draw this frame shows in them.
+    pub fn into_frame(self) -> Frame {
+        let targets = self
+            .render_targets
+            .iter()
+            .map(|t| TextureCmd::CreateTarget {
+                id: t.id,
+                width: t.width,
+                height: t.height,
+            });
+        let uploads = self
+            .textures
+            .iter()
+            .map(|t| TextureCmd::Upload(placeholder(t)));
+        Frame {
+            items: self.items,
+            targets: self.targets,
+            textures: targets.chain(uploads).collect(),
+            screenshots: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::DrawCmd;
+
+    #[test]
+    fn dumps_round_trip_and_replay_with_stand_in_textures() {
+        let rect = DrawItem::Rect(DrawCmd {
+            x: 10.0,
+            y: 20.0,
+            w: 30.0,
+            h: 40.0,
+            color: [1.0, 0.5, 0.0, 1.0],
+            texture_id: 7,
+            uv: [0.0, 0.0, 1.0, 1.0],
+            clip: Some([0, 0, 100, 100]),
+            corners: None,
+        });
+        let dump = FrameDump {
+            version: VERSION,
+            size: [800, 600],
+            zoom: 1.5,
+            targets: vec![TargetPass {
+                id: 3,
+                clear: Some([0.0; 4]),
+                items: Vec::new(),
+            }],
+            items: vec![rect],
+            textures: vec![TextureInfo {
+                id: 7,
+                width: 16,
+                height: 4,
+                format: PixelFormat::Bc3,
+            }],
+            render_targets: vec![TargetInfo {
+                id: 3,
+                width: 64,
+                height: 64,
+            }],
+        };
+        let dir = std::env::temp_dir().join(format!("pob-frame-dump-{}", std::process::id()));
+        let path = dump.save(&dir).unwrap();
+        let loaded = FrameDump::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!((loaded.size, loaded.zoom), ([800, 600], 1.5));
+        assert_eq!(loaded.textures, dump.textures);
+        let DrawItem::Rect(cmd) = &loaded.items[0] else {
+            panic!("not a rect");
+        };
+        assert_eq!((cmd.texture_id, cmd.clip), (7, Some([0, 0, 100, 100])));
+
+        let frame = loaded.into_frame();
+        assert_eq!(frame.targets[0].id, 3);
+        assert!(matches!(
+            frame.textures[0],
+            TextureCmd::CreateTarget {
+                id: 3,
+                width: 64,
+                height: 64
+            }
+        ));
+        let TextureCmd::Upload(upload) = &frame.textures[1] else {
+            panic!("not an upload");
+        };
+        assert_eq!((upload.width, upload.height), (16, 4));
+        assert_eq!(upload.pixels.len(), PixelFormat::Rgba8.size(16, 4).unwrap());
+        // Squares alternate every CHECKER texels.
+        assert_ne!(upload.pixels[0], upload.pixels[CHECKER as usize * 4]);
+    }
+}
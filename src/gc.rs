@@ -0,0 +1,231 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+/// Lua's pause while the host steps the collector: the collector only
+/// starts a cycle on its own, mid-frame, once the heap is this many
+/// percent of what was live after the last one.
+const FRAME_PAUSE: i32 = 400;
+
+/// Heap growth since the last cycle, in percent, at which the host starts
+/// the next one between frames, well before Lua itself would.
+const START_GROWTH: usize = 150;
+
+/// Work per collector step; the budget is checked between steps.
+const STEP_KB: i32 = 64;
+
+const DEFAULT_BUDGET: Duration = Duration::from_millis(2);
+
+/// The longest budget SetGCMode takes, in ms.
+const MAX_BUDGET_MS: f64 = 1000.0;
+
+/// How Lua's garbage collector is scheduled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GcMode {
+    /// Lua's own, which can pause for long in a big calc frame.
+    Auto,
+    /// Incremental steps between frames, within a time budget.
+    Frame,
+}
+
+impl GcMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Some(Self::Auto),
+            "frame" => Some(Self::Frame),
+            _ => None,
+        }
+    }
+}
+
+pub struct GcScheduler {
+    mode: GcMode,
+    budget: Duration,
+    /// Lua's pause from before Frame mode raised it.
+    auto_pause: Option<i32>,
+    /// Bytes in use when the last cycle finished, and whether one has been
+    /// started since.
+    live: usize,
+    collecting: bool,
+    /// Time spent collecting between the last two frames, the longest
+    /// such pause and the cycles finished between frames.
+    pub last: Duration,
+    pub longest: Duration,
+    pub cycles: u64,
+}
+
+pub type SharedGc = Arc<Mutex<GcScheduler>>;
+
+impl GcScheduler {
+    /// Starts in Lua's own mode, until SetGCMode asks for another.
+    pub fn new(lua: &Lua) -> Self {
+        Self {
+            mode: GcMode::Auto,
+            budget: DEFAULT_BUDGET,
+            auto_pause: None,
+            live: lua.used_memory(),
+            collecting: false,
+            last: Duration::ZERO,
+            longest: Duration::ZERO,
+            cycles: 0,
+        }
+    }
+
+    pub fn set_mode(&mut self, lua: &Lua, mode: GcMode, budget: Option<Duration>) {
+        self.budget = budget.unwrap_or(DEFAULT_BUDGET);
+        match (self.mode, mode) {
+            (GcMode::Auto, GcMode::Frame) => self.auto_pause = Some(lua.gc_set_pause(FRAME_PAUSE)),
+            (GcMode::Frame, GcMode::Auto) => {
+                lua.gc_set_pause(self.auto_pause.take().unwrap_or(200));
+            }
+            _ => {}
+        }
+        self.mode = mode;
+    }
+}
+
+/// Steps the collector until the budget is spent or a cycle ends, and
+/// returns the time it took. Call between frames. `gc` isn't locked while
+/// collecting, as finalizers may call SetGCMode.
+pub fn step(gc: &SharedGc, lua: &Lua) -> LuaResult<Duration> {
+    let budget = {
+        let mut gc = gc.lock().unwrap();
+        gc.last = Duration::ZERO;
+        if gc.mode == GcMode::Auto {
+            return Ok(Duration::ZERO);
+        }
+        if !gc.collecting && lua.used_memory() * 100 < gc.live * START_GROWTH {
+            return Ok(Duration::ZERO);
+        }
+        gc.collecting = true;
+        gc.budget
+    };
+    let start = Instant::now();
+    let mut finished = false;
+    while !finished && start.elapsed() < budget {
+        finished = lua.gc_step_kbytes(STEP_KB)?;
+    }
+    let mut gc = gc.lock().unwrap();
+    gc.last = start.elapsed();
+    gc.longest = gc.longest.max(gc.last);
+    if finished {
+        gc.collecting = false;
+        gc.live = lua.used_memory();
+        gc.cycles += 1;
+    }
+    Ok(gc.last)
+}
+
+/// Registers `SetGCMode("auto" | "frame", [budgetMs])` and
+/// `GetGCStats()`, which returns a table of the mode, `lastMs` and
+/// `longestMs` paused between frames, and the `cycles` finished there.
+pub fn register(lua: &Lua, gc: SharedGc) -> LuaResult<()> {
+    let g = lua.globals();
+    let stats = gc.clone();
+    g.set(
+        "GetGCStats",
+        lua.create_function(move |lua, ()| {
+            let gc = stats.lock().unwrap();
+            let t = lua.create_table()?;
+            let mode = match gc.mode {
+                GcMode::Auto => "auto",
+                GcMode::Frame => "frame",
+            };
+            t.set("mode", mode)?;
+            t.set("lastMs", gc.last.as_secs_f64() * 1000.0)?;
+            t.set("longestMs", gc.longest.as_secs_f64() * 1000.0)?;
+            t.set("cycles", gc.cycles)?;
+            Ok(t)
+        })?,
+    )?;
+    g.set(
+        "SetGCMode",
+        lua.create_function(move |lua, (name, budget): (String, Option<f64>)| {
+            let mode = GcMode::parse(&name)
+                .ok_or_else(|| LuaError::RuntimeError(format!("unknown GC mode \"{}\"", name)))?;
+            let budget = budget.map(|ms| {
+                let ms = if ms.is_nan() {
+                    0.0
+                } else {
+                    ms.clamp(0.0, MAX_BUDGET_MS)
+                };
+                Duration::from_secs_f64(ms / 1000.0)
+            });
+            gc.lock().unwrap().set_mode(lua, mode, budget);
+            Ok(())
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_mode_collects_between_frames_once_the_heap_grows() {
+        let lua = Lua::new();
+        let gc: SharedGc = Arc::new(Mutex::new(GcScheduler::new(&lua)));
+        register(&lua, gc.clone()).unwrap();
+        // Lua's own collector stays in charge until asked otherwise.
+        assert_eq!(gc.lock().unwrap().mode, GcMode::Auto);
+
+        lua.load(r#"SetGCMode("frame", 50)"#).exec().unwrap();
+        // Nothing new to collect yet.
+        assert_eq!(step(&gc, &lua).unwrap(), Duration::ZERO);
+
+        // A finalizer run by the step may itself change the mode.
+        lua.load(
+            r#"
+            local keep = {}
+            for i = 1, 100000 do keep[i] = { i } end
+            keep = nil
+            local finalized = newproxy(true)
+            getmetatable(finalized).__gc = function() ran = SetGCMode("frame", 50) == nil end
+            finalized = nil
+            "#,
+        )
+        .exec()
+        .unwrap();
+        for _ in 0..100 {
+            step(&gc, &lua).unwrap();
+            if gc.lock().unwrap().cycles > 0 {
+                break;
+            }
+        }
+        assert!(lua.globals().get::<_, bool>("ran").unwrap());
+        let mut g = gc.lock().unwrap();
+        assert_eq!(g.cycles, 1);
+        assert!(g.last <= Duration::from_millis(500));
+
+        g.set_mode(&lua, GcMode::Auto, None);
+        drop(g);
+        assert_eq!(step(&gc, &lua).unwrap(), Duration::ZERO);
+        assert_eq!(gc.lock().unwrap().cycles, 1);
+        assert!(GcMode::parse("generational").is_none());
+    }
+
+    #[test]
+    fn stats_reach_lua_and_budgets_are_capped() {
+        let lua = Lua::new();
+        let gc: SharedGc = Arc::new(Mutex::new(GcScheduler::new(&lua)));
+        register(&lua, gc.clone()).unwrap();
+        lua.load(r#"SetGCMode("frame", math.huge)"#).exec().unwrap();
+        assert_eq!(gc.lock().unwrap().budget, Duration::from_secs(1));
+        lua.load(r#"SetGCMode("frame", 0/0)"#).exec().unwrap();
+        assert_eq!(gc.lock().unwrap().budget, Duration::ZERO);
+
+        {
+            let mut g = gc.lock().unwrap();
+            g.last = Duration::from_millis(3);
+            g.longest = Duration::from_millis(7);
+            g.cycles = 4;
+        }
+        let (mode, last, longest, cycles): (String, f64, f64, u64) = lua
+            .load("local s = GetGCStats() return s.mode, s.lastMs, s.longestMs, s.cycles")
+            .eval()
+            .unwrap();
+        assert_eq!(mode, "frame");
+        assert_eq!((last, longest, cycles), (3.0, 7.0, 4));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+
+use winit::event::TouchPhase;
+
+use crate::host_thread::HostEvent;
+
+/// Zoom change (as a fraction) that counts as one wheel notch.
+const PINCH_STEP: f64 = 0.1;
+
+/// Turns touchpad and touchscreen gestures into the mouse and wheel input
+/// PoB already understands: pinches become WHEELUP/WHEELDOWN, one finger
+/// drags like the left button, two fingers drag at their centroid and pinch
+/// by finger distance.
+#[derive(Default)]
+pub struct GestureTranslator {
+    pinch: f64,
+    pan: Option<[f64; 2]>,
+    touches: BTreeMap<u64, [f64; 2]>,
+    /// Finger distance when the current two-finger gesture last zoomed.
+    spread: Option<f64>,
+    dragging: bool,
+    /// Set once a multi-finger gesture started, until every finger lifts.
+    multi: bool,
+}
+
+impl GestureTranslator {
+    /// Trackpad pinch; `delta` is the relative zoom change winit reports.
+    pub fn pinch(&mut self, delta: f64, phase: TouchPhase) -> Vec<HostEvent> {
+        if phase == TouchPhase::Started {
+            self.pinch = 0.0;
+        }
+        self.pinch += delta;
+        let mut out = Vec::new();
+        while self.pinch.abs() >= PINCH_STEP {
+            let step = PINCH_STEP.copysign(self.pinch);
+            self.pinch -= step;
+            out.push(wheel(step > 0.0));
+        }
+        if matches!(phase, TouchPhase::Ended | TouchPhase::Cancelled) {
+            self.pinch = 0.0;
+        }
+        out
+    }
+
+    /// Trackpad pan starting at `cursor`, emulated as a left-button drag.
+    pub fn pan(&mut self, cursor: [f64; 2], delta: [f64; 2], phase: TouchPhase) -> Vec<HostEvent> {
+        match phase {
+            TouchPhase::Started => {
+                self.pan = Some(cursor);
+                vec![mouse_move(cursor), button(true)]
+            }
+            TouchPhase::Moved => {
+                let Some(pos) = &mut self.pan else {
+                    return Vec::new();
+                };
+                pos[0] += delta[0];
+                pos[1] += delta[1];
+                vec![mouse_move(*pos)]
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => match self.pan.take() {
+                Some(_) => vec![button(false), mouse_move(cursor)],
+                None => Vec::new(),
+            },
+        }
+    }
+
+    pub fn touch(&mut self, id: u64, phase: TouchPhase, pos: [f64; 2]) -> Vec<HostEvent> {
+        let mut out = Vec::new();
+        match phase {
+            TouchPhase::Started => {
+                self.touches.insert(id, pos);
+                match self.touches.len() {
+                    1 if !self.multi => {
+                        out.push(mouse_move(pos));
+                        out.push(button(true));
+                        self.dragging = true;
+                    }
+                    2 => {
+                        // Restart the drag at the centroid so the view doesn't
+                        // jump to the second finger.
+                        if self.dragging {
+                            out.push(button(false));
+                        }
+                        self.multi = true;
+                        self.spread = Some(self.finger_distance());
+                        out.push(mouse_move(self.centroid()));
+                        out.push(button(true));
+                        self.dragging = true;
+                    }
+                    _ => {}
+                }
+            }
+            TouchPhase::Moved => {
+                if self.touches.insert(id, pos).is_none() {
+                    return out;
+                }
+                match self.touches.len() {
+                    1 if !self.multi => out.push(mouse_move(pos)),
+                    2 => {
+                        out.push(mouse_move(self.centroid()));
+                        let dist = self.finger_distance();
+                        if let Some(spread) = &mut self.spread
+                            && *spread > 0.0
+                        {
+                            let mut ratio = dist / *spread - 1.0;
+                            while ratio.abs() >= PINCH_STEP {
+                                out.push(wheel(ratio > 0.0));
+                                *spread *= 1.0 + PINCH_STEP.copysign(ratio);
+                                ratio = dist / *spread - 1.0;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&id);
+                if self.dragging && (self.multi || self.touches.is_empty()) {
+                    out.push(button(false));
+                    self.dragging = false;
+                }
+                if self.touches.is_empty() {
+                    self.multi = false;
+                    self.spread = None;
+                }
+            }
+        }
+        out
+    }
+
+    fn centroid(&self) -> [f64; 2] {
+        let n = self.touches.len().max(1) as f64;
+        let sum = self
+            .touches
+            .values()
+            .fold([0.0, 0.0], |a, p| [a[0] + p[0], a[1] + p[1]]);
+        [sum[0] / n, sum[1] / n]
+    }
+
+    fn finger_distance(&self) -> f64 {
+        let mut pts = self.touches.values();
+        match (pts.next(), pts.next()) {
+            (Some(a), Some(b)) => ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt(),
+            _ => 0.0,
+        }
+    }
+}
+
+fn mouse_move(pos: [f64; 2]) -> HostEvent {
+    HostEvent::MouseMove {
+        x: pos[0] as f32,
+        y: pos[1] as f32,
+    }
+}
+
+fn button(down: bool) -> HostEvent {
+    let key = "LEFTBUTTON".to_string();
+    if down {
+        HostEvent::KeyDown {
+            key,
+            double_click: false,
+        }
+    } else {
+        HostEvent::KeyUp { key }
+    }
+}
+
+fn wheel(up: bool) -> HostEvent {
+    HostEvent::KeyDown {
+        key: if up { "WHEELUP" } else { "WHEELDOWN" }.to_string(),
+        double_click: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinch_emits_wheel_notches() {
+        let mut g = GestureTranslator::default();
+        assert!(g.pinch(0.05, TouchPhase::Started).is_empty());
+        assert_eq!(
+            g.pinch(0.17, TouchPhase::Moved),
+            vec![wheel(true), wheel(true)]
+        );
+        assert_eq!(g.pinch(-0.14, TouchPhase::Moved), vec![wheel(false)]);
+    }
+
+    #[test]
+    fn single_touch_drags_and_second_finger_pinches() {
+        let mut g = GestureTranslator::default();
+        assert_eq!(
+            g.touch(1, TouchPhase::Started, [10.0, 10.0]),
+            vec![mouse_move([10.0, 10.0]), button(true)]
+        );
+        assert_eq!(
+            g.touch(1, TouchPhase::Moved, [20.0, 10.0]),
+            vec![mouse_move([20.0, 10.0])]
+        );
+        assert_eq!(
+            g.touch(2, TouchPhase::Started, [40.0, 10.0]),
+            vec![button(false), mouse_move([30.0, 10.0]), button(true)]
+        );
+        // spreading the fingers from 20px to 30px apart zooms in
+        let out = g.touch(2, TouchPhase::Moved, [50.0, 10.0]);
+        assert_eq!(out[0], mouse_move([35.0, 10.0]));
+        assert!(out[1..].iter().all(|e| *e == wheel(true)) && out.len() > 1);
+        assert_eq!(
+            g.touch(2, TouchPhase::Ended, [50.0, 10.0]),
+            vec![button(false)]
+        );
+        // the remaining finger doesn't restart a drag until all are lifted
+        assert!(g.touch(1, TouchPhase::Moved, [25.0, 10.0]).is_empty());
+        assert!(g.touch(1, TouchPhase::Ended, [25.0, 10.0]).is_empty());
+    }
+}
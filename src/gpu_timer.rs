@@ -0,0 +1,182 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// GPU time the last timed frame took.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GpuTimes {
+    /// The screen pass, text included, in milliseconds.
+    pub pass_ms: f32,
+    /// The text part of it, where timestamps can be taken mid-pass.
+    pub text_ms: Option<f32>,
+}
+
+impl fmt::Display for GpuTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2}ms", self.pass_ms)?;
+        if let Some(text) = self.text_ms {
+            write!(f, " (text {:.2}ms)", text)?;
+        }
+        Ok(())
+    }
+}
+
+pub type SharedGpuTimes = Arc<Mutex<Option<GpuTimes>>>;
+
+/// Start of the pass, end of the shapes and images, end of the text.
+const STAMPS: u32 = 3;
+const STAMP_BYTES: u64 = STAMPS as u64 * 8;
+
+fn times(stamps: [u64; 3], period: f32, inside_passes: bool) -> GpuTimes {
+    let ms = |from: u64, to: u64| to.saturating_sub(from) as f32 * period / 1_000_000.0;
+    GpuTimes {
+        pass_ms: ms(stamps[0], stamps[2]),
+        text_ms: inside_passes.then(|| ms(stamps[1], stamps[2])),
+    }
+}
+
+/// Timestamp queries around the screen pass, to tell frames held up by
+/// the GPU from ones held up by Lua. Only made where the adapter has them.
+pub struct GpuTimer {
+    set: wgpu::QuerySet,
+    resolve: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    /// Nanoseconds per tick.
+    period: f32,
+    /// Stamps can go between draws; otherwise only the pass is timed.
+    inside_passes: bool,
+    /// Whether this frame is timed. Frames go untimed while the last
+    /// readback is still in flight.
+    timing: bool,
+    reading: bool,
+    /// Set when the readback mapping finished, with whether it worked.
+    mapped: Arc<Mutex<Option<bool>>>,
+}
+
+impl GpuTimer {
+    /// The device features the timer uses, if the adapter has them.
+    pub const FEATURES: wgpu::Features =
+        wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES);
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let buffer = |label, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: STAMP_BYTES,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        Some(Self {
+            set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("gpu timer"),
+                ty: wgpu::QueryType::Timestamp,
+                count: STAMPS,
+            }),
+            resolve: buffer(
+                "gpu timer resolve",
+                wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            readback: buffer(
+                "gpu timer readback",
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            ),
+            period: queue.get_timestamp_period(),
+            inside_passes: device
+                .features()
+                .contains(wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES),
+            timing: false,
+            reading: false,
+            mapped: Arc::default(),
+        })
+    }
+
+    /// Starts a frame, returning the times of the last timed one once the
+    /// GPU has finished it.
+    pub fn begin(&mut self, device: &wgpu::Device) -> Option<GpuTimes> {
+        let mut done = None;
+        if self.reading {
+            device.poll(wgpu::Maintain::Poll);
+            let mapped = self.mapped.lock().unwrap().take();
+            if mapped == Some(true) {
+                let slice = self.readback.slice(..);
+                let data = slice.get_mapped_range();
+                let stamps: &[u64] = bytemuck::cast_slice(&data);
+                done = Some(times(
+                    [stamps[0], stamps[1], stamps[2]],
+                    self.period,
+                    self.inside_passes,
+                ));
+                drop(data);
+                self.readback.unmap();
+            }
+            self.reading = mapped.is_none();
+        }
+        self.timing = !self.reading;
+        done
+    }
+
+    /// Timestamp writes for the screen pass where stamps can't go inside it.
+    pub fn pass_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        (self.timing && !self.inside_passes).then_some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(STAMPS - 1),
+        })
+    }
+
+    /// Stamps point `index` of the pass.
+    pub fn stamp(&self, pass: &mut wgpu::RenderPass, index: u32) {
+        if self.timing && self.inside_passes {
+            pass.write_timestamp(&self.set, index);
+        }
+    }
+
+    /// Copies the stamps out once the pass is recorded.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.timing {
+            encoder.resolve_query_set(&self.set, 0..STAMPS, &self.resolve, 0);
+            encoder.copy_buffer_to_buffer(&self.resolve, 0, &self.readback, 0, STAMP_BYTES);
+        }
+    }
+
+    /// Starts reading the stamps back after the frame is submitted.
+    pub fn submitted(&mut self) {
+        if !self.timing {
+            return;
+        }
+        let mapped = self.mapped.clone();
+        self.readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |r| {
+                *mapped.lock().unwrap() = Some(r.is_ok());
+            });
+        self.reading = true;
+        self.timing = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_become_pass_and_text_times() {
+        // A 10ns period: 300000 ticks are 3ms.
+        let t = times([1_000, 201_000, 301_000], 10.0, true);
+        assert_eq!(
+            t,
+            GpuTimes {
+                pass_ms: 3.0,
+                text_ms: Some(1.0)
+            }
+        );
+        assert_eq!(t.to_string(), "3.00ms (text 1.00ms)");
+        // Without mid-pass stamps only the pass is timed.
+        assert_eq!(times([0, 0, 50_000], 1.0, false).to_string(), "0.05ms");
+        // Ticks that went backwards (a GPU reset) aren't negative times.
+        assert_eq!(times([500, 0, 100], 1.0, true).pass_ms, 0.0);
+    }
+}
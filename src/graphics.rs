@@ -1,12 +1,19 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::LazyLock;
 use std::sync::Mutex;
 
+use serde::{Deserialize, Serialize};
 use wgpu::ShaderStages;
 
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+use crate::escapes::{self, SharedPalette};
+use crate::font_metrics::SharedFontMetrics;
+use crate::text_effects::{EffectText, TextEffects};
+use crate::texture_cache;
 
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Serialize, Deserialize)]
 pub struct Vertex {
     pub position: [f32; 2],
     pub uv: [f32; 2],
@@ -45,7 +52,7 @@ pub struct ScreenUniform {
     pub size: [f32; 2],
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DrawCmd {
     pub x: f32,
     pub y: f32,
@@ -55,46 +62,499 @@ pub struct DrawCmd {
     pub texture_id: u32,
     pub uv: [f32; 4], // [tcLeft, tcTop, tcRight, tcBottom]
     pub clip: Option<[u32; 4]>,
+    /// Per-corner colours (top-left, top-right, bottom-right, bottom-left)
+    /// replacing `color`, for gradients.
+    pub corners: Option<[[f32; 4]; 4]>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DrawQuadCmd {
     pub texture_id: u32,
     pub color: [f32; 4],
+    /// Colours for each of the four points, replacing `color`.
+    pub corners: Option<[[f32; 4]; 4]>,
     pub clip: Option<[u32; 4]>,
     pub positions: [[f32; 2]; 4],
     pub uvs: [[f32; 2]; 4],
 }
 
+/// Untextured triangles with per-vertex colour, for vector primitives.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MeshCmd {
+    pub vertices: Vec<Vertex>,
+    pub clip: Option<[u32; 4]>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub enum DrawItem {
     Rect(DrawCmd),
     Quad(DrawQuadCmd),
+    Mesh(MeshCmd),
     Text(TextCmd),
+    /// Items up to the next `EndTarget` render into target `id` instead of
+    /// the screen; `clear` wipes it first.
+    BeginTarget {
+        id: u32,
+        clear: Option<[f32; 4]>,
+    },
+    EndTarget,
+}
+
+/// Draws recorded between a render target's Begin and End.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TargetPass {
+    pub id: u32,
+    pub clear: Option<[f32; 4]>,
+    pub items: Vec<DrawItem>,
+}
+
+/// Separates render target passes from the items drawn to the screen. A
+/// target still open when the frame ends is closed there.
+pub fn split_passes(items: impl IntoIterator<Item = DrawItem>) -> (Vec<TargetPass>, Vec<DrawItem>) {
+    let mut targets: Vec<TargetPass> = Vec::new();
+    let mut screen = Vec::new();
+    let mut open = false;
+    for item in items {
+        match item {
+            DrawItem::BeginTarget { id, clear } => {
+                targets.push(TargetPass {
+                    id,
+                    clear,
+                    items: Vec::new(),
+                });
+                open = true;
+            }
+            DrawItem::EndTarget => open = false,
+            item if open => targets.last_mut().unwrap().items.push(item),
+            item => screen.push(item),
+        }
+    }
+    (targets, screen)
+}
+
+/// Adds a skipped frame's target passes to `kept`, the ones still to be
+/// rendered. A clearing pass replaces what came before it in its target;
+/// one drawing over the previous contents needs those passes kept.
+pub fn merge_passes(kept: &mut Vec<TargetPass>, next: Vec<TargetPass>) {
+    for pass in next {
+        if pass.clear.is_some() {
+            kept.retain(|k| k.id != pass.id);
+        }
+        kept.push(pass);
+    }
+}
+
+/// The text commands among `items`, in order.
+pub fn text_cmds(items: &[DrawItem]) -> Vec<TextCmd> {
+    items
+        .iter()
+        .filter_map(|d| match d {
+            DrawItem::Text(t) => Some(t.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A copy of a rendered frame on its way back from the GPU.
+pub struct Capture {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    /// Bytes per row, padded to wgpu's copy alignment.
+    stride: u32,
+    bgra: bool,
+}
+
+impl Capture {
+    /// Records a copy of `texture`, which needs `COPY_SRC` usage, into
+    /// `encoder`.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Self {
+        let (width, height) = (texture.width(), texture.height());
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let stride = (width * 4).div_ceil(align) * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("capture"),
+            size: (stride * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(stride),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        let bgra = matches!(
+            texture.format(),
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        Self {
+            buffer,
+            width,
+            height,
+            stride,
+            bgra,
+        }
+    }
+
+    /// Waits for the submitted copy and writes it to `path` as a PNG.
+    pub fn save(&self, device: &wgpu::Device, path: &std::path::Path) -> Result<(), String> {
+        let pixels = self.pixels(device)?;
+        image::save_buffer(
+            path,
+            &pixels,
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /// Waits for the submitted copy and returns its RGBA pixels.
+    pub fn pixels(&self, device: &wgpu::Device) -> Result<Vec<u8>, String> {
+        let slice = self.buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |r| {
+            tx.send(r).ok();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for row in slice.get_mapped_range().chunks(self.stride as usize) {
+            pixels.extend_from_slice(&row[..(self.width * 4) as usize]);
+        }
+        self.buffer.unmap();
+        if self.bgra {
+            for px in pixels.chunks_mut(4) {
+                px.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
 }
 
 pub type DrawQueue = Arc<Mutex<Vec<DrawItem>>>;
 
 pub type CursorPos = Arc<Mutex<[f32; 2]>>;
 
+/// How the pixels of a texture upload are laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PixelFormat {
+    /// sRGB with straight alpha; premultiplied when uploaded.
+    Rgba8,
+    /// BC3 (DXT5) blocks, 16 bytes per 4x4 texels, compressed from
+    /// premultiplied pixels.
+    Bc3,
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Turns straight-alpha sRGB pixels into premultiplied ones, which the
+/// pipeline blends. Colours are scaled in linear space, where the sampler
+/// filters, so clear texels next to opaque ones no longer pull edges
+/// towards black.
+pub fn premultiply_alpha(pixels: &mut [u8]) {
+    // Indexed by alpha, then colour.
+    static TABLE: LazyLock<Vec<u8>> = LazyLock::new(|| {
+        (0..256 * 256)
+            .map(|i| {
+                let (a, c) = ((i / 256) as f32 / 255.0, (i % 256) as f32 / 255.0);
+                (linear_to_srgb(srgb_to_linear(c) * a) * 255.0).round() as u8
+            })
+            .collect()
+    });
+    for px in pixels.chunks_exact_mut(4) {
+        let row = &TABLE[px[3] as usize * 256..][..256];
+        for c in &mut px[..3] {
+            *c = row[*c as usize];
+        }
+    }
+}
+
+impl PixelFormat {
+    /// Bytes of pixel data for an image of this size; None past what
+    /// memory could hold.
+    pub fn size(self, width: u32, height: u32) -> Option<usize> {
+        let (w, h, per) = match self {
+            Self::Rgba8 => (width, height, 4),
+            Self::Bc3 => (width.div_ceil(4), height.div_ceil(4), 16),
+        };
+        let bytes = u64::from(w).checked_mul(u64::from(h))?.checked_mul(per)?;
+        usize::try_from(bytes).ok()
+    }
+}
+
+/// Texture data: decoded into memory, or mapped from a texture cache file.
+#[derive(Clone)]
+pub enum Pixels {
+    Owned(Vec<u8>),
+    /// The mapping and where in it the pixels start.
+    Mapped(Arc<memmap2::Mmap>, usize),
+}
+
+impl Pixels {
+    /// The pixels to change, copied out of a mapping first.
+    pub fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let Self::Mapped(..) = self {
+            *self = Self::Owned(self.to_vec());
+        }
+        match self {
+            Self::Owned(pixels) => pixels,
+            Self::Mapped(..) => unreachable!(),
+        }
+    }
+}
+
+impl std::ops::Deref for Pixels {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Owned(pixels) => pixels,
+            Self::Mapped(map, start) => &map[*start..],
+        }
+    }
+}
+
+impl From<Vec<u8>> for Pixels {
+    fn from(pixels: Vec<u8>) -> Self {
+        Self::Owned(pixels)
+    }
+}
+
+/// Uploads the GPU refused, by texture id, until the image handle hears.
+pub type UploadErrors = Arc<Mutex<HashMap<u32, String>>>;
+
 #[derive(Clone)]
 pub struct TextureUploadCmd {
     pub id: u32,
-    pub rgba: Vec<u8>,
+    pub pixels: Pixels,
+    pub format: PixelFormat,
     pub width: u32,
     pub height: u32,
+    /// Rgba8 pixels already premultiplied, as cached ones are. BC3 blocks
+    /// always are.
+    pub premultiplied: bool,
+}
+
+impl TextureUploadCmd {
+    /// Why the GPU would refuse this upload, if it would.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!("empty {}x{} image", self.width, self.height));
+        }
+        let expected = self.format.size(self.width, self.height);
+        if expected != Some(self.pixels.len()) {
+            return Err(format!(
+                "{}x{} {:?} image has {} bytes, expected {}",
+                self.width,
+                self.height,
+                self.format,
+                self.pixels.len(),
+                expected.map_or("more than fit in memory".into(), |n| n.to_string())
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `rows` of `bytes_per_row` spaced out to wgpu's copy alignment, which
+/// some backends want even for queue writes, and the padded row length.
+fn pad_rows(pixels: &[u8], bytes_per_row: u32, rows: u32) -> (Cow<'_, [u8]>, u32) {
+    let padded = bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    if padded == bytes_per_row || rows <= 1 {
+        return (Cow::Borrowed(pixels), bytes_per_row);
+    }
+    let mut out = vec![0; padded as usize * rows as usize];
+    for (src, dst) in pixels
+        .chunks_exact(bytes_per_row as usize)
+        .zip(out.chunks_exact_mut(padded as usize))
+    {
+        dst[..bytes_per_row as usize].copy_from_slice(src);
+    }
+    (Cow::Owned(out), padded)
+}
+
+/// How a texture is sampled between texels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFilter {
+    Linear,
+    /// Hard texel edges, for pixel art drawn larger than it is.
+    Nearest,
+}
+
+impl TextureFilter {
+    /// The SimpleGraphic-style flag name.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "LINEAR" => Some(Self::Linear),
+            "NEAREST" => Some(Self::Nearest),
+            _ => None,
+        }
+    }
+}
+
+/// RGBA pixels scaled down to fit within `max` on both sides, keeping
+/// the aspect; None when they already fit. Draws address textures by
+/// UV, so only the detail is lost.
+pub fn fit_texture(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    max: u32,
+) -> Option<(Vec<u8>, u32, u32)> {
+    if width <= max && height <= max {
+        return None;
+    }
+    let scale = max as f64 / width.max(height) as f64;
+    let fit = |side: u32| ((side as f64 * scale).round() as u32).clamp(1, max);
+    let (w, h) = (fit(width), fit(height));
+    let image = image::RgbaImage::from_raw(width, height, pixels.to_vec())?;
+    let shrunk = image::imageops::resize(&image, w, h, image::imageops::FilterType::Triangle);
+    Some((shrunk.into_raw(), w, h))
+}
+
+pub enum TextureCmd {
+    Upload(TextureUploadCmd),
+    /// Picks how a texture is sampled, from now and after any re-upload;
+    /// None goes back to linear.
+    SetFilter {
+        id: u32,
+        filter: Option<TextureFilter>,
+    },
+    /// Creates (or recreates, cleared) an offscreen render target.
+    CreateTarget {
+        id: u32,
+        width: u32,
+        height: u32,
+    },
+    Unload(u32),
+}
+
+pub type TextureQueue = Arc<Mutex<Vec<TextureCmd>>>;
+
+/// GPU textures beyond this budget are evicted least-recently-used first and
+/// re-uploaded from the CPU copy when drawn again.
+const TEXTURE_BUDGET_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Largest render target side; wgpu's default texture dimension limit.
+pub const MAX_TARGET_SIZE: u32 = 8192;
+
+/// What the renderer holds for textures, for GetMemoryUsage.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextureMemory {
+    /// Textures and render targets on the GPU.
+    pub gpu_bytes: u64,
+    pub gpu_textures: usize,
+    /// CPU copies kept to re-upload evicted textures.
+    pub cpu_bytes: u64,
+    pub cpu_textures: usize,
+}
+
+/// Render targets are the surface format, four bytes a texel.
+fn target_bytes(size: (u32, u32)) -> u64 {
+    size.0 as u64 * size.1 as u64 * 4
+}
+
+/// What the renderer drew in a frame, for GetRenderStats.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Draws queued for the screen and render targets, text included.
+    pub items: usize,
+    /// Draw calls, each a run of draws with one texture and clip rect.
+    pub batches: usize,
+    pub vertices: usize,
+    /// Texture binds; a batch on the texture already bound needs none.
+    pub texture_switches: usize,
+    /// Draws skipped for lying outside their clip rect or the render area.
+    pub culled: usize,
+}
+
+struct GpuTexture {
+    bind_group: wgpu::BindGroup,
+    bytes: u64,
+    last_used: u64,
+}
+
+/// Builds a pipeline from WGSL read at run time, with compile and
+/// validation errors returned instead of raised on the device.
+pub fn try_pipeline(
+    device: &wgpu::Device,
+    source: &str,
+    build: impl FnOnce(&wgpu::ShaderModule) -> wgpu::RenderPipeline,
+) -> Result<wgpu::RenderPipeline, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    let pipeline = build(&shader);
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(e) => Err(e.to_string()),
+        None => Ok(pipeline),
+    }
 }
 
-pub type TextureUploadQueue = Arc<Mutex<Vec<TextureUploadCmd>>>;
+/// An offscreen texture Draw* calls can be redirected into. It has its own
+/// screen uniform so target passes and the screen pass don't share one.
+struct RenderTarget {
+    view: Arc<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
 
 pub struct Renderer {
     pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
     vertex_buffer: wgpu::Buffer,
     uniform_buffer: wgpu::Buffer,
     screen_bind_group: wgpu::BindGroup,
+    screen_bind_group_layout: wgpu::BindGroupLayout,
     texture_bind_group_layout: wgpu::BindGroupLayout,
     sampler: wgpu::Sampler,
-    textures: HashMap<u32, wgpu::BindGroup>,
+    nearest_sampler: wgpu::Sampler,
+    /// Filters set with `TextureCmd::SetFilter`.
+    filters: HashMap<u32, TextureFilter>,
+    white_bind_group: wgpu::BindGroup,
+    format: wgpu::TextureFormat,
+    textures: HashMap<u32, GpuTexture>,
+    targets: HashMap<u32, RenderTarget>,
+    /// What evicted textures are uploaded again from, Rgba8 premultiplied.
+    texture_store: HashMap<u32, TextureUploadCmd>,
+    texture_bytes: u64,
+    texture_budget: u64,
+    frame_index: u64,
     byte_offset: u64,
+    /// What was drawn since `begin_frame`.
+    pub stats: RenderStats,
 }
 
 impl Renderer {
@@ -164,31 +624,7 @@ impl Renderer {
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::layout()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                ..Default::default()
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        });
+        let pipeline = Self::pipeline(device, &pipeline_layout, &shader, format);
 
         let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
@@ -204,6 +640,13 @@ impl Renderer {
             min_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
+        let nearest_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
 
         let white_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
@@ -251,64 +694,198 @@ impl Renderer {
             ],
         });
 
-        let mut textures = HashMap::new();
-        textures.insert(0u32, white_bind_group);
-
         Self {
             pipeline,
+            pipeline_layout,
             vertex_buffer,
             uniform_buffer,
             screen_bind_group,
+            screen_bind_group_layout,
             texture_bind_group_layout,
             sampler,
-            textures,
+            nearest_sampler,
+            filters: HashMap::new(),
+            white_bind_group,
+            format,
+            textures: HashMap::new(),
+            targets: HashMap::new(),
+            texture_store: HashMap::new(),
+            texture_bytes: 0,
+            texture_budget: TEXTURE_BUDGET_BYTES,
+            frame_index: 0,
             byte_offset: 0,
+            stats: RenderStats::default(),
         }
     }
 
+    fn pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::layout()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// Swaps in a pipeline built from `source`, keeping the current one if
+    /// it doesn't compile.
+    pub fn reload_shader(&mut self, device: &wgpu::Device, source: &str) -> Result<(), String> {
+        self.pipeline = try_pipeline(device, source, |shader| {
+            Self::pipeline(device, &self.pipeline_layout, shader, self.format)
+        })?;
+        Ok(())
+    }
+
     pub fn begin_frame(&mut self) {
         self.byte_offset = 0;
+        self.frame_index += 1;
+        self.stats = RenderStats::default();
+    }
+
+    /// Applies `cmd`; an upload that failed comes back as its texture id
+    /// and why.
+    pub fn apply_texture_cmd(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cmd: TextureCmd,
+    ) -> Result<(), (u32, String)> {
+        match cmd {
+            TextureCmd::Upload(upload) => {
+                let id = upload.id;
+                return self
+                    .load_texture(device, queue, upload)
+                    .map_err(|e| (id, e));
+            }
+            TextureCmd::CreateTarget { id, width, height } => {
+                self.create_target(device, id, width, height)
+            }
+            TextureCmd::SetFilter { id, filter } => self.set_filter(id, filter),
+            TextureCmd::Unload(id) => self.unload_texture(id),
+        }
+        Ok(())
     }
 
     pub fn load_texture(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        id: u32,
-        rgba: &[u8],
-        width: u32,
-        height: u32,
-    ) {
+        mut upload: TextureUploadCmd,
+    ) -> Result<(), String> {
+        upload.validate()?;
+        if upload.format == PixelFormat::Rgba8 && !upload.premultiplied {
+            premultiply_alpha(upload.pixels.to_mut());
+            upload.premultiplied = true;
+        }
+        let max_size = device.limits().max_texture_dimension_2d;
+        if upload.width > max_size || upload.height > max_size {
+            eprintln!(
+                "texture {}: {}x{} is over this GPU's {} limit, scaling it down",
+                upload.id, upload.width, upload.height, max_size
+            );
+        }
+        self.evict(upload.id);
+        device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let gpu = self.upload(device, queue, &upload);
+        let invalid = pollster::block_on(device.pop_error_scope());
+        if let Some(e) = invalid.or(pollster::block_on(device.pop_error_scope())) {
+            return Err(e.to_string());
+        }
+        self.texture_bytes += gpu.bytes;
+        self.textures.insert(upload.id, gpu);
+        self.texture_store.insert(upload.id, upload);
+        Ok(())
+    }
+
+    /// Evicts the texture so it's sampled the new way when next drawn.
+    fn set_filter(&mut self, id: u32, filter: Option<TextureFilter>) {
+        let old = match filter {
+            Some(filter) => self.filters.insert(id, filter),
+            None => self.filters.remove(&id),
+        };
+        if old != filter {
+            self.evict(id);
+        }
+    }
+
+    pub fn unload_texture(&mut self, id: u32) {
+        self.evict(id);
+        self.filters.remove(&id);
+        self.texture_store.remove(&id);
+        self.targets.remove(&id);
+    }
+
+    /// Size and format of a loaded texture.
+    pub fn texture_info(&self, id: u32) -> Option<(u32, u32, PixelFormat)> {
+        let upload = self.texture_store.get(&id)?;
+        Some((upload.width, upload.height, upload.format))
+    }
+
+    pub fn memory(&self) -> TextureMemory {
+        let targets: u64 = self.targets.values().map(|t| target_bytes(t.size)).sum();
+        TextureMemory {
+            gpu_bytes: self.texture_bytes + targets,
+            gpu_textures: self.textures.len() + self.targets.len(),
+            cpu_bytes: self
+                .texture_store
+                .values()
+                .map(|u| u.pixels.len() as u64)
+                .sum(),
+            cpu_textures: self.texture_store.len(),
+        }
+    }
+
+    /// Size of a render target.
+    pub fn target_size(&self, id: u32) -> Option<(u32, u32)> {
+        self.targets.get(&id).map(|t| t.size)
+    }
+
+    fn create_target(&mut self, device: &wgpu::Device, id: u32, width: u32, height: u32) {
+        let size = (
+            width.clamp(1, MAX_TARGET_SIZE),
+            height.clamp(1, MAX_TARGET_SIZE),
+        );
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
+            label: Some("render target"),
             size: wgpu::Extent3d {
-                width: width,
-                height: height,
+                width: size.0,
+                height: size.1,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            // Same format as the surface, so the pipelines (and glyphon's)
+            // can render into it unchanged.
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
-
-        queue.write_texture(
-            texture.as_image_copy(),
-            rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * width),
-                rows_per_image: None,
-            },
-            wgpu::Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
-            },
-        );
-
         let view = texture.create_view(&Default::default());
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
@@ -324,141 +901,519 @@ impl Renderer {
                 },
             ],
         });
-
-        self.textures.insert(id, bind_group);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: std::mem::size_of::<ScreenUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.screen_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        self.targets.insert(
+            id,
+            RenderTarget {
+                view: Arc::new(view),
+                bind_group,
+                uniform_buffer,
+                screen_bind_group,
+                size,
+            },
+        );
     }
 
-    pub fn draw<'a>(
-        &'a mut self,
-        pass: &mut wgpu::RenderPass<'a>,
+    /// Renders one target pass, text included, into its texture. `slot`
+    /// picks the text renderer, which must differ between passes of a frame.
+    pub fn render_target(
+        &mut self,
+        device: &wgpu::Device,
         queue: &wgpu::Queue,
-        screen_size: (u32, u32),
-        cmds: &[DrawItem],
+        encoder: &mut wgpu::CommandEncoder,
+        text: &mut TextRenderer,
+        slot: usize,
+        target: &TargetPass,
     ) {
+        let Some(rt) = self.targets.get(&target.id) else {
+            return;
+        };
+        let view = rt.view.clone();
+        let size = rt.size;
         let uniform = ScreenUniform {
-            size: [screen_size.0 as f32, screen_size.1 as f32],
+            size: [size.0 as f32, size.1 as f32],
         };
-        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
-
-        pass.set_pipeline(&self.pipeline);
-        pass.set_bind_group(0, &self.screen_bind_group, &[]);
-        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-
-        let tid_of = |item: &DrawItem| match item {
-            DrawItem::Rect(c) => c.texture_id,
-            DrawItem::Quad(c) => c.texture_id,
-            DrawItem::Text(_) => 0u32,
+        queue.write_buffer(&rt.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        if let Err(e) = text.prepare(device, queue, slot, size, 1.0, &text_cmds(&target.items)) {
+            eprintln!("render target {}: {}", target.id, e);
+        }
+        text.render_effects(slot, encoder).ok();
+        // Targets hold premultiplied colour like any other texture.
+        let load = match target.clear {
+            Some([r, g, b, a]) => wgpu::LoadOp::Clear(wgpu::Color {
+                r: (r * a) as f64,
+                g: (g * a) as f64,
+                b: (b * a) as f64,
+                a: a as f64,
+            }),
+            None => wgpu::LoadOp::Load,
         };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render target"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        self.draw_items(&mut pass, queue, Some(target.id), size, 1.0, &target.items);
+        text.render(slot, &mut pass).ok();
+    }
 
-        let clip_of = |item: &DrawItem| match item {
-            DrawItem::Rect(c) => c.clip,
-            DrawItem::Quad(c) => c.clip,
-            DrawItem::Text(_) => None,
-        };
+    /// Makes every texture referenced by `cmds` resident, then trims the least
+    /// recently used ones not needed this frame until back under budget.
+    pub fn prepare_textures(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cmds: &[DrawItem],
+    ) {
+        for item in cmds {
+            let id = match item {
+                DrawItem::Rect(c) => c.texture_id,
+                DrawItem::Quad(c) => c.texture_id,
+                _ => continue,
+            };
+            if let Some(gpu) = self.textures.get_mut(&id) {
+                gpu.last_used = self.frame_index;
+            } else if let Some(upload) = self.texture_store.get(&id) {
+                let mut gpu = self.upload(device, queue, upload);
+                gpu.last_used = self.frame_index;
+                self.texture_bytes += gpu.bytes;
+                self.textures.insert(id, gpu);
+            }
+        }
 
-        // batch by texture_id
-        let vertex_size = std::mem::size_of::<Vertex>() as u64;
-        let mut i = 0;
-        while i < cmds.len() {
-            let tid = tid_of(&cmds[i]);
-            let start = i;
-            while i < cmds.len()
-                && tid_of(&cmds[i]) == tid
-                && clip_of(&cmds[i]) == clip_of(&cmds[start])
-            {
-                i += 1;
+        if self.texture_bytes <= self.texture_budget {
+            return;
+        }
+        let mut idle: Vec<(u64, u32)> = self
+            .textures
+            .iter()
+            .filter(|(_, t)| t.last_used < self.frame_index)
+            .map(|(id, t)| (t.last_used, *id))
+            .collect();
+        idle.sort_unstable();
+        let before = self.texture_bytes;
+        let mut evicted = 0;
+        for (_, id) in idle {
+            if self.texture_bytes <= self.texture_budget {
+                break;
             }
-            let mut vertices: Vec<Vertex> = Vec::new();
-            for item in &cmds[start..i] {
-                match item {
-                    DrawItem::Rect(cmd) => {
-                        let x2 = cmd.x + cmd.w;
-                        let y2 = cmd.y + cmd.h;
-                        let tl = Vertex {
-                            position: [cmd.x, cmd.y],
-                            uv: [cmd.uv[0], cmd.uv[1]],
-                            color: cmd.color,
-                        };
-                        let tr = Vertex {
-                            position: [x2, cmd.y],
-                            uv: [cmd.uv[2], cmd.uv[1]],
-                            color: cmd.color,
-                        };
-                        let bl = Vertex {
-                            position: [cmd.x, y2],
-                            uv: [cmd.uv[0], cmd.uv[3]],
-                            color: cmd.color,
-                        };
-                        let br = Vertex {
-                            position: [x2, y2],
-                            uv: [cmd.uv[2], cmd.uv[3]],
-                            color: cmd.color,
-                        };
-
-                        // triangle 1
-                        vertices.push(tl);
-                        vertices.push(tr);
-                        vertices.push(bl);
-                        // triangle 2
-                        vertices.push(tr);
-                        vertices.push(br);
-                        vertices.push(bl);
-                    }
-                    DrawItem::Quad(cmd) => {
-                        let [p1, p2, p3, p4] = cmd.positions;
-                        let [uv1, uv2, uv3, uv4] = cmd.uvs;
-                        let v = |p: [f32; 2], uv: [f32; 2]| Vertex {
-                            position: p,
-                            uv,
-                            color: cmd.color,
-                        };
-                        vertices.extend_from_slice(&[
-                            v(p1, uv1),
-                            v(p2, uv2),
-                            v(p3, uv3),
-                            v(p1, uv1),
-                            v(p3, uv3),
-                            v(p4, uv4),
-                        ]);
-                    }
-                    DrawItem::Text(_) => continue,
-                }
+            self.evict(id);
+            evicted += 1;
+        }
+        if evicted > 0 {
+            eprintln!(
+                "texture budget: evicted {} textures, {} -> {} bytes",
+                evicted, before, self.texture_bytes
+            );
+        }
+    }
+
+    fn evict(&mut self, id: u32) {
+        if let Some(gpu) = self.textures.remove(&id) {
+            self.texture_bytes -= gpu.bytes;
+        }
+    }
+
+    fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        upload: &TextureUploadCmd,
+    ) -> GpuTexture {
+        let TextureUploadCmd {
+            id,
+            ref pixels,
+            format,
+            width,
+            height,
+            ..
+        } = *upload;
+        let pixels: &[u8] = pixels;
+        let filter = self.filters.get(&id).copied();
+        let sampler = match filter.unwrap_or(TextureFilter::Linear) {
+            TextureFilter::Linear => &self.sampler,
+            TextureFilter::Nearest => &self.nearest_sampler,
+        };
+        let bc = device
+            .features()
+            .contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+        let max_size = device.limits().max_texture_dimension_2d;
+        let oversize = width > max_size || height > max_size;
+        let mut converted;
+        let (pixels, format, bytes_per_row) = match format {
+            PixelFormat::Bc3 if bc && !oversize => (
+                pixels,
+                wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+                width.div_ceil(4) * 16,
+            ),
+            PixelFormat::Bc3 => {
+                converted = texture_cache::decode_bc3(pixels, width, height);
+                (
+                    &converted[..],
+                    wgpu::TextureFormat::Rgba8UnormSrgb,
+                    4 * width,
+                )
             }
+            PixelFormat::Rgba8 => (pixels, wgpu::TextureFormat::Rgba8UnormSrgb, 4 * width),
+        };
+        let (pixels, width, height, bytes_per_row) =
+            match fit_texture(pixels, width, height, max_size) {
+                Some((shrunk, w, h)) => {
+                    converted = shrunk;
+                    (&converted[..], w, h, 4 * w)
+                }
+                None => (pixels, width, height, bytes_per_row),
+            };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let bytes = pixels.len() as u64;
+        let rows = match format {
+            wgpu::TextureFormat::Bc3RgbaUnormSrgb => height.div_ceil(4),
+            _ => height,
+        };
+        let (pixels, bytes_per_row) = pad_rows(pixels, bytes_per_row, rows);
+        queue.write_texture(
+            texture.as_image_copy(),
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let view = texture.create_view(&Default::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+
+        GpuTexture {
+            bind_group,
+            bytes,
+            last_used: self.frame_index,
+        }
+    }
 
+    /// Draws `cmds` to the screen. Their coordinates are in UI units,
+    /// `zoom` physical pixels each.
+    pub fn draw<'a>(
+        &'a mut self,
+        pass: &mut wgpu::RenderPass<'a>,
+        queue: &wgpu::Queue,
+        screen_size: (u32, u32),
+        zoom: f32,
+        cmds: &[DrawItem],
+    ) {
+        let uniform = ScreenUniform {
+            size: [screen_size.0 as f32 / zoom, screen_size.1 as f32 / zoom],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        self.draw_items(pass, queue, None, screen_size, zoom, cmds);
+    }
+
+    /// Draws `cmds` into the screen (`target` None) or a render target.
+    fn draw_items<'a>(
+        &'a mut self,
+        pass: &mut wgpu::RenderPass<'a>,
+        queue: &wgpu::Queue,
+        target: Option<u32>,
+        screen_size: (u32, u32),
+        zoom: f32,
+        cmds: &[DrawItem],
+    ) {
+        let screen_bind_group = match target.and_then(|id| self.targets.get(&id)) {
+            Some(rt) => &rt.screen_bind_group,
+            None => &self.screen_bind_group,
+        };
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, screen_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        let mut bound = None;
+        let vertex_size = std::mem::size_of::<Vertex>() as u64;
+        for batch in batches(cmds, screen_size, zoom, &mut self.stats) {
+            let tid = batch.texture_id;
+            // A target can't sample itself while it's being drawn into.
             let bg = self
                 .textures
                 .get(&tid)
-                .unwrap_or_else(|| self.textures.get(&0).unwrap());
-            match clip_of(&cmds[start]) {
-                Some([cx, cy, cw, ch]) => {
-                    pass.set_scissor_rect(cx, cy, cw.max(1), ch.max(1));
-                }
-                None => {
-                    pass.set_scissor_rect(0, 0, screen_size.0, screen_size.1);
-                }
-            }
-            pass.set_bind_group(1, bg, &[]);
-            if vertices.is_empty() {
-                continue;
-            }
+                .map(|t| &t.bind_group)
+                .or_else(|| {
+                    self.targets
+                        .get(&tid)
+                        .filter(|_| target != Some(tid))
+                        .map(|t| &t.bind_group)
+                })
+                .unwrap_or(&self.white_bind_group);
             let buffer_cap = self.vertex_buffer.size();
-            if self.byte_offset + vertices.len() as u64 * vertex_size > buffer_cap {
+            let bytes = batch.vertices.len() as u64 * vertex_size;
+            if self.byte_offset + bytes > buffer_cap {
                 break;
             }
+            let [sx, sy, sw, sh] = batch.scissor;
+            pass.set_scissor_rect(sx, sy, sw, sh);
+            if bound != Some(tid) {
+                pass.set_bind_group(1, bg, &[]);
+                self.stats.texture_switches += 1;
+                bound = Some(tid);
+            }
             queue.write_buffer(
                 &self.vertex_buffer,
                 self.byte_offset,
-                bytemuck::cast_slice(&vertices),
+                bytemuck::cast_slice(&batch.vertices),
             );
             let vert_start = (self.byte_offset / vertex_size) as u32;
-            let vert_end = vert_start + vertices.len() as u32;
+            let vert_end = vert_start + batch.vertices.len() as u32;
             pass.draw(vert_start..vert_end, 0..1);
-            self.byte_offset += vertices.len() as u64 * vertex_size;
+            self.byte_offset += bytes;
+            self.stats.batches += 1;
+            self.stats.vertices += batch.vertices.len();
         }
     }
 }
 
-#[derive(Clone)]
+/// One draw call: vertices sharing a texture and scissor rect.
+struct Batch {
+    texture_id: u32,
+    scissor: [u32; 4],
+    vertices: Vec<Vertex>,
+}
+
+/// Groups runs of `cmds` with the same texture and clip rect into batches,
+/// leaving out the draws that fall outside them or the screen.
+fn batches(
+    cmds: &[DrawItem],
+    screen_size: (u32, u32),
+    zoom: f32,
+    stats: &mut RenderStats,
+) -> Vec<Batch> {
+    let tid_of = |item: &DrawItem| match item {
+        DrawItem::Rect(c) => c.texture_id,
+        DrawItem::Quad(c) => c.texture_id,
+        _ => 0u32,
+    };
+
+    let clip_of = |item: &DrawItem| match item {
+        DrawItem::Rect(c) => c.clip,
+        DrawItem::Quad(c) => c.clip,
+        DrawItem::Mesh(c) => c.clip,
+        _ => None,
+    };
+
+    stats.items += cmds.len();
+    let mut batches = Vec::new();
+    let mut i = 0;
+    while i < cmds.len() {
+        let tid = tid_of(&cmds[i]);
+        let start = i;
+        while i < cmds.len()
+            && tid_of(&cmds[i]) == tid
+            && clip_of(&cmds[i]) == clip_of(&cmds[start])
+        {
+            i += 1;
+        }
+        let clip = clip_of(&cmds[start]).map(|c| zoom_rect(c, zoom));
+        let Some(scissor) = scissor(clip, screen_size) else {
+            stats.culled += i - start;
+            continue;
+        };
+        let area = scissor.map(|v| v as f32 / zoom);
+        let mut vertices: Vec<Vertex> = Vec::new();
+        for item in &cmds[start..i] {
+            if let Some(extent) = extent(item)
+                && !overlaps(extent, area)
+            {
+                stats.culled += 1;
+                continue;
+            }
+            match item {
+                DrawItem::Rect(cmd) => {
+                    let x2 = cmd.x + cmd.w;
+                    let y2 = cmd.y + cmd.h;
+                    let [c_tl, c_tr, c_br, c_bl] = cmd.corners.unwrap_or([cmd.color; 4]);
+                    let tl = Vertex {
+                        position: [cmd.x, cmd.y],
+                        uv: [cmd.uv[0], cmd.uv[1]],
+                        color: c_tl,
+                    };
+                    let tr = Vertex {
+                        position: [x2, cmd.y],
+                        uv: [cmd.uv[2], cmd.uv[1]],
+                        color: c_tr,
+                    };
+                    let bl = Vertex {
+                        position: [cmd.x, y2],
+                        uv: [cmd.uv[0], cmd.uv[3]],
+                        color: c_bl,
+                    };
+                    let br = Vertex {
+                        position: [x2, y2],
+                        uv: [cmd.uv[2], cmd.uv[3]],
+                        color: c_br,
+                    };
+
+                    // triangle 1
+                    vertices.push(tl);
+                    vertices.push(tr);
+                    vertices.push(bl);
+                    // triangle 2
+                    vertices.push(tr);
+                    vertices.push(br);
+                    vertices.push(bl);
+                }
+                DrawItem::Quad(cmd) => {
+                    let [p1, p2, p3, p4] = cmd.positions;
+                    let [uv1, uv2, uv3, uv4] = cmd.uvs;
+                    let [c1, c2, c3, c4] = cmd.corners.unwrap_or([cmd.color; 4]);
+                    let v = |p: [f32; 2], uv: [f32; 2], color: [f32; 4]| Vertex {
+                        position: p,
+                        uv,
+                        color,
+                    };
+                    vertices.extend_from_slice(&[
+                        v(p1, uv1, c1),
+                        v(p2, uv2, c2),
+                        v(p3, uv3, c3),
+                        v(p1, uv1, c1),
+                        v(p3, uv3, c3),
+                        v(p4, uv4, c4),
+                    ]);
+                }
+                DrawItem::Mesh(cmd) => vertices.extend_from_slice(&cmd.vertices),
+                _ => continue,
+            }
+        }
+        if !vertices.is_empty() {
+            batches.push(Batch {
+                texture_id: tid,
+                scissor,
+                vertices,
+            });
+        }
+    }
+    batches
+}
+
+/// The box (x, y, width, height) a draw covers.
+fn extent(item: &DrawItem) -> Option<[f32; 4]> {
+    let points: Vec<[f32; 2]> = match item {
+        DrawItem::Rect(c) => vec![[c.x, c.y], [c.x + c.w, c.y + c.h]],
+        DrawItem::Quad(c) => c.positions.to_vec(),
+        DrawItem::Mesh(c) => c.vertices.iter().map(|v| v.position).collect(),
+        _ => return None,
+    };
+    let (mut lo, mut hi) = ([f32::MAX; 2], [f32::MIN; 2]);
+    for p in points {
+        for k in 0..2 {
+            lo[k] = lo[k].min(p[k]);
+            hi[k] = hi[k].max(p[k]);
+        }
+    }
+    (lo[0] <= hi[0]).then(|| [lo[0], lo[1], hi[0] - lo[0], hi[1] - lo[1]])
+}
+
+/// Whether a draw would show nothing or carries non-finite numbers that
+/// would end up in the vertex buffer.
+pub fn is_degenerate(item: &DrawItem) -> bool {
+    let finite = |v: &[f32]| v.iter().all(|n| n.is_finite());
+    match item {
+        DrawItem::Rect(c) => {
+            !finite(&[c.x, c.y, c.w, c.h]) || !finite(&c.uv) || c.w == 0.0 || c.h == 0.0
+        }
+        DrawItem::Quad(c) => {
+            let [a, b, cc, d] = c.positions;
+            // Twice the area of each triangle the quad is drawn as; a
+            // bow-tie's halves cancel out in the whole quad's area.
+            let area = |p: [f32; 2], q: [f32; 2], r: [f32; 2]| {
+                (q[0] - p[0]) * (r[1] - p[1]) - (r[0] - p[0]) * (q[1] - p[1])
+            };
+            !finite(c.positions.as_flattened())
+                || !finite(c.uvs.as_flattened())
+                || (area(a, b, cc) == 0.0 && area(a, cc, d) == 0.0)
+        }
+        DrawItem::Mesh(c) => {
+            c.vertices.is_empty()
+                || !c
+                    .vertices
+                    .iter()
+                    .all(|v| finite(&v.position) && finite(&v.uv))
+        }
+        DrawItem::Text(c) => !finite(&[c.x, c.y, c.size]) || c.size <= 0.0 || c.text.is_empty(),
+        _ => false,
+    }
+}
+
+/// A rect in UI units as the physical pixels it covers at `zoom`.
+fn zoom_rect([x, y, w, h]: [u32; 4], zoom: f32) -> [u32; 4] {
+    let x0 = (x as f32 * zoom).floor();
+    let y0 = (y as f32 * zoom).floor();
+    let x1 = ((x + w) as f32 * zoom).ceil();
+    let y1 = ((y + h) as f32 * zoom).ceil();
+    [x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32]
+}
+
+/// A clip rect limited to the render area, which wgpu requires of scissor
+/// rects; None when nothing of it is visible.
+fn scissor(clip: Option<[u32; 4]>, size: (u32, u32)) -> Option<[u32; 4]> {
+    let [cx, cy, cw, ch] = clip.unwrap_or([0, 0, size.0, size.1]);
+    let (x, y) = (cx.min(size.0), cy.min(size.1));
+    let w = cw.max(1).min(size.0 - x);
+    let h = ch.max(1).min(size.1 - y);
+    (w > 0 && h > 0).then_some([x, y, w, h])
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TextCmd {
     pub x: f32,
     pub y: f32,
@@ -468,17 +1423,59 @@ pub struct TextCmd {
     pub align: String,
     pub font: String,
     pub clip: Option<[u32; 4]>,
+    /// Round the text origin to whole physical pixels so glyphs don't land
+    /// on fractional positions after viewport offsets.
+    pub snap: bool,
+    /// Drawn behind the text to lift it off busy backgrounds.
+    #[serde(default)]
+    pub effect: Option<TextEffect>,
 }
 
-pub type TextQueue = Arc<Mutex<Vec<TextCmd>>>;
+/// Copies of a text in one colour, escapes ignored, drawn under it from
+/// the same shaped glyphs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TextEffect {
+    /// One copy moved by `offset` UI units.
+    Shadow { offset: [f32; 2], color: [f32; 4] },
+    /// Copies moved `width` UI units in eight directions.
+    Outline { width: f32, color: [f32; 4] },
+}
 
+/// Text for the screen (slot 0) and each render target pass of a frame
+/// (slots 1..). Slots share the glyph atlas but each keeps its own prepared
+/// vertices.
 pub struct TextRenderer {
     font_system: glyphon::FontSystem,
     swash_cache: glyphon::SwashCache,
     atlas: glyphon::TextAtlas,
-    renderer: glyphon::TextRenderer,
+    renderers: Vec<glyphon::TextRenderer>,
+    /// Width, height and baseline of texts shaped in earlier frames, so
+    /// off-screen ones can be skipped before shaping.
+    measured: HashMap<(String, u32, String), [f32; 3]>,
+    /// Effect texts shaped without their colours, kept across frames.
+    shaped: HashMap<ShapedKey, glyphon::Buffer>,
+    effects: TextEffects,
+    /// Texts skipped by the last `prepare`.
+    pub culled: usize,
+    /// What the colour escapes stand for; the host's, once it has a window.
+    pub palette: SharedPalette,
+    /// `[text] shaping`.
+    pub shaping: glyphon::Shaping,
+    /// Sizes and line spacing by font.
+    pub metrics: SharedFontMetrics,
 }
 
+/// Measured sizes kept before the cache starts over.
+const MEASURED_CAPACITY: usize = 8192;
+
+/// Text, font, size and line spacing, layout size and shaping of a
+/// shaped effect text.
+type ShapedKey = (String, String, [u32; 2], (u32, u32), glyphon::Shaping);
+
+/// Shaped effect texts kept before the cache starts over; fewer than the
+/// measured sizes since each holds its glyphs.
+const SHAPED_CAPACITY: usize = 1024;
+
 impl TextRenderer {
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
         let font_system = glyphon::FontSystem::new();
@@ -491,95 +1488,204 @@ impl TextRenderer {
             font_system,
             swash_cache,
             atlas,
-            renderer,
+            renderers: vec![renderer],
+            measured: HashMap::new(),
+            shaped: HashMap::new(),
+            effects: TextEffects::new(device, format),
+            culled: 0,
+            palette: SharedPalette::default(),
+            shaping: glyphon::Shaping::Basic,
+            metrics: SharedFontMetrics::default(),
+        }
+    }
+
+    /// Lays out `cmd`; without `colored` the colour escapes are dropped so
+    /// the whole text takes the area's colour.
+    fn shape(&mut self, cmd: &TextCmd, screen_size: (u32, u32), colored: bool) -> glyphon::Buffer {
+        let metrics = self.metrics.get(&cmd.font);
+        let mut buffer = glyphon::Buffer::new(
+            &mut self.font_system,
+            glyphon::Metrics::new(metrics.size(cmd.size), metrics.line_spacing(cmd.size)),
+        );
+        buffer.set_size(
+            &mut self.font_system,
+            screen_size.0 as f32,
+            screen_size.1 as f32,
+        );
+
+        let attrs = match cmd.font.as_str() {
+            "FIXED" => glyphon::Attrs::new().family(glyphon::Family::Monospace),
+            _ => glyphon::Attrs::new().family(glyphon::Family::SansSerif),
+        };
+
+        let palette = self.palette.lock().unwrap();
+        let spans = escapes::color_spans(&cmd.text, cmd.color, &palette);
+        let rich: Vec<(&str, glyphon::Attrs)> = spans
+            .iter()
+            .map(|(s, c)| {
+                let gc = glyphon::Color::rgba(
+                    (c[0] * 255.0) as u8,
+                    (c[1] * 255.0) as u8,
+                    (c[2] * 255.0) as u8,
+                    (c[3] * 255.0) as u8,
+                );
+                (*s, if colored { attrs.color(gc) } else { attrs })
+            })
+            .collect();
+
+        buffer.set_rich_text(&mut self.font_system, rich, self.shaping);
+        // Placement is up to the alignment, so right-to-left lines mustn't
+        // be pushed against the far edge of the buffer.
+        for line in &mut buffer.lines {
+            line.set_align(Some(glyphon::cosmic_text::Align::Left));
         }
+        buffer.shape_until_scroll(&mut self.font_system);
+        buffer
     }
 
+    /// Lays out `cmds` for `render`. Their coordinates are in UI units,
+    /// `zoom` physical pixels each, and snapped texts start on whole
+    /// physical pixels.
     pub fn prepare(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        slot: usize,
         screen_size: (u32, u32),
+        zoom: f32,
         cmds: &[TextCmd],
     ) -> Result<(), glyphon::PrepareError> {
-        let mut text_areas: Vec<glyphon::TextArea> = Vec::new();
-        let mut buffers: Vec<glyphon::Buffer> = Vec::new();
+        while self.renderers.len() <= slot {
+            self.renderers.push(glyphon::TextRenderer::new(
+                &mut self.atlas,
+                device,
+                wgpu::MultisampleState::default(),
+                None,
+            ));
+        }
+        if self.measured.len() > MEASURED_CAPACITY {
+            self.measured.clear();
+        }
+        if self.shaped.len() > SHAPED_CAPACITY {
+            self.shaped.clear();
+        }
+        self.culled = 0;
+        // Layout happens in UI units; only the final placement is zoomed.
+        let scale = zoom;
+        let logical = (
+            (screen_size.0 as f32 / zoom) as u32,
+            (screen_size.1 as f32 / zoom) as u32,
+        );
+        let mut placed = Vec::new();
         for cmd in cmds {
-            let mut buffer = glyphon::Buffer::new(
-                &mut self.font_system,
-                glyphon::Metrics::new(cmd.size, cmd.size * 1.2),
-            );
-            buffer.set_size(
-                &mut self.font_system,
-                screen_size.0 as f32,
-                screen_size.1 as f32,
-            );
-
-            let attrs = match cmd.font.as_str() {
-                "FIXED" => glyphon::Attrs::new().family(glyphon::Family::Monospace),
-                _ => glyphon::Attrs::new().family(glyphon::Family::SansSerif),
+            let area = match cmd.clip {
+                Some([cx, cy, cw, ch]) => [cx as f32, cy as f32, cw as f32, ch as f32],
+                None => [0.0, 0.0, logical.0 as f32, logical.1 as f32],
             };
-
-            let spans = parse_color_spans(&cmd.text, cmd.color);
-            let rich: Vec<(&str, glyphon::Attrs)> = spans
-                .iter()
-                .map(|(s, c)| {
-                    let gc = glyphon::Color::rgba(
-                        (c[0] * 255.0) as u8,
-                        (c[1] * 255.0) as u8,
-                        (c[2] * 255.0) as u8,
-                        (c[3] * 255.0) as u8,
-                    );
-                    (*s, attrs.color(gc))
-                })
-                .collect();
-
-            buffer.set_rich_text(&mut self.font_system, rich, glyphon::Shaping::Basic);
-            buffer.shape_until_scroll(&mut self.font_system);
-            buffers.push(buffer);
-        }
-
-        for (i, cmd) in cmds.iter().enumerate() {
-            let cmd_color = glyphon::Color::rgba(
-                (cmd.color[0] * 255.0) as u8,
-                (cmd.color[1] * 255.0) as u8,
-                (cmd.color[2] * 255.0) as u8,
-                (cmd.color[3] * 255.0) as u8,
-            );
-            let line_w = buffers[i]
+            let origin = |[w, h, baseline]: [f32; 3]| {
+                let (mut left, mut top) =
+                    text_origin(&cmd.align, [cmd.x, cmd.y], [w, h], baseline, area);
+                if cmd.snap {
+                    left = snap_to_pixel(left, scale);
+                    top = snap_to_pixel(top, scale);
+                }
+                (left, top)
+            };
+            let key = (cmd.text.clone(), cmd.size.to_bits(), cmd.font.clone());
+            if let Some(&size) = self.measured.get(&key) {
+                let (left, top) = origin(size);
+                if !overlaps([left, top, size[0], size[1]], area) {
+                    self.culled += 1;
+                    continue;
+                }
+            }
+            let buffer = self.shape(cmd, logical, true);
+            let line_w = buffer
                 .layout_runs()
                 .map(|r| r.line_w)
                 .fold(0.0f32, f32::max);
-            let left = match cmd.align.as_str() {
-                "RIGHT_X" => cmd.x - line_w,
-                "CENTER_X" => cmd.x - line_w / 2.0,
-                _ => cmd.x,
-            };
-            let bounds = match cmd.clip {
-                Some([cx, cy, cw, ch]) => glyphon::TextBounds {
-                    left: cx as i32,
-                    top: cy as i32,
-                    right: (cx + cw) as i32,
-                    bottom: (cy + ch) as i32,
-                },
-                None => glyphon::TextBounds {
-                    left: 0,
-                    top: 0,
-                    right: screen_size.0 as i32,
-                    bottom: screen_size.1 as i32,
-                },
+            let lines = buffer.layout_runs().count().max(1);
+            let baseline = buffer
+                .layout_runs()
+                .next()
+                .map(|r| r.line_y)
+                .unwrap_or(cmd.size);
+            let spacing = self.metrics.get(&cmd.font).line_spacing(cmd.size);
+            let size = [line_w, lines as f32 * spacing, baseline];
+            self.measured.insert(key, size);
+            let (left, top) = origin(size);
+            let bounds = glyphon::TextBounds {
+                left: (area[0] * zoom) as i32,
+                top: (area[1] * zoom) as i32,
+                right: ((area[0] + area[2]) * zoom).ceil() as i32,
+                bottom: ((area[1] + area[3]) * zoom).ceil() as i32,
             };
-            text_areas.push(glyphon::TextArea {
-                buffer: &buffers[i],
-                left: left,
-                top: cmd.y,
-                scale: 1.0,
-                bounds,
-                default_color: cmd_color,
-            })
+            let effect = cmd.effect.map(|effect| {
+                let metrics = self.metrics.get(&cmd.font);
+                let key = (
+                    cmd.text.clone(),
+                    cmd.font.clone(),
+                    [
+                        metrics.size(cmd.size).to_bits(),
+                        metrics.line_spacing(cmd.size).to_bits(),
+                    ],
+                    logical,
+                    self.shaping,
+                );
+                if !self.shaped.contains_key(&key) {
+                    let plain = self.shape(cmd, logical, false);
+                    self.shaped.insert(key.clone(), plain);
+                }
+                (key, effect, [line_w, size[1]])
+            });
+            placed.push((cmd, buffer, effect, left * zoom, top * zoom, bounds));
         }
 
-        self.renderer.prepare(
+        let rgba = |c: [f32; 4]| {
+            glyphon::Color::rgba(
+                (c[0] * 255.0) as u8,
+                (c[1] * 255.0) as u8,
+                (c[2] * 255.0) as u8,
+                (c[3] * 255.0) as u8,
+            )
+        };
+        let area = |buffer, left, top, bounds, color| glyphon::TextArea {
+            buffer,
+            left,
+            top,
+            scale,
+            bounds,
+            default_color: rgba(color),
+        };
+        let effect_texts = placed
+            .iter()
+            .filter_map(|(cmd, _, effect, left, top, bounds)| {
+                let (key, effect, [w, h]) = effect.as_ref()?;
+                // Copies fade with the text.
+                let mut color = effect.color();
+                color[3] *= cmd.color[3];
+                Some(EffectText {
+                    area: area(&self.shaped[key], *left, *top, *bounds, color),
+                    effect: *effect,
+                    rect: [*left, *top, w * zoom, h * zoom],
+                })
+            })
+            .collect();
+        self.effects.prepare(
+            device,
+            queue,
+            slot,
+            screen_size,
+            zoom,
+            &mut self.font_system,
+            &mut self.swash_cache,
+            effect_texts,
+        )?;
+        let text_areas = placed.iter().map(|(cmd, buffer, _, left, top, bounds)| {
+            area(buffer, *left, *top, *bounds, cmd.color)
+        });
+
+        self.renderers[slot].prepare(
             device,
             queue,
             &mut self.font_system,
@@ -594,75 +1700,669 @@ impl TextRenderer {
         Ok(())
     }
 
+    /// Draws the shadows and outlines `prepare` found for `slot` into their
+    /// layer, before the pass that `render`s them.
+    pub fn render_effects(
+        &self,
+        slot: usize,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), glyphon::RenderError> {
+        self.effects.render_layer(slot, encoder)
+    }
+
+    /// Draws the texts `prepare` laid out for `slot`, over their effects.
     pub fn render<'pass>(
         &'pass self,
+        slot: usize,
         pass: &mut wgpu::RenderPass<'pass>,
     ) -> Result<(), glyphon::RenderError> {
-        self.renderer.render(&self.atlas, pass)?;
+        self.effects.draw(slot, pass);
+        if let Some(renderer) = self.renderers.get(slot) {
+            renderer.render(&self.atlas, pass)?;
+        }
         Ok(())
     }
 }
 
-fn parse_color_spans<'a>(text: &'a str, default_color: [f32; 4]) -> Vec<(&'a str, [f32; 4])> {
-    let alpha = default_color[3];
-    let mut spans: Vec<(&'a str, [f32; 4])> = Vec::new();
-    let mut color = default_color;
-    let mut start = 0;
+/// Whether boxes `a` and `b` (x, y, width, height) share any area.
+fn overlaps(a: [f32; 4], b: [f32; 4]) -> bool {
+    a[0] < b[0] + b[2] && b[0] < a[0] + a[2] && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
+}
 
-    let bytes = text.as_bytes();
-    let mut i = 0;
-    while i < bytes.len() {
-        if bytes[i] != b'^' {
-            i += 1;
-            continue;
+/// Resolves a SimpleGraphic align string to the top-left corner of a text
+/// block of `size` (width, height). `pos` already includes the viewport
+/// offset; `area` is the viewport (or screen) the call was made in.
+///
+/// Horizontal modes follow SimpleGraphic: `LEFT`, `CENTER`/`RIGHT` (relative
+/// to the viewport edges) and `CENTER_X`/`RIGHT_X` (relative to `x`). Forks
+/// may append a vertical mode separated by a space or `|`: `TOP` (default),
+/// `CENTER_Y`, `BOTTOM_Y` or `BASELINE`.
+fn text_origin(
+    align: &str,
+    pos: [f32; 2],
+    size: [f32; 2],
+    baseline: f32,
+    area: [f32; 4],
+) -> (f32, f32) {
+    let [x, y] = pos;
+    let [w, h] = size;
+    let [ax, _, aw, _] = area;
+    let (mut left, mut top) = (x, y);
+    for token in align.split([' ', '|']) {
+        match token {
+            "CENTER" => left = ax + (aw - w) / 2.0 + (x - ax),
+            "RIGHT" => left = ax + aw - w - (x - ax),
+            "CENTER_X" => left = x - w / 2.0,
+            "RIGHT_X" => left = x - w,
+            "CENTER_Y" => top = y - h / 2.0,
+            "BOTTOM_Y" => top = y - h,
+            "BASELINE" => top = y - baseline,
+            _ => {}
         }
+    }
+    (left, top)
+}
+
+/// Rounds a coordinate to the nearest physical pixel at `scale` physical
+/// pixels per unit.
+fn snap_to_pixel(v: f32, scale: f32) -> f32 {
+    (v * scale).round() / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_origin_matches_simplegraphic_modes() {
+        let area = [100.0, 50.0, 400.0, 300.0];
+        let size = [40.0, 20.0];
+        let at = |align| text_origin(align, [110.0, 60.0], size, 16.0, area);
+        assert_eq!(at("LEFT"), (110.0, 60.0));
+        assert_eq!(at("CENTER_X"), (90.0, 60.0));
+        assert_eq!(at("RIGHT_X"), (70.0, 60.0));
+        // viewport-relative: 10px in from the viewport's centre / right edge
+        assert_eq!(at("CENTER"), (290.0, 60.0));
+        assert_eq!(at("RIGHT"), (450.0, 60.0));
+        assert_eq!(at("CENTER_X CENTER_Y"), (90.0, 50.0));
+        assert_eq!(at("RIGHT_X|BASELINE"), (70.0, 44.0));
+    }
+
+    #[test]
+    fn text_outside_its_area_is_culled() {
+        let screen = [0.0, 0.0, 800.0, 600.0];
+        assert!(overlaps([790.0, 590.0, 40.0, 20.0], screen));
+        assert!(!overlaps([800.0, 10.0, 40.0, 20.0], screen));
+        assert!(!overlaps([-40.0, 10.0, 40.0, 20.0], screen));
+        assert!(!overlaps(
+            [10.0, 10.0, 40.0, 20.0],
+            [100.0, 100.0, 50.0, 50.0]
+        ));
+    }
+
+    #[test]
+    fn draws_batch_by_texture_and_clip() {
+        let rect = |texture_id, x, clip| {
+            DrawItem::Rect(DrawCmd {
+                x,
+                y: 0.0,
+                w: 10.0,
+                h: 10.0,
+                color: [1.0; 4],
+                texture_id,
+                uv: [0.0, 0.0, 1.0, 1.0],
+                clip,
+                corners: None,
+            })
+        };
+        let cmds = [
+            rect(1, 0.0, None),
+            rect(1, 20.0, None),
+            // Off screen, so its batch is left with one rect.
+            rect(2, 900.0, None),
+            rect(2, 40.0, None),
+            // Outside its own clip rect, and a clip rect off the screen.
+            rect(2, 60.0, Some([0, 0, 50, 50])),
+            rect(1, 0.0, Some([1000, 0, 50, 50])),
+        ];
+        let mut stats = RenderStats::default();
+        let batches = batches(&cmds, (800, 600), 1.0, &mut stats);
+        let shape: Vec<_> = batches
+            .iter()
+            .map(|b| (b.texture_id, b.scissor, b.vertices.len()))
+            .collect();
+        assert_eq!(shape, [(1, [0, 0, 800, 600], 12), (2, [0, 0, 800, 600], 6)]);
+        assert_eq!((stats.items, stats.culled), (6, 3));
+    }
+
+    #[test]
+    fn draw_extents_cover_rects_quads_and_meshes() {
+        let rect = DrawItem::Rect(DrawCmd {
+            x: 900.0,
+            y: 10.0,
+            w: -200.0,
+            h: 20.0,
+            color: [1.0; 4],
+            texture_id: 0,
+            uv: [0.0, 0.0, 1.0, 1.0],
+            clip: None,
+            corners: None,
+        });
+        assert_eq!(extent(&rect), Some([700.0, 10.0, 200.0, 20.0]));
+        let quad = DrawItem::Quad(DrawQuadCmd {
+            texture_id: 0,
+            color: [1.0; 4],
+            corners: None,
+            clip: None,
+            positions: [[-50.0, -5.0], [-10.0, -20.0], [-15.0, -1.0], [-60.0, -8.0]],
+            uvs: [[0.0; 2]; 4],
+        });
+        assert_eq!(extent(&quad), Some([-60.0, -20.0, 50.0, 19.0]));
+        assert!(!overlaps(extent(&quad).unwrap(), [0.0, 0.0, 800.0, 600.0]));
+        let empty = DrawItem::Mesh(MeshCmd {
+            vertices: Vec::new(),
+            clip: None,
+        });
+        assert_eq!(extent(&empty), None);
+    }
+
+    #[test]
+    fn degenerate_draws_are_recognized() {
+        let rect = |x: f32, w: f32| {
+            DrawItem::Rect(DrawCmd {
+                x,
+                y: 0.0,
+                w,
+                h: 10.0,
+                color: [1.0; 4],
+                texture_id: 0,
+                uv: [0.0, 0.0, 1.0, 1.0],
+                clip: None,
+                corners: None,
+            })
+        };
+        assert!(!is_degenerate(&rect(5.0, -10.0)));
+        assert!(is_degenerate(&rect(5.0, 0.0)));
+        assert!(is_degenerate(&rect(f32::NAN, 10.0)));
+        assert!(is_degenerate(&rect(5.0, f32::INFINITY)));
+        let quad = |positions| {
+            DrawItem::Quad(DrawQuadCmd {
+                texture_id: 0,
+                color: [1.0; 4],
+                corners: None,
+                clip: None,
+                positions,
+                uvs: [[0.0; 2]; 4],
+            })
+        };
+        assert!(!is_degenerate(&quad([
+            [0.0, 0.0],
+            [4.0, 0.0],
+            [4.0, 4.0],
+            [0.0, 4.0]
+        ])));
+        assert!(is_degenerate(&quad([
+            [0.0, 0.0],
+            [2.0, 2.0],
+            [4.0, 4.0],
+            [1.0, 1.0]
+        ])));
+        // A bow-tie has no area overall but two visible triangles.
+        assert!(!is_degenerate(&quad([
+            [0.0, 0.0],
+            [4.0, 4.0],
+            [4.0, 0.0],
+            [0.0, 4.0]
+        ])));
+    }
+
+    #[test]
+    fn texture_filters_parse_from_flag_names() {
+        assert_eq!(
+            TextureFilter::parse("nearest"),
+            Some(TextureFilter::Nearest)
+        );
+        assert_eq!(TextureFilter::parse("LINEAR"), Some(TextureFilter::Linear));
+        assert_eq!(TextureFilter::parse("BICUBIC"), None);
+    }
+
+    #[test]
+    fn oversize_textures_shrink_to_fit_keeping_their_aspect() {
+        assert!(fit_texture(&[0; 16], 2, 2, 2).is_none());
+        let pixels: Vec<u8> = (0..8 * 2).flat_map(|_| [200, 100, 0, 255]).collect();
+        let (shrunk, w, h) = fit_texture(&pixels, 8, 2, 4).unwrap();
+        assert_eq!((w, h), (4, 1));
+        assert_eq!(shrunk.len(), 4 * 4);
+        assert_eq!(&shrunk[..4], &[200, 100, 0, 255]);
+        // A sliver keeps at least one texel.
+        assert_eq!(fit_texture(&[0; 4 * 100], 100, 1, 10).unwrap().2, 1);
+    }
+
+    #[test]
+    fn uploads_are_validated_and_rows_padded_to_the_copy_alignment() {
+        let mut upload = TextureUploadCmd {
+            id: 1,
+            pixels: vec![7; 3 * 2 * 4].into(),
+            format: PixelFormat::Rgba8,
+            width: 3,
+            height: 2,
+            premultiplied: false,
+        };
+        assert!(upload.validate().is_ok());
+        let (padded, row) = pad_rows(&upload.pixels, 12, 2);
+        assert_eq!(row, 256);
+        assert_eq!(padded.len(), 512);
+        assert_eq!(
+            (&padded[..12], padded[12], padded[256]),
+            (&[7; 12][..], 0, 7)
+        );
+        // Aligned or single rows go as they are.
+        assert!(matches!(pad_rows(&[0; 512], 256, 2).0, Cow::Borrowed(_)));
+        assert!(matches!(pad_rows(&[0; 12], 12, 1).0, Cow::Borrowed(_)));
 
-        if i > start {
-            spans.push((&text[start..i], color));
+        upload.pixels.to_mut().pop();
+        assert!(upload.validate().unwrap_err().contains("expected 24"));
+        upload.width = 0;
+        assert!(upload.validate().is_err());
+    }
+
+    #[test]
+    fn premultiplied_edges_blend_without_dark_halos() {
+        // A scaled sprite's edge: an opaque red texel next to a clear one,
+        // sampled halfway between them and drawn over white.
+        let mut texels = [255, 0, 0, 255, 0, 0, 0, 0];
+        premultiply_alpha(&mut texels);
+        let linear = |c: u8| srgb_to_linear(c as f32 / 255.0);
+        let sample: Vec<f32> = (0..3)
+            .map(|i| (linear(texels[i]) + linear(texels[4 + i])) / 2.0)
+            .collect();
+        let alpha = 0.5;
+        let over_white: Vec<f32> = sample.iter().map(|c| c + (1.0 - alpha)).collect();
+        // Half red over white, not the darker 0.75 straight alpha gives.
+        for (got, want) in over_white.iter().zip([1.0, 0.5, 0.5]) {
+            assert!((got - want).abs() < 0.01, "{:?}", over_white);
+        }
+
+        let mut half = [255, 128, 0, 128, 10, 20, 30, 255];
+        premultiply_alpha(&mut half);
+        assert_eq!(half[3], 128);
+        assert_eq!(&half[4..], [10, 20, 30, 255]);
+        let red = srgb_to_linear(half[0] as f32 / 255.0);
+        assert!((red - 128.0 / 255.0).abs() < 0.005);
+    }
+
+    /// Software rasterizers don't all cope with several contexts at once.
+    static GPU: Mutex<()> = Mutex::new(());
+
+    /// A device on whatever adapter there is, software ones included. None
+    /// where there is none, and those checks are skipped.
+    fn test_gpu() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+        pollster::block_on(adapter.request_device(&Default::default(), None)).ok()
+    }
+
+    /// Renders a frame of `items` at `zoom` over white, the way the window
+    /// does, after `textures`, and returns its RGBA pixels.
+    fn render_frame(
+        (device, queue): &(wgpu::Device, wgpu::Queue),
+        size: (u32, u32),
+        zoom: f32,
+        textures: Vec<TextureCmd>,
+        items: Vec<DrawItem>,
+    ) -> Vec<u8> {
+        let _gpu = GPU.lock().unwrap_or_else(|e| e.into_inner());
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let mut renderer = Renderer::new(device, format, queue);
+        let mut text = TextRenderer::new(device, queue, format);
+        for cmd in textures {
+            renderer.apply_texture_cmd(device, queue, cmd).unwrap();
+        }
+        let frame = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = frame.create_view(&Default::default());
+        let (targets, screen) = split_passes(items);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        renderer.begin_frame();
+        for target in &targets {
+            renderer.prepare_textures(device, queue, &target.items);
+        }
+        renderer.prepare_textures(device, queue, &screen);
+        for (i, target) in targets.iter().enumerate() {
+            renderer.render_target(device, queue, &mut encoder, &mut text, i + 1, target);
         }
-        i += 1;
-        if i >= bytes.len() {
-            start = i;
-            break;
+        text.prepare(device, queue, 0, size, zoom, &text_cmds(&screen))
+            .unwrap();
+        text.render_effects(0, &mut encoder).unwrap();
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            renderer.draw(&mut pass, queue, size, zoom, &screen);
+            text.render(0, &mut pass).unwrap();
+        }
+        let capture = Capture::new(device, &mut encoder, &frame);
+        queue.submit(std::iter::once(encoder.finish()));
+        capture.pixels(device).unwrap()
+    }
+
+    fn sprite(id: u32, [x, y, w, h]: [f32; 4]) -> DrawItem {
+        DrawItem::Rect(DrawCmd {
+            x,
+            y,
+            w,
+            h,
+            color: [1.0; 4],
+            texture_id: id,
+            uv: [0.0, 0.0, 1.0, 1.0],
+            clip: None,
+            corners: None,
+        })
+    }
+
+    fn label(text: &str, x: f32, y: f32) -> DrawItem {
+        DrawItem::Text(TextCmd {
+            x,
+            y,
+            size: 16.0,
+            text: text.into(),
+            color: [0.1, 0.2, 0.6, 0.7],
+            align: "LEFT".into(),
+            font: "VAR".into(),
+            clip: None,
+            snap: true,
+            effect: None,
+        })
+    }
+
+    #[test]
+    fn texture_budget_evicts_the_least_recently_used_and_reuploads_it() {
+        let Some((device, queue)) = test_gpu() else {
+            return;
+        };
+        let _gpu = GPU.lock().unwrap_or_else(|e| e.into_inner());
+        let mut renderer = Renderer::new(&device, wgpu::TextureFormat::Rgba8UnormSrgb, &queue);
+        for id in 1..=3 {
+            let upload = TextureUploadCmd {
+                id,
+                pixels: vec![255; 4 * 4 * 4].into(),
+                format: PixelFormat::Rgba8,
+                width: 4,
+                height: 4,
+                premultiplied: false,
+            };
+            renderer
+                .apply_texture_cmd(&device, &queue, TextureCmd::Upload(upload))
+                .unwrap();
         }
+        let each = renderer.texture_bytes / 3;
+        let frame = |renderer: &mut Renderer, ids: &[u32]| {
+            renderer.begin_frame();
+            let items: Vec<_> = ids.iter().map(|&id| sprite(id, [0.0; 4])).collect();
+            renderer.prepare_textures(&device, &queue, &items);
+            let mut resident: Vec<u32> = renderer.textures.keys().copied().collect();
+            resident.sort_unstable();
+            resident
+        };
+        frame(&mut renderer, &[1, 2, 3]);
+        frame(&mut renderer, &[2]);
+        frame(&mut renderer, &[3]);
+        // Room for two: 1 was drawn longest ago.
+        renderer.texture_budget = 2 * each;
+        assert_eq!(frame(&mut renderer, &[2]), [2, 3]);
+        // Drawing 1 again brings it back from the CPU copy, at 3's cost.
+        assert_eq!(frame(&mut renderer, &[1]), [1, 2]);
+        assert_eq!(renderer.texture_bytes, 2 * each);
+        assert_eq!(renderer.memory().cpu_textures, 3);
+    }
 
-        if (bytes[i] == b'X' || bytes[i] == b'x') && i + 7 <= bytes.len() {
-            // ^xRRGGBB
-            if let Ok(hex) = u32::from_str_radix(&text[i + 1..i + 7], 16) {
-                color = [
-                    ((hex >> 16) & 0xFF) as f32 / 255.0,
-                    ((hex >> 8) & 0xFF) as f32 / 255.0,
-                    ((hex) & 0xFF) as f32 / 255.0,
-                    alpha,
-                ];
+    #[test]
+    fn scaled_sprite_edges_fade_to_the_background() {
+        let Some(gpu) = test_gpu() else {
+            return;
+        };
+        // Opaque red beside a clear texel that still holds a colour, as
+        // exported images often do, stretched over 8 pixels.
+        let textures = vec![TextureCmd::Upload(TextureUploadCmd {
+            id: 1,
+            pixels: vec![255, 0, 0, 255, 0, 255, 0, 0].into(),
+            format: PixelFormat::Rgba8,
+            width: 2,
+            height: 1,
+            premultiplied: false,
+        })];
+        let frame = render_frame(
+            &gpu,
+            (8, 1),
+            1.0,
+            textures,
+            vec![sprite(1, [0.0, 0.0, 8.0, 1.0])],
+        );
+        for (x, px) in frame.chunks_exact(4).enumerate() {
+            // How much of the red texel each pixel centre samples.
+            let red = (1.5 - (x as f32 + 0.5) / 4.0).clamp(0.0, 1.0);
+            let want = (linear_to_srgb(1.0 - red) * 255.0).round();
+            // Red over white keeps its red channel, and nothing of the
+            // clear texel shows.
+            assert_eq!(px[0], 255, "pixel {}: {:?}", x, px);
+            assert!((px[1] as f32 - want).abs() <= 2.0, "pixel {}: {:?}", x, px);
+            assert_eq!(px[1], px[2]);
+        }
+    }
+
+    #[test]
+    fn text_in_render_targets_blends_like_text_on_screen() {
+        let Some(gpu) = test_gpu() else {
+            return;
+        };
+        let direct = render_frame(&gpu, (64, 24), 1.0, vec![], vec![label("Hxg", 2.0, 2.0)]);
+        let textures = vec![TextureCmd::CreateTarget {
+            id: 5,
+            width: 64,
+            height: 24,
+        }];
+        let items = vec![
+            DrawItem::BeginTarget {
+                id: 5,
+                clear: Some([0.0; 4]),
+            },
+            label("Hxg", 2.0, 2.0),
+            DrawItem::EndTarget,
+            sprite(5, [0.0, 0.0, 64.0, 24.0]),
+        ];
+        let through_target = render_frame(&gpu, (64, 24), 1.0, textures, items);
+        assert!(
+            direct.chunks_exact(4).any(|px| px[2] < 250),
+            "no text drawn"
+        );
+        for (i, (a, b)) in direct.iter().zip(&through_target).enumerate() {
+            assert!(a.abs_diff(*b) <= 2, "byte {}: {} vs {}", i, a, b);
+        }
+    }
+
+    #[test]
+    fn effects_draw_like_copies_of_the_text_under_it() {
+        let Some(gpu) = test_gpu() else {
+            return;
+        };
+        let with = |effect, x, y, color: Option<[f32; 4]>| {
+            let mut text = label("Hxg", x, y);
+            if let DrawItem::Text(t) = &mut text {
+                t.effect = effect;
+                t.color = color.unwrap_or(t.color);
+            }
+            text
+        };
+        let frame = |items| render_frame(&gpu, (64, 32), 1.0, vec![], items);
+        // The copy fades with the text's 0.7.
+        let shadow = TextEffect::Shadow {
+            offset: [2.0, 3.0],
+            color: [0.0, 0.0, 0.0, 1.0],
+        };
+        let shadowed = frame(vec![with(Some(shadow), 4.0, 4.0, None)]);
+        let by_hand = frame(vec![
+            with(None, 6.0, 7.0, Some([0.0, 0.0, 0.0, 0.7])),
+            with(None, 4.0, 4.0, None),
+        ]);
+        for (i, (a, b)) in shadowed.iter().zip(&by_hand).enumerate() {
+            assert!(a.abs_diff(*b) <= 2, "byte {}: {} vs {}", i, a, b);
+        }
+
+        // An outline darkens pixels beside the glyphs, and only those.
+        let plain = frame(vec![with(None, 4.0, 4.0, None)]);
+        let outline = TextEffect::Outline {
+            width: 1.0,
+            color: [0.0, 0.0, 0.0, 1.0],
+        };
+        let outlined = frame(vec![with(Some(outline), 4.0, 4.0, None)]);
+        let inked = |px: &[u8]| px[0] < 250;
+        let at = |f: &[u8], x: i32, y: i32| {
+            (0..64).contains(&x)
+                && (0..32).contains(&y)
+                && inked(&f[(y * 64 + x) as usize * 4..][..4])
+        };
+        let mut ring = 0;
+        for y in 0..32 {
+            for x in 0..64 {
+                if at(&outlined, x, y) && !at(&plain, x, y) {
+                    ring += 1;
+                    let near = (-2..=2).any(|dy| (-2..=2).any(|dx| at(&plain, x + dx, y + dy)));
+                    assert!(near, "outline at {},{} away from the text", x, y);
+                }
             }
-            i += 7;
-        } else if bytes[i].is_ascii_digit() {
-            color = pob_digit_color(bytes[i] - b'0', alpha);
-            i += 1;
         }
-        start = i;
+        assert!(ring > 20, "only {} outline pixels", ring);
     }
-    if start < text.len() {
-        spans.push((&text[start..], color));
+
+    #[test]
+    fn snapped_text_lands_on_whole_physical_pixels() {
+        let Some(gpu) = test_gpu() else {
+            return;
+        };
+        let at = |x: f32, snap: bool, zoom: f32| {
+            let mut text = label("Hxg", x, 2.0);
+            if let DrawItem::Text(t) = &mut text {
+                t.snap = snap;
+            }
+            render_frame(&gpu, (96, 40), zoom, vec![], vec![text])
+        };
+        // 10.3 units is 15.45 pixels at 150%, which snaps to 15: 10 units.
+        for zoom in [1.0, 1.5] {
+            let whole = at(10.0, true, zoom);
+            assert_eq!(at(10.3, true, zoom), whole, "zoom {}", zoom);
+            assert_ne!(at(10.3, false, zoom), whole, "zoom {}", zoom);
+        }
+        // 10.4 units is 20.8 pixels at 200%, which snaps to 21, half a unit
+        // from 10; a grid of whole units would have put it at 10.
+        let whole = at(10.5, true, 2.0);
+        assert_eq!(at(10.4, true, 2.0), whole);
+        assert_ne!(at(10.0, true, 2.0), whole);
     }
 
-    spans
-}
+    #[test]
+    fn zoomed_clip_rects_cover_whole_pixels() {
+        assert_eq!(zoom_rect([10, 20, 30, 40], 1.0), [10, 20, 30, 40]);
+        assert_eq!(zoom_rect([10, 20, 30, 40], 1.5), [15, 30, 45, 60]);
+        assert_eq!(zoom_rect([3, 0, 3, 1], 1.25), [3, 0, 5, 2]);
+    }
 
-fn pob_digit_color(digit: u8, alpha: f32) -> [f32; 4] {
-    let (r, g, b): (f32, f32, f32) = match digit {
-        0 => (0.0, 0.0, 0.0),    // black
-        1 => (1.0, 0.0, 0.0),    // red
-        2 => (0.0, 1.0, 0.0),    // green
-        3 => (0.0, 0.0, 1.0),    // blue
-        4 => (1.0, 1.0, 0.0),    // yellow
-        5 => (0.5, 0.5, 0.5),    // gray
-        6 => (0.5, 0.5, 0.5),    // gray
-        7 => (1.0, 1.0, 1.0),    // white
-        8 => (0.75, 0.75, 0.75), // light gray
-        9 => (0.3, 0.3, 0.3),    // dark gray
-        _ => (1.0, 1.0, 1.0),
-    };
-    [r, g, b, alpha]
+    #[test]
+    fn snap_to_pixel_rounds_to_physical_grid() {
+        assert_eq!(snap_to_pixel(10.4, 1.0), 10.0);
+        assert_eq!(snap_to_pixel(10.6, 1.0), 11.0);
+        // at 2x DPI half-unit positions are already on the physical grid
+        assert_eq!(snap_to_pixel(10.5, 2.0), 10.5);
+        assert_eq!(snap_to_pixel(10.3, 2.0), 10.5);
+    }
+
+    #[test]
+    fn split_passes_routes_target_draws_and_clamps_scissor() {
+        let rect = |texture_id| {
+            DrawItem::Rect(DrawCmd {
+                x: 0.0,
+                y: 0.0,
+                w: 1.0,
+                h: 1.0,
+                color: [1.0; 4],
+                texture_id,
+                uv: [0.0, 0.0, 1.0, 1.0],
+                clip: None,
+                corners: None,
+            })
+        };
+        let items = vec![
+            rect(1),
+            DrawItem::BeginTarget { id: 7, clear: None },
+            rect(2),
+            rect(3),
+            DrawItem::EndTarget,
+            rect(7),
+            DrawItem::BeginTarget {
+                id: 8,
+                clear: Some([0.0; 4]),
+            },
+            rect(4),
+        ];
+        let (targets, screen) = split_passes(items);
+        let ids = |items: &[DrawItem]| -> Vec<u32> {
+            items
+                .iter()
+                .map(|i| match i {
+                    DrawItem::Rect(c) => c.texture_id,
+                    _ => 0,
+                })
+                .collect()
+        };
+        assert_eq!(ids(&screen), [1, 7]);
+        assert_eq!(targets.len(), 2);
+        assert_eq!((targets[0].id, ids(&targets[0].items)), (7, vec![2, 3]));
+        assert_eq!((targets[1].id, ids(&targets[1].items)), (8, vec![4]));
+
+        assert_eq!(scissor(None, (256, 128)), Some([0, 0, 256, 128]));
+        assert_eq!(
+            scissor(Some([200, 100, 400, 400]), (256, 128)),
+            Some([200, 100, 56, 28])
+        );
+        assert_eq!(scissor(Some([300, 0, 10, 10]), (256, 128)), None);
+    }
+
+    #[test]
+    fn skipped_passes_keep_what_later_passes_draw_over() {
+        let pass = |id, clear: bool, n| TargetPass {
+            id,
+            clear: clear.then_some([0.0; 4]),
+            items: vec![DrawItem::EndTarget; n],
+        };
+        let mut kept = vec![pass(1, true, 1), pass(2, true, 2)];
+        // Target 1 is drawn over, target 2 cleared and redrawn.
+        merge_passes(&mut kept, vec![pass(1, false, 3), pass(2, true, 4)]);
+        let summary: Vec<_> = kept.iter().map(|p| (p.id, p.items.len())).collect();
+        assert_eq!(summary, [(1, 1), (1, 3), (2, 4)]);
+        merge_passes(&mut kept, vec![pass(1, true, 5)]);
+        let summary: Vec<_> = kept.iter().map(|p| (p.id, p.items.len())).collect();
+        assert_eq!(summary, [(2, 4), (1, 5)]);
+    }
 }
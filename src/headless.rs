@@ -0,0 +1,232 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+use crate::build_stats;
+use crate::layout::Layout;
+use crate::lua_host::{HostShared, LuaHost};
+
+/// PoB without a window: scripts are launched and take frames, but what
+/// they draw is thrown away.
+pub struct Headless {
+    pub host: LuaHost,
+    shared: HostShared,
+    deadline: Rc<Cell<Option<Instant>>>,
+}
+
+impl Headless {
+    pub fn start(layout: Layout) -> LuaResult<Self> {
+        let shared = HostShared::default();
+        let host = LuaHost::new(layout, shared.clone())?;
+        // Calc passes run for a while without a frame to check in.
+        let deadline = Rc::new(Cell::new(None::<Instant>));
+        let d = deadline.clone();
+        host.lua.set_hook(
+            LuaHookTriggers::new().every_nth_instruction(100_000),
+            move |_, _| match d.get() {
+                Some(t) if Instant::now() > t => Err(LuaError::RuntimeError("timed out".into())),
+                _ => Ok(()),
+            },
+        );
+        host.launch()?;
+        host.callback("OnInit")?;
+        Ok(Self {
+            host,
+            shared,
+            deadline,
+        })
+    }
+
+    /// Runs one frame.
+    pub fn frame(&self) -> LuaResult<()> {
+        self.host.pump_subscripts()?;
+        self.host.callback("OnFrame")?;
+        self.shared.draw_queue.lock().unwrap().clear();
+        self.shared.texture_queue.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Opens `build` (a build code, file or `pob://` link) and runs frames
+    /// until PoB has set it up, failing at `deadline`. Lua code still
+    /// running then is stopped too.
+    pub fn load(&self, build: &str, deadline: Instant) -> LuaResult<()> {
+        self.by(deadline, || self.load_by(build, deadline))
+    }
+
+    /// Loads `build` and runs a full calc pass over it for its summary.
+    pub fn summarize(&self, build: &str, deadline: Instant) -> LuaResult<Summary> {
+        self.by(deadline, || {
+            self.load_by(build, deadline)?;
+            self.host
+                .lua
+                .load("main.modes.BUILD.calcsTab:BuildOutput()")
+                .exec()?;
+            build_stats::summary(&self.host.lua)?
+                .ok_or_else(|| LuaError::RuntimeError(format!("{} did not load", build)))
+        })
+    }
+
+    fn by<T>(&self, deadline: Instant, f: impl FnOnce() -> LuaResult<T>) -> LuaResult<T> {
+        self.deadline.set(Some(deadline));
+        let result = f();
+        self.deadline.set(None);
+        result
+    }
+
+    fn load_by(&self, build: &str, deadline: Instant) -> LuaResult<()> {
+        // SetMode takes effect on the next frame; downloads take longer.
+        // The build mode is reused, but each build gets a new calcs tab.
+        let calcs_tab: LuaFunction = self
+            .host
+            .lua
+            .load(
+                r#"
+                local build = main and main.mode == "BUILD" and main.modes.BUILD
+                return build and build.calcsTab
+                "#,
+            )
+            .into_function()?;
+        let before: LuaValue = calcs_tab.call(())?;
+        self.host.open_item(build)?;
+        loop {
+            self.frame()?;
+            let now: LuaValue = calcs_tab.call(())?;
+            if matches!(now, LuaValue::Table(_)) && now != before {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                return Err(LuaError::RuntimeError(format!("{} did not load", build)));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// The main stats of a build, from `build_stats::summary`.
+pub type Summary = Vec<(&'static str, f64)>;
+
+#[derive(Debug, PartialEq)]
+pub enum JobError {
+    TimedOut,
+    Failed(String),
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TimedOut => write!(f, "timed out"),
+            Self::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+struct Job {
+    build: String,
+    deadline: Instant,
+    reply: Sender<Result<Summary, JobError>>,
+    /// Set by whichever of the caller (giving up) and the state (done)
+    /// gets there first.
+    settled: Arc<AtomicBool>,
+}
+
+/// How long past a job's deadline its state gets to report the timeout
+/// itself before it is given up on.
+const DEADLINE_GRACE: Duration = Duration::from_secs(1);
+
+/// Headless states on their own threads, each summarizing one build and
+/// then replaced by a fresh state, so no request sees another's leftovers.
+/// Replacements start while the thread waits for its next job. A state
+/// that misses its deadline, e.g. stuck in a download or a compiled loop
+/// the instruction hook never sees, is left to finish on its own and a new
+/// thread takes its place.
+pub struct StatePool {
+    jobs: Sender<Job>,
+    layout: Layout,
+    rx: Arc<Mutex<Receiver<Job>>>,
+}
+
+impl StatePool {
+    pub fn spawn(layout: &Layout, states: usize) -> std::io::Result<Self> {
+        let (jobs, rx) = channel::<Job>();
+        let pool = Self {
+            jobs,
+            layout: layout.clone(),
+            rx: Arc::new(Mutex::new(rx)),
+        };
+        for _ in 0..states.max(1) {
+            pool.add_state()?;
+        }
+        Ok(pool)
+    }
+
+    fn add_state(&self) -> std::io::Result<()> {
+        let (layout, rx) = (self.layout.clone(), self.rx.clone());
+        std::thread::Builder::new()
+            .name("state".into())
+            .spawn(move || serve(layout, &rx))?;
+        Ok(())
+    }
+
+    /// Summarizes `build` on the next free state, failing if that takes
+    /// longer than `timeout`, waiting for the state included.
+    pub fn summarize(&self, build: &str, timeout: Duration) -> Result<Summary, JobError> {
+        let (reply, result) = channel();
+        let settled = Arc::new(AtomicBool::new(false));
+        let deadline = Instant::now() + timeout;
+        let job = Job {
+            build: build.to_string(),
+            deadline,
+            reply,
+            settled: settled.clone(),
+        };
+        self.jobs
+            .send(job)
+            .map_err(|_| JobError::Failed("the state pool stopped".into()))?;
+        let wait = deadline.saturating_duration_since(Instant::now()) + DEADLINE_GRACE;
+        match result.recv_timeout(wait) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(JobError::Failed("the state stopped".into()))
+            }
+            // The state finished just now after all.
+            Err(RecvTimeoutError::Timeout) if settled.swap(true, Ordering::AcqRel) => result
+                .recv()
+                .unwrap_or_else(|_| Err(JobError::Failed("the state stopped".into()))),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = self.add_state() {
+                    eprintln!("state pool: {}", e);
+                }
+                Err(JobError::TimedOut)
+            }
+        }
+    }
+}
+
+fn serve(layout: Layout, jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let state = Headless::start(layout.clone()).map_err(|e| e.to_string());
+        let Ok(job) = jobs.lock().unwrap().recv() else {
+            return;
+        };
+        let result = match state {
+            _ if Instant::now() > job.deadline => Err(JobError::TimedOut),
+            Ok(state) => state.summarize(&job.build, job.deadline).map_err(|e| {
+                match Instant::now() > job.deadline {
+                    true => JobError::TimedOut,
+                    false => JobError::Failed(e.to_string()),
+                }
+            }),
+            Err(e) => Err(JobError::Failed(e)),
+        };
+        // Given up on and already replaced.
+        if job.settled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        job.reply.send(result).ok();
+    }
+}
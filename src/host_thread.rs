@@ -0,0 +1,604 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{Receiver, Sender, SyncSender},
+    thread::JoinHandle,
+};
+
+use mlua::prelude::*;
+use rfd::{MessageButtons, MessageLevel};
+use serde::{Deserialize, Serialize};
+use winit::event_loop::EventLoopProxy;
+
+use crate::automation::Script;
+use crate::backup::Backups;
+use crate::callback_errors::ErrorPolicy;
+use crate::dialogs;
+use crate::graphics::{self, CursorPos, DrawItem, TargetPass, TextureCmd};
+use crate::input_log::{Entry, InputLog};
+use crate::integrity;
+use crate::layout::Layout;
+use crate::lua_host::{HostShared, LuaHost};
+use crate::platform;
+use crate::startup;
+use crate::tray::TrayAction;
+
+/// Input forwarded from the winit thread to the Lua thread.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HostEvent {
+    MouseMove {
+        x: f32,
+        y: f32,
+    },
+    KeyDown {
+        key: String,
+        double_click: bool,
+    },
+    KeyUp {
+        key: String,
+    },
+    Char(String),
+    /// Build files or `pob://` links handed over by a second launch.
+    Open(Vec<String>),
+    /// The window gained (true) or lost keyboard focus.
+    Focus(bool),
+    /// Sent by `Waker`; never dispatched or recorded.
+    Wake,
+}
+
+/// Cuts short the host's wait for its next paced frame, so results from
+/// background threads reach Lua without waiting out a minimized or
+/// unfocused window's frame time. The default waker does nothing.
+#[derive(Clone, Default)]
+pub struct Waker(Option<Sender<HostEvent>>);
+
+impl Waker {
+    pub fn new(events: Sender<HostEvent>) -> Self {
+        Self(Some(events))
+    }
+
+    pub fn wake(&self) {
+        if let Some(events) = &self.0 {
+            events.send(HostEvent::Wake).ok();
+        }
+    }
+}
+
+/// Everything the renderer needs to present one OnFrame worth of output.
+pub struct Frame {
+    pub items: Vec<DrawItem>,
+    /// Render target passes, drawn once before the screen items.
+    pub targets: Vec<TargetPass>,
+    pub textures: Vec<TextureCmd>,
+    /// PNG files to save this frame to once it is drawn.
+    pub screenshots: Vec<PathBuf>,
+}
+
+/// Wakeups sent from the Lua thread into the winit event loop.
+pub enum UserEvent {
+    FrameReady,
+    HostExited,
+    /// Another launch forwarded its arguments to this instance.
+    Activate(Vec<String>),
+    /// A replayed recording resizes the window to the recorded size.
+    Resize([u32; 2]),
+    /// A tray menu entry or the tray icon was clicked. Builds without a
+    /// tray never send it.
+    #[allow(dead_code)]
+    Tray(TrayAction),
+}
+
+/// Runs the Lua host on its own thread so long OnFrame calls never block the
+/// OS event loop. `Lua` is not `Send`, so the host is built on the thread.
+/// The thread returns whether the host stopped without an error.
+pub fn spawn(
+    layout: Layout,
+    args: Vec<String>,
+    shared: HostShared,
+    events: Receiver<HostEvent>,
+    frames: SyncSender<Frame>,
+    proxy: EventLoopProxy<UserEvent>,
+    input: InputLog,
+) -> JoinHandle<bool> {
+    std::thread::Builder::new()
+        .name("lua-host".into())
+        .spawn(move || {
+            // Recorded and scripted runs have nobody to read a message box.
+            let interactive = matches!(input, InputLog::Live);
+            let result = run(layout, args, shared, events, frames, &proxy, input);
+            if let Err(e) = &result {
+                eprintln!("lua host stopped: {}", e);
+                if interactive {
+                    dialogs::message_box(
+                        MessageLevel::Error,
+                        "Path of Building stopped",
+                        &e.to_string(),
+                        MessageButtons::Ok,
+                    );
+                }
+            }
+            proxy.send_event(UserEvent::HostExited).ok();
+            result.is_ok()
+        })
+        .expect("failed to spawn lua host thread")
+}
+
+fn run(
+    layout: Layout,
+    args: Vec<String>,
+    shared: HostShared,
+    events: Receiver<HostEvent>,
+    frames: SyncSender<Frame>,
+    proxy: &EventLoopProxy<UserEvent>,
+    mut input: InputLog,
+) -> LuaResult<()> {
+    let draw_queue = shared.draw_queue.clone();
+    let texture_queue = shared.texture_queue.clone();
+    let cursor_pos = shared.cursor_pos.clone();
+    let screen_size = shared.screen_size.clone();
+    let dpi_scale = shared.dpi_scale.clone();
+    let gpu_times = shared.gpu_times.clone();
+    let render_stats = shared.render_stats.clone();
+    let settings = shared.settings.clone();
+    let focused = shared.focused.clone();
+    let hidden = shared.hidden.clone();
+    let host = startup::time("Lua host", || LuaHost::new(layout, shared))?;
+    let on_error = settings.lua.on_error.as_deref();
+    let policy = on_error.and_then(ErrorPolicy::parse).unwrap_or_else(|| {
+        if let Some(name) = on_error {
+            eprintln!("lua: unknown on_error policy \"{}\"", name);
+        }
+        // Nobody can answer a prompt in recorded or scripted runs.
+        match input {
+            InputLog::Live => ErrorPolicy::Prompt,
+            _ => ErrorPolicy::Crash,
+        }
+    });
+    host.set_error_policy(policy);
+    integrity::spawn(host.layout.clone(), host.vfs.clone());
+
+    host.set_args(&args)?;
+    startup::time("Launch.lua", || host.launch())?;
+    println!(
+        "main object set: {}",
+        host.main_object.lock().unwrap().is_some()
+    );
+
+    startup::time("OnInit", || host.callback("OnInit"))?;
+    host.open_items(&args)?;
+
+    let mut backups = Backups::new(platform::user_path().join("Backups"), &settings.backup);
+    if let Some(b) = &backups
+        && b.crashed()
+        && let Some((name, xml)) = b.latest()
+    {
+        host.offer_recovery(&name, &xml)?;
+    }
+    host.lua
+        .load(
+            r##"
+      -- Log any runtime errors PoB catches
+      local origSEM = launch.ShowErrMsg
+      launch.ShowErrMsg = function(self, fmt, ...)
+          local msg = string.format(fmt, ...)
+          print("ShowErrMsg: " .. tostring(msg))
+          return origSEM(self, fmt, ...)
+      end
+
+      -- Log when any control is actually dispatched
+      local ControlHostClass = main.__index
+      local origGMC = ControlHostClass.GetMouseOverControl
+      ControlHostClass.GetMouseOverControl = function(self)
+          local result = origGMC(self)
+          if result then
+              local cx, cy = GetCursorPos()
+              if cx > 0 or cy > 0 then
+                  local name = "?"
+                  for n, c in pairs(self.controls) do
+                      if c == result then name = n; break end
+                  end
+                  print("DISPATCH -> " .. name .. " at " .. math.floor(cx) .. "," .. math.floor(cy))
+              end
+          end
+          return result
+      end
+  "##,
+        )
+        .exec()?;
+
+    let script = match &input {
+        InputLog::Automate(path) => Some(Script::load(&host.lua, path)?),
+        _ => None,
+    };
+    let mut frame_no = 0u64;
+    let mut gc_time = std::time::Duration::ZERO;
+    let mut last_size = None;
+    let mut last_view = None;
+    // Unfocused frames are spaced out to this, if the settings ask.
+    let background_frame = settings
+        .display
+        .unfocused_fps
+        .filter(|&fps| fps > 0.0)
+        .map(|fps| std::time::Duration::from_secs_f32(1.0 / fps.clamp(0.1, 240.0)));
+    let mut early = None;
+    loop {
+        let started = std::time::Instant::now();
+        let mut batch = coalesce(early.take().into_iter().chain(events.try_iter()));
+        let mut ended = false;
+        let mut replayed = None;
+        if let InputLog::Replay(replay) = &mut input {
+            // Live input would make the session drift from the recording,
+            // and so would the wall clock.
+            batch.clear();
+            replayed = Some(replay.time(frame_no));
+            for entry in replay.take(frame_no) {
+                match entry {
+                    Entry::Input(event) => batch.push(event),
+                    Entry::Resize(size) => {
+                        proxy.send_event(UserEvent::Resize(size)).ok();
+                    }
+                    Entry::End => ended = true,
+                }
+            }
+        }
+        if let Some(script) = &script {
+            batch.clear();
+            ended = !script
+                .resume()
+                .map_err(|e| LuaError::RuntimeError(format!("automation: {}", e)))?;
+            batch.extend(script.take_events());
+        }
+        let size = *screen_size.lock().unwrap();
+        if last_size != Some(size) {
+            input.record(frame_no, || Entry::Resize(size));
+            last_size = Some(size);
+        }
+        // Scripts that cache their layout hear when the window was resized
+        // or moved to a display with another scale.
+        let view = (size, *dpi_scale.lock().unwrap());
+        if last_view.is_some_and(|v| v != view) {
+            host.callback_args(
+                "OnResize",
+                (size[0], size[1], view.1).into_lua_multi(&host.lua)?,
+            )?;
+        }
+        last_view = Some(view);
+        dispatch_batch(&host, &mut input, frame_no, &cursor_pos, batch)?;
+        if ended || host.exit_requested() {
+            return shutdown(&host, backups, &mut input, frame_no);
+        }
+
+        host.pump_subscripts()?;
+        host.pump_dialogs()?;
+        host.pump_hotkeys()?;
+        host.refresh_svgs();
+        host.poll_builds()?;
+        host.poll_clipboard()?;
+        host.pump_ipc();
+
+        if let Some(b) = &mut backups
+            && b.due(std::time::Instant::now())
+        {
+            match host.backup_xml() {
+                Ok(Some((name, xml))) => {
+                    if let Err(e) = b.save(&name, &xml) {
+                        eprintln!("backup {}: {}", name, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("backup: {}", e),
+            }
+        }
+
+        // Forks that animate by elapsed time take the delta as an argument.
+        let delta = host.begin_frame(replayed);
+        let t = std::time::Instant::now();
+        if host.capture.selecting() {
+            host.capture.draw(&draw_queue);
+        } else {
+            host.draw_backdrop(&draw_queue, *screen_size.lock().unwrap());
+            host.callback_args(
+                "OnFrame",
+                LuaMultiValue::from_vec(vec![LuaValue::Number(delta)]),
+            )?;
+        }
+        let lua_ms = t.elapsed().as_millis();
+        if host.exit_requested() {
+            return shutdown(&host, backups, &mut input, frame_no);
+        }
+
+        host.close_render_target();
+        let (targets, items) = graphics::split_passes(draw_queue.lock().unwrap().drain(..));
+        let frame = Frame {
+            items,
+            targets,
+            textures: texture_queue.lock().unwrap().drain(..).collect(),
+            screenshots: script
+                .as_ref()
+                .map(Script::take_screenshots)
+                .unwrap_or_default(),
+        };
+        let draw_count = frame.items.len();
+        let tex_count = frame.textures.len();
+        let gpu = gpu_times
+            .lock()
+            .unwrap()
+            .map_or_else(|| "-".to_string(), |t| t.to_string());
+        // The renderer's counts are from the frame before this one, like
+        // the GPU time.
+        let culled = render_stats.lock().unwrap().culled;
+        eprintln!(
+            "OnFrame: {}ms | gpu: {} | gc: {:.1}ms | draws: {} | tex: {} | dropped: {} | culled: {}",
+            lua_ms,
+            gpu,
+            gc_time.as_secs_f64() * 1000.0,
+            draw_count,
+            tex_count,
+            host.take_dropped_draws(),
+            culled
+        );
+
+        if lua_ms > 50 || tex_count > 0 {
+            eprintln!("OnFrame: {}ms | tex uploads queued: {}", lua_ms, tex_count);
+        }
+
+        // Blocks while the previous frame is still unpresented, which paces
+        // OnFrame to the display rate.
+        if frames.send(frame).is_err() || proxy.send_event(UserEvent::FrameReady).is_err() {
+            return shutdown(&host, backups, &mut input, frame_no);
+        }
+        frame_no += 1;
+        // While the window thread draws the frame.
+        gc_time = host.collect_garbage()?;
+        let pace = if *hidden.lock().unwrap() {
+            // Nothing paces a window that isn't drawn.
+            Some(background_frame.map_or(HIDDEN_FRAME, |f| f.max(HIDDEN_FRAME)))
+        } else if !*focused.lock().unwrap() {
+            background_frame
+        } else {
+            None
+        };
+        if let Some(frame) = pace {
+            // Input, like the click that focuses the window, ends the wait.
+            early = events
+                .recv_timeout(frame.saturating_sub(started.elapsed()))
+                .ok();
+        }
+    }
+}
+
+/// The one way out of the host loop, whether the window closed, a script
+/// called Exit or a replay ran out. OnExit is where PoB saves its settings.
+fn shutdown(
+    host: &LuaHost,
+    backups: Option<Backups>,
+    input: &mut InputLog,
+    frame_no: u64,
+) -> LuaResult<()> {
+    let result = host.callback("OnExit");
+    input.record(frame_no, || Entry::End);
+    if let Some(b) = backups {
+        b.finish();
+    }
+    result
+}
+
+/// How often OnFrame runs while the window is minimized or covered, to
+/// keep timers and subscripts going.
+const HIDDEN_FRAME: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Collapses runs of mouse moves into their last position, so high polling
+/// rate mice cost one OnMouseMove per frame. A pending move is emitted before
+/// any other event, keeping clicks at the position they happened.
+fn coalesce(events: impl Iterator<Item = HostEvent>) -> Vec<HostEvent> {
+    let mut out: Vec<HostEvent> = Vec::new();
+    for event in events {
+        if let (HostEvent::MouseMove { .. }, Some(HostEvent::MouseMove { .. })) =
+            (&event, out.last())
+        {
+            out.pop();
+        }
+        out.push(event);
+    }
+    out
+}
+
+/// Hands one frame's input to Lua in the order it arrived, so a key's
+/// OnKeyDown comes before the OnChar it typed. Each event is recorded
+/// first; a region selection takes the events meant for it.
+fn dispatch_batch(
+    host: &LuaHost,
+    input: &mut InputLog,
+    frame_no: u64,
+    cursor_pos: &CursorPos,
+    batch: Vec<HostEvent>,
+) -> LuaResult<()> {
+    for event in batch {
+        if event == HostEvent::Wake {
+            continue;
+        }
+        input.record(frame_no, || Entry::Input(event.clone()));
+        if let HostEvent::MouseMove { x, y } = event {
+            *cursor_pos.lock().unwrap() = [x, y];
+        }
+        if host.capture.selecting() {
+            host.capture.event(&event);
+            continue;
+        }
+        dispatch(host, event)?;
+    }
+    Ok(())
+}
+
+/// `text` without the control characters some platforms type for Enter,
+/// Escape, Tab or Backspace, and macOS's private-use codes for function
+/// and arrow keys. EditControl gets those keys from OnKeyDown and would
+/// insert them as text.
+fn printable(text: &str) -> String {
+    text.chars()
+        .filter(|&c| !c.is_control() && !('\u{F700}'..='\u{F8FF}').contains(&c))
+        .collect()
+}
+
+fn dispatch(host: &LuaHost, event: HostEvent) -> LuaResult<()> {
+    match event {
+        HostEvent::MouseMove { .. } => host.callback("OnMouseMove"),
+        HostEvent::KeyDown { key, double_click } => {
+            let key = LuaValue::String(host.lua.create_string(&key)?);
+            host.callback_args(
+                "OnKeyDown",
+                LuaMultiValue::from_vec(vec![key, LuaValue::Boolean(double_click)]),
+            )
+        }
+        HostEvent::KeyUp { key } => {
+            let key = LuaValue::String(host.lua.create_string(&key)?);
+            host.callback_args("OnKeyUp", LuaMultiValue::from_vec(vec![key]))
+        }
+        HostEvent::Char(text) => {
+            let text = printable(&text);
+            if text.is_empty() {
+                return Ok(());
+            }
+            let ch = LuaValue::String(host.lua.create_string(&text)?);
+            host.callback_args("OnChar", LuaMultiValue::from_vec(vec![ch]))
+        }
+        HostEvent::Open(items) => host.open_items(&items),
+        HostEvent::Focus(focused) => host.callback_args(
+            "OnFocus",
+            LuaMultiValue::from_vec(vec![focused.into_lua(&host.lua)?]),
+        ),
+        HostEvent::Wake => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_keeps_last_move_before_clicks() {
+        let down = || HostEvent::KeyDown {
+            key: "LEFTBUTTON".into(),
+            double_click: false,
+        };
+        let events = vec![
+            HostEvent::MouseMove { x: 1.0, y: 1.0 },
+            HostEvent::MouseMove { x: 2.0, y: 2.0 },
+            down(),
+            HostEvent::MouseMove { x: 3.0, y: 3.0 },
+            HostEvent::MouseMove { x: 4.0, y: 4.0 },
+        ];
+        assert_eq!(
+            coalesce(events.into_iter()),
+            vec![
+                HostEvent::MouseMove { x: 2.0, y: 2.0 },
+                down(),
+                HostEvent::MouseMove { x: 4.0, y: 4.0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn batches_reach_lua_in_arrival_order() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = crate::lua_host::HostShared::default();
+        let cursor_pos = shared.cursor_pos.clone();
+        let host = LuaHost::new(layout, shared).unwrap();
+        host.lua
+            .load(
+                r#"
+                log = {}
+                local function note(what) return function(_, arg) table.insert(log, what .. (arg or "")) end end
+                SetMainObject({ OnMouseMove = note("move"), OnKeyDown = note("down "),
+                    OnChar = note("char "), OnKeyUp = note("up ") })
+                "#,
+            )
+            .exec()
+            .unwrap();
+        let key = |k: &str| HostEvent::KeyDown {
+            key: k.into(),
+            double_click: false,
+        };
+        let batch = vec![
+            HostEvent::MouseMove { x: 5.0, y: 6.0 },
+            key("a"),
+            HostEvent::Char("a".into()),
+            HostEvent::KeyUp { key: "a".into() },
+        ];
+        dispatch_batch(&host, &mut InputLog::Live, 0, &cursor_pos, batch).unwrap();
+        let log: Vec<String> = host.lua.load("return log").eval().unwrap();
+        assert_eq!(log, ["move", "down a", "char a", "up a"]);
+        assert_eq!(*cursor_pos.lock().unwrap(), [5.0, 6.0]);
+    }
+
+    #[test]
+    fn recorded_control_characters_never_reach_on_char() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = crate::lua_host::HostShared::default();
+        let host = LuaHost::new(layout, shared.clone()).unwrap();
+        host.lua
+            .load(
+                r#"
+                log = {}
+                SetMainObject({ OnKeyDown = function(_, k) table.insert(log, k) end,
+                    OnChar = function(_, c) table.insert(log, c) end })
+                "#,
+            )
+            .exec()
+            .unwrap();
+        // Typing "hi", Enter, Backspace and Escape on a platform that also
+        // sends text for the last three, plus a macOS arrow key.
+        let recording = [
+            r#"{"frame":0,"ms":0,"entry":{"Input":{"KeyDown":{"key":"h","double_click":false}}}}"#,
+            r#"{"frame":0,"ms":1,"entry":{"Input":{"Char":"h"}}}"#,
+            r#"{"frame":0,"ms":2,"entry":{"Input":{"Char":"i"}}}"#,
+            r#"{"frame":1,"ms":3,"entry":{"Input":{"KeyDown":{"key":"RETURN","double_click":false}}}}"#,
+            r#"{"frame":1,"ms":4,"entry":{"Input":{"Char":"\r"}}}"#,
+            r#"{"frame":1,"ms":5,"entry":{"Input":{"Char":"\b"}}}"#,
+            r#"{"frame":1,"ms":6,"entry":{"Input":{"Char":"\u001b"}}}"#,
+            r#"{"frame":1,"ms":7,"entry":{"Input":{"Char":"\uf702"}}}"#,
+            r#"{"frame":1,"ms":8,"entry":{"Input":{"Char":"é"}}}"#,
+        ];
+        let path = std::env::temp_dir().join(format!("pob-onchar-{}.jsonl", std::process::id()));
+        std::fs::write(&path, recording.join("\n")).unwrap();
+        let mut replay = crate::input_log::Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        for frame in 0..2 {
+            let batch = replay
+                .take(frame)
+                .into_iter()
+                .filter_map(|e| match e {
+                    Entry::Input(event) => Some(event),
+                    _ => None,
+                })
+                .collect();
+            dispatch_batch(&host, &mut InputLog::Live, frame, &shared.cursor_pos, batch).unwrap();
+        }
+        let log: Vec<String> = host.lua.load("return log").eval().unwrap();
+        assert_eq!(log, ["h", "h", "i", "RETURN", "é"]);
+    }
+
+    #[test]
+    fn exit_winds_down_through_on_exit() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let host = LuaHost::new(layout, crate::lua_host::HostShared::default()).unwrap();
+        host.lua
+            .load(
+                r#"
+                SetMainObject({ OnKeyDown = function() Exit("update ready") end,
+                    OnExit = function() saved = true end })
+                "#,
+            )
+            .exec()
+            .unwrap();
+        assert!(!host.exit_requested());
+        let key = HostEvent::KeyDown {
+            key: "q".into(),
+            double_click: false,
+        };
+        let cursor_pos = CursorPos::default();
+        dispatch_batch(&host, &mut InputLog::Live, 0, &cursor_pos, vec![key]).unwrap();
+        assert!(host.exit_requested());
+        shutdown(&host, None, &mut InputLog::Live, 0).unwrap();
+        assert!(host.lua.load("return saved").eval::<bool>().unwrap());
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use global_hotkey::hotkey::HotKey;
+use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+use mlua::prelude::*;
+
+enum Change {
+    Register(HotKey),
+    Unregister(HotKey),
+}
+
+/// Hotkey changes asked for by Lua and what became of them. The window
+/// thread applies the changes, as the OS registrations have to live there.
+#[derive(Default)]
+pub struct HotkeyRequests {
+    changes: Vec<Change>,
+    /// Hotkeys the OS refused, with why.
+    refused: Vec<(u32, String)>,
+    /// BringToFront was called since the window thread last looked.
+    raise: bool,
+}
+
+pub type SharedHotkeys = Arc<Mutex<HotkeyRequests>>;
+
+/// The OS side of the hotkeys, on the window thread. The manager is only
+/// created once Lua registers a hotkey.
+pub struct HotkeyManager {
+    requests: SharedHotkeys,
+    manager: Option<Result<GlobalHotKeyManager, String>>,
+}
+
+impl HotkeyManager {
+    pub fn new(requests: SharedHotkeys) -> Self {
+        Self {
+            requests,
+            manager: None,
+        }
+    }
+
+    /// Applies the changes Lua asked for. Returns whether the window
+    /// should come to the front.
+    pub fn apply(&mut self) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let changes = std::mem::take(&mut requests.changes);
+        if !changes.is_empty() {
+            let manager = self
+                .manager
+                .get_or_insert_with(|| GlobalHotKeyManager::new().map_err(|e| e.to_string()));
+            for change in changes {
+                match (change, &*manager) {
+                    (Change::Register(key), Ok(m)) => {
+                        if let Err(e) = m.register(key) {
+                            requests.refused.push((key.id(), e.to_string()));
+                        }
+                    }
+                    (Change::Register(key), Err(e)) => requests.refused.push((key.id(), e.clone())),
+                    (Change::Unregister(key), Ok(m)) => {
+                        m.unregister(key).ok();
+                    }
+                    (Change::Unregister(_), Err(_)) => {}
+                }
+            }
+        }
+        std::mem::take(&mut requests.raise)
+    }
+}
+
+struct Binding {
+    keys: String,
+    on_press: LuaRegistryKey,
+    on_conflict: Option<LuaRegistryKey>,
+}
+
+/// Global hotkeys Lua registered, by hotkey id, with their callbacks.
+#[derive(Clone, Default)]
+pub struct Hotkeys {
+    requests: SharedHotkeys,
+    bindings: Arc<Mutex<HashMap<u32, Binding>>>,
+}
+
+impl Hotkeys {
+    pub fn new(requests: SharedHotkeys) -> Self {
+        Self {
+            requests,
+            bindings: Arc::default(),
+        }
+    }
+
+    fn bind(
+        &self,
+        lua: &Lua,
+        keys: String,
+        on_press: LuaFunction,
+        on_conflict: Option<LuaFunction>,
+    ) -> LuaResult<Result<(), String>> {
+        let key = match HotKey::from_str(&keys) {
+            Ok(key) => key,
+            Err(e) => return Ok(Err(e.to_string())),
+        };
+        let binding = Binding {
+            keys,
+            on_press: lua.create_registry_value(on_press)?,
+            on_conflict: on_conflict
+                .map(|f| lua.create_registry_value(f))
+                .transpose()?,
+        };
+        // Binding the same keys again only swaps the callbacks.
+        if self
+            .bindings
+            .lock()
+            .unwrap()
+            .insert(key.id(), binding)
+            .is_none()
+        {
+            let mut requests = self.requests.lock().unwrap();
+            requests.changes.push(Change::Register(key));
+        }
+        Ok(Ok(()))
+    }
+
+    fn unbind(&self, keys: &str) -> bool {
+        let Ok(key) = HotKey::from_str(keys) else {
+            return false;
+        };
+        let bound = self.bindings.lock().unwrap().remove(&key.id()).is_some();
+        if bound {
+            let mut requests = self.requests.lock().unwrap();
+            requests.changes.push(Change::Unregister(key));
+        }
+        bound
+    }
+
+    /// Calls back for hotkeys pressed since the last call, and drops the
+    /// ones the OS refused after telling their conflict callbacks.
+    pub fn pump(&self, lua: &Lua) -> LuaResult<()> {
+        let refused = std::mem::take(&mut self.requests.lock().unwrap().refused);
+        for (id, reason) in refused {
+            let Some(binding) = self.bindings.lock().unwrap().remove(&id) else {
+                continue;
+            };
+            lua.remove_registry_value(binding.on_press)?;
+            match binding.on_conflict {
+                Some(key) => {
+                    let f: LuaFunction = lua.registry_value(&key)?;
+                    lua.remove_registry_value(key)?;
+                    if let Err(e) = f.call::<_, ()>(reason) {
+                        eprintln!("hotkey {} conflict callback: {}", binding.keys, e);
+                    }
+                }
+                None => eprintln!("hotkey {}: {}", binding.keys, reason),
+            }
+        }
+        let pressed: Vec<u32> = GlobalHotKeyEvent::receiver()
+            .try_iter()
+            .filter(|e| e.state == HotKeyState::Pressed)
+            .map(|e| e.id)
+            .collect();
+        for id in pressed {
+            let bindings = self.bindings.lock().unwrap();
+            let Some(binding) = bindings.get(&id) else {
+                continue;
+            };
+            let f: LuaFunction = lua.registry_value(&binding.on_press)?;
+            let keys = binding.keys.clone();
+            // The callback may register or unregister hotkeys itself.
+            drop(bindings);
+            if let Err(e) = f.call::<_, ()>(()) {
+                eprintln!("hotkey {} callback: {}", keys, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Registers `RegisterGlobalHotkey(keys, onPress, [onConflict]) -> ok, err`,
+/// `UnregisterGlobalHotkey(keys) -> wasBound` and `BringToFront()`. Keys
+/// look like "Ctrl+Shift+I" and work while other programs have focus;
+/// `onConflict(err)` hears when the OS refuses them, e.g. because another
+/// program holds them, and the binding is dropped.
+pub fn register(lua: &Lua, hotkeys: Hotkeys) -> LuaResult<()> {
+    let g = lua.globals();
+    let h = hotkeys.clone();
+    g.set(
+        "RegisterGlobalHotkey",
+        lua.create_function(
+            move |lua,
+                  (keys, on_press, on_conflict): (
+                String,
+                LuaFunction,
+                Option<LuaFunction>,
+            )| {
+                Ok(match h.bind(lua, keys, on_press, on_conflict)? {
+                    Ok(()) => (true, None),
+                    Err(e) => (false, Some(e)),
+                })
+            },
+        )?,
+    )?;
+    let h = hotkeys.clone();
+    g.set(
+        "UnregisterGlobalHotkey",
+        lua.create_function(move |_, keys: String| Ok(h.unbind(&keys)))?,
+    )?;
+    g.set(
+        "BringToFront",
+        lua.create_function(move |_, ()| {
+            hotkeys.requests.lock().unwrap().raise = true;
+            Ok(())
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_queue_changes_and_conflicts_reach_lua() {
+        let lua = Lua::new();
+        let hotkeys = Hotkeys::default();
+        register(&lua, hotkeys.clone()).unwrap();
+        let (ok, err): (bool, Option<String>) = lua
+            .load(
+                r#"
+                RegisterGlobalHotkey("Ctrl+Shift+I", function() end,
+                    function(e) conflict = e end)
+                RegisterGlobalHotkey("Ctrl+Shift+I", function() end,
+                    function(e) conflict = "again: " .. e end)
+                RegisterGlobalHotkey("Ctrl+Alt+P", function() end)
+                return RegisterGlobalHotkey("Ctrl+Nope", function() end)
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(!ok && err.is_some());
+        // The second binding of the same keys isn't registered twice.
+        assert_eq!(hotkeys.requests.lock().unwrap().changes.len(), 2);
+
+        let id = HotKey::from_str("Ctrl+Shift+I").unwrap().id();
+        hotkeys
+            .requests
+            .lock()
+            .unwrap()
+            .refused
+            .push((id, "taken".into()));
+        hotkeys.pump(&lua).unwrap();
+        let conflict: String = lua.globals().get("conflict").unwrap();
+        assert_eq!(conflict, "again: taken");
+        assert!(!hotkeys.unbind("Ctrl+Shift+I"));
+        assert!(hotkeys.unbind("ctrl+alt+p"));
+        assert_eq!(hotkeys.requests.lock().unwrap().changes.len(), 3);
+    }
+}
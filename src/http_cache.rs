@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// A stored response, replayed to Lua as if it came off the wire.
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub status: u16,
+    /// Header lines exactly as the header callback received them.
+    pub header_lines: Vec<String>,
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    expires: Instant,
+    /// When it was stored, in store order; the lowest is evicted first.
+    seq: u64,
+}
+
+impl CachedResponse {
+    fn bytes(&self) -> usize {
+        self.body.len() + self.header_lines.iter().map(String::len).sum::<usize>()
+    }
+
+    pub fn is_fresh(&self, now: Instant) -> bool {
+        now < self.expires
+    }
+}
+
+/// In-memory GET cache shared by every Lua state, so repeated trade and
+/// poe.ninja lookups don't spend rate-limit budget.
+pub struct HttpCache {
+    entries: HashMap<String, CachedResponse>,
+    pub max_entries: usize,
+    /// Responses bigger than this aren't stored, and the oldest entries
+    /// make way once all of them together would pass `max_total_bytes`.
+    pub max_entry_bytes: usize,
+    pub max_total_bytes: usize,
+    /// Lifetime for responses that don't say how long they're good for.
+    pub default_ttl: Duration,
+    total_bytes: usize,
+    next_seq: u64,
+}
+
+impl Default for HttpCache {
+    fn default() -> Self {
+        Self::new(256, Duration::from_secs(60))
+    }
+}
+
+impl HttpCache {
+    pub fn new(max_entries: usize, default_ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries,
+            max_entry_bytes: 4 << 20,
+            max_total_bytes: 64 << 20,
+            default_ttl,
+            total_bytes: 0,
+            next_seq: 0,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Option<&CachedResponse> {
+        self.entries.get(url)
+    }
+
+    /// Stores a 200 response unless its Cache-Control forbids it or it is
+    /// over `max_entry_bytes`, evicting the oldest entries to make room.
+    pub fn store(
+        &mut self,
+        url: &str,
+        response: Response,
+        cache_control: Option<&str>,
+        now: Instant,
+    ) {
+        if self.max_entries == 0 || response.status != 200 {
+            return;
+        }
+        let Some(ttl) = lifetime(cache_control, self.default_ttl) else {
+            return;
+        };
+        // Nothing to revalidate with and already stale: not worth keeping.
+        if ttl.is_zero() && response.etag.is_none() && response.last_modified.is_none() {
+            return;
+        }
+        let entry = CachedResponse {
+            status: response.status,
+            header_lines: response.header_lines,
+            body: response.body,
+            etag: response.etag,
+            last_modified: response.last_modified,
+            expires: now + ttl,
+            seq: self.next_seq,
+        };
+        let bytes = entry.bytes();
+        if bytes > self.max_entry_bytes || bytes > self.max_total_bytes {
+            return;
+        }
+        self.next_seq += 1;
+        self.remove(url);
+        while self.entries.len() >= self.max_entries
+            || self.total_bytes + bytes > self.max_total_bytes
+        {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.seq)
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+        self.total_bytes += bytes;
+        self.entries.insert(url.to_string(), entry);
+    }
+
+    fn remove(&mut self, url: &str) {
+        if let Some(entry) = self.entries.remove(url) {
+            self.total_bytes -= entry.bytes();
+        }
+    }
+
+    /// Extends an entry after the server answered 304 Not Modified.
+    pub fn refresh(&mut self, url: &str, cache_control: Option<&str>, now: Instant) {
+        let ttl = lifetime(cache_control, self.default_ttl);
+        match (self.entries.get_mut(url), ttl) {
+            (Some(entry), Some(ttl)) => entry.expires = now + ttl,
+            (Some(_), None) => self.remove(url),
+            _ => {}
+        }
+    }
+}
+
+/// A response as handed to [`HttpCache::store`].
+pub struct Response {
+    pub status: u16,
+    pub header_lines: Vec<String>,
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+const MAX_AGE_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Freshness lifetime from Cache-Control; None when it mustn't be stored.
+fn lifetime(cache_control: Option<&str>, default: Duration) -> Option<Duration> {
+    let Some(cc) = cache_control else {
+        return Some(default);
+    };
+    let mut ttl = default;
+    for directive in cc.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+        if directive == "no-store" {
+            return None;
+        }
+        if directive == "no-cache" {
+            ttl = Duration::ZERO;
+        } else if let Some(secs) = directive.strip_prefix("max-age=")
+            && let Ok(secs) = secs.trim_matches('"').parse::<u64>()
+        {
+            // A year is as good as forever, and can't overflow an Instant.
+            ttl = Duration::from_secs(secs.min(MAX_AGE_SECS));
+        }
+    }
+    Some(ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str, etag: Option<&str>) -> Response {
+        Response {
+            status: 200,
+            header_lines: vec!["HTTP/1.1 200 OK\r\n".into(), "\r\n".into()],
+            body: body.as_bytes().to_vec(),
+            etag: etag.map(String::from),
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn honours_cache_control_and_evicts() {
+        let mut cache = HttpCache::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.store("a", response("1", None), Some("max-age=5"), now);
+        cache.store("b", response("2", None), Some("no-store"), now);
+        cache.store("c", response("3", Some("\"v1\"")), Some("no-cache"), now);
+        assert!(
+            cache
+                .get("a")
+                .unwrap()
+                .is_fresh(now + Duration::from_secs(4))
+        );
+        assert!(
+            !cache
+                .get("a")
+                .unwrap()
+                .is_fresh(now + Duration::from_secs(5))
+        );
+        assert!(cache.get("b").is_none());
+        // no-cache is kept for revalidation but never fresh
+        assert!(!cache.get("c").unwrap().is_fresh(now));
+        cache.refresh("c", Some("max-age=30"), now);
+        assert!(cache.get("c").unwrap().is_fresh(now));
+
+        cache.store("d", response("4", None), None, now);
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.get("d").is_some());
+    }
+
+    #[test]
+    fn byte_limits_skip_big_responses_and_evict_the_oldest() {
+        let mut cache = HttpCache::new(100, Duration::from_secs(60));
+        let headers = response("", None).header_lines.concat().len();
+        cache.max_entry_bytes = headers + 10;
+        cache.max_total_bytes = 3 * (headers + 10);
+        let now = Instant::now();
+        cache.store("big", response(&"x".repeat(11), None), None, now);
+        assert!(cache.get("big").is_none());
+
+        for url in ["a", "b", "c"] {
+            cache.store(url, response(&"x".repeat(10), None), None, now);
+        }
+        // Replacing an entry frees its bytes first, so nothing else goes.
+        cache.store("a", response(&"y".repeat(10), None), None, now);
+        assert_eq!(cache.entries.len(), 3);
+        // A fourth doesn't fit; b is now the oldest.
+        cache.store("d", response(&"z".repeat(10), None), None, now);
+        assert!(cache.get("b").is_none());
+        for url in ["a", "c", "d"] {
+            assert!(cache.get(url).is_some(), "{}", url);
+        }
+        assert_eq!(cache.total_bytes, cache.max_total_bytes);
+        cache.refresh("c", Some("no-store"), now);
+        assert_eq!(cache.total_bytes, 2 * (headers + 10));
+    }
+}
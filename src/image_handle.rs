@@ -0,0 +1,442 @@
+use std::sync::{
+    Arc, Mutex,
+    mpsc::{Receiver, TryRecvError, channel},
+};
+
+use mlua::prelude::*;
+
+use crate::asset_cache;
+use crate::graphics::{
+    PixelFormat, TextureCmd, TextureFilter, TextureQueue, TextureUploadCmd, UploadErrors,
+};
+use crate::host_thread::Waker;
+use crate::sprite_sheet::{SpriteSheet, SpriteSheets};
+use crate::svg::{SvgImage, SvgImages};
+use crate::texture_cache::TextureCache;
+use crate::texture_ids::{SharedTextureIds, TextureLease};
+use crate::vfs::SharedVfs;
+
+/// What image handles need to load and forget their textures.
+#[derive(Clone)]
+pub struct Images {
+    pub ids: SharedTextureIds,
+    pub queue: TextureQueue,
+    pub svgs: SvgImages,
+    pub sheets: SpriteSheets,
+    pub files: SharedVfs,
+    /// The window's scale factor, which SVG images are rasterized at.
+    pub dpi_scale: Arc<Mutex<f32>>,
+    /// Uploads the window thread couldn't make textures of.
+    pub upload_errors: UploadErrors,
+    pub textures: Option<Arc<TextureCache>>,
+    /// Woken when a background decode is done.
+    pub waker: Waker,
+}
+
+type Decoded = Result<TextureUploadCmd, String>;
+
+/// The userdata behind `NewImageHandle()`. Its texture lives as long as
+/// the handle does.
+pub struct ImageHandle {
+    lease: TextureLease,
+    images: Images,
+    valid: bool,
+    width: u32,
+    height: u32,
+    /// An image decoding in the background, by the name errors report.
+    pending: Option<(String, Receiver<Decoded>)>,
+}
+
+impl ImageHandle {
+    fn new(images: Images) -> Self {
+        let (svgs, sheets) = (images.svgs.clone(), images.sheets.clone());
+        let errors = images.upload_errors.clone();
+        let lease = TextureLease::new(images.ids.clone(), images.queue.clone(), move |id| {
+            svgs.lock().unwrap().remove(&id);
+            sheets.lock().unwrap().remove(&id);
+            errors.lock().unwrap().remove(&id);
+        });
+        Self {
+            lease,
+            images,
+            valid: false,
+            width: 0,
+            height: 0,
+            pending: None,
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.lease.id()
+    }
+
+    fn set_filter(&self, filter: Option<TextureFilter>) {
+        let id = self.id();
+        let queue = &self.images.queue;
+        queue
+            .lock()
+            .unwrap()
+            .push(TextureCmd::SetFilter { id, filter });
+    }
+
+    /// Queues an upload the GPU will take; others invalidate the handle.
+    fn upload(&mut self, name: &str, upload: TextureUploadCmd) {
+        if let Err(e) = upload.validate() {
+            eprintln!("Load image {}: {}", name, e);
+            self.valid = false;
+            return;
+        }
+        (self.width, self.height) = (upload.width, upload.height);
+        self.valid = true;
+        // Whatever failed before is replaced by this upload.
+        self.images.upload_errors.lock().unwrap().remove(&upload.id);
+        let queue = &self.images.queue;
+        queue.lock().unwrap().push(TextureCmd::Upload(upload));
+    }
+
+    /// Loads an image file: a cached RGBA blob, an SVG or anything the
+    /// `image` crate decodes. Failures leave the handle as it was. Raster
+    /// images decode in the background when `background` is set.
+    fn load(&mut self, path: &str, background: bool) {
+        self.pending = None;
+        let id = self.id();
+        let data = match self.images.files.lock().unwrap().read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Load image {}: {}", path, e);
+                return;
+            }
+        };
+        if path.to_ascii_lowercase().ends_with(".svg") {
+            let mut svg = match SvgImage::parse(&data) {
+                Ok(svg) => svg,
+                Err(e) => {
+                    eprintln!("Load image {}: {}", path, e);
+                    return;
+                }
+            };
+            let size = svg.size();
+            let upload = svg.rasterize(id, *self.images.dpi_scale.lock().unwrap());
+            self.images.svgs.lock().unwrap().insert(id, svg);
+            self.upload(path, upload);
+            if !self.valid {
+                return;
+            }
+            // Handles report the SVG's own size, not the rasterized one.
+            (self.width, self.height) = size;
+            return;
+        }
+        self.images.svgs.lock().unwrap().remove(&id);
+        if background {
+            self.decode_in_background(path.to_string(), data);
+            return;
+        }
+        match decode(id, &data, path, self.images.textures.as_deref()) {
+            Ok(upload) => self.upload(path, upload),
+            Err(e) => eprintln!("Load image {}: {}", path, e),
+        }
+    }
+
+    fn decode_in_background(&mut self, name: String, data: Vec<u8>) {
+        let (tx, rx) = channel();
+        let (id, textures, hint) = (self.id(), self.images.textures.clone(), name.clone());
+        let waker = self.images.waker.clone();
+        std::thread::spawn(move || {
+            tx.send(decode(id, &data, &hint, textures.as_deref())).ok();
+            waker.wake();
+        });
+        self.pending = Some((name, rx));
+    }
+
+    /// Uploads a finished background decode, and notices an upload the
+    /// GPU refused.
+    fn poll(&mut self) {
+        // The window thread has reported the error already.
+        if self
+            .images
+            .upload_errors
+            .lock()
+            .unwrap()
+            .remove(&self.id())
+            .is_some()
+        {
+            self.valid = false;
+        }
+        let Some((name, rx)) = &self.pending else {
+            return;
+        };
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            Ok(Ok(upload)) => {
+                let name = name.clone();
+                self.upload(&name, upload)
+            }
+            Ok(Err(e)) => eprintln!("Load image {}: {}", name, e),
+            Err(TryRecvError::Disconnected) => {}
+        }
+        self.pending = None;
+    }
+}
+
+/// Decodes a cached RGBA blob or an encoded image. `name` hints at the
+/// format; without a known extension it is guessed from the data.
+fn decode(id: u32, data: &[u8], name: &str, textures: Option<&TextureCache>) -> Decoded {
+    if let Some((width, height, rgba)) = asset_cache::decode_image(data) {
+        return Ok(TextureUploadCmd {
+            id,
+            pixels: rgba.to_vec().into(),
+            format: PixelFormat::Rgba8,
+            width,
+            height,
+            premultiplied: false,
+        });
+    }
+    if let Some(upload) = textures.and_then(|c| c.load(id, data)) {
+        return Ok(upload);
+    }
+    let img = match image::ImageFormat::from_path(name) {
+        Ok(format) => image::load_from_memory_with_format(data, format),
+        Err(_) => image::load_from_memory(data),
+    }
+    .map_err(|e| e.to_string())?
+    .to_rgba8();
+    let upload = TextureUploadCmd {
+        id,
+        width: img.width(),
+        height: img.height(),
+        pixels: img.into_raw().into(),
+        format: PixelFormat::Rgba8,
+        premultiplied: false,
+    };
+    Ok(match textures {
+        Some(cache) => cache.store(data, upload),
+        None => upload,
+    })
+}
+
+impl LuaUserData for ImageHandle {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Load(path, [flags...]): an "ASYNC" flag decodes in the background,
+        // and "NEAREST" or "LINEAR" picks the filter, linear by default.
+        methods.add_method_mut("Load", |_, this, (path, flags): (String, LuaMultiValue)| {
+            let flags: Vec<String> = flags
+                .iter()
+                .filter_map(|f| match f {
+                    LuaValue::String(s) => s.to_str().ok().map(str::to_string),
+                    _ => None,
+                })
+                .collect();
+            let background = flags.iter().any(|f| f == "ASYNC");
+            this.set_filter(flags.iter().find_map(|f| TextureFilter::parse(f)));
+            this.load(&path, background);
+            Ok(())
+        });
+        // SetFilter("NEAREST" | "LINEAR" | nil), until the next Load; nil
+        // goes back to linear.
+        methods.add_method("SetFilter", |_, this, name: Option<String>| {
+            let filter = match name.as_deref() {
+                None => None,
+                Some(name) => Some(TextureFilter::parse(name).ok_or_else(|| {
+                    LuaError::RuntimeError(format!("unknown filter \"{}\"", name))
+                })?),
+            };
+            this.set_filter(filter);
+            Ok(())
+        });
+        // LoadFromMemory(data, [name]): encoded image bytes, such as an
+        // HTTP response body, always decoded in the background. The name
+        // is used for messages and to tell the format.
+        methods.add_method_mut(
+            "LoadFromMemory",
+            |_, this, (data, name): (LuaString, Option<String>)| {
+                this.images.svgs.lock().unwrap().remove(&this.id());
+                let name = name.unwrap_or_else(|| "<memory>".into());
+                this.decode_in_background(name, data.as_bytes().to_vec());
+                Ok(())
+            },
+        );
+        methods.add_method_mut("IsLoading", |_, this, ()| {
+            this.poll();
+            Ok(this.pending.is_some())
+        });
+        methods.add_method_mut("IsValid", |_, this, ()| {
+            this.poll();
+            Ok(this.valid)
+        });
+        methods.add_method_mut("ImageSize", |_, this, ()| {
+            this.poll();
+            Ok((this.width, this.height))
+        });
+        methods.add_method_mut("Unload", |_, this, ()| {
+            this.pending = None;
+            let id = this.id();
+            this.images
+                .queue
+                .lock()
+                .unwrap()
+                .push(TextureCmd::Unload(id));
+            this.images.svgs.lock().unwrap().remove(&id);
+            this.valid = false;
+            Ok(())
+        });
+        methods.add_method(
+            "SetSubImage",
+            |_, this, (name, x, y, w, h): (String, u32, u32, u32, u32)| {
+                let mut sheets = this.images.sheets.lock().unwrap();
+                sheets
+                    .entry(this.id())
+                    .or_default()
+                    .set(&name, [x, y, w, h]);
+                Ok(())
+            },
+        );
+        methods.add_method("LoadSubImages", |_, this, path: String| {
+            let text = this
+                .images
+                .files
+                .lock()
+                .unwrap()
+                .read_to_string(&path)
+                .map_err(LuaError::external)?;
+            let sheet = SpriteSheet::parse(&text)
+                .map_err(|e| LuaError::RuntimeError(format!("{}: {}", path, e)))?;
+            let count = sheet.len();
+            this.images.sheets.lock().unwrap().insert(this.id(), sheet);
+            Ok(count)
+        });
+        methods.add_method("GetSubImage", |_, this, name: String| {
+            let uv = this
+                .images
+                .sheets
+                .lock()
+                .unwrap()
+                .get(&this.id())
+                .and_then(|s| s.uv(&name, this.width, this.height));
+            Ok(uv.into_iter().flatten().collect::<mlua::Variadic<f32>>())
+        });
+        methods.add_method("SetLoadingPriority", |_, _, _: LuaMultiValue| Ok(()));
+    }
+}
+
+/// The texture id and size of an image handle or render target passed to
+/// a draw call; id 0 (plain white) for anything else.
+pub fn texture_of(value: &LuaValue) -> (u32, [u32; 2]) {
+    match value {
+        LuaValue::UserData(ud) => ud
+            .borrow_mut::<ImageHandle>()
+            .map(|mut h| {
+                h.poll();
+                (h.id(), [h.width, h.height])
+            })
+            .unwrap_or((0, [0, 0])),
+        LuaValue::Table(t) => (
+            t.get("id").unwrap_or(0),
+            [t.get("width").unwrap_or(0), t.get("height").unwrap_or(0)],
+        ),
+        _ => (0, [0, 0]),
+    }
+}
+
+/// Registers `NewImageHandle()`.
+pub fn register(lua: &Lua, images: Images) -> LuaResult<()> {
+    lua.globals().set(
+        "NewImageHandle",
+        lua.create_function(move |_, ()| Ok(ImageHandle::new(images.clone())))?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::archive::Archives;
+    use crate::vfs::Vfs;
+
+    #[test]
+    fn handles_load_files_and_memory_and_keep_their_id_private() {
+        let dir = std::env::temp_dir().join(format!("pob-image-handle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbaImage::new(3, 2)
+            .save(dir.join("icon.png"))
+            .unwrap();
+        let images = Images {
+            ids: SharedTextureIds::default(),
+            queue: TextureQueue::default(),
+            svgs: Arc::new(Mutex::new(HashMap::new())),
+            sheets: Arc::new(Mutex::new(HashMap::new())),
+            files: Arc::new(Mutex::new(Vfs::new(
+                dir.clone(),
+                Archives::new(dir.clone()),
+            ))),
+            dpi_scale: Arc::new(Mutex::new(1.0)),
+            upload_errors: Arc::default(),
+            textures: None,
+            waker: Waker::default(),
+        };
+        let lua = Lua::new();
+        register(&lua, images.clone()).unwrap();
+        let handle: LuaValue = lua
+            .load(
+                r#"
+                local h = NewImageHandle()
+                h:Load("icon.png", "NEAREST")
+                assert(h:IsValid())
+                local w, hh = h:ImageSize()
+                assert(w == 3 and hh == 2)
+                assert(not pcall(function() h.id = 5 end))
+                return h
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(texture_of(&handle), (1, [3, 2]));
+        assert!(matches!(
+            images.queue.lock().unwrap()[..],
+            [
+                TextureCmd::SetFilter {
+                    id: 1,
+                    filter: Some(TextureFilter::Nearest)
+                },
+                TextureCmd::Upload(TextureUploadCmd {
+                    id: 1,
+                    width: 3,
+                    ..
+                })
+            ]
+        ));
+
+        // The same PNG from memory decodes in the background.
+        let png = std::fs::read(dir.join("icon.png")).unwrap();
+        let h: LuaAnyUserData = lua.load("return NewImageHandle()").eval().unwrap();
+        h.call_method::<_, ()>("LoadFromMemory", lua.create_string(&png).unwrap())
+            .unwrap();
+        for _ in 0..200 {
+            if !h.call_method::<_, bool>("IsLoading", ()).unwrap() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(h.call_method::<_, bool>("IsValid", ()).unwrap());
+        assert_eq!(
+            h.call_method::<_, (u32, u32)>("ImageSize", ()).unwrap(),
+            (3, 2)
+        );
+        assert_eq!(images.queue.lock().unwrap().len(), 3);
+
+        // An upload the GPU refused invalidates the handle.
+        images
+            .upload_errors
+            .lock()
+            .unwrap()
+            .insert(2, "out of memory".into());
+        assert!(!h.call_method::<_, bool>("IsValid", ()).unwrap());
+        assert!(images.upload_errors.lock().unwrap().is_empty());
+        assert!(
+            lua.load(r#"NewImageHandle():SetFilter("BICUBIC")"#)
+                .exec()
+                .is_err()
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
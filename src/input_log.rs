@@ -0,0 +1,203 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::host_thread::HostEvent;
+
+/// One recorded happening, replayed on the host frame it was seen on.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Entry {
+    Input(HostEvent),
+    /// The window's inner size changed.
+    Resize([u32; 2]),
+    /// The session closed; replay stops here.
+    End,
+}
+
+/// A log line: the host frame, milliseconds since recording started, and
+/// the entry.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    frame: u64,
+    ms: u64,
+    entry: Entry,
+}
+
+/// Writes entries as JSON lines, flushed one by one so a crash keeps
+/// everything up to it.
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    fn log(&mut self, frame: u64, entry: Entry) -> io::Result<()> {
+        let line = Line {
+            frame,
+            ms: self.start.elapsed().as_millis() as u64,
+            entry,
+        };
+        serde_json::to_writer(&mut self.out, &line)?;
+        self.out.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Milliseconds a replayed frame past the last entry is taken to last.
+const FRAME_MS: f64 = 1000.0 / 60.0;
+
+/// A recording read back for replay.
+pub struct Replay {
+    entries: VecDeque<(u64, Entry)>,
+    /// The first recorded time of each frame with entries, in ms.
+    times: Vec<(u64, u64)>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut entries = VecDeque::new();
+        let mut times: Vec<(u64, u64)> = Vec::new();
+        for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line: Line = serde_json::from_str(&line).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, e))
+            })?;
+            match times.last() {
+                Some(&(frame, _)) if line.frame <= frame => {}
+                last => times.push((line.frame, line.ms.max(last.map_or(0, |t| t.1)))),
+            }
+            entries.push_back((line.frame, line.entry));
+        }
+        Ok(Self { entries, times })
+    }
+
+    /// The session clock on `frame`: the recorded time on frames with
+    /// entries, spread evenly over the frames between them, and 60 Hz
+    /// after the last. It depends only on the recording, so every replay
+    /// sees the same delta times.
+    pub fn time(&self, frame: u64) -> Duration {
+        let next = self.times.partition_point(|&(f, _)| f <= frame);
+        let (f0, ms0) = match next {
+            0 => (0, 0),
+            n => self.times[n - 1],
+        };
+        let ms = match self.times.get(next) {
+            Some(&(f1, ms1)) => {
+                ms0 as f64 + (ms1 - ms0) as f64 * (frame - f0) as f64 / (f1 - f0) as f64
+            }
+            None => ms0 as f64 + (frame - f0) as f64 * FRAME_MS,
+        };
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+
+    /// Entries due on or before `frame`, in recorded order. A recording
+    /// that ran out without `End` ends right after its last entry.
+    pub fn take(&mut self, frame: u64) -> Vec<Entry> {
+        let mut out = Vec::new();
+        while self.entries.front().is_some_and(|(f, _)| *f <= frame) {
+            out.extend(self.entries.pop_front().map(|(_, e)| e));
+        }
+        if self.entries.is_empty() && out.last() != Some(&Entry::End) {
+            out.push(Entry::End);
+        }
+        out
+    }
+}
+
+/// What the host does with input for this session.
+pub enum InputLog {
+    Live,
+    Record(Recorder),
+    /// Feeds a recording in place of live input.
+    Replay(Replay),
+    /// Runs a Lua test script that synthesizes the input.
+    Automate(PathBuf),
+}
+
+impl InputLog {
+    /// Logs the entry when recording. A failed write ends the recording
+    /// rather than the session.
+    pub fn record(&mut self, frame: u64, entry: impl FnOnce() -> Entry) {
+        let Self::Record(rec) = self else { return };
+        if let Err(e) = rec.log(frame, entry()).and_then(|_| rec.flush()) {
+            eprintln!("input recording stopped: {}", e);
+            *self = Self::Live;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_recorded_entries_by_frame() {
+        let path = std::env::temp_dir().join(format!("pob-input-{}.jsonl", std::process::id()));
+        let mut rec = Recorder::create(&path).unwrap();
+        rec.log(0, Entry::Resize([800, 600])).unwrap();
+        rec.log(3, Entry::Input(HostEvent::MouseMove { x: 1.5, y: 2.0 }))
+            .unwrap();
+        let down = HostEvent::KeyDown {
+            key: "LEFTBUTTON".into(),
+            double_click: false,
+        };
+        rec.log(3, Entry::Input(down)).unwrap();
+        rec.log(7, Entry::Input(HostEvent::Char("é".into())))
+            .unwrap();
+        rec.flush().unwrap();
+        drop(rec);
+
+        let mut replay = Replay::load(&path).unwrap();
+        assert_eq!(replay.take(0), vec![Entry::Resize([800, 600])]);
+        assert!(replay.take(2).is_empty());
+        assert_eq!(replay.take(5).len(), 2);
+        assert_eq!(
+            replay.take(9),
+            vec![Entry::Input(HostEvent::Char("é".into())), Entry::End]
+        );
+
+        std::fs::write(&path, "{\"frame\":1}\n").unwrap();
+        let err = Replay::load(&path).err().unwrap();
+        assert!(err.to_string().starts_with("line 1:"));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn replay_clock_follows_the_recorded_times() {
+        let path = std::env::temp_dir().join(format!("pob-clock-{}.jsonl", std::process::id()));
+        let lines = [
+            r#"{"frame":2,"ms":40,"entry":{"Resize":[800,600]}}"#,
+            r#"{"frame":2,"ms":45,"entry":{"Input":{"Char":"a"}}}"#,
+            r#"{"frame":6,"ms":140,"entry":{"Input":{"Char":"b"}}}"#,
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let ms = |frame| replay.time(frame).as_secs_f64() * 1000.0;
+        assert_eq!(ms(0), 0.0);
+        assert_eq!(ms(1), 20.0);
+        assert_eq!(ms(2), 40.0);
+        assert_eq!(ms(4), 90.0);
+        assert_eq!(ms(6), 140.0);
+        assert!((ms(12) - 240.0).abs() < 1e-9);
+    }
+}
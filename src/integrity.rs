@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use quick_xml::{Reader, events::Event};
+use sha1::{Digest, Sha1};
+
+use crate::layout::Layout;
+use crate::vfs::{SharedVfs, Vfs};
+
+/// A Lua file listed in PoB's `manifest.xml`.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    name: String,
+    path: PathBuf,
+    sha1: String,
+}
+
+/// Lua files that differ from the manifest, by their manifest names.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub checked: usize,
+    pub modified: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "script check: {} of {} files match manifest.xml",
+            self.checked - self.modified.len() - self.missing.len(),
+            self.checked
+        )?;
+        for name in &self.modified {
+            write!(f, "\n  modified: {}", name)?;
+        }
+        for name in &self.missing {
+            write!(f, "\n  missing:  {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the `<File>` entries of a manifest found in `dir`. `program` and
+/// `tree` files live in the script directory, `runtime` files in the
+/// runtime directory and the rest next to the manifest.
+fn parse(text: &str, dir: &Path, layout: &Layout) -> Result<Vec<Entry>, String> {
+    let mut reader = Reader::from_str(text);
+    let mut entries = Vec::new();
+    loop {
+        let e = match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Empty(e) | Event::Start(e) if e.name().as_ref() == b"File" => e,
+            Event::Eof => return Ok(entries),
+            _ => continue,
+        };
+        let (mut name, mut part, mut sha1) = (None, String::new(), None);
+        for attr in e.attributes() {
+            let attr = attr.map_err(|e| e.to_string())?;
+            let value = attr
+                .unescape_value()
+                .map_err(|e| e.to_string())?
+                .into_owned();
+            match attr.key.as_ref() {
+                b"name" => name = Some(value),
+                b"part" => part = value,
+                b"sha1" => sha1 = Some(value),
+                _ => {}
+            }
+        }
+        let (Some(name), Some(sha1)) = (name, sha1) else {
+            continue;
+        };
+        if !name.ends_with(".lua") {
+            continue;
+        }
+        let base = match part.as_str() {
+            "program" | "tree" => &layout.script_dir,
+            "runtime" => &layout.runtime_dir,
+            _ => dir,
+        };
+        entries.push(Entry {
+            path: base.join(&name),
+            name,
+            sha1: sha1.to_ascii_lowercase(),
+        });
+    }
+}
+
+/// The manifest sits next to `Launch.lua` in an install and one level up
+/// in a checkout.
+fn manifest(layout: &Layout, vfs: &mut Vfs) -> Option<Vec<Entry>> {
+    let dirs = [
+        Some(layout.script_dir.as_path()),
+        layout.script_dir.parent(),
+    ];
+    for dir in dirs.into_iter().flatten() {
+        let path = dir.join("manifest.xml");
+        if let Ok(text) = vfs.read_to_string(&path.to_string_lossy()) {
+            return parse(&text, dir, layout)
+                .map_err(|e| eprintln!("{}: {}", path.display(), e))
+                .ok();
+        }
+    }
+    None
+}
+
+/// Whether `data` hashes to `sha1`, allowing for either line ending style
+/// since git may have converted them on checkout.
+fn matches(data: &[u8], sha1: &str) -> bool {
+    let hash = |data: &[u8]| format!("{:x}", Sha1::digest(data)) == sha1;
+    if hash(data) {
+        return true;
+    }
+    let text = String::from_utf8_lossy(data);
+    hash(text.replace("\r\n", "\n").as_bytes()) || hash(text.replace('\n', "\r\n").as_bytes())
+}
+
+/// Hashes the scripts against the manifest. None without a manifest.
+pub fn check(layout: &Layout, vfs: &SharedVfs) -> Option<Report> {
+    let entries = manifest(layout, &mut vfs.lock().unwrap())?;
+    let mut report = Report {
+        checked: entries.len(),
+        ..Report::default()
+    };
+    for entry in entries {
+        // One file at a time, so the host can keep loading in between.
+        let data = vfs.lock().unwrap().read(&entry.path.to_string_lossy());
+        match data {
+            Ok(data) if matches(&data, &entry.sha1) => {}
+            Ok(_) => report.modified.push(entry.name),
+            Err(_) => report.missing.push(entry.name),
+        }
+    }
+    Some(report)
+}
+
+/// Runs `check` on a background thread and prints the report.
+pub fn spawn(layout: Layout, vfs: SharedVfs) {
+    std::thread::spawn(move || {
+        if let Some(report) = check(&layout, &vfs) {
+            println!("{}", report);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::archive::Archives;
+
+    #[test]
+    fn reports_modified_and_missing_scripts() {
+        let dir = std::env::temp_dir().join(format!("pob-integrity-{}", std::process::id()));
+        let layout = Layout::standard(&dir);
+        std::fs::create_dir_all(layout.script_dir.join("Modules")).unwrap();
+        std::fs::create_dir_all(layout.runtime_dir.join("lua")).unwrap();
+        std::fs::write(layout.script_dir.join("Launch.lua"), "launch\r\n").unwrap();
+        std::fs::write(layout.script_dir.join("Modules/Main.lua"), "edited").unwrap();
+        std::fs::write(layout.runtime_dir.join("lua/xml.lua"), "xml").unwrap();
+        let sha1 = |text: &str| format!("{:x}", Sha1::digest(text.as_bytes()));
+        let manifest = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <PoBVersion>
+                <Version number="2.50.0" />
+                <File name="changelog.txt" part="default" sha1="{}" />
+                <File name="Launch.lua" part="program" sha1="{}" />
+                <File name="Modules/Main.lua" part="program" sha1="{}" />
+                <File name="TreeData/3_25/tree.lua" part="tree" sha1="{}" />
+                <File name="lua/xml.lua" part="runtime" sha1="{}" />
+            </PoBVersion>"#,
+            sha1("log"),
+            sha1("launch\n"),
+            sha1("main"),
+            sha1("tree"),
+            sha1("xml"),
+        );
+        std::fs::write(dir.join("PathOfBuilding/manifest.xml"), manifest).unwrap();
+
+        let vfs = Vfs::new(
+            layout.script_dir.clone(),
+            Archives::new(layout.script_dir.clone()),
+        );
+        let report = check(&layout, &Arc::new(Mutex::new(vfs))).unwrap();
+        assert_eq!(
+            report,
+            Report {
+                checked: 4,
+                modified: vec!["Modules/Main.lua".into()],
+                missing: vec!["TreeData/3_25/tree.lua".into()],
+            }
+        );
+        assert!(report.to_string().starts_with("script check: 2 of 4"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex, mpsc},
+    time::Duration,
+};
+
+use crate::host_thread::Waker;
+
+/// The socket in the user path that tools connect to, or on Windows the
+/// file there naming the pipe. Either only opens to the current user.
+#[cfg(unix)]
+const SOCKET_FILE: &str = "ipc.sock";
+#[cfg(windows)]
+const PIPE_FILE: &str = "ipc.pipe";
+
+/// Longest message either side accepts.
+const MAX_MESSAGE: usize = 16 << 20;
+
+/// How long a connection waits for the host to answer, e.g. while a build
+/// loads.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request's first byte. Every message either way is a little-endian
+/// `u32` length and that many bytes: a command and its UTF-8 argument, or
+/// a status byte (`0` ok, `1` error) and the UTF-8 result.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Replies with the runtime version.
+    Ping = 0,
+    /// Opens a build code, file or `pob://` link; replies once it is
+    /// queued, before a download finishes.
+    LoadBuild = 1,
+    /// Replies with the open build's code.
+    ExportBuild = 2,
+    /// Replies with the open build's main stats as a JSON object.
+    Summary = 3,
+}
+
+impl Command {
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(Self::Ping),
+            1 => Some(Self::LoadBuild),
+            2 => Some(Self::ExportBuild),
+            3 => Some(Self::Summary),
+            _ => None,
+        }
+    }
+}
+
+/// A request waiting for the host thread.
+pub struct Call {
+    pub command: Command,
+    pub arg: String,
+    reply: mpsc::Sender<Result<String, String>>,
+}
+
+impl Call {
+    pub fn answer(self, result: Result<String, String>) {
+        self.reply.send(result).ok();
+    }
+}
+
+/// Requests from every connection, taken by the host each frame.
+pub type SharedCalls = Arc<Mutex<Vec<Call>>>;
+
+/// Starts serving tools on the user path `dir`, handing their requests to
+/// the host through `calls`.
+#[cfg(unix)]
+pub fn listen(dir: &Path, calls: SharedCalls, waker: Waker) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join(SOCKET_FILE);
+    // Left behind by an instance that didn't shut down cleanly; the
+    // instance lock keeps a live one from being replaced.
+    std::fs::remove_file(&path).ok();
+    let listener = std::os::unix::net::UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    std::thread::Builder::new()
+        .name("ipc".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => spawn_client(stream, &calls, &waker),
+                    Err(e) => eprintln!("ipc: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Starts serving tools on a named pipe only the current user can open,
+/// named in `PIPE_FILE` on the user path `dir`, handing their requests to
+/// the host through `calls`.
+#[cfg(windows)]
+pub fn listen(dir: &Path, calls: SharedCalls, waker: Waker) -> io::Result<()> {
+    let name = format!(r"\\.\pipe\pob-runtime-ipc-{}", std::process::id());
+    let wide: Vec<u16> = name.encode_utf16().chain([0]).collect();
+    let sddl = pipe::user_only_sddl()?;
+    // The first instance fails if someone else already made the name.
+    let mut next = pipe::create(&wide, &sddl, true)?;
+    std::fs::write(dir.join(PIPE_FILE), format!("{}\n", name))?;
+    std::thread::Builder::new()
+        .name("ipc".into())
+        .spawn(move || {
+            loop {
+                let accepted = pipe::accept(&next);
+                // A client holds its instance, so the next one waits on a new one.
+                let stream = match pipe::create(&wide, &sddl, false) {
+                    Ok(pipe) => std::mem::replace(&mut next, pipe),
+                    Err(e) => {
+                        eprintln!("ipc: {}", e);
+                        return;
+                    }
+                };
+                match accepted {
+                    Ok(()) => spawn_client(stream, &calls, &waker),
+                    Err(e) => eprintln!("ipc: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn spawn_client(stream: impl Read + Write + Send + 'static, calls: &SharedCalls, waker: &Waker) {
+    let (calls, waker) = (calls.clone(), waker.clone());
+    let spawned = std::thread::Builder::new()
+        .name("ipc client".into())
+        .spawn(move || {
+            if let Err(e) = serve(stream, &calls, &waker) {
+                eprintln!("ipc: {}", e);
+            }
+        });
+    if let Err(e) = spawned {
+        eprintln!("ipc: {}", e);
+    }
+}
+
+#[cfg(windows)]
+mod pipe {
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+
+    #[repr(C)]
+    struct SecurityAttributes {
+        length: u32,
+        descriptor: *mut c_void,
+        inherit: i32,
+    }
+
+    unsafe extern "system" {
+        fn GetCurrentProcess() -> isize;
+        fn CloseHandle(handle: isize) -> i32;
+        fn LocalFree(mem: *mut c_void) -> *mut c_void;
+        fn CreateNamedPipeW(
+            name: *const u16,
+            open_mode: u32,
+            pipe_mode: u32,
+            instances: u32,
+            out_size: u32,
+            in_size: u32,
+            timeout: u32,
+            attributes: *const SecurityAttributes,
+        ) -> isize;
+        fn ConnectNamedPipe(pipe: isize, overlapped: *mut c_void) -> i32;
+    }
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn OpenProcessToken(process: isize, access: u32, token: *mut isize) -> i32;
+        fn GetTokenInformation(
+            token: isize,
+            class: u32,
+            info: *mut c_void,
+            len: u32,
+            needed: *mut u32,
+        ) -> i32;
+        fn ConvertSidToStringSidW(sid: *mut c_void, out: *mut *mut u16) -> i32;
+        fn ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl: *const u16,
+            revision: u32,
+            descriptor: *mut *mut c_void,
+            size: *mut u32,
+        ) -> i32;
+    }
+
+    const TOKEN_QUERY: u32 = 0x8;
+    const TOKEN_USER: u32 = 1;
+    const PIPE_ACCESS_DUPLEX: u32 = 0x3;
+    const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x8_0000;
+    const PIPE_REJECT_REMOTE_CLIENTS: u32 = 0x8;
+    const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+
+    /// A security descriptor giving the current user, and no one else,
+    /// full access.
+    pub fn user_only_sddl() -> io::Result<Vec<u16>> {
+        unsafe {
+            let mut token = 0;
+            if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // TOKEN_USER: the SID pointer, its attributes, then the SID.
+            let mut info = [0usize; 64];
+            let mut needed = 0;
+            let ok = GetTokenInformation(
+                token,
+                TOKEN_USER,
+                info.as_mut_ptr().cast(),
+                std::mem::size_of_val(&info) as u32,
+                &mut needed,
+            );
+            let got = io::Error::last_os_error();
+            CloseHandle(token);
+            if ok == 0 {
+                return Err(got);
+            }
+            let mut sid = std::ptr::null_mut();
+            if ConvertSidToStringSidW(info[0] as *mut c_void, &mut sid) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let len = (0..).take_while(|&i| *sid.add(i) != 0).count();
+            let sid_text = String::from_utf16_lossy(std::slice::from_raw_parts(sid, len));
+            LocalFree(sid.cast());
+            Ok(format!("D:P(A;;GA;;;{})", sid_text)
+                .encode_utf16()
+                .chain([0])
+                .collect())
+        }
+    }
+
+    /// A new instance of the pipe `name`, waiting for a client.
+    pub fn create(name: &[u16], sddl: &[u16], first: bool) -> io::Result<File> {
+        unsafe {
+            let mut descriptor = std::ptr::null_mut();
+            let ok = ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                1,
+                &mut descriptor,
+                std::ptr::null_mut(),
+            );
+            if ok == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let attributes = SecurityAttributes {
+                length: std::mem::size_of::<SecurityAttributes>() as u32,
+                descriptor,
+                inherit: 0,
+            };
+            let mode = match first {
+                true => PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                false => PIPE_ACCESS_DUPLEX,
+            };
+            let pipe = CreateNamedPipeW(
+                name.as_ptr(),
+                mode,
+                PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                64 << 10,
+                64 << 10,
+                0,
+                &attributes,
+            );
+            let made = io::Error::last_os_error();
+            LocalFree(descriptor);
+            if pipe == -1 {
+                return Err(made);
+            }
+            Ok(File::from_raw_handle(pipe as _))
+        }
+    }
+
+    /// Waits for a client to open `pipe`.
+    pub fn accept(pipe: &File) -> io::Result<()> {
+        let ok = unsafe { ConnectNamedPipe(pipe.as_raw_handle() as isize, std::ptr::null_mut()) };
+        let e = io::Error::last_os_error();
+        match ok != 0 || e.raw_os_error() == Some(ERROR_PIPE_CONNECTED) {
+            true => Ok(()),
+            false => Err(e),
+        }
+    }
+}
+
+/// Answers one connection's requests until it closes.
+fn serve(mut stream: impl Read + Write, calls: &SharedCalls, waker: &Waker) -> io::Result<()> {
+    while let Some(request) = read_message(&mut stream)? {
+        let result = match request.split_first() {
+            None => Err("empty request".to_string()),
+            Some((&b, arg)) => match Command::from_byte(b) {
+                None => Err(format!("unknown command {}", b)),
+                Some(command) => {
+                    let (reply, answer) = mpsc::channel();
+                    calls.lock().unwrap().push(Call {
+                        command,
+                        arg: String::from_utf8_lossy(arg).into_owned(),
+                        reply,
+                    });
+                    waker.wake();
+                    answer
+                        .recv_timeout(REPLY_TIMEOUT)
+                        .unwrap_or_else(|_| Err("the host did not answer".into()))
+                }
+            },
+        };
+        let (status, text) = match result {
+            Ok(text) => (0, text),
+            Err(text) => (1, text),
+        };
+        let mut reply = vec![status];
+        reply.extend_from_slice(text.as_bytes());
+        write_message(&mut stream, &reply)?;
+    }
+    Ok(())
+}
+
+/// None once the other side closed the connection.
+fn read_message(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} byte message", len),
+        ));
+    }
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+fn write_message(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_le_bytes())?;
+    w.write_all(data)?;
+    w.flush()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    fn request(stream: &mut UnixStream, command: u8, arg: &str) -> (u8, String) {
+        let mut msg = vec![command];
+        msg.extend_from_slice(arg.as_bytes());
+        write_message(stream, &msg).unwrap();
+        let reply = read_message(stream).unwrap().unwrap();
+        (reply[0], String::from_utf8(reply[1..].to_vec()).unwrap())
+    }
+
+    #[test]
+    fn requests_reach_the_host_and_replies_come_back() {
+        let dir = std::env::temp_dir().join(format!("pob-ipc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let calls = SharedCalls::default();
+        listen(&dir, calls.clone(), Waker::default()).unwrap();
+
+        // Stands in for the host thread's per-frame pump.
+        let host = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let call = loop {
+                    if let Some(call) = calls.lock().unwrap().pop() {
+                        break call;
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                };
+                let result = match call.command {
+                    Command::LoadBuild => Err(format!("{}: not a build code", call.arg)),
+                    command => Ok(format!("{:?}", command)),
+                };
+                call.answer(result);
+            }
+        });
+        let mode = std::fs::metadata(dir.join(SOCKET_FILE))
+            .unwrap()
+            .permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o600
+        );
+        let mut stream = UnixStream::connect(dir.join(SOCKET_FILE)).unwrap();
+        assert_eq!(request(&mut stream, 3, ""), (0, "Summary".into()));
+        assert_eq!(
+            request(&mut stream, 1, "x"),
+            (1, "x: not a build code".into())
+        );
+        assert_eq!(request(&mut stream, 9, ""), (1, "unknown command 9".into()));
+        host.join().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
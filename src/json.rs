@@ -0,0 +1,252 @@
+use mlua::prelude::*;
+use serde_json::{Map, Number, Value};
+
+/// Nesting depth treated as a reference cycle when encoding.
+const MAX_DEPTH: usize = 512;
+
+/// Registers `require("native.json")`: a serde_json-backed drop-in for the
+/// dkjson calls PoB scripts make (`encode`, `decode`, `null`).
+pub fn register(lua: &Lua) -> LuaResult<()> {
+    let module = lua.create_table()?;
+
+    let null = lua.create_table()?;
+    let null_meta = lua.create_table()?;
+    null_meta.set(
+        "__tojson",
+        lua.create_function(|_, _: LuaValue| Ok("null"))?,
+    )?;
+    null_meta.set(
+        "__tostring",
+        lua.create_function(|_, _: LuaValue| Ok("null"))?,
+    )?;
+    null.set_metatable(Some(null_meta));
+    module.set("null", null)?;
+
+    // dkjson tags decoded tables with `{__jsontype = "object"|"array"}` so
+    // they re-encode the same way.
+    for kind in ["object", "array"] {
+        let meta = lua.create_table()?;
+        meta.set("__jsontype", kind)?;
+        module.set(format!("_{}meta", kind), meta)?;
+    }
+
+    module.set(
+        "encode",
+        lua.create_function(|lua, (value, state): (LuaValue, Option<LuaTable>)| {
+            let null: LuaTable = module_table(lua)?.get("null")?;
+            let keyorder: Vec<String> = match &state {
+                Some(s) => s
+                    .get::<_, Option<Vec<String>>>("keyorder")?
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            };
+            let indent = match &state {
+                Some(s) => s.get::<_, Option<bool>>("indent")?.unwrap_or(false),
+                None => false,
+            };
+            let json = to_json(&value, &null, &keyorder, 0)?;
+            let out = if indent {
+                serde_json::to_string_pretty(&json)
+            } else {
+                serde_json::to_string(&json)
+            };
+            out.map_err(LuaError::external)
+        })?,
+    )?;
+
+    module.set(
+        "decode",
+        lua.create_function(
+            |lua,
+             (text, pos, nullval, objectmeta, arraymeta): (
+                LuaString,
+                Option<usize>,
+                LuaValue,
+                Option<LuaTable>,
+                Option<LuaTable>,
+            )| {
+                let bytes = text.as_bytes();
+                let start = pos.unwrap_or(1).saturating_sub(1).min(bytes.len());
+                let src = String::from_utf8_lossy(&bytes[start..]);
+                let value: Value = match serde_json::from_str(&src) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return (
+                            LuaValue::Nil,
+                            start + e.column().max(1),
+                            format!("{} at line {} column {}", e, e.line(), e.column()),
+                        )
+                            .into_lua_multi(lua);
+                    }
+                };
+                let m = module_table(lua)?;
+                let metas = Metas {
+                    object: match objectmeta {
+                        Some(t) => t,
+                        None => m.get("_objectmeta")?,
+                    },
+                    array: match arraymeta {
+                        Some(t) => t,
+                        None => m.get("_arraymeta")?,
+                    },
+                };
+                let out = to_lua(lua, value, &nullval, &metas)?;
+                (out, bytes.len() + 1).into_lua_multi(lua)
+            },
+        )?,
+    )?;
+
+    lua.set_named_registry_value(MODULE, module)?;
+    let preload: LuaTable = lua
+        .globals()
+        .get::<_, LuaTable>("package")?
+        .get("preload")?;
+    preload.set(
+        MODULE,
+        lua.create_function(|lua, _: LuaMultiValue| module_table(lua))?,
+    )?;
+    Ok(())
+}
+
+const MODULE: &str = "native.json";
+
+fn module_table(lua: &Lua) -> LuaResult<LuaTable<'_>> {
+    lua.named_registry_value(MODULE)
+}
+
+struct Metas<'lua> {
+    object: LuaTable<'lua>,
+    array: LuaTable<'lua>,
+}
+
+fn to_json(
+    value: &LuaValue,
+    null: &LuaTable,
+    keyorder: &[String],
+    depth: usize,
+) -> LuaResult<Value> {
+    if depth > MAX_DEPTH {
+        return Err(LuaError::RuntimeError("reference cycle".into()));
+    }
+    Ok(match value {
+        LuaValue::Nil => Value::Null,
+        LuaValue::Boolean(b) => Value::Bool(*b),
+        LuaValue::Integer(n) => Value::Number((*n).into()),
+        LuaValue::Number(n) => number(*n),
+        LuaValue::String(s) => Value::String(s.to_string_lossy().into_owned()),
+        LuaValue::Table(t) if t == null => Value::Null,
+        LuaValue::Table(t) => {
+            let json_type = t
+                .get_metatable()
+                .and_then(|m| m.get::<_, Option<String>>("__jsontype").ok().flatten());
+            let len = t.raw_len();
+            let pairs = t.clone().pairs::<LuaValue, LuaValue>().count();
+            let is_array = match json_type.as_deref() {
+                Some("array") => true,
+                Some("object") => false,
+                _ => pairs == len,
+            };
+            if is_array {
+                let mut out = Vec::with_capacity(len);
+                for i in 1..=len {
+                    out.push(to_json(&t.raw_get(i)?, null, keyorder, depth + 1)?);
+                }
+                Value::Array(out)
+            } else {
+                let mut entries: Vec<(String, LuaValue)> = Vec::with_capacity(pairs);
+                for pair in t.clone().pairs::<LuaValue, LuaValue>() {
+                    let (k, v) = pair?;
+                    let key = match k {
+                        LuaValue::String(s) => s.to_string_lossy().into_owned(),
+                        LuaValue::Integer(n) => n.to_string(),
+                        LuaValue::Number(n) => n.to_string(),
+                        _ => continue,
+                    };
+                    entries.push((key, v));
+                }
+                // keyorder entries first, in the given order, then the rest
+                // sorted so output is stable across runs.
+                let rank = |k: &str| keyorder.iter().position(|o| o == k).unwrap_or(usize::MAX);
+                entries.sort_by(|(a, _), (b, _)| rank(a).cmp(&rank(b)).then_with(|| a.cmp(b)));
+                let mut map = Map::new();
+                for (k, v) in entries {
+                    map.insert(k, to_json(&v, null, keyorder, depth + 1)?);
+                }
+                Value::Object(map)
+            }
+        }
+        other => {
+            return Err(LuaError::RuntimeError(format!(
+                "cannot encode {} as json",
+                other.type_name()
+            )));
+        }
+    })
+}
+
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 2f64.powi(53) {
+        Value::Number((n as i64).into())
+    } else {
+        // NaN and infinities have no JSON form; dkjson writes null.
+        Number::from_f64(n).map_or(Value::Null, Value::Number)
+    }
+}
+
+fn to_lua<'lua>(
+    lua: &'lua Lua,
+    value: Value,
+    nullval: &LuaValue<'lua>,
+    metas: &Metas<'lua>,
+) -> LuaResult<LuaValue<'lua>> {
+    Ok(match value {
+        Value::Null => nullval.clone(),
+        Value::Bool(b) => LuaValue::Boolean(b),
+        Value::Number(n) => LuaValue::Number(n.as_f64().unwrap_or(0.0)),
+        Value::String(s) => LuaValue::String(lua.create_string(&s)?),
+        Value::Array(items) => {
+            let t = lua.create_table_with_capacity(items.len(), 0)?;
+            for (i, item) in items.into_iter().enumerate() {
+                t.raw_set(i + 1, to_lua(lua, item, nullval, metas)?)?;
+            }
+            t.set_metatable(Some(metas.array.clone()));
+            LuaValue::Table(t)
+        }
+        Value::Object(map) => {
+            let t = lua.create_table_with_capacity(0, map.len())?;
+            for (k, v) in map {
+                t.raw_set(k, to_lua(lua, v, nullval, metas)?)?;
+            }
+            t.set_metatable(Some(metas.object.clone()));
+            LuaValue::Table(t)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_like_dkjson() {
+        let lua = Lua::new();
+        register(&lua).unwrap();
+        let (encoded, name, empty, missing, err): (String, String, String, bool, String) = lua
+            .load(
+                r#"
+                local json = require("native.json")
+                local s = json.encode({ b = 1, a = { 1, 2.5, "x" }, c = json.null }, { keyorder = { "c" } })
+                local t = json.decode('{"name":"Maven","tags":[],"gone":null}')
+                local _, _, err = json.decode('{"a":')
+                return s, t.name, json.encode(t.tags), t.gone == nil, err
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(encoded, r#"{"c":null,"a":[1,2.5,"x"],"b":1}"#);
+        assert_eq!(name, "Maven");
+        assert_eq!(empty, "[]");
+        assert!(missing);
+        assert!(err.contains("EOF"));
+    }
+}
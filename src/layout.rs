@@ -0,0 +1,161 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// Name of the manifest looked for next to the runtime's working directory.
+pub const MANIFEST: &str = "pob.toml";
+
+/// Where one PoB install keeps its Lua sources, runtime libraries and
+/// passive tree assets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub fork: String,
+    pub script_dir: PathBuf,
+    pub runtime_dir: PathBuf,
+    pub tree_dir: PathBuf,
+}
+
+/// `pob.toml`: the installed forks, and which one starts without `--fork`.
+///
+/// ```toml
+/// default = "poe2"
+///
+/// [forks.poe1]
+/// root = "PathOfBuilding"
+///
+/// [forks.poe2]
+/// root = "PathOfBuilding-PoE2"
+/// tree = "src/TreeData"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Manifest {
+    default: Option<String>,
+    forks: BTreeMap<String, ForkManifest>,
+}
+
+/// Paths of one fork. `root` is relative to the manifest, the others to
+/// `root`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ForkManifest {
+    root: PathBuf,
+    src: PathBuf,
+    runtime: PathBuf,
+    tree: PathBuf,
+}
+
+impl Default for ForkManifest {
+    fn default() -> Self {
+        Self {
+            root: "PathOfBuilding".into(),
+            src: "src".into(),
+            runtime: "runtime".into(),
+            tree: "src/TreeData".into(),
+        }
+    }
+}
+
+impl Layout {
+    /// The upstream layout: a `PathOfBuilding` checkout under `base`.
+    pub fn standard(base: &Path) -> Self {
+        Self::from_fork("default", base, ForkManifest::default())
+    }
+
+    fn from_fork(name: &str, base: &Path, fork: ForkManifest) -> Self {
+        let root = base.join(fork.root);
+        Self {
+            fork: name.to_string(),
+            script_dir: root.join(fork.src),
+            runtime_dir: root.join(fork.runtime),
+            tree_dir: root.join(fork.tree),
+        }
+    }
+
+    /// Reads `base/pob.toml` and picks `fork`, or the manifest's default.
+    /// Without a manifest only the standard layout is available.
+    pub fn resolve(base: &Path, fork: Option<&str>) -> Result<Self, String> {
+        let path = base.join(MANIFEST);
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                Self::parse(base, &text, fork).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            Err(_) => match fork {
+                None => Ok(Self::standard(base)),
+                Some(name) => Err(format!(
+                    "--fork {}: no {} in {}",
+                    name,
+                    MANIFEST,
+                    base.display()
+                )),
+            },
+        }
+    }
+
+    fn parse(base: &Path, text: &str, fork: Option<&str>) -> Result<Self, String> {
+        let mut manifest: Manifest = toml::from_str(text).map_err(|e| e.to_string())?;
+        if manifest.forks.is_empty() {
+            return match fork {
+                None => Ok(Self::standard(base)),
+                Some(name) => Err(format!("no fork named {}", name)),
+            };
+        }
+        let name = fork
+            .map(str::to_string)
+            .or(manifest.default.clone())
+            .or_else(|| manifest.forks.keys().next().cloned())
+            .unwrap_or_default();
+        match manifest.forks.remove(&name) {
+            Some(entry) => Ok(Self::from_fork(&name, base, entry)),
+            None => Err(format!(
+                "no fork named {} (installed: {})",
+                name,
+                manifest
+                    .forks
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_forks_from_manifest() {
+        let base = Path::new("/opt/pob");
+        let text = r#"
+            default = "poe1"
+            [forks.poe1]
+            [forks.poe2]
+            root = "PoE2"
+            src = "lua"
+            tree = "assets/tree"
+        "#;
+        let poe1 = Layout::parse(base, text, None).unwrap();
+        assert_eq!(
+            poe1,
+            Layout::from_fork("poe1", base, ForkManifest::default())
+        );
+        assert_eq!(poe1.script_dir, base.join("PathOfBuilding/src"));
+
+        let poe2 = Layout::parse(base, text, Some("poe2")).unwrap();
+        assert_eq!(poe2.script_dir, base.join("PoE2/lua"));
+        assert_eq!(poe2.runtime_dir, base.join("PoE2/runtime"));
+        assert_eq!(poe2.tree_dir, base.join("PoE2/assets/tree"));
+
+        let err = Layout::parse(base, text, Some("poe3")).unwrap_err();
+        assert!(err.contains("poe1, poe2"), "{}", err);
+        assert_eq!(
+            Layout::parse(base, "", None).unwrap(),
+            Layout::standard(base)
+        );
+    }
+}
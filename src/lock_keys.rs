@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// The keys whose state toggles, by PoB key name.
+const LOCKS: [&str; 3] = ["CAPSLOCK", "NUMLOCK", "SCROLLLOCK"];
+
+/// Which lock keys are on, as far as the window can tell. The state at
+/// launch isn't reported, so each press flips it and Caps Lock is
+/// corrected from the case of typed letters.
+#[derive(Debug, Default)]
+pub struct LockKeys {
+    on: HashSet<&'static str>,
+}
+
+pub type SharedLockKeys = Arc<Mutex<LockKeys>>;
+
+impl LockKeys {
+    pub fn press(&mut self, key: &str) {
+        if let Some(&lock) = LOCKS.iter().find(|&&l| l == key)
+            && !self.on.remove(lock)
+        {
+            self.on.insert(lock);
+        }
+    }
+
+    /// A letter typed with or without Shift held shows Caps Lock's state.
+    pub fn typed(&mut self, text: &str, shift: bool) {
+        let mut chars = text.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return;
+        };
+        if c.is_lowercase() == c.is_uppercase() {
+            return;
+        }
+        if c.is_uppercase() != shift {
+            self.on.insert("CAPSLOCK");
+        } else {
+            self.on.remove("CAPSLOCK");
+        }
+    }
+
+    pub fn is_on(&self, key: &str) -> bool {
+        self.on.contains(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_flip_and_letters_correct_caps_lock() {
+        let mut locks = LockKeys::default();
+        locks.press("NUMLOCK");
+        locks.press("SPACE");
+        assert!(locks.is_on("NUMLOCK") && !locks.is_on("SPACE"));
+        locks.press("NUMLOCK");
+        assert!(!locks.is_on("NUMLOCK"));
+
+        // Caps Lock was on at launch: an unshifted capital gives it away.
+        locks.typed("A", false);
+        assert!(locks.is_on("CAPSLOCK"));
+        locks.typed("a", true);
+        assert!(locks.is_on("CAPSLOCK"));
+        locks.typed("7", false);
+        locks.typed("ab", false);
+        assert!(locks.is_on("CAPSLOCK"));
+        locks.press("CAPSLOCK");
+        assert!(!locks.is_on("CAPSLOCK"));
+        locks.typed("B", true);
+        assert!(!locks.is_on("CAPSLOCK"));
+    }
+}
@@ -1,51 +1,405 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     io::{Read, Write},
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    path::Path,
+    sync::{Arc, LazyLock, Mutex},
 };
 
-use arboard::Clipboard;
 use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
-use glyphon::{Buffer, FontSystem};
 use mlua::prelude::*;
 
+use crate::asset_cache::{self, AssetCache};
+use crate::build_stats;
+use crate::callback_errors::{CallbackErrors, ErrorPolicy};
+use crate::callback_targets::{self, SharedTargets};
+use crate::capture::RegionCapture;
+use crate::clipboard::{Clipboard, ClipboardWatch, NewlineMode, WatchMode, is_item_text};
+use crate::codec;
+use crate::dialogs::{self, Dialogs};
+use crate::dir_watch::{self, DirWatcher};
+use crate::discord;
+use crate::dpi_override::{self, SharedDpiOverride};
+use crate::escapes::{self, SharedPalette};
+use crate::font_metrics::FontMetricsTable;
+use crate::gc::{self, GcScheduler, SharedGc};
+use crate::gpu_timer::SharedGpuTimes;
 use crate::graphics::{
-    CursorPos, DrawCmd, DrawItem, DrawQuadCmd, DrawQueue, TextQueue, TextureUploadQueue,
+    CursorPos, DrawItem, DrawQuadCmd, DrawQueue, MAX_TARGET_SIZE, MeshCmd, RenderStats, TextEffect,
+    TextureCmd, TextureMemory, TextureQueue, UploadErrors, Vertex, is_degenerate,
 };
+use crate::host_thread::Waker;
+use crate::hotkeys::{self, Hotkeys, SharedHotkeys};
+use crate::image_handle::{self, Images, texture_of};
+use crate::ipc::{Command, SharedCalls};
+use crate::json;
+use crate::layout::Layout;
+use crate::lock_keys::SharedLockKeys;
+use crate::net::{self, NetSettings, NetState};
+use crate::oauth;
+use crate::platform::{
+    MIN_OPACITY, MonitorInfo, WindowMode, WindowStyle, notify, open_url, resident_bytes, user_path,
+};
+use crate::post::{ColorFilter, PostParams};
+use crate::profile;
+use crate::settings::Settings;
+use crate::shapes;
+use crate::shaping::{self, ShapeCache};
+use crate::snapshot::{self, Snapshots};
+use crate::sound;
+use crate::sprite_sheet::{SpriteSheets, nine_patch};
+use crate::subscript::{SubEnv, SubScripts, SubValue};
+use crate::svg::SvgImages;
+use crate::texture_cache::TextureCache;
+use crate::texture_ids::{SharedTextureIds, TextureLease};
+use crate::theme::{Backdrop, SharedTheme};
+use crate::tray::{self, RecentBuild};
+use crate::vfs::{self, SharedVfs, Vfs};
+use crate::window_icon::{self, IconImage, SharedWindowIcon};
+use crate::workers;
+use crate::xml;
+
+/// State shared between the window thread and the Lua host.
+#[derive(Clone)]
+pub struct HostShared {
+    pub screen_size: Arc<Mutex<[u32; 2]>>,
+    pub draw_queue: DrawQueue,
+    pub texture_queue: TextureQueue,
+    pub cursor_pos: CursorPos,
+    /// Keys and mouse buttons held, by PoB key name.
+    pub pressed_keys: Arc<Mutex<HashSet<String>>>,
+    /// Caps, Num and Scroll Lock.
+    pub lock_keys: SharedLockKeys,
+    /// Set while a mouse drag holds the pointer captured.
+    pub mouse_capture: Arc<Mutex<bool>>,
+    /// Parsed `runtime.toml`; the default is used until main loads it.
+    pub settings: Arc<Settings>,
+    /// The window's scale factor, which SVG images are rasterized at.
+    pub dpi_scale: Arc<Mutex<f32>>,
+    /// Gamma, brightness and colour filter of the final screen pass.
+    pub post: Arc<Mutex<PostParams>>,
+    /// Physical pixels per UI unit; `screen_size` is in UI units.
+    pub zoom: Arc<Mutex<f32>>,
+    /// The window mode asked for; the window thread applies changes.
+    pub window_mode: Arc<Mutex<WindowMode>>,
+    /// On-top, opacity and click-through, applied like `window_mode`.
+    pub window_style: Arc<Mutex<WindowStyle>>,
+    /// The newest builds under `main.buildPath`, for the tray menu.
+    pub recent_builds: Arc<Mutex<Vec<RecentBuild>>>,
+    /// Whether the window has keyboard focus.
+    pub focused: Arc<Mutex<bool>>,
+    /// Whether the window is minimized or covered, so frames go undrawn.
+    pub hidden: Arc<Mutex<bool>>,
+    /// The displays attached, refreshed when the window moves between them.
+    pub monitors: Arc<Mutex<Vec<MonitorInfo>>>,
+    /// Set while the screen is grabbed; the window thread hides the window
+    /// meanwhile.
+    pub hide_for_grab: Arc<Mutex<bool>>,
+    /// GPU time of the last timed frame, where the adapter can tell.
+    pub gpu_times: SharedGpuTimes,
+    /// Global hotkey changes, applied by the window thread.
+    pub hotkeys: SharedHotkeys,
+    /// Colours of the text escapes.
+    pub palette: SharedPalette,
+    /// Clear colour and vignette behind the UI.
+    pub theme: SharedTheme,
+    /// Texture memory as of the last frame drawn.
+    pub texture_memory: Arc<Mutex<TextureMemory>>,
+    pub upload_errors: UploadErrors,
+    /// What the renderer drew for the last frame shown.
+    pub render_stats: Arc<Mutex<RenderStats>>,
+    /// The window icon, until the window thread sets it.
+    pub window_icon: SharedWindowIcon,
+    /// PoB's display scaling, which the window thread turns into `zoom`.
+    pub dpi_override: SharedDpiOverride,
+    /// Requests from tools connected over IPC.
+    pub ipc_calls: SharedCalls,
+    /// Cuts the host's frame wait short for background results.
+    pub waker: Waker,
+    /// Set for the host behind the window. Headless states leave the
+    /// settings file alone.
+    pub windowed: bool,
+}
+
+impl Default for HostShared {
+    fn default() -> Self {
+        Self {
+            screen_size: Arc::new(Mutex::new([1280, 720])),
+            draw_queue: Arc::new(Mutex::new(Vec::new())),
+            texture_queue: Arc::new(Mutex::new(Vec::new())),
+            cursor_pos: Arc::new(Mutex::new([0.0, 0.0])),
+            pressed_keys: Arc::new(Mutex::new(HashSet::new())),
+            lock_keys: Arc::default(),
+            mouse_capture: Arc::new(Mutex::new(false)),
+            settings: Arc::new(Settings::default()),
+            dpi_scale: Arc::new(Mutex::new(1.0)),
+            post: Arc::default(),
+            zoom: Arc::new(Mutex::new(1.0)),
+            window_mode: Arc::default(),
+            window_style: Arc::default(),
+            recent_builds: Arc::default(),
+            focused: Arc::new(Mutex::new(true)),
+            hidden: Arc::default(),
+            monitors: Arc::default(),
+            hide_for_grab: Arc::default(),
+            gpu_times: Arc::default(),
+            hotkeys: Arc::default(),
+            palette: Arc::default(),
+            theme: Arc::default(),
+            texture_memory: Arc::default(),
+            upload_errors: Arc::default(),
+            render_stats: Arc::default(),
+            window_icon: Arc::default(),
+            dpi_override: Arc::default(),
+            ipc_calls: Arc::default(),
+            waker: Waker::default(),
+            windowed: false,
+        }
+    }
+}
+
+/// The render target Draw* calls currently go to, with the viewport that
+/// was set when its Begin was called.
+type ActiveTarget = Arc<Mutex<Option<(u32, Option<[u32; 4]>)>>>;
+
+/// Frame timing behind GetTime, GetDeltaTime, GetFrameTime,
+/// GetElapsedFrames and GetFrameCount.
+#[derive(Default)]
+struct FrameClock {
+    count: u64,
+    /// When the last frame started, since the host did.
+    last_start: Option<std::time::Duration>,
+    /// Seconds between the starts of the last two frames.
+    delta: f64,
+    /// The current frame's time in the recording being replayed, which
+    /// GetTime reports in place of the wall clock.
+    replayed: Option<std::time::Duration>,
+}
 
 pub struct LuaHost {
     pub lua: Lua,
     pub main_object: Arc<Mutex<Option<LuaRegistryKey>>>,
-    pub root_dir: PathBuf,
+    callback_targets: SharedTargets,
+    pub layout: Layout,
+    subscripts: SubScripts,
+    dialogs: Dialogs,
+    hotkeys: Hotkeys,
+    pub capture: RegionCapture,
+    backdrop: Backdrop,
+    svgs: SvgImages,
+    texture_queue: TextureQueue,
+    texture_ids: SharedTextureIds,
+    dpi_scale: Arc<Mutex<f32>>,
+    builds_watch: DirWatcher,
+    recent_builds: Arc<Mutex<Vec<RecentBuild>>>,
+    clipboard: Arc<LazyLock<Mutex<Clipboard>>>,
+    newlines: Arc<Mutex<NewlineMode>>,
+    clipboard_watch: Mutex<ClipboardWatch>,
+    focused: Arc<Mutex<bool>>,
+    start_time: std::time::Instant,
+    frame_clock: Arc<Mutex<FrameClock>>,
+    /// Set by Exit.
+    exit_requested: Arc<Mutex<bool>>,
+    callback_errors: Mutex<CallbackErrors>,
+    gc: SharedGc,
+    /// Draws filtered out since the last `take_dropped_draws`.
+    dropped_draws: Arc<Mutex<usize>>,
+    viewport: Arc<Mutex<Option<[u32; 4]>>>,
+    active_target: ActiveTarget,
+    ipc_calls: SharedCalls,
+    pub vfs: SharedVfs,
 }
 
 impl LuaHost {
-    pub fn new(
-        root_dir: PathBuf,
-        screen_size: Arc<Mutex<[u32; 2]>>,
-        draw_queue: DrawQueue,
-        texture_queue: TextureUploadQueue,
-        cursor_pos: CursorPos,
-        pressed_keys: Arc<Mutex<HashSet<String>>>,
-    ) -> LuaResult<Self> {
+    pub fn new(layout: Layout, shared: HostShared) -> LuaResult<Self> {
+        let vfs = Vfs::standard(&layout);
+        Self::with_vfs(layout, shared, vfs)
+    }
+
+    /// A host whose file access all goes through `vfs`.
+    pub fn with_vfs(layout: Layout, shared: HostShared, vfs: Vfs) -> LuaResult<Self> {
+        let HostShared {
+            screen_size,
+            draw_queue,
+            texture_queue,
+            cursor_pos,
+            pressed_keys,
+            lock_keys,
+            mouse_capture,
+            settings,
+            dpi_scale,
+            post,
+            zoom,
+            window_mode,
+            window_style,
+            recent_builds,
+            focused,
+            hidden: _,
+            monitors,
+            hide_for_grab,
+            gpu_times: _,
+            hotkeys,
+            palette,
+            theme,
+            texture_memory,
+            upload_errors,
+            render_stats,
+            window_icon,
+            dpi_override,
+            ipc_calls,
+            waker,
+            windowed,
+        } = shared;
         let lua = unsafe { Lua::unsafe_new() };
         let main_object: Arc<Mutex<Option<LuaRegistryKey>>> = Arc::new(Mutex::new(None));
         let mo = main_object.clone();
-        let clipboard = Arc::new(Mutex::new(Clipboard::new().unwrap()));
-        let font_system = Arc::new(Mutex::new(FontSystem::new()));
+        // These are slow to set up and may never be needed, so they wait
+        // for their first use.
+        let clipboard: Arc<LazyLock<Mutex<Clipboard>>> =
+            Arc::new(LazyLock::new(|| Mutex::new(Clipboard::open())));
+        let newlines = Arc::new(Mutex::new(NewlineMode::platform_default()));
+        let watch = settings.clipboard.watch.as_deref().unwrap_or("off");
+        let watch = WatchMode::parse(watch).unwrap_or_else(|| {
+            eprintln!("clipboard: unknown watch mode \"{}\"", watch);
+            WatchMode::Off
+        });
+        let shaping = shaping::from_settings(&settings.text);
+        let metrics = Arc::new(FontMetricsTable::from_settings(&settings.text));
+        let shapes = Arc::new(LazyLock::new(move || {
+            Mutex::new(ShapeCache::new(shaping, metrics))
+        }));
         let viewport: Arc<Mutex<Option<[u32; 4]>>> = Arc::new(Mutex::new(None));
+        let active_target: ActiveTarget = Arc::default();
+        let dropped_draws = Arc::new(Mutex::new(0));
+        let sprite_sheets: SpriteSheets = Arc::new(Mutex::new(HashMap::new()));
+        let svgs: SvgImages = Arc::new(Mutex::new(HashMap::new()));
+        let vfs: SharedVfs = Arc::new(Mutex::new(vfs));
+        let builds_watch = DirWatcher::spawn();
+        let net = {
+            let settings = settings.clone();
+            net::lazy(move || NetState::new(&settings.network))
+        };
+        let sub_env = SubEnv {
+            script_path: layout.script_dir.clone(),
+            runtime_path: layout.runtime_dir.clone(),
+            net: net.clone(),
+            snapshots: Snapshots::default(),
+            vfs: vfs.clone(),
+            waker: waker.clone(),
+        };
+        let subscripts = SubScripts::new(sub_env.clone());
 
         let start_time = std::time::Instant::now();
+        let frame_clock: Arc<Mutex<FrameClock>> = Arc::default();
+        let exit_requested: Arc<Mutex<bool>> = Arc::default();
+        let gc: SharedGc = Arc::new(Mutex::new(GcScheduler::new(&lua)));
+        gc::register(&lua, gc.clone())?;
+        let callback_targets: SharedTargets = Arc::default();
+        callback_targets::register(&lua, callback_targets.clone())?;
+        json::register(&lua)?;
+        codec::register(&lua)?;
+        xml::register(&lua)?;
+        net::register(&lua, net.clone())?;
+        oauth::register(&lua)?;
+        snapshot::register(&lua, sub_env.snapshots.clone())?;
+        workers::register(&lua, sub_env)?;
+        let dialogs = Dialogs::default();
+        dialogs::register(&lua, dialogs.clone())?;
+        let hotkeys = Hotkeys::new(hotkeys);
+        hotkeys::register(&lua, hotkeys.clone())?;
+        sound::register(&lua, vfs.clone(), settings.sound.clone())?;
+        discord::register(&lua, &settings.discord)?;
+        asset_cache::register(
+            &lua,
+            AssetCache::new(user_path().join("Cache").join("Assets"), dpi_scale.clone()),
+        )?;
+        #[cfg(feature = "storage")]
+        crate::storage::register(&lua, user_path())?;
 
+        let capture;
+        let backdrop;
+        // Image handles and render targets share one texture id space.
+        let texture_ids = SharedTextureIds::default();
         {
             let g = lua.globals();
-            let script_path = Arc::new(root_dir.join("PathOfBuilding/src"));
-            let runtime_path = root_dir.join("PathOfBuilding/runtime/lua");
+            let script_path = Arc::new(layout.script_dir.clone());
+            let runtime_path = layout.runtime_dir.join("lua");
 
+            let clock = frame_clock.clone();
+            let now = move || {
+                clock
+                    .lock()
+                    .unwrap()
+                    .replayed
+                    .unwrap_or(start_time.elapsed())
+            };
+            let now_us = now.clone();
             g.set(
                 "GetTime",
-                lua.create_function(move |_, ()| Ok(start_time.elapsed().as_millis() as u64))?,
+                lua.create_function(move |_, ()| Ok(now().as_millis() as u64))?,
+            )?;
+            g.set(
+                "GetTimeUS",
+                lua.create_function(move |_, ()| Ok(now_us().as_micros() as u64))?,
+            )?;
+            let clock = frame_clock.clone();
+            g.set(
+                "GetDeltaTime",
+                lua.create_function(move |_, ()| Ok(clock.lock().unwrap().delta))?,
+            )?;
+            let clock = frame_clock.clone();
+            g.set(
+                "GetFrameTime",
+                lua.create_function(move |_, ()| Ok(clock.lock().unwrap().delta * 1000.0))?,
+            )?;
+            // The last frame's length in 60 Hz frames, for animations that
+            // step a fixed amount per frame.
+            let clock = frame_clock.clone();
+            g.set(
+                "GetElapsedFrames",
+                lua.create_function(move |_, ()| Ok(clock.lock().unwrap().delta * 60.0))?,
+            )?;
+            let clock = frame_clock.clone();
+            g.set(
+                "GetFrameCount",
+                lua.create_function(move |_, ()| Ok(clock.lock().unwrap().count))?,
+            )?;
+            // GetMemoryUsage() -> table of byte counts: lua, gpuTextures,
+            // cpuTextures (copies kept for re-upload) and rss (nil where the
+            // OS doesn't say), plus counts of textures and shaped strings.
+            let sc = shapes.clone();
+            g.set(
+                "GetMemoryUsage",
+                lua.create_function(move |lua, ()| {
+                    let textures = *texture_memory.lock().unwrap();
+                    let t = lua.create_table()?;
+                    t.set("lua", lua.used_memory())?;
+                    t.set("gpuTextures", textures.gpu_bytes)?;
+                    t.set("gpuTextureCount", textures.gpu_textures)?;
+                    t.set("cpuTextures", textures.cpu_bytes)?;
+                    t.set("cpuTextureCount", textures.cpu_textures)?;
+                    // Measuring nothing yet shouldn't load the fonts.
+                    let shaped = LazyLock::get(&sc).map_or(0, |c| c.lock().unwrap().len());
+                    t.set("shapedStrings", shaped)?;
+                    t.set("rss", resident_bytes())?;
+                    Ok(t)
+                })?,
+            )?;
+
+            // GetRenderStats() -> items, batches, vertices, textureSwitches
+            // and culled for the last frame the window drew, a frame or so
+            // behind the one being built.
+            g.set(
+                "GetRenderStats",
+                lua.create_function(move |lua, ()| {
+                    let stats = *render_stats.lock().unwrap();
+                    let t = lua.create_table()?;
+                    t.set("items", stats.items)?;
+                    t.set("batches", stats.batches)?;
+                    t.set("vertices", stats.vertices)?;
+                    t.set("textureSwitches", stats.texture_switches)?;
+                    t.set("culled", stats.culled)?;
+                    Ok(t)
+                })?,
             )?;
 
             g.set(
@@ -53,6 +407,81 @@ impl LuaHost {
                 lua.create_function(|_, _: String| Ok(()))?,
             )?;
 
+            // SetWindowMode("windowed" | "borderless" | "fullscreen"); the
+            // new screen size shows up in GetScreenSize once applied.
+            let mode = window_mode.clone();
+            g.set(
+                "SetWindowMode",
+                lua.create_function(move |_, name: String| {
+                    *mode.lock().unwrap() = WindowMode::parse(&name).ok_or_else(|| {
+                        LuaError::RuntimeError(format!("unknown window mode {:?}", name))
+                    })?;
+                    Ok(())
+                })?,
+            )?;
+            let mode = window_mode.clone();
+            g.set(
+                "GetWindowMode",
+                lua.create_function(move |_, ()| Ok(mode.lock().unwrap().name()))?,
+            )?;
+
+            // For overlaying the game: SetWindowOnTop(bool),
+            // SetWindowOpacity(0.1 to 1) and SetClickThrough(bool), read
+            // back by GetWindowStyle() -> onTop, opacity, clickThrough.
+            let style = window_style.clone();
+            g.set(
+                "SetWindowOnTop",
+                lua.create_function(move |_, on: bool| {
+                    style.lock().unwrap().always_on_top = on;
+                    Ok(())
+                })?,
+            )?;
+            let style = window_style.clone();
+            g.set(
+                "SetWindowOpacity",
+                lua.create_function(move |_, opacity: f32| {
+                    style.lock().unwrap().opacity = opacity.clamp(MIN_OPACITY, 1.0);
+                    Ok(())
+                })?,
+            )?;
+            let style = window_style.clone();
+            g.set(
+                "SetClickThrough",
+                lua.create_function(move |_, on: bool| {
+                    style.lock().unwrap().click_through = on;
+                    Ok(())
+                })?,
+            )?;
+            g.set(
+                "GetWindowStyle",
+                lua.create_function(move |_, ()| {
+                    let s = *window_style.lock().unwrap();
+                    Ok((s.always_on_top, s.opacity, s.click_through))
+                })?,
+            )?;
+            // GetMonitors() -> { {name, x, y, width, height, scale, primary,
+            // current} }, in physical pixels.
+            let mons = monitors.clone();
+            g.set(
+                "GetMonitors",
+                lua.create_function(move |lua, ()| {
+                    let list = lua.create_table()?;
+                    for m in mons.lock().unwrap().iter() {
+                        let t = lua.create_table()?;
+                        t.set("name", m.name.as_str())?;
+                        t.set("x", m.position[0])?;
+                        t.set("y", m.position[1])?;
+                        t.set("width", m.size[0])?;
+                        t.set("height", m.size[1])?;
+                        t.set("scale", m.scale)?;
+                        t.set("primary", m.primary)?;
+                        t.set("current", m.current)?;
+                        list.push(t)?;
+                    }
+                    Ok(list)
+                })?,
+            )?;
+
             g.set("ConExecute", lua.create_function(|_, _: String| Ok(()))?)?;
 
             g.set("ConClear", lua.create_function(|_, ()| Ok(()))?)?;
@@ -104,6 +533,7 @@ impl LuaHost {
             )?;
 
             let sp = script_path.clone();
+            let files = vfs.clone();
             g.set(
                 "PLoadModule",
                 lua.create_function(move |lua, (name, args): (String, LuaMultiValue)| {
@@ -116,7 +546,10 @@ impl LuaHost {
                     // build the full module path
                     let module_path = sp.join(full_name);
 
-                    let code = std::fs::read_to_string(&module_path)
+                    let code = files
+                        .lock()
+                        .unwrap()
+                        .read_to_string(&module_path.to_string_lossy())
                         .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
                     match lua.load(&code).call::<LuaMultiValue, LuaMultiValue>(args) {
                         Ok(results) => {
@@ -132,6 +565,7 @@ impl LuaHost {
             )?;
 
             let sp = script_path.clone();
+            let files = vfs.clone();
             g.set(
                 "LoadModule",
                 lua.create_function(move |lua, (name, args): (String, LuaMultiValue)| {
@@ -144,7 +578,10 @@ impl LuaHost {
                     // build the full module path
                     let module_path = sp.join(full_name);
 
-                    let code = std::fs::read_to_string(&module_path)
+                    let code = files
+                        .lock()
+                        .unwrap()
+                        .read_to_string(&module_path.to_string_lossy())
                         .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
                     lua.load(&code).call::<LuaMultiValue, LuaMultiValue>(args)
                 })?,
@@ -156,33 +593,104 @@ impl LuaHost {
                 lua.create_function(move |_, ()| Ok(sp.to_string_lossy().into_owned()))?,
             )?;
 
-            let runtime_dir = root_dir.join("PathOfBuilding/runtime");
+            let runtime_dir = layout.runtime_dir.clone();
             g.set(
                 "GetRuntimePath",
                 lua.create_function(move |_, ()| Ok(runtime_dir.to_string_lossy().into_owned()))?,
             )?;
             g.set(
                 "GetUserPath",
-                lua.create_function(|_, ()| {
-                    let path = dirs::data_dir().unwrap_or_default().join("PathOfBuilding");
-                    std::fs::create_dir_all(&path).ok();
-                    Ok(path.to_string_lossy().into_owned() + "/")
+                lua.create_function(|_, ()| Ok(user_path().to_string_lossy().into_owned() + "/"))?,
+            )?;
+            g.set(
+                "GetProfile",
+                lua.create_function(|_, ()| Ok(profile::current()))?,
+            )?;
+            let files = vfs.clone();
+            g.set(
+                "NewFileSearch",
+                lua.create_function(move |lua, (spec, dirs): (String, Option<bool>)| {
+                    let found = files.lock().unwrap().search(&spec, dirs.unwrap_or(false));
+                    if found.is_empty() {
+                        return Ok(LuaValue::Nil);
+                    }
+                    let found = Arc::new(found);
+                    let pos = Arc::new(Mutex::new(0usize));
+                    let t = lua.create_table()?;
+                    let p = pos.clone();
+                    let len = found.len();
+                    t.set(
+                        "NextFile",
+                        lua.create_function(move |_, _: LuaValue| {
+                            let mut p = p.lock().unwrap();
+                            *p += 1;
+                            Ok(*p < len)
+                        })?,
+                    )?;
+                    let current = move || found[(*pos.lock().unwrap()).min(len - 1)].clone();
+                    let file = current.clone();
+                    t.set(
+                        "GetFileName",
+                        lua.create_function(move |_, _: LuaValue| Ok(file().name))?,
+                    )?;
+                    let file = current.clone();
+                    t.set(
+                        "GetFileSize",
+                        lua.create_function(move |_, _: LuaValue| Ok(file().size))?,
+                    )?;
+                    t.set(
+                        "GetFileModifiedTime",
+                        lua.create_function(move |_, _: LuaValue| {
+                            Ok(current()
+                                .modified
+                                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                                .map_or(0.0, |d| d.as_secs_f64()))
+                        })?,
+                    )?;
+                    Ok(LuaValue::Table(t))
                 })?,
             )?;
+            let pal = palette.clone();
             g.set(
                 "StripEscapes",
-                lua.create_function(|_, s: String| {
-                    let out = strip_pob_escapes(&s);
+                lua.create_function(move |_, s: String| {
+                    let out = escapes::strip(&s, &pal.lock().unwrap());
                     Ok(out)
                 })?,
             )?;
+            // RegisterColorEscape(name, r, g, b) makes `^name` draw in that
+            // colour, for fork themes; without a colour it is removed.
+            let pal = palette.clone();
+            g.set(
+                "RegisterColorEscape",
+                lua.create_function(
+                    move |_,
+                          (name, red, green, blue): (
+                        String,
+                        Option<f32>,
+                        Option<f32>,
+                        Option<f32>,
+                    )| {
+                        if !escapes::valid_name(&name) {
+                            return Err(LuaError::runtime(format!(
+                                "RegisterColorEscape: {:?} isn't a letter then letters, digits or _",
+                                name
+                            )));
+                        }
+                        let rgb = match (red, green, blue) {
+                            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+                            (None, None, None) => None,
+                            _ => return Err(LuaError::runtime("RegisterColorEscape needs r, g and b")),
+                        };
+                        pal.lock().unwrap().set_name(&name, rgb);
+                        Ok(())
+                    },
+                )?,
+            )?;
 
             g.set(
-                "MakeDir",
-                lua.create_function(|_, path: String| {
-                    std::fs::create_dir_all(&path).map_err(LuaError::external)?;
-                    Ok(())
-                })?,
+                "IsMouseCaptured",
+                lua.create_function(move |_, ()| Ok(*mouse_capture.lock().unwrap()))?,
             )?;
 
             g.set(
@@ -191,22 +699,52 @@ impl LuaHost {
                     Ok(pressed_keys.lock().unwrap().contains(&key))
                 })?,
             )?;
+            // IsKeyToggled("CAPSLOCK" | "NUMLOCK" | "SCROLLLOCK")
+            g.set(
+                "IsKeyToggled",
+                lua.create_function(move |_, key: String| {
+                    Ok(lock_keys.lock().unwrap().is_on(&key))
+                })?,
+            )?;
 
             // clipboard
+            let nl = newlines.clone();
+            g.set(
+                "SetClipboardNewlines",
+                lua.create_function(move |_, mode: String| {
+                    let parsed = NewlineMode::parse(&mode).ok_or_else(|| {
+                        LuaError::RuntimeError(format!("unknown newline mode: {}", mode))
+                    })?;
+                    *nl.lock().unwrap() = parsed;
+                    Ok(())
+                })?,
+            )?;
             let cb = clipboard.clone();
+            let nl = newlines.clone();
             g.set(
                 "Copy",
                 lua.create_function(move |_, text: String| {
-                    cb.lock().unwrap().set_text(text).ok();
+                    cb.lock()
+                        .unwrap()
+                        .set_text(nl.lock().unwrap().for_copy(&text));
                     Ok(())
                 })?,
             )?;
             let cb = clipboard.clone();
+            let nl = newlines.clone();
             g.set(
                 "Paste",
                 lua.create_function(move |_, ()| {
-                    let text = cb.lock().unwrap().get_text().unwrap_or_default();
-                    Ok(text)
+                    let text = cb.lock().unwrap().get_text();
+                    Ok(nl.lock().unwrap().for_paste(&text))
+                })?,
+            )?;
+            let cb = clipboard.clone();
+            g.set(
+                "ClearClipboard",
+                lua.create_function(move |_, ()| {
+                    cb.lock().unwrap().clear();
+                    Ok(())
                 })?,
             )?;
 
@@ -274,19 +812,54 @@ impl LuaHost {
                     Ok((v[0], v[1]))
                 })?,
             )?;
-            g.set("GetScreenScale", lua.create_function(|_, ()| Ok(1.0f32))?)?;
-            g.set("GetAsyncCount", lua.create_function(|_, ()| Ok(0u32))?)?;
+            // The runtime applies the user's UI zoom itself and GetScreenSize
+            // is already divided by it, so PoB must not scale again.
+            g.set("GetScreenScale", lua.create_function(|_, ()| Ok(1.0))?)?;
+            // GetUIZoom() -> the zoom the runtime applies, the user's or that
+            // of PoB's display scaling, for layouts that want to know it.
             g.set(
-                "GetDPIScaleOverridePercent",
-                lua.create_function(|_, ()| Ok(1.0f32))?,
+                "GetUIZoom",
+                lua.create_function(move |_, ()| Ok(*zoom.lock().unwrap()))?,
             )?;
+            g.set("GetAsyncCount", lua.create_function(|_, ()| Ok(0u32))?)?;
+
+            // SetPostProcess(gamma, brightness, filter): nil keeps a value.
+            // The filter is "none", "protanopia", "deuteranopia" or
+            // "tritanopia".
+            let pp = post.clone();
             g.set(
-                "SetDPIScaleOverridePercent",
-                lua.create_function(|_, _: LuaMultiValue| Ok(()))?,
+                "SetPostProcess",
+                lua.create_function(
+                    move |_,
+                          (gamma, brightness, filter): (
+                        Option<f32>,
+                        Option<f32>,
+                        Option<String>,
+                    )| {
+                        let mut params = pp.lock().unwrap();
+                        let filter = match filter {
+                            Some(name) => ColorFilter::parse(&name).ok_or_else(|| {
+                                LuaError::RuntimeError(format!("unknown color filter {:?}", name))
+                            })?,
+                            None => params.filter,
+                        };
+                        *params = PostParams {
+                            gamma: gamma.unwrap_or(params.gamma),
+                            brightness: brightness.unwrap_or(params.brightness),
+                            filter,
+                            ..*params
+                        }
+                        .clamped();
+                        Ok(())
+                    },
+                )?,
             )?;
             g.set(
-                "SetClearColor",
-                lua.create_function(|_, _: LuaMultiValue| Ok(()))?,
+                "GetPostProcess",
+                lua.create_function(move |_, ()| {
+                    let params = *post.lock().unwrap();
+                    Ok((params.gamma, params.brightness, params.filter.name()))
+                })?,
             )?;
             g.set(
                 "SetCursorPos",
@@ -307,13 +880,25 @@ impl LuaHost {
             g.set(
                 "OpenURL",
                 lua.create_function(|_, url: String| {
-                    std::process::Command::new("xdg-open")
-                        .arg(&url)
-                        .spawn()
-                        .ok();
+                    open_url(&url);
                     Ok(())
                 })?,
             )?;
+            // Notify(title, text, [evenIfFocused]) -> shown. By default
+            // only notifies while the window is in the background.
+            let focus = focused.clone();
+            g.set(
+                "Notify",
+                lua.create_function(
+                    move |_, (title, text, always): (String, String, Option<bool>)| {
+                        let show = always == Some(true) || !*focus.lock().unwrap();
+                        if show {
+                            notify(&title, &text);
+                        }
+                        Ok(show)
+                    },
+                )?,
+            )?;
             g.set(
                 "SetProfiling",
                 lua.create_function(|_, _: LuaMultiValue| Ok(()))?,
@@ -332,17 +917,50 @@ impl LuaHost {
                 "GetWorkDir",
                 lua.create_function(|_, ()| Ok(String::new()))?,
             )?;
+            let (nc, ns) = (net.clone(), settings.clone());
+            g.set(
+                "SetProxy",
+                lua.create_function(move |_, url: Option<String>| {
+                    // nil or "" falls back to the settings file / environment.
+                    nc.config.lock().unwrap().proxy = url
+                        .filter(|u| !u.is_empty())
+                        .or_else(|| NetSettings::new(&ns.network).proxy);
+                    Ok(())
+                })?,
+            )?;
+            let subs = subscripts.clone();
             g.set(
                 "LaunchSubScript",
-                lua.create_function(|_, _: LuaMultiValue| Ok(()))?,
+                lua.create_function(
+                    move |_,
+                          (script, funcs, subs_list, args): (
+                        String,
+                        Option<String>,
+                        Option<String>,
+                        LuaMultiValue,
+                    )| {
+                        let args = args.iter().map(|v| SubValue::from_lua(v, 0)).collect();
+                        Ok(subs.launch(
+                            script,
+                            funcs.as_deref().unwrap_or(""),
+                            subs_list.as_deref().unwrap_or(""),
+                            args,
+                        ))
+                    },
+                )?,
             )?;
+            let subs = subscripts.clone();
             g.set(
                 "AbortSubScript",
-                lua.create_function(|_, _: LuaMultiValue| Ok(()))?,
+                lua.create_function(move |_, id: u32| {
+                    subs.abort(id);
+                    Ok(())
+                })?,
             )?;
+            let subs = subscripts.clone();
             g.set(
                 "IsSubScriptRunning",
-                lua.create_function(|_, _: LuaMultiValue| Ok(false))?,
+                lua.create_function(move |_, id: u32| Ok(subs.is_running(id)))?,
             )?;
             g.set(
                 "GetCloudProvider",
@@ -355,15 +973,25 @@ impl LuaHost {
                 })?,
             )?;
 
+            // Exit([message]) ends the session once the running callback
+            // returns, through OnExit like closing the window.
+            let exit = exit_requested.clone();
             g.set(
                 "Exit",
-                lua.create_function(|_, ()| -> LuaResult<()> {
-                    std::process::exit(0);
+                lua.create_function(move |_, message: Option<String>| {
+                    if let Some(message) = message {
+                        println!("Exit: {}", message);
+                    }
+                    *exit.lock().unwrap() = true;
+                    Ok(())
                 })?,
             )?;
 
             let color: Arc<Mutex<[f32; 4]>> = Arc::new(Mutex::new([1.0, 1.0, 1.0, 1.0]));
+            // Set by SetDrawColorQuad; any SetDrawColor clears it.
+            let corner_colors: Arc<Mutex<Option<[[f32; 4]; 4]>>> = Arc::default();
             let color_set = color.clone();
+            let corners_set = corner_colors.clone();
             let color_draw = color.clone();
             g.set(
                 "SetDrawColor",
@@ -381,48 +1009,67 @@ impl LuaHost {
                             to_f32(b),
                             a.map(to_f32).unwrap_or(1.0),
                         ];
+                        *corners_set.lock().unwrap() = None;
                         Ok(())
                     },
                 )?,
             )?;
 
+            // SetDrawColorQuad(r, g, b, [a], ...) with one colour per corner:
+            // top-left, top-right, bottom-right, bottom-left. DrawImageQuad
+            // takes them in its point order.
+            let corners_set = corner_colors.clone();
+            g.set(
+                "SetDrawColorQuad",
+                lua.create_function(move |_, values: mlua::Variadic<f32>| {
+                    let stride = match values.len() {
+                        12 => 3,
+                        16 => 4,
+                        n => {
+                            return Err(LuaError::RuntimeError(format!(
+                                "SetDrawColorQuad expects 12 or 16 numbers, got {}",
+                                n
+                            )));
+                        }
+                    };
+                    let mut corners = [[1.0; 4]; 4];
+                    for (corner, c) in corners.iter_mut().zip(values.chunks(stride)) {
+                        corner[..stride].copy_from_slice(c);
+                    }
+                    *corners_set.lock().unwrap() = Some(corners);
+                    Ok(())
+                })?,
+            )?;
+
             let dq = draw_queue.clone();
+            let dropped = dropped_draws.clone();
             let vp = viewport.clone();
+            let sheets = sprite_sheets.clone();
+            let corners_draw = corner_colors.clone();
             g.set(
                 "DrawImage",
                 lua.create_function(
                     move |_,
-                          (handle, x, y, w, h, tcl, tct, tcr, tcb): (
+                          (handle, x, y, w, h, tc): (
                         LuaValue,
                         f32,
                         f32,
                         f32,
                         f32,
-                        Option<f32>,
-                        Option<f32>,
-                        Option<f32>,
-                        Option<f32>,
+                        LuaMultiValue,
                     )| {
-                        let texture_id = if let LuaValue::Table(t) = &handle {
-                            t.get::<_, u32>("id").unwrap_or(0)
-                        } else {
-                            0
-                        };
+                        let (texture_id, size) = texture_of(&handle);
                         let color = *color_draw.lock().unwrap();
-                        let uv = [
-                            tcl.unwrap_or(0.0),
-                            tct.unwrap_or(0.0),
-                            tcr.unwrap_or(0.0),
-                            tcb.unwrap_or(0.0),
-                        ];
+                        let uv = image_uv(&sheets, texture_id, size, tc);
                         let clip = *vp.lock().unwrap();
                         let (ox, oy) = match *vp.lock().unwrap() {
                             Some([vx, vy, _, _]) => (vx as f32, vy as f32),
                             None => (0.0, 0.0),
                         };
-                        dq.lock()
-                            .unwrap()
-                            .push(DrawItem::Rect(crate::graphics::DrawCmd {
+                        push_draw(
+                            &dq,
+                            &dropped,
+                            DrawItem::Rect(crate::graphics::DrawCmd {
                                 x: x + ox,
                                 y: y + oy,
                                 w,
@@ -431,90 +1078,260 @@ impl LuaHost {
                                 texture_id,
                                 uv,
                                 clip,
-                            }));
+                                corners: *corners_draw.lock().unwrap(),
+                            }),
+                        );
                         Ok(())
                     },
                 )?,
             )?;
 
-            let fs = font_system.clone();
+            // DrawImageNinePatch(handle, x, y, w, h, left, top, right, bottom,
+            // [tc...]): margins are in image pixels and keep their size while
+            // the edges and centre stretch.
+            let dq = draw_queue.clone();
+            let dropped = dropped_draws.clone();
+            let vp = viewport.clone();
+            let sheets = sprite_sheets.clone();
+            let color_patch = color.clone();
+            g.set(
+                "DrawImageNinePatch",
+                lua.create_function(
+                    move |_,
+                          (handle, x, y, w, h, left, top, right, bottom, tc): (
+                        LuaValue,
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        LuaMultiValue,
+                    )| {
+                        let (texture_id, size) = texture_of(&handle);
+                        let uv = image_uv(&sheets, texture_id, size, tc);
+                        let [ox, oy] = viewport_origin(&vp);
+                        let clip = *vp.lock().unwrap();
+                        let color = *color_patch.lock().unwrap();
+                        for (rect, uv) in nine_patch(
+                            [x + ox, y + oy, w, h],
+                            [left, top, right, bottom],
+                            uv,
+                            size.map(|n| n as f32),
+                        ) {
+                            push_draw(
+                                &dq,
+                                &dropped,
+                                DrawItem::Rect(crate::graphics::DrawCmd {
+                                    x: rect[0],
+                                    y: rect[1],
+                                    w: rect[2],
+                                    h: rect[3],
+                                    color,
+                                    texture_id,
+                                    uv,
+                                    clip,
+                                    corners: None,
+                                }),
+                            );
+                        }
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            // Vector primitives in the current draw colour. Widths are in
+            // pixels; DrawCircle without one is filled. Arc angles are in
+            // radians, clockwise from the positive x axis.
+            let dq = draw_queue.clone();
+            let dropped = dropped_draws.clone();
+            let vp = viewport.clone();
+            let color_line = color.clone();
+            g.set(
+                "DrawLine",
+                lua.create_function(
+                    move |_, (x1, y1, x2, y2, width): (f32, f32, f32, f32, Option<f32>)| {
+                        let [ox, oy] = viewport_origin(&vp);
+                        let vertices = shapes::stroke(
+                            &[[x1 + ox, y1 + oy], [x2 + ox, y2 + oy]],
+                            false,
+                            width.unwrap_or(1.0),
+                            *color_line.lock().unwrap(),
+                        );
+                        push_mesh(&dq, &dropped, &vp, vertices);
+                        Ok(())
+                    },
+                )?,
+            )?;
+            let dq = draw_queue.clone();
+            let dropped = dropped_draws.clone();
+            let vp = viewport.clone();
+            let color_circle = color.clone();
+            g.set(
+                "DrawCircle",
+                lua.create_function(
+                    move |_, (x, y, radius, width): (f32, f32, f32, Option<f32>)| {
+                        let [ox, oy] = viewport_origin(&vp);
+                        let center = [x + ox, y + oy];
+                        let color = *color_circle.lock().unwrap();
+                        let vertices = match width {
+                            Some(width) => shapes::stroke(
+                                &shapes::circle_points(center, radius),
+                                true,
+                                width,
+                                color,
+                            ),
+                            None => shapes::disc(center, radius, color),
+                        };
+                        push_mesh(&dq, &dropped, &vp, vertices);
+                        Ok(())
+                    },
+                )?,
+            )?;
+            let dq = draw_queue.clone();
+            let dropped = dropped_draws.clone();
+            let vp = viewport.clone();
+            let color_arc = color.clone();
+            g.set(
+                "DrawArc",
+                lua.create_function(
+                    move |_,
+                          (x, y, radius, start, end, width): (
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        Option<f32>,
+                    )| {
+                        let [ox, oy] = viewport_origin(&vp);
+                        let vertices = shapes::stroke(
+                            &shapes::arc_points([x + ox, y + oy], radius, start, end),
+                            false,
+                            width.unwrap_or(1.0),
+                            *color_arc.lock().unwrap(),
+                        );
+                        push_mesh(&dq, &dropped, &vp, vertices);
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            let sc = shapes.clone();
+            let pal = palette.clone();
             g.set(
                 "DrawStringWidth",
-                lua.create_function(move |_, (size, _font, text): (f32, String, String)| {
-                    let mut fs = fs.lock().unwrap();
-                    let mut buf = Buffer::new(&mut fs, glyphon::Metrics::new(size, size * 1.2));
-                    buf.set_size(&mut fs, f32::MAX, f32::MAX);
-                    let stripped = strip_pob_escapes(&text);
-                    buf.set_text(
-                        &mut fs,
-                        &stripped,
-                        glyphon::Attrs::new(),
-                        glyphon::Shaping::Basic,
-                    );
-                    buf.shape_until_scroll(&mut fs);
-                    let width = buf.layout_runs().map(|r| r.line_w).fold(0.0f32, f32::max);
+                lua.create_function(move |_, (size, font, text): (f32, String, String)| {
+                    let text = escapes::strip(&text, &pal.lock().unwrap());
+                    let width = sc.lock().unwrap().width(&font, size, &text);
                     Ok(width as u32)
                 })?,
             )?;
 
-            let fs = font_system.clone();
+            // DrawStringCursorIndex(size, font, text, x, y) -> the caret
+            // position nearest x.
+            let sc = shapes.clone();
+            let pal = palette.clone();
             g.set(
                 "DrawStringCursorIndex",
                 lua.create_function(
                     move |_,
-                          (size, _font, text, cursor_x, _cursor_y): (
+                          (size, font, text, cursor_x, _cursor_y): (
                         f32,
                         String,
                         String,
                         f32,
                         f32,
                     )| {
-                        let mut fs = fs.lock().unwrap();
-                        let mut buf = Buffer::new(&mut fs, glyphon::Metrics::new(size, size * 1.2));
-                        buf.set_size(&mut fs, f32::MAX, f32::MAX);
-                        let stripped = strip_pob_escapes(&text);
-                        buf.set_text(
-                            &mut fs,
-                            &stripped,
-                            glyphon::Attrs::new(),
-                            glyphon::Shaping::Basic,
-                        );
-                        buf.shape_until_scroll(&mut fs);
-                        for run in buf.layout_runs() {
-                            for glyph in run.glyphs.iter() {
-                                if cursor_x < glyph.x + glyph.w * 0.5 {
-                                    return Ok(glyph.start as i64);
-                                }
-                            }
-                        }
-                        Ok(stripped.len() as i64)
+                        let (stripped, map) = escapes::strip_mapped(&text, &pal.lock().unwrap());
+                        let offset = sc
+                            .lock()
+                            .unwrap()
+                            .cursor_index(&font, size, &stripped, cursor_x);
+                        Ok(offset_to_caret(&map, offset))
                     },
                 )?,
             )?;
 
+            // DrawStringIndexToX(size, font, text, caret) -> x: where the
+            // caret is drawn, the inverse of DrawStringCursorIndex.
+            let sc = shapes.clone();
+            let pal = palette.clone();
+            g.set(
+                "DrawStringIndexToX",
+                lua.create_function(
+                    move |_, (size, font, text, caret): (f32, String, String, i64)| {
+                        let (stripped, map) = escapes::strip_mapped(&text, &pal.lock().unwrap());
+                        let offset = caret_to_offset(&map, caret);
+                        Ok(sc
+                            .lock()
+                            .unwrap()
+                            .span(&font, size, &stripped, offset, offset)
+                            .0)
+                    },
+                )?,
+            )?;
+
+            // DrawStringSelection(size, font, text, first, last) -> left, right:
+            // where a highlight of text:sub(first, last) starts and ends.
+            let sc = shapes.clone();
+            let pal = palette.clone();
+            g.set(
+                "DrawStringSelection",
+                lua.create_function(
+                    move |_, (size, font, text, first, last): (f32, String, String, i64, i64)| {
+                        let (stripped, map) = escapes::strip_mapped(&text, &pal.lock().unwrap());
+                        let start = caret_to_offset(&map, first);
+                        let end = caret_to_offset(&map, last.max(first - 1) + 1);
+                        Ok(sc.lock().unwrap().span(&font, size, &stripped, start, end))
+                    },
+                )?,
+            )?;
+
+            // Pixel-snapping of text origins; DrawString's optional 7th arg
+            // overrides it per call. The optional 8th is a shadow or
+            // outline, see `text_effect`.
+            let text_snap = Arc::new(Mutex::new(true));
+            let snap = text_snap.clone();
+            g.set(
+                "SetTextSnapping",
+                lua.create_function(move |_, enabled: bool| {
+                    *snap.lock().unwrap() = enabled;
+                    Ok(())
+                })?,
+            )?;
+
             let dq = draw_queue.clone();
+            let dropped = dropped_draws.clone();
             let color_text = color.clone();
             let vp_text = viewport.clone();
+            let snap_text = text_snap.clone();
             g.set(
                 "DrawString",
                 lua.create_function(
                     move |_,
-                          (x, y, align, size, font, text): (
+                          (x, y, align, size, font, text, snap, effect): (
                         f32,
                         f32,
                         String,
                         f32,
                         String,
                         String,
+                        Option<bool>,
+                        Option<LuaTable>,
                     )| {
+                        let effect = effect.map(|t| text_effect(&t)).transpose()?;
                         let color = *color_text.lock().unwrap();
                         let (ox, oy) = match *vp_text.lock().unwrap() {
                             Some([vx, vy, _, _]) => (vx as f32, vy as f32),
                             None => (0.0, 0.0),
                         };
-                        dq.lock()
-                            .unwrap()
-                            .push(DrawItem::Text(crate::graphics::TextCmd {
+                        push_draw(
+                            &dq,
+                            &dropped,
+                            DrawItem::Text(crate::graphics::TextCmd {
                                 x: x + ox,
                                 y: y + oy,
                                 size,
@@ -523,7 +1340,10 @@ impl LuaHost {
                                 align,
                                 font,
                                 clip: *vp_text.lock().unwrap(),
-                            }));
+                                snap: snap.unwrap_or(*snap_text.lock().unwrap()),
+                                effect,
+                            }),
+                        );
                         Ok(())
                     },
                 )?,
@@ -538,7 +1358,9 @@ impl LuaHost {
             )?;
 
             let dq = draw_queue.clone();
+            let dropped = dropped_draws.clone();
             let color_quad = color.clone();
+            let corners_quad = corner_colors.clone();
             let vp_quad = viewport.clone();
             g.set(
                 "DrawImageQuad",
@@ -576,118 +1398,176 @@ impl LuaHost {
                     let s4 = next_f32(0.0);
                     let t4 = next_f32(0.0);
 
-                    let texture_id = if let LuaValue::Table(t) = &handle {
-                        t.get::<_, u32>("id").unwrap_or(0)
-                    } else {
-                        0
-                    };
-                    dq.lock().unwrap().push(DrawItem::Quad(DrawQuadCmd {
-                        texture_id,
-                        color: *color_quad.lock().unwrap(),
-                        clip: *vp_quad.lock().unwrap(),
-                        positions: [
-                            [x1 + ox, y1 + oy],
-                            [x2 + ox, y2 + oy],
-                            [x3 + ox, y3 + oy],
-                            [x4 + ox, y4 + oy],
-                        ],
-                        uvs: [[s1, t1], [s2, t2], [s3, t3], [s4, t4]],
-                    }));
+                    let (texture_id, _) = texture_of(&handle);
+                    push_draw(
+                        &dq,
+                        &dropped,
+                        DrawItem::Quad(DrawQuadCmd {
+                            texture_id,
+                            color: *color_quad.lock().unwrap(),
+                            corners: *corners_quad.lock().unwrap(),
+                            clip: *vp_quad.lock().unwrap(),
+                            positions: [
+                                [x1 + ox, y1 + oy],
+                                [x2 + ox, y2 + oy],
+                                [x3 + ox, y3 + oy],
+                                [x4 + ox, y4 + oy],
+                            ],
+                            uvs: [[s1, t1], [s2, t2], [s3, t3], [s4, t4]],
+                        }),
+                    );
                     Ok(())
                 })?,
             )?;
 
-            lua.load(
-                r#"
-                local _require = require
-                local _utf8 = {
-                    reverse = string.reverse,
-                    gsub    = string.gsub,
-                    find    = string.find,
-                    sub     = string.sub,
-                    match   = string.match,
-                    next    = function(s, i, n) return i + (n or 1) end,
-                }
-                function require(name)
-                    if name == "lcurl.safe" then return nil end
-                    if name == "lua-utf8" then return _utf8 end
-                    return _require(name)
-                end
-                "#,
-            )
-            .exec()?;
+            install_require_overrides(&lua)?;
+            vfs::register(&lua, vfs.clone())?;
+            dir_watch::register(&lua, &builds_watch)?;
+            // Headless states have no window to show it.
+            if windowed {
+                *window_icon.lock().unwrap() = Some(IconImage::standard(&layout, &vfs));
+            }
+            window_icon::register(&lua, window_icon, &layout, vfs.clone())?;
+            dpi_override::register(&lua, dpi_override, windowed.then(Settings::path))?;
             lua.load("arg = {}").exec()?;
 
-            let next_id = Arc::new(Mutex::new(1));
-            let tuq = texture_queue.clone();
+            image_handle::register(
+                &lua,
+                Images {
+                    ids: texture_ids.clone(),
+                    queue: texture_queue.clone(),
+                    svgs: svgs.clone(),
+                    sheets: sprite_sheets.clone(),
+                    files: vfs.clone(),
+                    dpi_scale: dpi_scale.clone(),
+                    upload_errors,
+                    waker: waker.clone(),
+                    textures: settings.textures.cache.unwrap_or(true).then(|| {
+                        Arc::new(TextureCache::new(
+                            user_path().join("Cache").join("Textures"),
+                            settings.textures.compress.unwrap_or(false),
+                        ))
+                    }),
+                },
+            )?;
+
+            capture = RegionCapture::new(
+                texture_ids.clone(),
+                texture_queue.clone(),
+                window_mode.clone(),
+                screen_size.clone(),
+                monitors.clone(),
+                hide_for_grab,
+                settings.capture.ocr_command.clone(),
+            );
+            capture.register(&lua)?;
+            backdrop = Backdrop::new(
+                theme,
+                texture_ids.clone(),
+                texture_queue.clone(),
+                &settings.theme,
+            );
+            backdrop.register(&lua)?;
+
+            let (tids, tuq) = (texture_ids.clone(), texture_queue.clone());
+            let (viewport, active_target) = (viewport.clone(), active_target.clone());
             g.set(
-                "NewImageHandle",
-                lua.create_function(move |lua, ()| {
-                    let id = {
-                        let mut n = next_id.lock().unwrap();
-                        let id = *n;
-                        *n += 1;
-                        id
-                    };
+                "NewRenderTarget",
+                lua.create_function(move |lua, (width, height): (u32, u32)| {
+                    let lease = TextureLease::new(tids.clone(), tuq.clone(), |_| {});
+                    let id = lease.id();
+                    let width = width.clamp(1, MAX_TARGET_SIZE);
+                    let height = height.clamp(1, MAX_TARGET_SIZE);
+                    tuq.lock()
+                        .unwrap()
+                        .push(TextureCmd::CreateTarget { id, width, height });
 
                     let t = lua.create_table()?;
                     t.set("id", id)?;
-                    t.set("valid", false)?;
-                    t.set("width", 0u32)?;
-                    t.set("height", 0u32)?;
-
-                    let tuq2 = tuq.clone();
+                    t.set("lease", lua.create_userdata(lease)?)?;
+                    t.set("valid", true)?;
+                    t.set("width", width)?;
+                    t.set("height", height)?;
+                    t.set(
+                        "IsValid",
+                        lua.create_function(|_, this: LuaTable| this.get::<_, bool>("valid"))?,
+                    )?;
+                    t.set(
+                        "ImageSize",
+                        lua.create_function(|_, this: LuaTable| {
+                            Ok((this.get::<_, u32>("width")?, this.get::<_, u32>("height")?))
+                        })?,
+                    )?;
 
+                    // Begin() clears to transparent, Begin(r, g, b, [a]) to a
+                    // colour, Begin(false) draws over the previous contents.
+                    let dq = draw_queue.clone();
+                    let vp = viewport.clone();
+                    let active = active_target.clone();
                     t.set(
-                        "Load",
+                        "Begin",
                         lua.create_function(
-                            move |_, (this, path, _): (LuaTable, String, LuaMultiValue)| {
-                                let img = match image::open(&path) {
-                                    Ok(img) => img.to_rgba8(),
-                                    Err(e) => {
-                                        println!("Load image {}: {}", path, e);
-                                        return Ok(());
-                                    }
+                            move |lua,
+                                  (this, r, g, b, a): (
+                                LuaTable,
+                                LuaValue,
+                                Option<f32>,
+                                Option<f32>,
+                                Option<f32>,
+                            )| {
+                                if !this.get::<_, bool>("valid")? {
+                                    return Err(LuaError::RuntimeError(
+                                        "render target has been unloaded".into(),
+                                    ));
+                                }
+                                let clear = match r {
+                                    LuaValue::Boolean(false) => None,
+                                    LuaValue::Integer(_) | LuaValue::Number(_) => Some([
+                                        lua.unpack::<f32>(r)?,
+                                        g.unwrap_or(0.0),
+                                        b.unwrap_or(0.0),
+                                        a.unwrap_or(1.0),
+                                    ]),
+                                    _ => Some([0.0; 4]),
                                 };
-                                let w = img.width();
-                                let h = img.height();
-                                let rgba = img.into_raw();
-                                tuq2.lock()
-                                    .unwrap()
-                                    .push(crate::graphics::TextureUploadCmd {
-                                        id,
-                                        rgba: rgba,
-                                        width: w,
-                                        height: h,
-                                    });
-                                this.set("valid", true)?;
-                                this.set("width", w)?;
-                                this.set("height", h)?;
-
+                                let mut active = active.lock().unwrap();
+                                if active.is_some() {
+                                    return Err(LuaError::RuntimeError(
+                                        "another render target is already active".into(),
+                                    ));
+                                }
+                                // Target draws use the target's own coordinates.
+                                *active = Some((id, vp.lock().unwrap().take()));
+                                dq.lock().unwrap().push(DrawItem::BeginTarget { id, clear });
                                 Ok(())
                             },
                         )?,
                     )?;
-
-                    t.set(
-                        "IsValid",
-                        lua.create_function(|_, this: LuaTable| Ok(this.get::<_, bool>("valid")?))?,
-                    )?;
+                    let dq = draw_queue.clone();
+                    let vp = viewport.clone();
+                    let active = active_target.clone();
                     t.set(
-                        "ImageSize",
-                        lua.create_function(|_, this: LuaTable| {
-                            Ok((this.get::<_, u32>("width")?, this.get::<_, u32>("height")?))
+                        "End",
+                        lua.create_function(move |_, _: LuaTable| {
+                            let mut active = active.lock().unwrap();
+                            let Some((_, saved)) = active.take_if(|(open, _)| *open == id) else {
+                                return Err(LuaError::RuntimeError(
+                                    "render target is not active".into(),
+                                ));
+                            };
+                            *vp.lock().unwrap() = saved;
+                            dq.lock().unwrap().push(DrawItem::EndTarget);
+                            Ok(())
                         })?,
                     )?;
+                    let tuq2 = tuq.clone();
                     t.set(
                         "Unload",
-                        lua.create_function(|_, this: LuaTable| this.set("valid", false))?,
-                    )?;
-                    t.set(
-                        "SetLoadingPriority",
-                        lua.create_function(|_, _: LuaMultiValue| Ok(()))?,
+                        lua.create_function(move |_, this: LuaTable| {
+                            tuq2.lock().unwrap().push(TextureCmd::Unload(id));
+                            this.set("valid", false)
+                        })?,
                     )?;
-
                     Ok(t)
                 })?,
             )?;
@@ -696,99 +1576,803 @@ impl LuaHost {
         Ok(Self {
             lua,
             main_object,
-            root_dir,
+            callback_targets,
+            layout,
+            subscripts,
+            dialogs,
+            hotkeys,
+            capture,
+            backdrop,
+            svgs,
+            texture_queue,
+            texture_ids,
+            dpi_scale,
+            builds_watch,
+            recent_builds,
+            clipboard,
+            newlines,
+            clipboard_watch: Mutex::new(ClipboardWatch::new(watch)),
+            focused,
+            start_time,
+            frame_clock,
+            exit_requested,
+            callback_errors: Mutex::new(CallbackErrors::new(ErrorPolicy::Crash)),
+            gc,
+            dropped_draws,
+            viewport,
+            active_target,
+            ipc_calls,
+            vfs,
         })
     }
 
+    /// Sets the global `arg` table to the command line arguments.
+    pub fn set_args(&self, items: &[String]) -> LuaResult<()> {
+        self.lua.globals().set("arg", items)
+    }
+
+    /// Opens build files, build codes and `pob://` links the way PoB's
+    /// import tab would, once OnInit has set up `main`.
+    pub fn open_items(&self, items: &[String]) -> LuaResult<()> {
+        for item in items {
+            if let Err(e) = self.open_item(item) {
+                eprintln!("open {}: {}", item, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens one build code, file or `pob://` link.
+    pub fn open_item(&self, item: &str) -> LuaResult<()> {
+        self.lua.load(OPEN_ITEM).call(item)
+    }
+
+    /// Answers the requests tools sent over IPC since the last frame.
+    pub fn pump_ipc(&self) {
+        let calls = std::mem::take(&mut *self.ipc_calls.lock().unwrap());
+        for call in calls {
+            let no_build = || LuaError::RuntimeError("no build is open".into());
+            let result = match call.command {
+                Command::Ping => Ok(env!("CARGO_PKG_VERSION").to_string()),
+                Command::LoadBuild => self.open_item(&call.arg).map(|()| String::new()),
+                Command::ExportBuild => {
+                    build_stats::export_code(&self.lua).and_then(|c| c.ok_or_else(no_build))
+                }
+                Command::Summary => build_stats::summary(&self.lua)
+                    .and_then(|s| s.ok_or_else(no_build))
+                    .map(|s| build_stats::summary_json(&s).to_string()),
+            };
+            call.answer(result.map_err(|e| e.to_string()));
+        }
+    }
+
     pub fn launch(&self) -> LuaResult<()> {
-        let path = self.root_dir.join("PathOfBuilding/src/Launch.lua");
-        let code =
-            std::fs::read_to_string(&path).map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+        let path = self.layout.script_dir.join("Launch.lua");
+        let code = self
+            .vfs
+            .lock()
+            .unwrap()
+            .read_to_string(&path.to_string_lossy())
+            .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
         self.lua.load(&code).exec()
     }
 
-    pub fn callback(&self, name: &str) -> LuaResult<()> {
+    /// Hands file dialogs that were closed and picked screen regions to
+    /// their callbacks.
+    pub fn pump_dialogs(&self) -> LuaResult<()> {
+        self.dialogs.pump(&self.lua)?;
+        self.capture.pump(&self.lua)
+    }
+
+    /// Runs the callbacks of global hotkeys pressed or refused since the
+    /// last call.
+    pub fn pump_hotkeys(&self) -> LuaResult<()> {
+        self.hotkeys.pump(&self.lua)
+    }
+
+    /// Runs OnSubFinished/OnSubError/OnSubCall for subscripts that have
+    /// reported since the last call.
+    pub fn pump_subscripts(&self) -> LuaResult<()> {
         let guard = self.main_object.lock().unwrap();
         let Some(key) = guard.as_ref() else {
             return Ok(());
         };
-
         let obj: LuaTable = self.lua.registry_value(key)?;
-        if let Ok(func) = obj.get::<_, LuaFunction>(name) {
-            func.call::<_, ()>(obj.clone())?;
+        self.subscripts.dispatch(&self.lua, &obj)
+    }
+
+    /// Advances the frame counter and delta time; call before OnFrame.
+    /// Returns the delta in seconds.
+    /// How many draws were dropped as degenerate since the last call.
+    pub fn take_dropped_draws(&self) -> usize {
+        std::mem::take(&mut *self.dropped_draws.lock().unwrap())
+    }
+
+    /// Queues the theme's background image and vignette; call before
+    /// OnFrame so they sit under everything it draws.
+    pub fn draw_backdrop(&self, queue: &DrawQueue, size: [u32; 2]) {
+        self.backdrop.draw(queue, size);
+    }
+
+    pub fn set_error_policy(&self, policy: ErrorPolicy) {
+        self.callback_errors.lock().unwrap().set_policy(policy);
+    }
+
+    pub fn exit_requested(&self) -> bool {
+        *self.exit_requested.lock().unwrap()
+    }
+
+    /// Gives the garbage collector its time between frames, and returns
+    /// how much it took.
+    pub fn collect_garbage(&self) -> LuaResult<std::time::Duration> {
+        gc::step(&self.gc, &self.lua)
+    }
+
+    /// Starts a frame at `replayed`, the recording's time when replaying
+    /// one, or else now, and returns the time since the last frame.
+    pub fn begin_frame(&self, replayed: Option<std::time::Duration>) -> f64 {
+        self.texture_ids.lock().unwrap().next_frame();
+        let now = replayed.unwrap_or_else(|| self.start_time.elapsed());
+        let mut clock = self.frame_clock.lock().unwrap();
+        clock.count += 1;
+        if let Some(last) = clock.last_start {
+            clock.delta = now.saturating_sub(last).as_secs_f64();
         }
-        Ok(())
+        clock.last_start = Some(now);
+        clock.replayed = replayed;
+        clock.delta
     }
 
-    pub fn callback_args(&self, name: &str, args: LuaMultiValue) -> LuaResult<()> {
-        let guard = self.main_object.lock().unwrap();
-        let Some(key) = guard.as_ref() else {
+    /// Forgets a render target whose End wasn't reached this frame, e.g.
+    /// after an error between Begin and End, and puts back the viewport
+    /// End would have. Call where the frame's passes are split.
+    pub fn close_render_target(&self) {
+        if let Some((_, saved)) = self.active_target.lock().unwrap().take() {
+            *self.viewport.lock().unwrap() = saved;
+        }
+    }
+
+    /// Renders SVG images again if the display scale changed since they
+    /// were last rasterized.
+    pub fn refresh_svgs(&self) {
+        let scale = *self.dpi_scale.lock().unwrap();
+        let mut svgs = self.svgs.lock().unwrap();
+        let mut queue = self.texture_queue.lock().unwrap();
+        for (id, svg) in svgs.iter_mut().filter(|(_, svg)| svg.scale != scale) {
+            queue.push(TextureCmd::Upload(svg.rasterize(*id, scale)));
+        }
+    }
+
+    /// Calls OnClipboardItem with item text copied since the last check,
+    /// when the settings turn the clipboard watch on.
+    pub fn poll_clipboard(&self) -> LuaResult<()> {
+        let focused = *self.focused.lock().unwrap();
+        if !self
+            .clipboard_watch
+            .lock()
+            .unwrap()
+            .due(focused, std::time::Instant::now())
+        {
+            return Ok(());
+        }
+        let Some(text) = self.clipboard.lock().unwrap().changed_text() else {
             return Ok(());
         };
+        if !is_item_text(&text) {
+            return Ok(());
+        }
+        let text = self.newlines.lock().unwrap().for_paste(&text);
+        self.callback_args("OnClipboardItem", text.into_lua_multi(&self.lua)?)
+    }
 
-        let obj: LuaTable = self.lua.registry_value(key)?;
-        let mut args_vec = vec![LuaValue::Table(obj.clone())];
-        args_vec.extend(args);
-        if let Ok(func) = obj.get::<_, LuaFunction>(name) {
-            func.call::<LuaMultiValue, ()>(LuaMultiValue::from_vec(args_vec))?;
+    /// Keeps the watcher on `main.buildPath` and, when files there change
+    /// outside PoB, calls OnBuildsChanged and rescans the builds list.
+    /// The recent builds are listed again whenever either happens.
+    pub fn poll_builds(&self) -> LuaResult<()> {
+        let Ok(main) = self.lua.globals().get::<_, LuaTable>("main") else {
+            return Ok(());
+        };
+        let Ok(path) = main.get::<_, String>("buildPath") else {
+            return Ok(());
+        };
+        let switched = self.builds_watch.watch(Path::new(&path));
+        let changed = self.builds_watch.take_changed();
+        if switched || changed {
+            *self.recent_builds.lock().unwrap() = tray::recent_builds(Path::new(&path));
+        }
+        if !changed {
+            return Ok(());
+        }
+        self.callback("OnBuildsChanged")?;
+        self.lua
+            .load(
+                r#"
+                local list = main.modes and main.modes.LIST
+                if main.mode == "LIST" and list and list.BuildList then
+                    list:BuildList()
+                end
+                "#,
+            )
+            .exec()
+    }
+
+    /// The open build as (name, XML) for a backup: from the main object's
+    /// OnBackup if it defines one, otherwise from PoB's build mode.
+    pub fn backup_xml(&self) -> LuaResult<Option<(String, String)>> {
+        let obj = match self.main_object.lock().unwrap().as_ref() {
+            Some(key) => self.lua.registry_value::<LuaValue>(key)?,
+            None => LuaValue::Nil,
+        };
+        self.lua
+            .load(
+                r#"
+                local obj = ...
+                if type(obj) == "table" and obj.OnBackup then
+                    return obj:OnBackup()
+                end
+                local build = main and main.mode == "BUILD" and main.modes and main.modes.BUILD
+                if build and build.SaveDB then
+                    return build.buildName or "Unnamed build", build:SaveDB("code")
+                end
+                "#,
+            )
+            .call::<_, (Option<String>, Option<String>)>(obj)
+            .map(|(name, xml)| Some((name?, xml?)))
+    }
+
+    /// Asks whether to reopen a backup left by a session that crashed.
+    pub fn offer_recovery(&self, name: &str, xml: &str) -> LuaResult<()> {
+        self.lua
+            .load(
+                r#"
+                local name, xml = ...
+                if not (main and main.OpenConfirmPopup) then
+                    print("A backup of " .. name .. " from the last session is in the Backups folder")
+                    return
+                end
+                main:OpenConfirmPopup("Recover Build",
+                    "Path of Building did not shut down cleanly.\nOpen the last backup of '" .. name .. "'?",
+                    "Recover", function()
+                        main:SetMode("BUILD", false, name, xml)
+                    end)
+                "#,
+            )
+            .call((name, xml))
+    }
+
+    pub fn callback(&self, name: &str) -> LuaResult<()> {
+        self.callback_args(name, LuaMultiValue::new())
+    }
+
+    /// Calls `name` on the callback targets and the main object, each with
+    /// itself as `self`.
+    pub fn callback_args(&self, name: &str, args: LuaMultiValue) -> LuaResult<()> {
+        let main = match self.main_object.lock().unwrap().as_ref() {
+            Some(key) => Some(self.lua.registry_value::<LuaTable>(key)?),
+            None => None,
+        };
+        // Neither lock is held during the calls, which may add targets or
+        // set another main object.
+        let targets = self
+            .callback_targets
+            .lock()
+            .unwrap()
+            .in_order(&self.lua, main)?;
+        for obj in targets {
+            let Ok(func) = obj.get::<_, LuaFunction>(name) else {
+                continue;
+            };
+            let mut args_vec = vec![LuaValue::Table(obj.clone())];
+            args_vec.extend(args.iter().cloned());
+            if let Err(e) = func.call::<LuaMultiValue, ()>(LuaMultiValue::from_vec(args_vec)) {
+                self.callback_errors.lock().unwrap().handle(name, e)?;
+            }
         }
         Ok(())
     }
 }
 
-fn strip_pob_escapes(s: &str) -> String {
-    let mut out = String::with_capacity(s.len());
-    let mut chars = s.chars().peekable();
-    while let Some(c) = chars.next() {
-        if c != '^' {
-            out.push(c);
-            continue;
-        }
-        match chars.peek().copied() {
-            Some('0'..='9') => {
-                chars.next();
-            }
-            Some('x') => {
-                chars.next();
-                for _ in 0..6 {
-                    match chars.peek() {
-                        Some(h) if h.is_ascii_hexdigit() => {
-                            chars.next();
-                        }
-                        _ => break,
-                    }
-                }
-            }
-            _ => out.push(c),
+/// Texture coords for a DrawImage-style call: `tcLeft, tcTop, tcRight,
+/// tcBottom`, the name of a sub-image registered on the handle, or the
+/// whole image.
+fn image_uv(sheets: &SpriteSheets, texture_id: u32, size: [u32; 2], tc: LuaMultiValue) -> [f32; 4] {
+    let mut tc = tc.into_iter();
+    match tc.next() {
+        Some(LuaValue::String(name)) => sheets
+            .lock()
+            .unwrap()
+            .get(&texture_id)
+            .and_then(|s| s.uv(name.to_str().unwrap_or(""), size[0], size[1]))
+            .unwrap_or([0.0, 0.0, 1.0, 1.0]),
+        Some(first) => {
+            let num = |v: Option<LuaValue>| match v {
+                Some(LuaValue::Number(n)) => n as f32,
+                Some(LuaValue::Integer(n)) => n as f32,
+                _ => 0.0,
+            };
+            [
+                num(Some(first)),
+                num(tc.next()),
+                num(tc.next()),
+                num(tc.next()),
+            ]
         }
+        None => [0.0, 0.0, 1.0, 1.0],
+    }
+}
+
+fn viewport_origin(viewport: &Mutex<Option<[u32; 4]>>) -> [f32; 2] {
+    match *viewport.lock().unwrap() {
+        Some([vx, vy, _, _]) => [vx as f32, vy as f32],
+        None => [0.0, 0.0],
+    }
+}
+
+/// Queues a draw unless it has nothing to show or non-finite numbers, which
+/// Lua produces mid layout transition; those are counted in `dropped`.
+fn push_draw(queue: &DrawQueue, dropped: &Mutex<usize>, item: DrawItem) {
+    if is_degenerate(&item) {
+        *dropped.lock().unwrap() += 1;
+        return;
     }
-    out
+    queue.lock().unwrap().push(item);
 }
 
+/// Queues primitive geometry, clipped to the current viewport.
+fn push_mesh(
+    queue: &DrawQueue,
+    dropped: &Mutex<usize>,
+    viewport: &Mutex<Option<[u32; 4]>>,
+    vertices: Vec<Vertex>,
+) {
+    let clip = *viewport.lock().unwrap();
+    push_draw(queue, dropped, DrawItem::Mesh(MeshCmd { vertices, clip }));
+}
+
+/// Reads DrawString's effect table: `{ shadow = {r, g, b, [a]}, [offset =
+/// {x, y}] }` or `{ outline = {r, g, b, [a]}, [width = w] }`, both one UI
+/// unit by default.
+fn text_effect(t: &LuaTable) -> LuaResult<TextEffect> {
+    let color = |c: Vec<f32>| match c[..] {
+        [r, g, b] => Ok([r, g, b, 1.0]),
+        [r, g, b, a] => Ok([r, g, b, a]),
+        _ => Err(LuaError::runtime("effect colour needs 3 or 4 numbers")),
+    };
+    if let Some(c) = t.get::<_, Option<Vec<f32>>>("shadow")? {
+        let offset = t.get::<_, Option<[f32; 2]>>("offset")?;
+        return Ok(TextEffect::Shadow {
+            offset: offset.unwrap_or([1.0, 1.0]),
+            color: color(c)?,
+        });
+    }
+    if let Some(c) = t.get::<_, Option<Vec<f32>>>("outline")? {
+        let width = t.get::<_, Option<f32>>("width")?;
+        return Ok(TextEffect::Outline {
+            width: width.unwrap_or(1.0),
+            color: color(c)?,
+        });
+    }
+    Err(LuaError::runtime("text effect needs shadow or outline"))
+}
+
+/// Caret positions are 1-based into the text with escapes, as PoB's edit
+/// controls keep them: caret `n` sits before the text's `n`th byte. These
+/// convert to and from byte offsets into the stripped, shaped text.
+fn caret_to_offset(map: &[usize], caret: i64) -> usize {
+    let before = (caret - 1).max(0) as usize;
+    map.partition_point(|&at| at < before)
+}
+
+fn offset_to_caret(map: &[usize], offset: usize) -> i64 {
+    map[offset.min(map.len() - 1)] as i64 + 1
+}
+
+/// Routes `require` of modules PoB expects from its C runtime to the host's
+/// native replacements.
+pub fn install_require_overrides(lua: &Lua) -> LuaResult<()> {
+    lua.load(
+        r#"
+        local _require = require
+        local _utf8 = {
+            reverse = string.reverse,
+            gsub    = string.gsub,
+            find    = string.find,
+            sub     = string.sub,
+            match   = string.match,
+            next    = function(s, i, n) return i + (n or 1) end,
+        }
+        function require(name)
+            if name == "lua-utf8" then return _utf8 end
+            if name == "base64" then return _require("native.base64") end
+            if name == "sha1" then return _require("native.sha1") end
+            if name == "xml" then return _require("native.xml") end
+            return _require(name)
+        end
+        "#,
+    )
+    .exec()
+}
+
+/// Lua side of `open_items`, called with one argument.
+const OPEN_ITEM: &str = r#"
+local item = ...
+local sites = {
+    pobbin = "https://pobb.in/%s/raw",
+    poeninja = "https://poe.ninja/pob/raw/%s",
+    pastebin = "https://pastebin.com/raw/%s",
+}
+local function openCode(code, name)
+    code = code:gsub("%s", "")
+    local ok, xml = pcall(Inflate, common.base64.decode((code:gsub("-", "+"):gsub("_", "/"))))
+    if not ok or not xml or xml == "" then
+        error("not a build code")
+    end
+    main:SetMode("BUILD", false, name, xml)
+end
+local site, id = item:match("^pob://(%w+)/([%w_-]+)")
+if site then
+    local url = assert(sites[site:lower()], "unknown build site " .. site):format(id)
+    launch:DownloadPage(url, function(response, errMsg)
+        if errMsg then
+            print("open " .. item .. ": " .. tostring(errMsg))
+            return
+        end
+        openCode(type(response) == "table" and response.body or response, "Imported build")
+    end)
+elseif item:lower():match("%.xml$") then
+    main:SetMode("BUILD", item, item:match("([^/\\]+)%.[xX][mM][lL]$"))
+else
+    openCode(item, "Imported build")
+end
+"#;
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn get_time_returns_u64() {
-        let root_dir = std::env::current_dir().unwrap();
-        let dq = Arc::new(Mutex::new(vec![]));
-        let tq = Arc::new(Mutex::new(vec![]));
-        let cp = Arc::new(Mutex::new([0.0, 0.0]));
-        let hs = Arc::new(Mutex::new(HashSet::new()));
-        let host = LuaHost::new(root_dir, dq, tq, cp, hs).unwrap();
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let host = LuaHost::new(layout, HostShared::default()).unwrap();
         let t: u64 = host.lua.load("return GetTime()").eval().unwrap();
         assert!(t < 1000);
+        let (ms, us): (u64, u64) = host
+            .lua
+            .load("return GetTime(), GetTimeUS()")
+            .eval()
+            .unwrap();
+        assert!(us >= ms * 1000);
+
+        host.begin_frame(None);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        host.begin_frame(None);
+        let (n, dt, ms, frames): (u64, f64, f64, f64) = host
+            .lua
+            .load("return GetFrameCount(), GetDeltaTime(), GetFrameTime(), GetElapsedFrames()")
+            .eval()
+            .unwrap();
+        assert_eq!(n, 2);
+        assert!((0.002..1.0).contains(&dt));
+        assert_eq!((ms, frames), (dt * 1000.0, dt * 60.0));
+    }
+
+    #[test]
+    fn replayed_frames_take_the_recorded_clock() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let host = LuaHost::new(layout, HostShared::default()).unwrap();
+        let at = std::time::Duration::from_millis;
+        host.begin_frame(Some(at(5000)));
+        host.begin_frame(Some(at(5050)));
+        let (t, dt): (u64, f64) = host
+            .lua
+            .load("return GetTime(), GetDeltaTime()")
+            .eval()
+            .unwrap();
+        assert_eq!(t, 5050);
+        assert!((dt - 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn carets_convert_to_x_and_back_across_escapes() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let host = LuaHost::new(layout, HostShared::default()).unwrap();
+        let (carets, end, left, right): (Vec<i64>, i64, f32, f32) = host
+            .lua
+            .load(
+                r#"
+                local text = "^7Fire ^x33FF88Trap"
+                local carets = {}
+                for _, caret in ipairs({ 1, 3, 7, 16, 18 }) do
+                    local x = DrawStringIndexToX(16, "VAR", text, caret)
+                    table.insert(carets, DrawStringCursorIndex(16, "VAR", text, x + 0.5, 0))
+                end
+                local left, right = DrawStringSelection(16, "VAR", text, 16, 19)
+                return carets, DrawStringCursorIndex(16, "VAR", text, 1000, 0), left, right
+                "#,
+            )
+            .eval()
+            .unwrap();
+        // Caret 1 sits before the escape, which the cursor skips past.
+        assert_eq!(carets, vec![3, 3, 7, 16, 18]);
+        assert_eq!(end, 20);
+        assert!(left > 0.0 && right > left);
+    }
+
+    #[test]
+    fn draw_string_takes_shadow_and_outline_effects() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = HostShared::default();
+        let host = LuaHost::new(layout, shared.clone()).unwrap();
+        host.lua
+            .load(
+                r#"
+                DrawString(0, 0, "LEFT", 14, "VAR", "12", nil, { shadow = { 0, 0, 0, 0.8 } })
+                DrawString(0, 0, "LEFT", 14, "VAR", "12", true,
+                    { outline = { 0, 0, 0 }, width = 2 })
+                DrawString(0, 0, "LEFT", 14, "VAR", "12")
+                assert(not pcall(DrawString, 0, 0, "LEFT", 14, "VAR", "12", nil, {}))
+                "#,
+            )
+            .exec()
+            .unwrap();
+        let effects: Vec<_> = shared
+            .draw_queue
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|item| match item {
+                DrawItem::Text(t) => t.effect,
+                _ => panic!("not text"),
+            })
+            .collect();
+        assert_eq!(
+            effects,
+            [
+                Some(TextEffect::Shadow {
+                    offset: [1.0, 1.0],
+                    color: [0.0, 0.0, 0.0, 0.8]
+                }),
+                Some(TextEffect::Outline {
+                    width: 2.0,
+                    color: [0.0, 0.0, 0.0, 1.0]
+                }),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn registered_color_escapes_are_stripped_and_drawn() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = HostShared::default();
+        let host = LuaHost::new(layout, shared.clone()).unwrap();
+        let (stripped, removed, bad): (String, String, bool) = host
+            .lua
+            .load(
+                r#"
+                RegisterColorEscape("ORANGE", 1, 0.5, 0)
+                local stripped = StripEscapes("^ORANGEFire")
+                RegisterColorEscape("GONE", 1, 1, 1)
+                RegisterColorEscape("GONE")
+                return stripped, StripEscapes("^GONE"), pcall(RegisterColorEscape, "^x", 1, 1, 1)
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            (stripped.as_str(), removed.as_str(), bad),
+            ("Fire", "^GONE", false)
+        );
+        let palette = shared.palette.lock().unwrap();
+        let spans = escapes::color_spans("^ORANGEFire", [1.0; 4], &palette);
+        assert_eq!(spans, [("Fire", [1.0, 0.5, 0.0, 1.0])]);
     }
 
     #[test]
     fn window_title_does_not_crash() {
-        let root_dir = std::env::current_dir().unwrap();
-        let dq = Arc::new(Mutex::new(vec![]));
-        let tq = Arc::new(Mutex::new(vec![]));
-        let cp = Arc::new(Mutex::new([0.0, 0.0]));
-        let hs = Arc::new(Mutex::new(HashSet::new()));
-        let host = LuaHost::new(root_dir, dq, tq, cp, hs).unwrap();
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let host = LuaHost::new(layout, HostShared::default()).unwrap();
         host.lua.load(r#"SetWindowTitle("test")"#).exec().unwrap();
     }
+
+    #[test]
+    fn open_items_imports_codes_links_and_files() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let host = LuaHost::new(layout, HostShared::default()).unwrap();
+        host.lua
+            .load(
+                r#"
+                opened = {}
+                main = { SetMode = function(self, mode, path, name, xml)
+                    table.insert(opened, { path = path, name = name, xml = xml })
+                end }
+                local packed = string.char(unpack(Deflate("<PathOfBuilding/>", 9)))
+                common = { base64 = { decode = function(s) return packed end } }
+                launch = { DownloadPage = function(self, url, callback)
+                    fetched = url
+                    callback({ body = "code" })
+                end }
+                "#,
+            )
+            .exec()
+            .unwrap();
+        host.open_items(&[
+            "pob://pobbin/AbC-1".into(),
+            "/builds/Witch.xml".into(),
+            "pob://nowhere/x".into(),
+        ])
+        .unwrap();
+        let fetched: String = host.lua.load("return fetched").eval().unwrap();
+        assert_eq!(fetched, "https://pobb.in/AbC-1/raw");
+        let (xml, path, name): (String, String, String) = host
+            .lua
+            .load("return opened[1].xml, opened[2].path, opened[2].name")
+            .eval()
+            .unwrap();
+        assert_eq!(xml, "<PathOfBuilding/>");
+        assert_eq!(
+            (path.as_str(), name.as_str()),
+            ("/builds/Witch.xml", "Witch")
+        );
+        let count: usize = host.lua.load("return #opened").eval().unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn render_target_begin_end_brackets_draws() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = HostShared::default();
+        let host = LuaHost::new(layout, shared.clone()).unwrap();
+        host.lua
+            .load(
+                r#"
+                local rt = NewRenderTarget(256, 128)
+                SetViewport(10, 10, 50, 50)
+                rt:Begin()
+                assert(not pcall(rt.Begin, rt))
+                DrawImage(nil, 1, 1, 2, 2)
+                rt:End()
+                assert(not pcall(rt.End, rt))
+                DrawImage(rt, 0, 0, 256, 128)
+                "#,
+            )
+            .exec()
+            .unwrap();
+        let items = std::mem::take(&mut *shared.draw_queue.lock().unwrap());
+        let (targets, screen) = crate::graphics::split_passes(items);
+        assert_eq!(targets.len(), 1);
+        match (&targets[0].items[..], &screen[..]) {
+            ([DrawItem::Rect(inner)], [DrawItem::Rect(outer)]) => {
+                assert_eq!((inner.x, inner.clip), (1.0, None));
+                assert_eq!(outer.texture_id, targets[0].id);
+                assert_eq!(outer.clip, Some([10, 10, 50, 50]));
+            }
+            _ => panic!("unexpected draw layout"),
+        }
+    }
+
+    #[test]
+    fn render_target_left_open_by_an_error_closes_at_frame_end() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = HostShared::default();
+        let host = LuaHost::new(layout, shared.clone()).unwrap();
+        host.lua
+            .load(
+                r#"
+                rt = NewRenderTarget(64, 64)
+                SetViewport(10, 10, 50, 50)
+                assert(not pcall(function()
+                    rt:Begin()
+                    error("boom")
+                end))
+                "#,
+            )
+            .exec()
+            .unwrap();
+        host.close_render_target();
+        let items = std::mem::take(&mut *shared.draw_queue.lock().unwrap());
+        assert_eq!(crate::graphics::split_passes(items).0.len(), 1);
+        host.lua
+            .load(
+                r#"
+                DrawImage(nil, 1, 1, 2, 2)
+                rt:Begin()
+                rt:End()
+                "#,
+            )
+            .exec()
+            .unwrap();
+        let items = std::mem::take(&mut *shared.draw_queue.lock().unwrap());
+        let (targets, screen) = crate::graphics::split_passes(items);
+        assert_eq!(targets.len(), 1);
+        let [DrawItem::Rect(rect)] = &screen[..] else {
+            panic!("unexpected draw layout");
+        };
+        assert_eq!(rect.clip, Some([10, 10, 50, 50]));
+    }
+
+    #[test]
+    fn draw_color_quad_colours_each_corner_until_set_draw_color() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = HostShared::default();
+        let host = LuaHost::new(layout, shared.clone()).unwrap();
+        host.lua
+            .load(
+                r#"
+                assert(not pcall(SetDrawColorQuad, 1, 0, 0))
+                SetDrawColorQuad(1, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0.5)
+                DrawImage(nil, 0, 0, 10, 10)
+                SetDrawColorQuad(1, 0, 0, 0.1, 0, 1, 0, 0.2, 0, 0, 1, 0.3, 1, 1, 1, 0.4)
+                DrawImageQuad(nil, 0, 0, 10, 0, 10, 10, 0, 10)
+                SetDrawColor(0.5, 0.5, 0.5)
+                DrawImage(nil, 0, 0, 10, 10)
+                "#,
+            )
+            .exec()
+            .unwrap();
+        let items = std::mem::take(&mut *shared.draw_queue.lock().unwrap());
+        let [
+            DrawItem::Rect(rect),
+            DrawItem::Quad(quad),
+            DrawItem::Rect(plain),
+        ] = &items[..]
+        else {
+            panic!("unexpected draw layout");
+        };
+        // Three numbers a corner leave alpha opaque.
+        assert_eq!(
+            rect.corners,
+            Some([
+                [1.0, 0.0, 0.0, 1.0],
+                [0.0, 1.0, 0.0, 1.0],
+                [0.0, 0.0, 1.0, 1.0],
+                [1.0, 1.0, 0.5, 1.0],
+            ])
+        );
+        assert_eq!(
+            quad.corners,
+            Some([
+                [1.0, 0.0, 0.0, 0.1],
+                [0.0, 1.0, 0.0, 0.2],
+                [0.0, 0.0, 1.0, 0.3],
+                [1.0, 1.0, 1.0, 0.4],
+            ])
+        );
+        assert_eq!((plain.corners, plain.color), (None, [0.5, 0.5, 0.5, 1.0]));
+    }
+
+    #[test]
+    fn memory_usage_reports_the_renderer_and_lua_heap() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = HostShared::default();
+        shared.texture_memory.lock().unwrap().gpu_bytes = 4096;
+        let host = LuaHost::new(layout, shared).unwrap();
+        let (lua, gpu, shaped): (u64, u64, u32) = host
+            .lua
+            .load(
+                r#"
+                local m = GetMemoryUsage()
+                return m.lua, m.gpuTextures, m.shapedStrings
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(lua > 0);
+        assert_eq!((gpu, shaped), (4096, 0));
+    }
+
+    #[test]
+    fn ui_zoom_reaches_lua_without_scaling_the_screen_twice() {
+        let layout = Layout::standard(&std::env::current_dir().unwrap());
+        let shared = HostShared::default();
+        let zoom = shared.zoom.clone();
+        let host = LuaHost::new(layout, shared).unwrap();
+        *zoom.lock().unwrap() = 1.5;
+        let (ui, screen): (f32, f32) = host
+            .lua
+            .load("return GetUIZoom(), GetScreenScale()")
+            .eval()
+            .unwrap();
+        assert_eq!((ui, screen), (1.5, 1.0));
+    }
 }
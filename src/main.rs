@@ -1,118 +1,554 @@
+mod activation;
+mod archive;
+mod asset_cache;
+mod automation;
+mod backup;
+mod bench;
+mod bootstrap;
+mod build_stats;
+mod callback_errors;
+mod callback_targets;
+mod capture;
+mod cli;
+mod clipboard;
+mod codec;
+mod cookies;
+mod dialogs;
+mod diff;
+mod dir_watch;
+mod discord;
+mod dpi_override;
+mod embed;
+mod escapes;
+mod font_metrics;
+mod frame_dump;
+mod gc;
+mod gestures;
+mod gpu_timer;
 mod graphics;
+mod headless;
+mod host_thread;
+mod hotkeys;
+mod http_cache;
+mod image_handle;
+mod input_log;
+mod integrity;
+mod ipc;
+mod json;
+mod layout;
+mod lock_keys;
 mod lua_host;
+mod net;
+mod oauth;
+mod platform;
+mod post;
+mod profile;
+mod rate_limit;
+mod serve;
+mod settings;
+mod shapes;
+mod shaping;
+mod snapshot;
+mod sound;
+mod sprite_sheet;
+mod startup;
+#[cfg(feature = "storage")]
+mod storage;
+mod subscript;
+mod svg;
+mod text_effects;
+mod texture_cache;
+mod texture_ids;
+mod theme;
+mod tray;
+mod vfs;
+mod window_icon;
+mod workers;
+mod xml;
 
-use std::collections::HashSet;
+// The window, surface, clipboard and HTTP go through `platform`, and the
+// scripts' files through `vfs` providers, so a browser build would swap in
+// winit's web event loop, fetch and an OPFS provider there. What stops it
+// is Lua: mlua builds LuaJIT, which has no wasm32 backend, and PUC Lua only
+// for emscripten, which winit's web backend and wgpu's WebGPU don't target.
+#[cfg(target_arch = "wasm32")]
+compile_error!("wasm32 needs a Lua that builds for wasm32-unknown-unknown");
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::graphics::{CursorPos, DrawItem, DrawQueue, TextCmd, TextureUploadQueue, Vertex};
-use crate::lua_host::LuaHost;
+use crate::cli::Args;
+use crate::dir_watch::DirWatcher;
+use crate::dpi_override::DpiOverride;
+use crate::escapes::Palette;
+use crate::font_metrics::FontMetricsTable;
+use crate::frame_dump::FrameDump;
+use crate::gestures::GestureTranslator;
+use crate::host_thread::{Frame, HostEvent, UserEvent, Waker};
+use crate::hotkeys::HotkeyManager;
+use crate::input_log::{InputLog, Recorder, Replay};
+use crate::layout::Layout;
+use crate::lua_host::HostShared;
+use crate::platform::{GfxState, WindowMode, WindowStyle};
+use crate::post::PostParams;
+use crate::profile::InstanceLock;
+use crate::settings::Settings;
+use crate::theme::Theme;
+use crate::tray::{Tray, TrayAction};
 
-use mlua::prelude::{LuaMultiValue, LuaValue};
+use rfd::{MessageButtons, MessageLevel};
 use winit::application::ApplicationHandler;
-use winit::event::{ElementState, WindowEvent};
-use winit::event_loop::EventLoop;
-use winit::window::Window;
-
-struct GfxState {
-    surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    renderer: graphics::Renderer,
-    text_renderer: graphics::TextRenderer,
+use winit::event::{DeviceEvent, ElementState, WindowEvent};
+use winit::event_loop::{EventLoop, EventLoopProxy};
+use winit::keyboard::KeyCode;
+use winit::window::{CursorGrabMode, Window};
+
+/// Pointer grab held while any mouse button is down, so drags keep going
+/// when the cursor hits the window edge.
+struct PointerCapture {
+    mode: CursorGrabMode,
+    buttons: u32,
+    /// Raw motion that pushed past the window edge, added to the real cursor.
+    overflow: [f64; 2],
+}
+
+impl PointerCapture {
+    /// Counts a button down. The first starts a capture in the mode `grab`
+    /// managed to set on the window, if any; true if it did.
+    fn press(capture: &mut Option<Self>, grab: impl FnOnce() -> Option<CursorGrabMode>) -> bool {
+        if let Some(c) = capture {
+            c.buttons += 1;
+            return false;
+        }
+        let Some(mode) = grab() else { return false };
+        *capture = Some(Self {
+            mode,
+            buttons: 1,
+            overflow: [0.0; 2],
+        });
+        true
+    }
+
+    /// Counts a button up, and returns the capture the last one ended.
+    fn release(capture: &mut Option<Self>) -> Option<Self> {
+        let c = capture.as_mut()?;
+        c.buttons = c.buttons.saturating_sub(1);
+        if c.buttons > 0 {
+            return None;
+        }
+        capture.take()
+    }
+
+    /// Adds raw motion to the overflow on axes where the real cursor, at
+    /// `cursor` in a window `size` pixels large, can't follow: pinned at an
+    /// edge, or locked in place. True if any did.
+    fn raw_motion(&mut self, cursor: [f64; 2], size: [f64; 2], delta: (f64, f64)) -> bool {
+        let mut moved = false;
+        for (axis, d) in [delta.0, delta.1].into_iter().enumerate() {
+            let pos = cursor[axis];
+            let pinned = self.mode == CursorGrabMode::Locked
+                || (d < 0.0 && pos <= 0.0)
+                || (d > 0.0 && pos >= size[axis]);
+            if pinned && d != 0.0 {
+                self.overflow[axis] += d;
+                moved = true;
+            }
+        }
+        moved
+    }
 }
 
 struct App {
-    screen_size: Arc<Mutex<[u32; 2]>>,
+    shared: HostShared,
     window: Option<Arc<Window>>,
     gfx: Option<GfxState>,
-    host: LuaHost,
-    draw_queue: DrawQueue,
-    texture_queue: TextureUploadQueue,
-    cursor_pos: CursorPos,
-    pressed_keys: Arc<Mutex<HashSet<String>>>,
+    events: Sender<HostEvent>,
+    frames: Receiver<Frame>,
+    frame: Frame,
+    cursor: [f64; 2],
+    capture: Option<PointerCapture>,
+    gestures: GestureTranslator,
+    /// The mode the window is in, to notice when the host asks for another.
+    window_mode: WindowMode,
+    /// The style last applied to the window.
+    window_style: WindowStyle,
+    /// Whether the window is hidden for a screen grab.
+    hidden_for_grab: bool,
+    /// Whether the surface wants premultiplied colours while the window
+    /// is see-through; None while it is opaque.
+    translucent: Option<bool>,
+    /// Taken to create the tray once the event loop runs, if enabled.
+    tray_proxy: Option<EventLoopProxy<UserEvent>>,
+    tray: Option<Tray>,
+    hotkeys: HotkeyManager,
+    /// `--shader-dir` and the watcher on it.
+    shader_watch: Option<(PathBuf, DirWatcher)>,
+    /// `--gpu-debug`: where the device traces to.
+    gpu_trace: Option<PathBuf>,
+    /// Ctrl+Shift+D was pressed: dump the next frame the host sends.
+    dump_next: bool,
+    /// The zoom the user chose, which PoB's display scaling overrides
+    /// while set.
+    ui_zoom: f32,
+    /// Covered by other windows, and minimized: nothing is drawn while
+    /// either holds.
+    occluded: bool,
+    minimized: bool,
 }
 
-impl ApplicationHandler for App {
-    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        let window = Arc::new(
-            event_loop
-                .create_window(
-                    Window::default_attributes()
-                        .with_title("Path Of Building")
-                        .with_inner_size(winit::dpi::LogicalSize::new(1280, 720)),
-                )
-                .unwrap(),
-        );
-        self.window = Some(window.clone());
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        });
-        println!("instance created");
-
-        let surface = instance.create_surface(window.clone()).unwrap();
-        println!("surface created");
-
-        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::default(),
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        }))
-        .expect("no adapter found");
-        println!("adapter: {}", adapter.get_info().name);
-
-        let (device, queue) = pollster::block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
+impl App {
+    fn new(
+        shared: HostShared,
+        events: Sender<HostEvent>,
+        frames: Receiver<Frame>,
+        event_loop: &EventLoop<UserEvent>,
+        shader_watch: Option<(PathBuf, DirWatcher)>,
+        gpu_trace: Option<PathBuf>,
+    ) -> Self {
+        let tray_enabled = shared.settings.tray.enabled == Some(true);
+        let hotkeys = HotkeyManager::new(shared.hotkeys.clone());
+        let ui_zoom = shared.settings.display.ui_zoom.unwrap_or(1.0);
+        App {
+            shared,
+            window: None,
+            gfx: None,
+            events,
+            frames,
+            frame: Frame {
+                items: Vec::new(),
+                targets: Vec::new(),
+                textures: Vec::new(),
+                screenshots: Vec::new(),
             },
-            None,
-        ))
-        .expect("failed to create device");
-        println!("device created");
-        device.on_uncaptured_error(Box::new(|e| {
-            eprintln!("wgpu device error: {:?}", e);
-        }));
-
-        let size = window.inner_size();
-        println!("screen size: {}x{}", size.width, size.height);
-        *self.screen_size.lock().unwrap() = [size.width, size.height];
-        let caps = surface.get_capabilities(&adapter);
-        let format = caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(caps.formats[0]);
-        println!("format: {:?}", format);
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-            desired_maximum_frame_latency: 2,
-            alpha_mode: caps.alpha_modes[0],
-            view_formats: vec![],
+            cursor: [0.0; 2],
+            capture: None,
+            gestures: GestureTranslator::default(),
+            window_mode: WindowMode::default(),
+            window_style: WindowStyle::default(),
+            hidden_for_grab: false,
+            translucent: None,
+            tray_proxy: tray_enabled.then(|| event_loop.create_proxy()),
+            tray: None,
+            hotkeys,
+            shader_watch,
+            gpu_trace,
+            dump_next: false,
+            ui_zoom,
+            occluded: false,
+            minimized: false,
+        }
+    }
+
+    fn send(&self, event: HostEvent) {
+        // Lua sees positions in UI units.
+        let event = match event {
+            HostEvent::MouseMove { x, y } => {
+                let zoom = *self.shared.zoom.lock().unwrap();
+                HostEvent::MouseMove {
+                    x: x / zoom,
+                    y: y / zoom,
+                }
+            }
+            event => event,
+        };
+        // The host thread only goes away right before HostExited arrives.
+        self.events.send(event).ok();
+    }
+
+    /// Takes a frame the window can't show, keeping what later frames need
+    /// from it: its texture changes and target passes.
+    fn skip_frame(&mut self) {
+        let Ok(next) = self.frames.try_recv() else {
+            return;
         };
+        if let Some(g) = &mut self.gfx {
+            for cmd in next.textures {
+                if let Err((id, e)) = g.renderer.apply_texture_cmd(&g.device, &g.queue, cmd) {
+                    eprintln!("texture {}: {}", id, e);
+                    self.shared.upload_errors.lock().unwrap().insert(id, e);
+                }
+            }
+            *self.shared.texture_memory.lock().unwrap() = g.renderer.memory();
+        }
+        graphics::merge_passes(&mut self.frame.targets, next.targets);
+        self.frame.items = next.items;
+        self.frame.screenshots.extend(next.screenshots);
+    }
 
-        println!("scale_factor: {}", window.scale_factor());
-        println!("physical size: {:?}", window.inner_size());
-
-        surface.configure(&device, &config);
-        let renderer = graphics::Renderer::new(&device, format, &queue);
-        let text_renderer = graphics::TextRenderer::new(&device, &queue, format);
-        self.gfx = Some(GfxState {
-            surface,
-            device,
-            queue,
-            config,
-            renderer,
-            text_renderer,
-        })
+    fn set_hidden(&mut self, occluded: bool, minimized: bool) {
+        (self.occluded, self.minimized) = (occluded, minimized);
+        let hidden = occluded || minimized;
+        let was_hidden = std::mem::replace(&mut *self.shared.hidden.lock().unwrap(), hidden);
+        if was_hidden
+            && !hidden
+            && let Some(w) = &self.window
+        {
+            w.request_redraw();
+        }
+    }
+
+    /// Switches the window to the mode last asked for, if it changed.
+    fn apply_window_mode(&mut self) {
+        let mode = *self.shared.window_mode.lock().unwrap();
+        if mode == self.window_mode {
+            return;
+        }
+        if let Some(w) = &self.window {
+            mode.apply(w);
+            self.window_mode = mode;
+        }
+    }
+
+    /// Sets the icon the host last asked for.
+    fn apply_window_icon(&mut self) {
+        let Some(w) = &self.window else {
+            return;
+        };
+        if let Some(icon) = self.shared.window_icon.lock().unwrap().take() {
+            w.set_window_icon(icon.to_winit());
+        }
+    }
+
+    /// Applies the on-top, opacity and click-through style last asked for.
+    fn apply_window_style(&mut self) {
+        let style = *self.shared.window_style.lock().unwrap();
+        if style == self.window_style {
+            return;
+        }
+        let (Some(w), Some(g)) = (&self.window, &mut self.gfx) else {
+            return;
+        };
+        style.apply(w);
+        let see_through = style.opacity < 1.0;
+        self.translucent = g.set_translucent(see_through);
+        if see_through && self.translucent.is_none() {
+            eprintln!("window opacity: the compositor can't blend this window");
+        }
+        self.window_style = style;
+    }
+
+    /// Hides the window while the host grabs the screen, so the grab shows
+    /// what is behind it.
+    fn apply_grab_hide(&mut self) {
+        let hide = *self.shared.hide_for_grab.lock().unwrap();
+        if hide == self.hidden_for_grab {
+            return;
+        }
+        if let Some(w) = &self.window {
+            w.set_visible(!hide);
+            self.hidden_for_grab = hide;
+        }
+    }
+
+    /// Raises the window, shown again if it was hidden to the tray, and
+    /// opens `items` in the host.
+    fn activate(&self, items: Vec<String>) {
+        if let Some(w) = &self.window {
+            w.set_visible(true);
+            w.set_minimized(false);
+            w.focus_window();
+        }
+        if !items.is_empty() {
+            self.events.send(HostEvent::Open(items)).ok();
+        }
+    }
+
+    /// F11 and Alt+Enter switch between windowed and borderless.
+    fn toggle_fullscreen(&mut self) {
+        {
+            let mut mode = self.shared.window_mode.lock().unwrap();
+            *mode = match *mode {
+                WindowMode::Windowed => WindowMode::Borderless,
+                _ => WindowMode::Windowed,
+            };
+        }
+        self.apply_window_mode();
+    }
+
+    /// Publishes the window size in UI units.
+    fn update_screen_size(&self) {
+        let Some(g) = &self.gfx else { return };
+        let zoom = *self.shared.zoom.lock().unwrap();
+        *self.shared.screen_size.lock().unwrap() = [
+            (g.config.width as f32 / zoom) as u32,
+            (g.config.height as f32 / zoom) as u32,
+        ];
+    }
+
+    /// Picks up edited shaders under `--shader-dir`.
+    fn reload_shaders(&mut self) {
+        if let (Some((dir, watch)), Some(g)) = (&self.shader_watch, &mut self.gfx)
+            && watch.take_changed()
+        {
+            g.reload_shaders(dir);
+        }
+    }
+
+    /// Publishes the displays for GetMonitors.
+    fn update_monitors(&self) {
+        if let Some(w) = &self.window {
+            *self.shared.monitors.lock().unwrap() = platform::monitors(w);
+        }
+    }
+
+    /// Steps the UI zoom by `step` tenths, or back to 1 for 0, and keeps
+    /// it for the next launch.
+    fn step_zoom(&mut self, step: i32) {
+        let zoom = *self.shared.zoom.lock().unwrap();
+        let zoom = match step {
+            0 => 1.0,
+            _ => ((zoom * 10.0).round() + step as f32) / 10.0,
+        };
+        self.ui_zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.set_zoom(self.ui_zoom);
+        if self.shared.windowed {
+            let path = Settings::path();
+            if let Err(e) = dpi_override::persist_zoom(&path, self.ui_zoom) {
+                eprintln!("{}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Rescales the UI when Lua changed PoB's display scaling.
+    fn apply_dpi_override(&mut self) {
+        let zoom = {
+            let mut dpi = self.shared.dpi_override.lock().unwrap();
+            if !std::mem::take(&mut dpi.changed) {
+                return;
+            }
+            dpi.zoom(self.ui_zoom)
+        };
+        self.set_zoom(zoom);
+    }
+
+    fn set_zoom(&mut self, zoom: f32) {
+        *self.shared.zoom.lock().unwrap() = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.update_screen_size();
+        self.send_cursor();
+        if let Some(w) = &self.window {
+            w.request_redraw();
+        }
+    }
+
+    fn send_cursor(&self) {
+        let overflow = self.capture.as_ref().map_or([0.0; 2], |c| c.overflow);
+        self.send(HostEvent::MouseMove {
+            x: (self.cursor[0] + overflow[0]) as f32,
+            y: (self.cursor[1] + overflow[1]) as f32,
+        });
+    }
+
+    fn press_button(&mut self) {
+        let window = &self.window;
+        let started = PointerCapture::press(&mut self.capture, || {
+            let w = window.as_ref()?;
+            [CursorGrabMode::Confined, CursorGrabMode::Locked]
+                .into_iter()
+                .find(|&m| w.set_cursor_grab(m).is_ok())
+        });
+        if started {
+            *self.shared.mouse_capture.lock().unwrap() = true;
+        }
+    }
+
+    fn release_button(&mut self) {
+        let Some(ended) = PointerCapture::release(&mut self.capture) else {
+            return;
+        };
+        *self.shared.mouse_capture.lock().unwrap() = false;
+        if let Some(w) = &self.window {
+            w.set_cursor_grab(CursorGrabMode::None).ok();
+        }
+        if ended.overflow != [0.0; 2] {
+            self.send_cursor();
+        }
+    }
+
+    /// Feeds raw motion into the capture overflow.
+    fn raw_motion(&mut self, delta: (f64, f64)) {
+        let Some(g) = &self.gfx else { return };
+        let size = [g.config.width, g.config.height].map(|v| v as f64 - 1.0);
+        let Some(c) = &mut self.capture else { return };
+        if c.raw_motion(self.cursor, size, delta) {
+            self.send_cursor();
+        }
+    }
+}
+
+impl ApplicationHandler<UserEvent> for App {
+    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let (window, gfx) = startup::time("window", || {
+            let monitor = self.shared.settings.window.monitor.as_deref();
+            let window = platform::create_window(event_loop, monitor);
+            let mut gfx = GfxState::new(window.clone(), self.gpu_trace.as_deref());
+            gfx.text_renderer.palette = self.shared.palette.clone();
+            gfx.text_renderer.shaping = shaping::from_settings(&self.shared.settings.text);
+            gfx.text_renderer.metrics =
+                Arc::new(FontMetricsTable::from_settings(&self.shared.settings.text));
+            (window, gfx)
+        });
+        *self.shared.dpi_scale.lock().unwrap() = window.scale_factor() as f32;
+        self.gfx = Some(gfx);
+        self.update_screen_size();
+        self.window = Some(window.clone());
+        self.apply_window_icon();
+        self.update_monitors();
+        window.request_redraw();
+        if let Some(proxy) = self.tray_proxy.take() {
+            match Tray::new(proxy) {
+                Ok(tray) => self.tray = Some(tray),
+                Err(e) => eprintln!("tray: {}", e),
+            }
+        }
+    }
+
+    fn user_event(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, event: UserEvent) {
+        match event {
+            UserEvent::FrameReady => {
+                self.apply_grab_hide();
+                self.apply_window_mode();
+                self.apply_window_icon();
+                self.apply_window_style();
+                self.apply_dpi_override();
+                if self.hotkeys.apply() {
+                    self.activate(Vec::new());
+                }
+                if let Some(tray) = &mut self.tray {
+                    tray.update(&self.shared.recent_builds.lock().unwrap());
+                }
+                if *self.shared.hidden.lock().unwrap() {
+                    self.skip_frame();
+                } else if let Some(w) = &self.window {
+                    w.request_redraw();
+                }
+            }
+            UserEvent::HostExited => event_loop.exit(),
+            UserEvent::Resize(size) => {
+                // Recordings keep the size in UI units.
+                let zoom = *self.shared.zoom.lock().unwrap();
+                let [width, height] = size.map(|v| (v as f32 * zoom) as u32);
+                if let Some(w) = &self.window {
+                    let _ = w.request_inner_size(winit::dpi::PhysicalSize::new(width, height));
+                }
+            }
+            UserEvent::Activate(items) => self.activate(items),
+            UserEvent::Tray(TrayAction::Show) => self.activate(Vec::new()),
+            UserEvent::Tray(TrayAction::Open(path)) => self.activate(vec![path]),
+            UserEvent::Tray(TrayAction::Quit) => event_loop.exit(),
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &winit::event_loop::ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.raw_motion(delta);
+        }
     }
 
     fn window_event(
@@ -122,18 +558,51 @@ impl ApplicationHandler for App {
         event: WindowEvent,
     ) {
         match event {
-            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::CloseRequested => {
+                let to_tray = self.shared.settings.tray.minimize_on_close == Some(true);
+                match (&self.window, &self.tray) {
+                    (Some(w), Some(_)) if to_tray => w.set_visible(false),
+                    _ => event_loop.exit(),
+                }
+            }
             WindowEvent::Resized(new_size) => {
+                let minimized = new_size.width == 0 || new_size.height == 0;
+                self.set_hidden(self.occluded, minimized);
                 if let Some(g) = &mut self.gfx {
-                    g.config.width = new_size.width.max(1);
-                    g.config.height = new_size.height.max(1);
-                    *self.screen_size.lock().unwrap() = [new_size.width, new_size.height];
-                    g.surface.configure(&g.device, &g.config);
+                    g.resize(new_size.width, new_size.height);
                 }
+                self.update_screen_size();
             }
+            WindowEvent::Focused(focused) => {
+                *self.shared.focused.lock().unwrap() = focused;
+                if !focused {
+                    // Keys let go of in another window would stay held.
+                    let held: Vec<String> =
+                        self.shared.pressed_keys.lock().unwrap().drain().collect();
+                    for key in held {
+                        if key.ends_with("BUTTON") {
+                            self.release_button();
+                        }
+                        self.send(HostEvent::KeyUp { key });
+                    }
+                }
+                self.send(HostEvent::Focus(focused));
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                *self.shared.dpi_scale.lock().unwrap() = scale_factor as f32;
+                // Not every platform follows up with a Resized.
+                if let (Some(w), Some(g)) = (&self.window, &mut self.gfx) {
+                    let size = w.inner_size();
+                    g.resize(size.width, size.height);
+                }
+                self.update_screen_size();
+                self.update_monitors();
+            }
+            WindowEvent::Moved(_) => self.update_monitors(),
+            WindowEvent::Occluded(occluded) => self.set_hidden(occluded, self.minimized),
             WindowEvent::CursorMoved { position, .. } => {
-                *self.cursor_pos.lock().unwrap() = [position.x as f32, position.y as f32];
-                self.host.callback("OnMouseMove").unwrap();
+                self.cursor = [position.x, position.y];
+                self.send_cursor();
             }
             WindowEvent::MouseInput { state, button, .. } => {
                 let btn = match button {
@@ -142,22 +611,21 @@ impl ApplicationHandler for App {
                     winit::event::MouseButton::Middle => "MIDDLEBUTTON",
                     _ => return,
                 };
+                let key = btn.to_string();
 
                 match state {
                     winit::event::ElementState::Pressed => {
-                        let key = LuaValue::String(self.host.lua.create_string(btn).unwrap());
-                        self.host
-                            .callback_args(
-                                "OnKeyDown",
-                                LuaMultiValue::from_vec(vec![key, LuaValue::Boolean(false)]),
-                            )
-                            .unwrap();
+                        self.press_button();
+                        self.shared.pressed_keys.lock().unwrap().insert(key.clone());
+                        self.send(HostEvent::KeyDown {
+                            key,
+                            double_click: false,
+                        });
                     }
                     winit::event::ElementState::Released => {
-                        let key = LuaValue::String(self.host.lua.create_string(btn).unwrap());
-                        self.host
-                            .callback_args("OnKeyUp", LuaMultiValue::from_vec(vec![key]))
-                            .unwrap();
+                        self.shared.pressed_keys.lock().unwrap().remove(&key);
+                        self.send(HostEvent::KeyUp { key });
+                        self.release_button();
                     }
                 }
             }
@@ -168,234 +636,497 @@ impl ApplicationHandler for App {
                 };
                 if lines != 0.0 {
                     let dir = if lines > 0.0 { "WHEELUP" } else { "WHEELDOWN" };
-                    let key = LuaValue::String(self.host.lua.create_string(dir).unwrap());
-                    self.host
-                        .callback_args(
-                            "OnKeyDown",
-                            LuaMultiValue::from_vec(vec![key, LuaValue::Boolean(false)]),
-                        )
-                        .unwrap();
+                    self.send(HostEvent::KeyDown {
+                        key: dir.to_string(),
+                        double_click: false,
+                    });
+                }
+            }
+            WindowEvent::PinchGesture { delta, phase, .. } => {
+                for e in self.gestures.pinch(delta, phase) {
+                    self.send(e);
+                }
+            }
+            WindowEvent::PanGesture { delta, phase, .. } => {
+                let delta = [delta.x as f64, delta.y as f64];
+                for e in self.gestures.pan(self.cursor, delta, phase) {
+                    self.send(e);
+                }
+            }
+            WindowEvent::Touch(touch) => {
+                let pos = [touch.location.x, touch.location.y];
+                for e in self.gestures.touch(touch.id, touch.phase, pos) {
+                    self.send(e);
                 }
             }
             WindowEvent::KeyboardInput { event, .. } => {
+                let ctrl = self.shared.pressed_keys.lock().unwrap().contains("CTRL");
+                if ctrl
+                    && event.state == ElementState::Pressed
+                    && let winit::keyboard::PhysicalKey::Code(code) = event.physical_key
+                    && let Some(step) = zoom_step(code)
+                {
+                    self.step_zoom(step);
+                    return;
+                }
+                // A click-through window can still be focused from the
+                // taskbar; Ctrl+Shift+O gives it the mouse back.
+                let shift = self.shared.pressed_keys.lock().unwrap().contains("SHIFT");
+                if ctrl
+                    && shift
+                    && event.state == ElementState::Pressed
+                    && event.physical_key == winit::keyboard::PhysicalKey::Code(KeyCode::KeyO)
+                {
+                    self.shared.window_style.lock().unwrap().click_through = false;
+                    self.apply_window_style();
+                    return;
+                }
+                if ctrl
+                    && shift
+                    && event.state == ElementState::Pressed
+                    && event.physical_key == winit::keyboard::PhysicalKey::Code(KeyCode::KeyD)
+                {
+                    self.dump_next = true;
+                    return;
+                }
+                let alt = self.shared.pressed_keys.lock().unwrap().contains("ALT");
+                if event.state == ElementState::Pressed
+                    && !event.repeat
+                    && let winit::keyboard::PhysicalKey::Code(code) = event.physical_key
+                    && (code == KeyCode::F11
+                        || alt && matches!(code, KeyCode::Enter | KeyCode::NumpadEnter))
+                {
+                    self.toggle_fullscreen();
+                    return;
+                }
                 if let Some(key_name) = pob_key_name(event.physical_key) {
-                    let name = LuaValue::String(self.host.lua.create_string(key_name).unwrap());
-                    let args = LuaMultiValue::from_vec(vec![name]);
+                    let key = key_name.to_string();
                     match event.state {
                         winit::event::ElementState::Pressed => {
-                            self.host.callback_args("OnKeyDown", args).unwrap();
-                            self.pressed_keys
-                                .lock()
-                                .unwrap()
-                                .insert(key_name.to_string());
+                            if !event.repeat {
+                                self.shared.lock_keys.lock().unwrap().press(&key);
+                            }
+                            self.shared.pressed_keys.lock().unwrap().insert(key.clone());
+                            self.send(HostEvent::KeyDown {
+                                key,
+                                double_click: false,
+                            });
                         }
                         winit::event::ElementState::Released => {
-                            self.host.callback_args("OnKeyUp", args).unwrap();
-                            self.pressed_keys
-                                .lock()
-                                .unwrap()
-                                .remove(&key_name.to_string());
+                            self.shared.pressed_keys.lock().unwrap().remove(&key);
+                            self.send(HostEvent::KeyUp { key });
                         }
                     }
                 }
-                if event.state == ElementState::Pressed {
-                    if let Some(text) = &event.text {
-                        let ch =
-                            LuaValue::String(self.host.lua.create_string(text.as_str()).unwrap());
-                        self.host
-                            .callback_args("OnChar", LuaMultiValue::from_vec(vec![ch]))
-                            .unwrap();
-                    }
+                if event.state == ElementState::Pressed
+                    && let Some(text) = &event.text
+                {
+                    let shift = self.shared.pressed_keys.lock().unwrap().contains("SHIFT");
+                    self.shared.lock_keys.lock().unwrap().typed(text, shift);
+                    self.send(HostEvent::Char(text.to_string()));
                 }
             }
             WindowEvent::RedrawRequested => {
+                if *self.shared.hidden.lock().unwrap() {
+                    return;
+                }
+                self.reload_shaders();
                 if let Some(g) = &mut self.gfx {
                     let frame = match g.surface.get_current_texture() {
                         Ok(f) => f,
                         Err(_) => return,
                     };
-                    let view = frame.texture.create_view(&Default::default());
+                    // Keep redrawing the last frame until the host sends a new one.
+                    let fresh = self.frames.try_recv().map(|next| self.frame = next).is_ok();
+                    let screen_view = frame.texture.create_view(&Default::default());
+                    let mut post = *self.shared.post.lock().unwrap();
+                    if let Some(premultiplied) = self.translucent {
+                        post.opacity = self.window_style.opacity;
+                        post.premultiplied = premultiplied;
+                    }
+                    let view = if post.is_identity() {
+                        &screen_view
+                    } else {
+                        g.post
+                            .scene_view(&g.device, (g.config.width, g.config.height))
+                    };
+                    if let Some(times) = g.gpu_timer.as_mut().and_then(|t| t.begin(&g.device)) {
+                        *self.shared.gpu_times.lock().unwrap() = Some(times);
+                    }
                     let mut encoder = g.device.create_command_encoder(&Default::default());
                     {
-                        let uploads = self
-                            .texture_queue
-                            .lock()
-                            .unwrap()
-                            .drain(..)
-                            .collect::<Vec<_>>();
-                        for upload in uploads {
-                            g.renderer.load_texture(
-                                &g.device,
-                                &g.queue,
-                                upload.id,
-                                &upload.rgba,
-                                upload.width,
-                                upload.height,
-                            );
+                        for cmd in self.frame.textures.drain(..) {
+                            if let Err((id, e)) =
+                                g.renderer.apply_texture_cmd(&g.device, &g.queue, cmd)
+                            {
+                                eprintln!("texture {}: {}", id, e);
+                                self.shared.upload_errors.lock().unwrap().insert(id, e);
+                            }
+                        }
+                        // Before the target passes are used up.
+                        if fresh && std::mem::take(&mut self.dump_next) {
+                            let zoom = *self.shared.zoom.lock().unwrap();
+                            let size = [g.config.width, g.config.height];
+                            let dump = FrameDump::capture(&self.frame, &g.renderer, size, zoom);
+                            match dump.save(&platform::user_path().join("FrameDumps")) {
+                                Ok(path) => println!("frame dumped to {}", path.display()),
+                                Err(e) => eprintln!("frame dump: {}", e),
+                            }
                         }
 
                         // text & images
                         g.renderer.begin_frame();
-                        let all_cmds = self
-                            .draw_queue
-                            .lock()
-                            .unwrap()
-                            .drain(..)
-                            .collect::<Vec<_>>();
-                        let texts: Vec<TextCmd> = all_cmds
-                            .iter()
-                            .filter_map(|d| {
-                                if let DrawItem::Text(t) = d {
-                                    Some(t.clone())
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect();
+                        let all_cmds = &self.frame.items;
+                        // Targets keep their contents, so each pass runs once
+                        // even when this frame is redrawn.
+                        let targets: Vec<_> = self.frame.targets.drain(..).collect();
+                        for target in &targets {
+                            g.renderer
+                                .prepare_textures(&g.device, &g.queue, &target.items);
+                        }
+                        g.renderer.prepare_textures(&g.device, &g.queue, all_cmds);
+                        *self.shared.texture_memory.lock().unwrap() = g.renderer.memory();
+                        for (i, target) in targets.iter().enumerate() {
+                            g.renderer.render_target(
+                                &g.device,
+                                &g.queue,
+                                &mut encoder,
+                                &mut g.text_renderer,
+                                i + 1,
+                                target,
+                            );
+                        }
+                        let texts = graphics::text_cmds(all_cmds);
+                        let zoom = *self.shared.zoom.lock().unwrap();
+                        g.text_renderer
+                            .prepare(
+                                &g.device,
+                                &g.queue,
+                                0,
+                                (g.config.width, g.config.height),
+                                zoom,
+                                &texts,
+                            )
+                            .unwrap();
+                        g.text_renderer.render_effects(0, &mut encoder).unwrap();
                         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                             label: None,
                             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                                view: &view,
+                                view,
                                 resolve_target: None,
                                 ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                                        r: 0.05,
-                                        g: 0.05,
-                                        b: 0.05,
-                                        a: 1.0,
-                                    }),
+                                    load: wgpu::LoadOp::Clear(
+                                        self.shared.theme.lock().unwrap().clear_color(),
+                                    ),
                                     store: wgpu::StoreOp::Store,
                                 },
                             })],
                             depth_stencil_attachment: None,
-                            timestamp_writes: None,
+                            timestamp_writes: g.gpu_timer.as_ref().and_then(|t| t.pass_writes()),
                             occlusion_query_set: None,
                         });
+                        let timer = g.gpu_timer.as_ref();
+                        if let Some(t) = timer {
+                            t.stamp(&mut pass, 0);
+                        }
                         g.renderer.draw(
                             &mut pass,
                             &g.queue,
                             (g.config.width, g.config.height),
-                            &all_cmds,
+                            zoom,
+                            all_cmds,
                         );
-
-                        g.text_renderer
-                            .prepare(
-                                &g.device,
-                                &g.queue,
-                                (g.config.width, g.config.height),
-                                &texts,
-                            )
-                            .unwrap();
-                        g.text_renderer.render(&mut pass).unwrap();
+                        if let Some(t) = timer {
+                            t.stamp(&mut pass, 1);
+                        }
+                        g.text_renderer.render(0, &mut pass).unwrap();
+                        if let Some(t) = timer {
+                            t.stamp(&mut pass, 2);
+                        }
+                    }
+                    if let Some(t) = &g.gpu_timer {
+                        t.resolve(&mut encoder);
                     }
+                    if !post.is_identity() {
+                        g.post.apply(&g.queue, &mut encoder, &post, &screen_view);
+                    }
+                    let mut shots = std::mem::take(&mut self.frame.screenshots);
+                    // The surface is only made copyable where the backend
+                    // supports it; elsewhere reading it back is a
+                    // validation error.
+                    if !g.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+                        for path in shots.drain(..) {
+                            eprintln!(
+                                "screenshot {}: this surface can't be read back",
+                                path.display()
+                            );
+                        }
+                    }
+                    let capture = (!shots.is_empty())
+                        .then(|| graphics::Capture::new(&g.device, &mut encoder, &frame.texture));
                     g.queue.submit(std::iter::once(encoder.finish()));
+                    if let Some(t) = &mut g.gpu_timer {
+                        t.submitted();
+                    }
+                    if let Some(capture) = capture {
+                        for path in &shots {
+                            if let Err(e) = capture.save(&g.device, path) {
+                                eprintln!("screenshot {}: {}", path.display(), e);
+                            }
+                        }
+                    }
                     frame.present();
+                    *self.shared.render_stats.lock().unwrap() = graphics::RenderStats {
+                        culled: g.renderer.stats.culled + g.text_renderer.culled,
+                        ..g.renderer.stats
+                    };
+                    if fresh {
+                        startup::first_frame();
+                    }
                 }
             }
             _ => {}
         }
     }
+}
 
-    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        let t = std::time::Instant::now();
-        self.host.callback("OnFrame").unwrap();
-        let lua_ms = t.elapsed().as_millis();
-
-        // count pending texture uploads
-        let tex_count = self.texture_queue.lock().unwrap().len();
-        let draw_count = self.draw_queue.lock().unwrap().len();
-        eprintln!(
-            "OnFrame: {}ms | draws: {} | tex: {}",
-            lua_ms, draw_count, tex_count
-        );
-
-        if lua_ms > 50 || tex_count > 0 {
-            eprintln!("OnFrame: {}ms | tex uploads queued: {}", lua_ms, tex_count);
+fn main() {
+    startup::begin();
+    let root_dir = std::env::current_dir().unwrap();
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| exit_with(&e));
+    if let Some(name) = &args.profile {
+        profile::set(name).unwrap_or_else(|e| exit_with(&e));
+    }
+    if let Some(path) = &args.render_dump {
+        show_dump(path);
+        return;
+    }
+    if args.register_url_handler {
+        activation::register_url_handler().unwrap_or_else(|e| exit_with(&e));
+        println!("registered as the pob:// handler");
+        return;
+    }
+    // The working directory changes below and differs between launches.
+    let open: Vec<String> = args
+        .open
+        .iter()
+        .map(|item| match std::fs::canonicalize(item) {
+            Ok(path) if !item.contains("://") => path.to_string_lossy().into_owned(),
+            _ => item.clone(),
+        })
+        .collect();
+    // The subcommands run without a window, often where no one could
+    // answer a message box.
+    let windowed = !(args.pack || args.bench || args.diff || args.serve);
+    let layout = match Layout::resolve(&root_dir, args.fork.as_deref()) {
+        Ok(layout)
+            if !args.bootstrap
+                && (layout.script_dir.join("Launch.lua").is_file()
+                    || embed::subtree("src").is_some()) =>
+        {
+            layout
         }
-
-        if let Some(w) = &self.window {
-            w.request_redraw();
+        Err(e) if root_dir.join(layout::MANIFEST).is_file() => fail_startup(&e, windowed),
+        // No checkout here: run sources downloaded into the data directory.
+        _ => bootstrap::sources(args.fork.as_deref(), args.bootstrap)
+            .unwrap_or_else(|e| fail_startup(&e, windowed)),
+    };
+    if args.pack {
+        let [output] = open.as_slice() else {
+            exit_with("usage: pack OUTPUT");
+        };
+        match embed::pack(&layout, Path::new(output)) {
+            Ok(count) => println!("packed {} files into {}", count, output),
+            Err(e) => exit_with(&format!("pack: {}", e)),
         }
+        return;
     }
-}
+    if args.bench {
+        let [build] = open.as_slice() else {
+            exit_with("usage: bench BUILD [--passes N]");
+        };
+        std::env::set_current_dir(&layout.script_dir).ok();
+        match bench::run(layout, build, args.passes.unwrap_or(20)) {
+            Ok(report) => println!("{}", report),
+            Err(e) => exit_with(&format!("bench: {}", e)),
+        }
+        return;
+    }
+    if args.diff {
+        let [a, b] = open.as_slice() else {
+            exit_with("usage: diff BUILD_A BUILD_B [--timeout SECS]");
+        };
+        std::env::set_current_dir(&layout.script_dir).ok();
+        let timeout = std::time::Duration::from_secs(args.timeout.unwrap_or(60));
+        match diff::run(&layout, a, b, timeout) {
+            Ok((a, b)) => print!("{}", diff::table(&a, &b)),
+            Err(e) => exit_with(&format!("diff: {}", e)),
+        }
+        return;
+    }
+    if args.serve {
+        std::env::set_current_dir(&layout.script_dir).ok();
+        let addr = args.listen.as_deref().unwrap_or(serve::DEFAULT_ADDR);
+        let workers = args.workers.unwrap_or(serve::DEFAULT_WORKERS);
+        let timeout = std::time::Duration::from_secs(args.timeout.unwrap_or(60));
+        if let Err(e) = serve::run(&layout, addr, workers, timeout) {
+            exit_with(&format!("serve: {}", e));
+        }
+        return;
+    }
+    let user_dir = platform::user_path();
+    let _instance = match InstanceLock::acquire(&user_dir) {
+        Ok(lock) => lock,
+        // Already running: hand the arguments over instead.
+        Err(e) => match activation::forward(&user_dir, &open) {
+            Ok(()) => return,
+            Err(_) => exit_with(&e),
+        },
+    };
+    // Paths are opened before the working directory changes.
+    let input = match (&args.record, &args.replay, &args.automate) {
+        (Some(path), None, None) => InputLog::Record(
+            Recorder::create(Path::new(path))
+                .unwrap_or_else(|e| exit_with(&format!("{}: {}", path, e))),
+        ),
+        (None, Some(path), None) => InputLog::Replay(
+            Replay::load(Path::new(path))
+                .unwrap_or_else(|e| exit_with(&format!("{}: {}", path, e))),
+        ),
+        (None, None, Some(path)) => InputLog::Automate(
+            std::fs::canonicalize(path).unwrap_or_else(|e| exit_with(&format!("{}: {}", path, e))),
+        ),
+        (None, None, None) => InputLog::Live,
+        _ => exit_with("only one of --record, --replay and --automate can be given"),
+    };
+    let shader_watch = args.shader_dir.as_ref().map(|dir| {
+        let dir =
+            std::fs::canonicalize(dir).unwrap_or_else(|e| exit_with(&format!("{}: {}", dir, e)));
+        let watch = DirWatcher::spawn();
+        watch.watch(&dir);
+        (dir, watch)
+    });
+    let settings = Settings::load();
+    // PoB's display scaling is in place before its first layout.
+    let dpi_override = DpiOverride {
+        percent: settings.display.dpi_override_percent.unwrap_or(0),
+        changed: false,
+    };
+    let zoom = dpi_override.zoom(settings.display.ui_zoom.unwrap_or(1.0));
+    let (event_tx, event_rx) = std::sync::mpsc::channel();
+    let shared = HostShared {
+        waker: Waker::new(event_tx.clone()),
+        post: Arc::new(Mutex::new(PostParams::from_settings(&settings.display))),
+        window_style: Arc::new(Mutex::new(WindowStyle::from_settings(&settings.window))),
+        zoom: Arc::new(Mutex::new(zoom.clamp(MIN_ZOOM, MAX_ZOOM))),
+        dpi_override: Arc::new(Mutex::new(dpi_override)),
+        palette: Arc::new(Mutex::new(Palette::from_settings(&settings.text))),
+        theme: Arc::new(Mutex::new(Theme::from_settings(&settings.theme))),
+        settings: Arc::new(settings),
+        windowed: true,
+        ..HostShared::default()
+    };
 
-fn main() {
-    let event_loop = EventLoop::new().unwrap();
+    let event_loop = EventLoop::<UserEvent>::with_user_event().build().unwrap();
+    let proxy = event_loop.create_proxy();
+    if let Err(e) = activation::listen(&user_dir, move |items| {
+        proxy.send_event(UserEvent::Activate(items)).ok();
+    }) {
+        eprintln!("activation: {}", e);
+    }
+    // macOS opens pob:// links in the running app with an Apple Event.
+    #[cfg(target_os = "macos")]
+    {
+        let proxy = event_loop.create_proxy();
+        activation::listen_for_links(move |items| {
+            proxy.send_event(UserEvent::Activate(items)).ok();
+        });
+    }
+    if shared.settings.ipc.enabled == Some(true)
+        && let Err(e) = ipc::listen(&user_dir, shared.ipc_calls.clone(), shared.waker.clone())
+    {
+        eprintln!("ipc: {}", e);
+    }
+    // A standalone executable may run without a checkout to work in.
+    std::env::set_current_dir(&layout.script_dir).ok();
+    let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel(1);
+    let host_thread = host_thread::spawn(
+        layout,
+        open,
+        shared.clone(),
+        event_rx,
+        frame_tx,
+        event_loop.create_proxy(),
+        input,
+    );
 
-    let screen_size = Arc::new(Mutex::new([1280u32, 720u32]));
-    let root_dir = std::env::current_dir().unwrap();
-    let draw_queue = Arc::new(Mutex::new(Vec::new()));
-    let texture_queue = Arc::new(Mutex::new(Vec::new()));
-    let cursor_pos = Arc::new(Mutex::new([0.0, 0.0]));
-    let pressed_keys = Arc::new(Mutex::new(HashSet::new()));
-    let host = lua_host::LuaHost::new(
-        root_dir,
-        screen_size.clone(),
-        draw_queue.clone(),
-        texture_queue.clone(),
-        cursor_pos.clone(),
-        pressed_keys.clone(),
-    )
-    .unwrap();
-
-    std::env::set_current_dir(host.root_dir.join("PathOfBuilding/src")).unwrap();
-    host.launch().unwrap();
-    println!(
-        "main object set: {}",
-        host.main_object.lock().unwrap().is_some()
+    let gpu_trace = args.gpu_debug.then(|| {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let dir = platform::user_path()
+            .join("GpuTraces")
+            .join(format!("trace-{}", secs));
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            exit_with(&format!("gpu trace {}: {}", dir.display(), e));
+        }
+        println!("gpu debug: validation on, tracing to {}", dir.display());
+        dir
+    });
+    let mut app = App::new(
+        shared,
+        event_tx,
+        frame_rx,
+        &event_loop,
+        shader_watch,
+        gpu_trace,
     );
+    event_loop.run_app(&mut app).unwrap();
 
-    host.callback("OnInit").unwrap();
-    let msg: Option<String> = host.lua.load("return launch.promptMsg").eval().unwrap();
-    println!("promptMsg: {:?}", msg);
-
-    host.lua
-        .load(
-            r##"
-      -- Log any runtime errors PoB catches
-      local origSEM = launch.ShowErrMsg
-      launch.ShowErrMsg = function(self, fmt, ...)
-          local msg = string.format(fmt, ...)
-          print("ShowErrMsg: " .. tostring(msg))
-          return origSEM(self, fmt, ...)
-      end
-
-      -- Log when any control is actually dispatched
-      local ControlHostClass = main.__index
-      local origGMC = ControlHostClass.GetMouseOverControl
-      ControlHostClass.GetMouseOverControl = function(self)
-          local result = origGMC(self)
-          if result then
-              local cx, cy = GetCursorPos()
-              if cx > 0 or cy > 0 then
-                  local name = "?"
-                  for n, c in pairs(self.controls) do
-                      if c == result then name = n; break end
-                  end
-                  print("DISPATCH -> " .. name .. " at " .. math.floor(cx) .. "," .. math.floor(cy))
-              end
-          end
-          return result
-      end
-  "##,
-        )
-        .exec()
-        .unwrap();
-
-    let mut app = App {
-        window: None,
-        gfx: None,
-        host,
-        draw_queue,
-        cursor_pos,
-        pressed_keys,
-        texture_queue,
-        screen_size,
-    };
+    // Unblock a host waiting to hand over a frame, then let it wind down.
+    drop(app);
+    if host_thread.join().is_ok_and(|clean| !clean) {
+        std::process::exit(1);
+    }
+}
 
+/// Draws a frame dump in a window of the size it was dumped at, with no
+/// scripts behind it, until the window is closed.
+fn show_dump(path: &str) {
+    let dump =
+        FrameDump::load(Path::new(path)).unwrap_or_else(|e| exit_with(&format!("{}: {}", path, e)));
+    let [width, height] = dump.size.map(|v| (v as f32 / dump.zoom) as u32);
+    let shared = HostShared {
+        zoom: Arc::new(Mutex::new(dump.zoom)),
+        ..HostShared::default()
+    };
+    let event_loop = EventLoop::<UserEvent>::with_user_event().build().unwrap();
+    // Input goes nowhere.
+    let (event_tx, _) = std::sync::mpsc::channel();
+    let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel(1);
+    frame_tx.send(dump.into_frame()).ok();
+    let proxy = event_loop.create_proxy();
+    proxy.send_event(UserEvent::Resize([width, height])).ok();
+    proxy.send_event(UserEvent::FrameReady).ok();
+    let mut app = App::new(shared, event_tx, frame_rx, &event_loop, None, None);
     event_loop.run_app(&mut app).unwrap();
 }
 
+/// Limits of the user's UI zoom.
+const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 3.0;
+
+/// Ctrl+= zooms in, Ctrl+- out and Ctrl+0 resets.
+fn zoom_step(code: winit::keyboard::KeyCode) -> Option<i32> {
+    use winit::keyboard::KeyCode;
+    match code {
+        KeyCode::Equal | KeyCode::NumpadAdd => Some(1),
+        KeyCode::Minus | KeyCode::NumpadSubtract => Some(-1),
+        KeyCode::Digit0 | KeyCode::Numpad0 => Some(0),
+        _ => None,
+    }
+}
+
 fn pob_key_name(key: winit::keyboard::PhysicalKey) -> Option<&'static str> {
     use winit::keyboard::{KeyCode, PhysicalKey};
 
@@ -418,6 +1149,9 @@ fn pob_key_name(key: winit::keyboard::PhysicalKey) -> Option<&'static str> {
         KeyCode::PageUp => Some("PGUP"),
         KeyCode::PageDown => Some("PGDN"),
         KeyCode::Insert => Some("INSERT"),
+        KeyCode::CapsLock => Some("CAPSLOCK"),
+        KeyCode::NumLock => Some("NUMLOCK"),
+        KeyCode::ScrollLock => Some("SCROLLLOCK"),
         KeyCode::ShiftLeft | KeyCode::ShiftRight => Some("SHIFT"),
         KeyCode::ControlLeft | KeyCode::ControlRight => Some("CTRL"),
         KeyCode::AltLeft | KeyCode::AltRight => Some("ALT"),
@@ -436,3 +1170,68 @@ fn pob_key_name(key: winit::keyboard::PhysicalKey) -> Option<&'static str> {
         _ => None,
     }
 }
+
+/// Reports a startup error and exits.
+fn exit_with(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(2);
+}
+
+/// Like `exit_with`, but a `windowed` launch with a display also shows the
+/// message in a box, for when there is no terminal to read it in.
+fn fail_startup(msg: &str, windowed: bool) -> ! {
+    if windowed && platform::has_display() {
+        dialogs::message_box(
+            MessageLevel::Error,
+            "Path of Building",
+            msg,
+            MessageButtons::Ok,
+        );
+    }
+    exit_with(msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pointer_capture_spans_buttons_and_gathers_raw_motion() {
+        let mut capture = None;
+        // No grab the window allows: no capture.
+        assert!(!PointerCapture::press(&mut capture, || None));
+        assert!(capture.is_none());
+
+        assert!(PointerCapture::press(&mut capture, || Some(
+            CursorGrabMode::Confined
+        )));
+        // A second button joins the capture without grabbing again.
+        assert!(!PointerCapture::press(&mut capture, || panic!(
+            "grabbed twice"
+        )));
+        assert!(PointerCapture::release(&mut capture).is_none());
+        assert!(capture.is_some());
+
+        // Confined: only motion past an edge the cursor is pinned at counts.
+        let c = capture.as_mut().unwrap();
+        let size = [99.0, 99.0];
+        assert!(!c.raw_motion([50.0, 50.0], size, (5.0, -5.0)));
+        assert!(c.raw_motion([99.0, 0.0], size, (4.0, -3.0)));
+        assert!(c.raw_motion([99.0, 50.0], size, (2.0, 1.0)));
+        assert_eq!(c.overflow, [6.0, -3.0]);
+
+        let ended = PointerCapture::release(&mut capture).unwrap();
+        assert_eq!(ended.overflow, [6.0, -3.0]);
+        assert!(capture.is_none());
+        assert!(PointerCapture::release(&mut capture).is_none());
+
+        // Locked: the cursor never moves, so all motion counts.
+        let mut locked = PointerCapture {
+            mode: CursorGrabMode::Locked,
+            buttons: 1,
+            overflow: [0.0; 2],
+        };
+        assert!(locked.raw_motion([50.0, 50.0], size, (1.5, 2.5)));
+        assert_eq!(locked.overflow, [1.5, 2.5]);
+    }
+}
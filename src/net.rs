@@ -0,0 +1,1030 @@
+use std::{
+    io::Read,
+    path::PathBuf,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use mlua::prelude::*;
+
+use crate::cookies::Session;
+use crate::http_cache::{CachedResponse, HttpCache, Response as CacheEntry};
+use crate::platform::{HttpClient, HttpError, HttpErrorKind, HttpOptions};
+use crate::rate_limit::RateLimiter;
+use crate::settings::NetworkSettings;
+
+/// Set by AbortSubScript; an in-flight transfer on that subscript's Lua
+/// state stops at the next chunk.
+pub struct CancelFlag(pub Arc<AtomicBool>);
+
+/// Proxy and TLS options applied to every request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetSettings {
+    pub proxy: Option<String>,
+    pub no_proxy: Vec<String>,
+    pub ca_bundle: Option<PathBuf>,
+    pub verify_tls: bool,
+}
+
+/// Shared by the main state and every subscript, so SetProxy applies to
+/// downloads already queued.
+pub type NetConfig = Arc<Mutex<NetSettings>>;
+
+impl Default for NetSettings {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            no_proxy: Vec::new(),
+            ca_bundle: None,
+            verify_tls: true,
+        }
+    }
+}
+
+impl NetSettings {
+    /// The standard proxy and CA variables, overridden by the settings file.
+    pub fn new(file: &NetworkSettings) -> Self {
+        let env = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|n| std::env::var(n).ok().filter(|v| !v.is_empty()))
+        };
+        Self::resolve(
+            file,
+            env(&[
+                "HTTPS_PROXY",
+                "https_proxy",
+                "ALL_PROXY",
+                "all_proxy",
+                "HTTP_PROXY",
+                "http_proxy",
+            ]),
+            env(&["NO_PROXY", "no_proxy"]),
+            env(&["POB_CA_BUNDLE", "SSL_CERT_FILE", "CURL_CA_BUNDLE"]),
+        )
+    }
+
+    fn resolve(
+        file: &NetworkSettings,
+        env_proxy: Option<String>,
+        env_no_proxy: Option<String>,
+        env_ca: Option<String>,
+    ) -> Self {
+        Self {
+            proxy: file.proxy.clone().or(env_proxy).filter(|p| !p.is_empty()),
+            no_proxy: file.no_proxy.clone().unwrap_or_else(|| {
+                env_no_proxy
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|h| h.trim().to_string())
+                    .filter(|h| !h.is_empty())
+                    .collect()
+            }),
+            ca_bundle: file.ca_bundle.clone().or(env_ca.map(PathBuf::from)),
+            verify_tls: file.verify_tls.unwrap_or(true),
+        }
+    }
+
+    /// The proxy to use for `url`, honouring the NO_PROXY list.
+    pub fn proxy_for(&self, url: &str) -> Option<&str> {
+        let proxy = self.proxy.as_deref()?;
+        let host = host_of(url).to_ascii_lowercase();
+        let bypass = self.no_proxy.iter().any(|entry| {
+            let entry = entry.to_ascii_lowercase();
+            if entry == "*" {
+                return true;
+            }
+            let entry = entry.trim_start_matches('*');
+            host == entry.trim_start_matches('.')
+                || (entry.starts_with('.') && host.ends_with(entry))
+                || host.ends_with(&format!(".{}", entry))
+        });
+        if bypass { None } else { Some(proxy) }
+    }
+}
+
+/// Everything a Lua state's HTTP shim shares with the others. The default
+/// has no cache or rate limiting.
+#[derive(Clone, Default)]
+pub struct NetState {
+    pub config: NetConfig,
+    pub cache: Option<Arc<Mutex<HttpCache>>>,
+    pub limiter: Option<Arc<Mutex<RateLimiter>>>,
+    pub max_wait: Duration,
+    pub session: Arc<Mutex<Session>>,
+}
+
+impl NetState {
+    pub fn new(file: &NetworkSettings) -> Self {
+        let rl = &file.rate_limit;
+        let cache = &file.cache;
+        Self {
+            config: Arc::new(Mutex::new(NetSettings::new(file))),
+            cache: cache.enabled.unwrap_or(true).then(|| {
+                let defaults = HttpCache::default();
+                let mut http = HttpCache::new(
+                    cache.max_entries.unwrap_or(defaults.max_entries),
+                    cache
+                        .default_ttl_secs
+                        .map_or(defaults.default_ttl, Duration::from_secs),
+                );
+                http.max_entry_bytes = cache.max_entry_bytes.unwrap_or(defaults.max_entry_bytes);
+                http.max_total_bytes = cache.max_total_bytes.unwrap_or(defaults.max_total_bytes);
+                Arc::new(Mutex::new(http))
+            }),
+            limiter: rl.enabled.unwrap_or(true).then(|| {
+                Arc::new(Mutex::new(RateLimiter::new(Duration::from_millis(
+                    rl.min_interval_ms.unwrap_or(0),
+                ))))
+            }),
+            max_wait: Duration::from_secs(rl.max_wait_secs.unwrap_or(60)),
+            session: Arc::default(),
+        }
+    }
+}
+
+/// A NetState built on first use, shared by the main state and every
+/// subscript.
+pub type SharedNet = Arc<LazyLock<NetState, Box<dyn FnOnce() -> NetState + Send>>>;
+
+/// A SharedNet that calls `init` when a state first touches the network.
+pub fn lazy(init: impl FnOnce() -> NetState + Send + 'static) -> SharedNet {
+    Arc::new(LazyLock::new(Box::new(init)))
+}
+
+/// Host part of a URL (or the string itself if it's a bare host).
+fn host_of(url: &str) -> &str {
+    let host = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or("");
+    let host = host.rsplit_once('@').map_or(host, |(_, h)| h);
+    match host.rsplit_once(':') {
+        Some((h, port)) if port.chars().all(|c| c.is_ascii_digit()) => h,
+        _ => host,
+    }
+}
+
+/// Minimum time between progress callbacks during a transfer.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+const READ_CHUNK: usize = 16 * 1024;
+
+/// The libcurl options the shim understands, as `(name, CURLoption)`. Each
+/// is exposed as `curl.OPT_<NAME>` and `easy:setopt_<name>(value)`.
+const OPTIONS: &[(&str, i64)] = &[
+    ("url", 10002),
+    ("proxy", 10004),
+    ("timeout", 13),
+    ("postfields", 10015),
+    ("useragent", 10018),
+    ("cookie", 10022),
+    ("httpheader", 10023),
+    ("customrequest", 10036),
+    ("noprogress", 43),
+    ("post", 47),
+    ("followlocation", 52),
+    ("ssl_verifypeer", 64),
+    ("cainfo", 10065),
+    ("maxredirs", 68),
+    ("connecttimeout", 78),
+    ("accept_encoding", 10102),
+    ("ipresolve", 113),
+];
+
+const INFO_EFFECTIVE_URL: i64 = 0x100001;
+const INFO_RESPONSE_CODE: i64 = 0x200002;
+
+// CURLcode values for the failures the shim can report.
+const E_UNSUPPORTED_PROTOCOL: i64 = 1;
+const E_URL_MALFORMAT: i64 = 3;
+const E_COULDNT_RESOLVE_PROXY: i64 = 5;
+const E_COULDNT_RESOLVE_HOST: i64 = 6;
+const E_COULDNT_CONNECT: i64 = 7;
+const E_WRITE_ERROR: i64 = 23;
+const E_OPERATION_TIMEDOUT: i64 = 28;
+const E_ABORTED_BY_CALLBACK: i64 = 42;
+const E_TOO_MANY_REDIRECTS: i64 = 47;
+const E_RECV_ERROR: i64 = 56;
+const E_SSL_CACERT_BADFILE: i64 = 77;
+
+/// Registers `lcurl.safe`: the subset of the Lua-cURL easy interface PoB's
+/// download scripts use, sent through `platform::HttpClient`. Also
+/// registers the globals `GetRateLimitInfo(urlOrHost)`,
+/// `SetSessionCookie(domain, name, value)`, `ClearSessionCookies([domain])`,
+/// `SetSessionHeader(host, name, value)` and `ClearSessionHeaders([host])`;
+/// a nil value removes the entry.
+pub fn register(lua: &Lua, net: SharedNet) -> LuaResult<()> {
+    lua.set_app_data(net.clone());
+    register_session(lua, net.clone())?;
+    lua.globals().set(
+        "GetRateLimitInfo",
+        lua.create_function(move |lua, target: String| {
+            let Some(limiter) = &net.limiter else {
+                return Ok(LuaValue::Nil);
+            };
+            let host = host_of(&target);
+            let Some(quota) = limiter.lock().unwrap().quota(host, Instant::now()) else {
+                return Ok(LuaValue::Nil);
+            };
+            let t = lua.create_table()?;
+            t.set("remaining", quota.remaining)?;
+            t.set("retryAfter", quota.retry_after.as_secs_f64())?;
+            let rules = lua.create_table()?;
+            for (i, w) in quota.windows.iter().enumerate() {
+                let r = lua.create_table()?;
+                r.set("rule", w.rule.as_str())?;
+                r.set("hits", w.hits)?;
+                r.set("period", w.period.as_secs())?;
+                r.set("current", w.current)?;
+                rules.set(i + 1, r)?;
+            }
+            t.set("rules", rules)?;
+            Ok(LuaValue::Table(t))
+        })?,
+    )?;
+    let module = lua.create_table()?;
+    for (name, code) in OPTIONS {
+        module.set(format!("OPT_{}", name.to_uppercase()), *code)?;
+    }
+    module.set("INFO_EFFECTIVE_URL", INFO_EFFECTIVE_URL)?;
+    module.set("INFO_RESPONSE_CODE", INFO_RESPONSE_CODE)?;
+    module.set("IPRESOLVE_WHATEVER", 0)?;
+    module.set("IPRESOLVE_V4", 1)?;
+    module.set("IPRESOLVE_V6", 2)?;
+    module.set(
+        "easy",
+        lua.create_function(|lua, opts: Option<LuaTable>| {
+            let ud = lua.create_userdata(Easy::default())?;
+            if let Some(opts) = opts {
+                for pair in opts.pairs::<String, LuaValue>() {
+                    let (name, value) = pair?;
+                    set_named(&ud, &name, value)?;
+                }
+            }
+            Ok(ud)
+        })?,
+    )?;
+
+    lua.set_named_registry_value("lcurl.safe", module)?;
+    let preload: LuaTable = lua
+        .globals()
+        .get::<_, LuaTable>("package")?
+        .get("preload")?;
+    preload.set(
+        "lcurl.safe",
+        lua.create_function(|lua, _: LuaMultiValue| {
+            lua.named_registry_value::<LuaTable>("lcurl.safe")
+        })?,
+    )?;
+    Ok(())
+}
+
+/// Session globals. Cookie and header values are credentials, so they
+/// are never printed, not even in errors.
+fn register_session(lua: &Lua, net: SharedNet) -> LuaResult<()> {
+    let g = lua.globals();
+    let s = net.clone();
+    g.set(
+        "SetSessionCookie",
+        lua.create_function(
+            move |_, (domain, name, value): (String, String, Option<String>)| {
+                s.session
+                    .lock()
+                    .unwrap()
+                    .set_cookie(&domain, &name, value.as_deref());
+                Ok(())
+            },
+        )?,
+    )?;
+    let s = net.clone();
+    g.set(
+        "ClearSessionCookies",
+        lua.create_function(move |_, domain: Option<String>| {
+            s.session.lock().unwrap().clear_cookies(domain.as_deref());
+            Ok(())
+        })?,
+    )?;
+    let s = net.clone();
+    g.set(
+        "SetSessionHeader",
+        lua.create_function(
+            move |_, (host, name, value): (Option<String>, String, Option<String>)| {
+                s.session.lock().unwrap().set_header(
+                    host.as_deref().unwrap_or(""),
+                    &name,
+                    value.as_deref(),
+                );
+                Ok(())
+            },
+        )?,
+    )?;
+    g.set(
+        "ClearSessionHeaders",
+        lua.create_function(move |_, host: Option<String>| {
+            net.session.lock().unwrap().clear_headers(host.as_deref());
+            Ok(())
+        })?,
+    )?;
+    Ok(())
+}
+
+/// One `curl.easy()` handle. Callbacks live in the userdata's named user
+/// values so they stay owned by the Lua state.
+#[derive(Default)]
+struct Easy {
+    url: String,
+    headers: Vec<String>,
+    user_agent: Option<String>,
+    cookie: Option<String>,
+    body: Option<Vec<u8>>,
+    post: bool,
+    method: Option<String>,
+    proxy: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    follow: bool,
+    max_redirs: Option<u32>,
+    ipresolve: i64,
+    verify_peer: Option<bool>,
+    ca_info: Option<String>,
+    no_progress: Option<bool>,
+    response_code: u16,
+    effective_url: String,
+}
+
+impl Easy {
+    fn set(&mut self, opt: i64, value: LuaValue) -> LuaResult<()> {
+        let text = || -> LuaResult<String> {
+            match &value {
+                LuaValue::String(s) => Ok(s.to_string_lossy().into_owned()),
+                LuaValue::Integer(n) => Ok(n.to_string()),
+                LuaValue::Number(n) => Ok(n.to_string()),
+                _ => Err(LuaError::RuntimeError(format!(
+                    "curl option {} expects a string",
+                    opt
+                ))),
+            }
+        };
+        let flag = || match &value {
+            LuaValue::Boolean(b) => *b,
+            LuaValue::Integer(n) => *n != 0,
+            LuaValue::Number(n) => *n != 0.0,
+            _ => false,
+        };
+        // Timeouts too long to count down, math.huge among them, are none.
+        let secs = || {
+            let t = match &value {
+                LuaValue::Integer(n) if *n > 0 => Duration::from_secs(*n as u64),
+                LuaValue::Number(n) if *n > 0.0 => Duration::try_from_secs_f64(*n).ok()?,
+                _ => return None,
+            };
+            Instant::now().checked_add(t).map(|_| t)
+        };
+        match opt {
+            10002 => self.url = text()?,
+            10004 => self.proxy = Some(text()?).filter(|p| !p.is_empty()),
+            13 => self.timeout = secs(),
+            10015 => {
+                self.body = Some(match &value {
+                    LuaValue::String(s) => s.as_bytes().to_vec(),
+                    _ => text()?.into_bytes(),
+                });
+                self.post = true;
+            }
+            10018 => self.user_agent = Some(text()?),
+            10022 => self.cookie = Some(text()?),
+            10023 => {
+                self.headers = match &value {
+                    LuaValue::Table(t) => t
+                        .clone()
+                        .sequence_values::<String>()
+                        .collect::<LuaResult<_>>()?,
+                    _ => vec![text()?],
+                }
+            }
+            10036 => self.method = Some(text()?),
+            43 => self.no_progress = Some(flag()),
+            47 => self.post = flag(),
+            52 => self.follow = flag(),
+            64 => self.verify_peer = Some(flag()),
+            10065 => self.ca_info = Some(text()?),
+            68 => self.max_redirs = value.as_u32(),
+            78 => self.connect_timeout = secs(),
+            // The transport negotiates gzip itself.
+            10102 => {}
+            113 => self.ipresolve = value.as_i64().unwrap_or(0),
+            _ => {
+                return Err(LuaError::RuntimeError(format!(
+                    "unsupported curl option {}",
+                    opt
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// How the handle connects; a proxy set on it wins over the
+    /// configured one.
+    fn options(&self, config: &NetSettings) -> HttpOptions {
+        let bundles = [
+            config.ca_bundle.clone(),
+            self.ca_info.as_ref().map(PathBuf::from),
+        ];
+        HttpOptions {
+            redirects: if self.follow {
+                self.max_redirs.unwrap_or(50)
+            } else {
+                0
+            },
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            proxy: self
+                .proxy
+                .as_deref()
+                .or(config.proxy_for(&self.url))
+                .map(String::from),
+            verify_tls: self.verify_peer.unwrap_or(config.verify_tls),
+            ca_bundles: bundles.into_iter().flatten().collect(),
+            ip_version: match self.ipresolve {
+                1 => Some(4),
+                2 => Some(6),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The error object `perform` returns, shaped like lcurl's `error:msg()`.
+#[derive(Clone)]
+struct CurlError {
+    code: i64,
+    msg: String,
+}
+
+impl CurlError {
+    fn new(code: i64, msg: impl Into<String>) -> Self {
+        Self {
+            code,
+            msg: msg.into(),
+        }
+    }
+
+    fn from_http(e: HttpError) -> Self {
+        let code = match e.kind {
+            HttpErrorKind::BadUrl => E_URL_MALFORMAT,
+            HttpErrorKind::UnknownScheme => E_UNSUPPORTED_PROTOCOL,
+            HttpErrorKind::Dns => E_COULDNT_RESOLVE_HOST,
+            HttpErrorKind::Connect => E_COULDNT_CONNECT,
+            HttpErrorKind::TooManyRedirects => E_TOO_MANY_REDIRECTS,
+            HttpErrorKind::Proxy => E_COULDNT_RESOLVE_PROXY,
+            HttpErrorKind::TimedOut => E_OPERATION_TIMEDOUT,
+            HttpErrorKind::BadCaBundle => E_SSL_CACERT_BADFILE,
+            HttpErrorKind::Other => E_RECV_ERROR,
+        };
+        Self::new(code, e.message)
+    }
+}
+
+impl LuaUserData for CurlError {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("msg", |_, this, ()| Ok(this.msg.clone()));
+        methods.add_method("no", |_, this, ()| Ok(this.code));
+        methods.add_method("name", |_, this, ()| Ok(format!("CURLE_{}", this.code)));
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(format!("[CURL-EASY][{}] {}", this.code, this.msg))
+        });
+    }
+}
+
+fn set_named(ud: &LuaAnyUserData, name: &str, value: LuaValue) -> LuaResult<()> {
+    match name {
+        "writefunction" | "headerfunction" | "progressfunction" | "xferinfofunction" => {
+            let key = if name == "xferinfofunction" {
+                "progressfunction"
+            } else {
+                name
+            };
+            ud.set_named_user_value(key, value)
+        }
+        _ => {
+            let Some((_, code)) = OPTIONS.iter().find(|(n, _)| *n == name) else {
+                return Err(LuaError::RuntimeError(format!(
+                    "unsupported curl option '{}'",
+                    name
+                )));
+            };
+            ud.borrow_mut::<Easy>()?.set(*code, value)
+        }
+    }
+}
+
+/// Calls a transfer callback; lcurl aborts when one returns `false`.
+fn call_callback<'lua>(f: &LuaFunction<'lua>, args: impl IntoLuaMulti<'lua>) -> LuaResult<bool> {
+    let result: LuaValue = f.call(args)?;
+    Ok(!matches!(result, LuaValue::Boolean(false)))
+}
+
+fn cancelled(lua: &Lua) -> bool {
+    lua.app_data_ref::<CancelFlag>()
+        .is_some_and(|f| f.0.load(Ordering::Relaxed))
+}
+
+struct Callbacks<'lua> {
+    write: Option<LuaFunction<'lua>>,
+    header: Option<LuaFunction<'lua>>,
+    progress: Option<LuaFunction<'lua>>,
+}
+
+fn aborted() -> CurlError {
+    CurlError::new(
+        E_ABORTED_BY_CALLBACK,
+        "Operation was aborted by an application callback",
+    )
+}
+
+fn perform<'lua>(lua: &'lua Lua, ud: &LuaAnyUserData<'lua>) -> LuaResult<Result<(), CurlError>> {
+    let mut callbacks = Callbacks {
+        write: ud.named_user_value("writefunction")?,
+        header: ud.named_user_value("headerfunction")?,
+        progress: ud.named_user_value("progressfunction")?,
+    };
+    let net = lua
+        .app_data_ref::<SharedNet>()
+        .map(|n| NetState::clone(&n))
+        .unwrap_or_default();
+
+    let (client, method, mut headers, body, url, cacheable) = {
+        let easy = ud.borrow::<Easy>()?;
+        let config = net.config.lock().unwrap().clone();
+        let client = match HttpClient::new(&easy.options(&config)) {
+            Ok(client) => client,
+            Err(e) => return Ok(Err(CurlError::from_http(e))),
+        };
+        let method = easy
+            .method
+            .clone()
+            .unwrap_or_else(|| if easy.post { "POST" } else { "GET" }.to_string());
+        let mut headers = Vec::new();
+        let (presets, jar_cookie) = {
+            let mut session = net.session.lock().unwrap();
+            (
+                session.headers_for(host_of(&easy.url)),
+                session.cookie_header(&easy.url, SystemTime::now()),
+            )
+        };
+        let mut authorized = easy.cookie.is_some() || jar_cookie.is_some();
+        // Presets go first so the handle's own headers override them.
+        for (name, value) in &presets {
+            authorized |=
+                name.eq_ignore_ascii_case("authorization") || name.eq_ignore_ascii_case("cookie");
+            headers.push((name.clone(), value.clone()));
+        }
+        for line in &easy.headers {
+            if let Some((name, value)) = line.split_once(':') {
+                authorized |= name.trim().eq_ignore_ascii_case("authorization")
+                    || name.trim().eq_ignore_ascii_case("cookie");
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        if let Some(ua) = &easy.user_agent {
+            headers.push(("User-Agent".to_string(), ua.clone()));
+        }
+        let cookie = match (jar_cookie, &easy.cookie) {
+            (Some(jar), Some(own)) => Some(format!("{}; {}", own, jar)),
+            (jar, own) => jar.or_else(|| own.clone()),
+        };
+        if let Some(cookie) = &cookie {
+            headers.push(("Cookie".to_string(), cookie.clone()));
+        }
+        if easy.no_progress == Some(true) {
+            callbacks.progress = None;
+        }
+        // Only anonymous GETs are shared through the cache.
+        let cacheable = method == "GET" && easy.body.is_none() && !authorized;
+        (
+            client,
+            method,
+            headers,
+            easy.body.clone(),
+            easy.url.clone(),
+            cacheable,
+        )
+    };
+    let host = host_of(&url).to_string();
+
+    let cache = net.cache.as_ref().filter(|_| cacheable);
+    let cached = cache.and_then(|c| c.lock().unwrap().get(&url).cloned());
+    if let Some(entry) = &cached {
+        if entry.is_fresh(Instant::now()) {
+            return replay(lua, ud, &callbacks, entry, &url);
+        }
+        if let Some(etag) = &entry.etag {
+            headers.push(("If-None-Match".to_string(), etag.clone()));
+        }
+        if let Some(modified) = &entry.last_modified {
+            headers.push(("If-Modified-Since".to_string(), modified.clone()));
+        }
+    }
+
+    if let Some(limiter) = &net.limiter
+        && let Err(e) = wait_for_quota(lua, limiter, &host, net.max_wait)
+    {
+        return Ok(Err(e));
+    }
+    // Like curl, an HTTP error status is still a completed transfer.
+    let response = match client.send(&method, &url, &headers, body.as_deref()) {
+        Ok(response) => response,
+        Err(e) => return Ok(Err(CurlError::from_http(e))),
+    };
+    {
+        let mut session = net.session.lock().unwrap();
+        for header in response.all("Set-Cookie") {
+            session.store_set_cookie(&response.url, header, SystemTime::now());
+        }
+    }
+    if let Some(limiter) = &net.limiter {
+        limiter.lock().unwrap().observe(
+            &host,
+            response.status,
+            |name| response.header(name).map(String::from),
+            Instant::now(),
+        );
+    }
+
+    let cache_control = response.header("Cache-Control").map(String::from);
+    if response.status == 304
+        && let (Some(cache), Some(entry)) = (cache, &cached)
+    {
+        cache
+            .lock()
+            .unwrap()
+            .refresh(&url, cache_control.as_deref(), Instant::now());
+        return replay(lua, ud, &callbacks, entry, &url);
+    }
+
+    let status = response.status;
+    let etag = response.header("ETag").map(String::from);
+    let last_modified = response.header("Last-Modified").map(String::from);
+    let mut lines = vec![format!(
+        "{} {} {}\r\n",
+        response.version, status, response.status_text
+    )];
+    for (name, value) in &response.headers {
+        lines.push(format!("{}: {}\r\n", name, value));
+    }
+    lines.push("\r\n".to_string());
+    let total: f64 = response
+        .header("Content-Length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0.0);
+    let effective_url = response.url.clone();
+    let store = cache.filter(|_| status == 200);
+
+    let body = match deliver(
+        lua,
+        ud,
+        &callbacks,
+        status,
+        &effective_url,
+        &lines,
+        response.body,
+        total,
+        store.is_some(),
+    )? {
+        Ok(body) => body,
+        Err(e) => return Ok(Err(e)),
+    };
+    if let (Some(cache), Some(body)) = (store, body) {
+        let response = CacheEntry {
+            status,
+            header_lines: lines,
+            body,
+            etag,
+            last_modified,
+        };
+        cache
+            .lock()
+            .unwrap()
+            .store(&url, response, cache_control.as_deref(), Instant::now());
+    }
+    Ok(Ok(()))
+}
+
+fn replay<'lua>(
+    lua: &'lua Lua,
+    ud: &LuaAnyUserData<'lua>,
+    callbacks: &Callbacks<'lua>,
+    entry: &CachedResponse,
+    url: &str,
+) -> LuaResult<Result<(), CurlError>> {
+    let result = deliver(
+        lua,
+        ud,
+        callbacks,
+        entry.status,
+        url,
+        &entry.header_lines,
+        std::io::Cursor::new(&entry.body[..]),
+        entry.body.len() as f64,
+        false,
+    )?;
+    Ok(result.map(|_| ()))
+}
+
+/// Waits until `host` has quota for one more request, or fails when that
+/// would take longer than `max_wait`.
+fn wait_for_quota(
+    lua: &Lua,
+    limiter: &Mutex<RateLimiter>,
+    host: &str,
+    max_wait: Duration,
+) -> Result<(), CurlError> {
+    loop {
+        let now = Instant::now();
+        let delay = {
+            let mut limiter = limiter.lock().unwrap();
+            let delay = limiter.delay(host, now);
+            if delay.is_zero() {
+                limiter.record(host, now);
+            }
+            delay
+        };
+        if delay.is_zero() {
+            return Ok(());
+        }
+        if delay > max_wait {
+            return Err(CurlError::new(
+                E_OPERATION_TIMEDOUT,
+                format!(
+                    "{} is rate limited; retry in {}s",
+                    host,
+                    delay.as_secs().max(1)
+                ),
+            ));
+        }
+        if cancelled(lua) {
+            return Err(aborted());
+        }
+        std::thread::sleep(delay.min(Duration::from_millis(100)));
+    }
+}
+
+/// Feeds a response through the Lua callbacks, keeping a copy of the body
+/// when `keep` is set.
+#[allow(clippy::too_many_arguments)]
+fn deliver<'lua>(
+    lua: &'lua Lua,
+    ud: &LuaAnyUserData<'lua>,
+    callbacks: &Callbacks<'lua>,
+    status: u16,
+    effective_url: &str,
+    header_lines: &[String],
+    mut reader: impl Read,
+    total: f64,
+    keep: bool,
+) -> LuaResult<Result<Option<Vec<u8>>, CurlError>> {
+    {
+        let mut easy = ud.borrow_mut::<Easy>()?;
+        easy.response_code = status;
+        easy.effective_url = effective_url.to_string();
+    }
+    if let Some(header) = &callbacks.header {
+        for line in header_lines {
+            if !call_callback(header, line.as_str())? {
+                return Ok(Err(CurlError::new(E_WRITE_ERROR, "Failed writing header")));
+            }
+        }
+    }
+
+    let mut kept = keep.then(Vec::new);
+    let mut buf = vec![0u8; READ_CHUNK];
+    let mut received = 0usize;
+    let mut last_progress: Option<Instant> = None;
+    loop {
+        if cancelled(lua) {
+            return Ok(Err(aborted()));
+        }
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                let code = if e.kind() == std::io::ErrorKind::TimedOut {
+                    E_OPERATION_TIMEDOUT
+                } else {
+                    E_RECV_ERROR
+                };
+                return Ok(Err(CurlError::new(code, e.to_string())));
+            }
+        };
+        received += n;
+        if let Some(kept) = &mut kept {
+            kept.extend_from_slice(&buf[..n]);
+        }
+        if let Some(write) = &callbacks.write
+            && !call_callback(write, lua.create_string(&buf[..n])?)?
+        {
+            return Ok(Err(CurlError::new(
+                E_WRITE_ERROR,
+                "Failed writing received data",
+            )));
+        }
+        if let Some(progress) = &callbacks.progress
+            && last_progress.is_none_or(|t| t.elapsed() >= PROGRESS_INTERVAL)
+        {
+            last_progress = Some(Instant::now());
+            if !call_callback(progress, (total, received as f64, 0.0, 0.0))? {
+                return Ok(Err(aborted()));
+            }
+        }
+    }
+    if let Some(progress) = &callbacks.progress {
+        call_callback(
+            progress,
+            (total.max(received as f64), received as f64, 0.0, 0.0),
+        )?;
+    }
+    Ok(Ok(kept))
+}
+
+impl LuaUserData for Easy {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function(
+            "setopt",
+            |_, (ud, opt, value): (LuaAnyUserData, LuaValue, LuaValue)| {
+                match opt {
+                    LuaValue::Table(opts) => {
+                        for pair in opts.pairs::<String, LuaValue>() {
+                            let (name, value) = pair?;
+                            set_named(&ud, &name, value)?;
+                        }
+                    }
+                    other => {
+                        let code = other.as_i64().ok_or_else(|| {
+                            LuaError::RuntimeError("setopt expects an option".into())
+                        })?;
+                        ud.borrow_mut::<Easy>()?.set(code, value)?;
+                    }
+                }
+                Ok(ud)
+            },
+        );
+        for (name, _) in OPTIONS {
+            methods.add_function(
+                format!("setopt_{}", name),
+                move |_, (ud, value): (LuaAnyUserData, LuaValue)| {
+                    set_named(&ud, name, value)?;
+                    Ok(ud)
+                },
+            );
+        }
+        for name in [
+            "writefunction",
+            "headerfunction",
+            "progressfunction",
+            "xferinfofunction",
+        ] {
+            methods.add_function(
+                format!("setopt_{}", name),
+                move |_, (ud, f): (LuaAnyUserData, LuaValue)| {
+                    set_named(&ud, name, f)?;
+                    Ok(ud)
+                },
+            );
+        }
+        methods.add_function("perform", |lua, ud: LuaAnyUserData| {
+            match perform(lua, &ud)? {
+                Ok(()) => ud.into_lua_multi(lua),
+                Err(e) => (LuaValue::Nil, e).into_lua_multi(lua),
+            }
+        });
+        methods.add_method("getinfo", |lua, this, info: i64| match info {
+            INFO_RESPONSE_CODE => this.response_code.into_lua(lua),
+            INFO_EFFECTIVE_URL => this.effective_url.clone().into_lua(lua),
+            _ => Ok(LuaValue::Nil),
+        });
+        methods.add_method(
+            "getinfo_response_code",
+            |_, this, ()| Ok(this.response_code),
+        );
+        methods.add_method("getinfo_effective_url", |_, this, ()| {
+            Ok(this.effective_url.clone())
+        });
+        methods.add_method_mut("reset", |_, this, ()| {
+            *this = Easy::default();
+            Ok(())
+        });
+        methods.add_method("close", |_, _, ()| Ok(()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    #[test]
+    fn perform_reports_progress_and_honours_cancel() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut req = [0u8; 1024];
+                let _ = stream.read(&mut req);
+                let body = vec![b'x'; 40_000];
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        let lua = Lua::new();
+        let net = lazy(NetState::default);
+        register(&lua, net.clone()).unwrap();
+        // Nothing is set up until the first transfer.
+        assert!(LazyLock::get(&net).is_none());
+        let cancel = Arc::new(AtomicBool::new(false));
+        lua.set_app_data(CancelFlag(cancel.clone()));
+        lua.globals()
+            .set("url", format!("http://127.0.0.1:{}/", port))
+            .unwrap();
+        let (len, code, last_now, last_total): (usize, u16, f64, f64) = lua
+            .load(
+                r#"
+                local curl = require("lcurl.safe")
+                local body, now, total = "", 0, 0
+                local easy = curl.easy()
+                easy:setopt_url(url)
+                easy:setopt(curl.OPT_NOPROGRESS, false)
+                easy:setopt_writefunction(function(data) body = body .. data end)
+                easy:setopt_progressfunction(function(t, n) total, now = t, n end)
+                assert(easy:perform())
+                return #body, easy:getinfo(curl.INFO_RESPONSE_CODE), now, total
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!((len, code), (40_000, 200));
+        assert_eq!((last_now, last_total), (40_000.0, 40_000.0));
+        assert!(LazyLock::get(&net).is_some());
+
+        cancel.store(true, Ordering::Relaxed);
+        let err: String = lua
+            .load(
+                r#"
+                local easy = require("lcurl.safe").easy({ url = url })
+                local ok, err = easy:perform()
+                return err:msg()
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(err.contains("aborted"));
+    }
+
+    #[test]
+    fn timeouts_too_long_to_count_down_are_none() {
+        let mut easy = Easy::default();
+        easy.set(13, LuaValue::Number(2.5)).unwrap();
+        assert_eq!(easy.timeout, Some(Duration::from_millis(2500)));
+        easy.set(13, LuaValue::Number(f64::INFINITY)).unwrap();
+        assert_eq!(easy.timeout, None);
+        easy.set(78, LuaValue::Integer(i64::MAX)).unwrap();
+        assert_eq!(easy.connect_timeout, None);
+    }
+
+    #[test]
+    fn settings_file_overrides_env_and_no_proxy_bypasses() {
+        let file = NetworkSettings {
+            proxy: Some("http://file:3128".into()),
+            ..Default::default()
+        };
+        let s = NetSettings::resolve(
+            &file,
+            Some("http://env:8080".into()),
+            Some("localhost, .corp.example".into()),
+            Some("/etc/ca.pem".into()),
+        );
+        assert_eq!(
+            s.proxy_for("https://www.pathofexile.com/api"),
+            Some("http://file:3128")
+        );
+        assert_eq!(s.proxy_for("http://localhost:8000/cb"), None);
+        assert_eq!(s.proxy_for("https://git.corp.example/x"), None);
+        assert_eq!(s.ca_bundle, Some(PathBuf::from("/etc/ca.pem")));
+        assert!(s.verify_tls);
+        let all = NetSettings::resolve(&file, None, Some("*".into()), None);
+        assert_eq!(all.proxy_for("https://www.pathofexile.com/api"), None);
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, TryRecvError, channel},
+    },
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use mlua::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::platform::open_url;
+
+/// How long a capture waits for the browser before giving up.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+const DONE_PAGE: &str = "<!DOCTYPE html><html><body style=\"font-family:sans-serif\">\
+    <h3>Path of Building</h3><p>Authorization finished, you can close this tab.</p>\
+    </body></html>";
+
+/// What the redirect carried.
+#[derive(Debug, PartialEq)]
+pub struct Redirect {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Localhost listener for an OAuth redirect URI. A thread waits for the
+/// browser and Lua polls for the result once per frame, so the main state
+/// never blocks on it.
+pub struct OAuthCapture {
+    port: u16,
+    path: String,
+    result: Option<Receiver<Result<Redirect, String>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl OAuthCapture {
+    pub fn bind(port: u16, path: &str, timeout: Duration) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let path = format!("/{}", path.trim_start_matches('/'));
+        let (tx, result) = channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let (p, c) = (path.clone(), closed.clone());
+        std::thread::Builder::new()
+            .name("oauth".into())
+            .spawn(move || {
+                let deadline = Instant::now().checked_add(timeout);
+                if let Some(result) = wait(&listener, &p, deadline, &c) {
+                    tx.send(result).ok();
+                }
+            })?;
+        Ok(Self {
+            port,
+            path,
+            result: Some(result),
+            closed,
+        })
+    }
+
+    pub fn redirect_uri(&self) -> String {
+        format!("http://localhost:{}{}", self.port, self.path)
+    }
+
+    /// The redirect once it has arrived, then None again.
+    pub fn poll(&mut self) -> Option<Result<Redirect, String>> {
+        let result = match self.result.as_ref()?.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err("authorization capture stopped".into()),
+        };
+        self.result = None;
+        Some(result)
+    }
+
+    pub fn close(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.result = None;
+    }
+}
+
+impl Drop for OAuthCapture {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Accepts connections until one brings the redirect to `path`; requests
+/// to other paths (favicon) are answered with 404 and ignored. None once
+/// the capture was closed.
+fn wait(
+    listener: &TcpListener,
+    path: &str,
+    deadline: Option<Instant>,
+    closed: &AtomicBool,
+) -> Option<Result<Redirect, String>> {
+    while !closed.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Some(redirect) = handle(stream, path) {
+                    return Some(Ok(redirect));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return Some(Err("timed out waiting for authorization".into()));
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Some(Err(e.to_string())),
+        }
+    }
+    None
+}
+
+fn handle(mut stream: TcpStream, want: &str) -> Option<Redirect> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 8192 {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&buf);
+    let target = head.lines().next()?.split_whitespace().nth(1)?.to_string();
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    if path != want {
+        stream
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .ok();
+        return None;
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        DONE_PAGE.len(),
+        DONE_PAGE
+    )
+    .ok();
+    let param = |name: &str| {
+        query.split('&').find_map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k) == name).then(|| percent_decode(v))
+        })
+    };
+    Some(Redirect {
+        code: param("code"),
+        state: param("state"),
+        error: param("error_description").or_else(|| param("error")),
+    })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 2;
+            }
+            (b'+', _) => out.push(b' '),
+            (b, _) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Random URL-safe token for PKCE verifiers and `state`.
+fn random_token(bytes: usize) -> LuaResult<String> {
+    let mut buf = vec![0u8; bytes];
+    getrandom::getrandom(&mut buf).map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+    Ok(URL_SAFE_NO_PAD.encode(buf))
+}
+
+impl LuaUserData for OAuthCapture {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("GetRedirectURI", |_, this, ()| Ok(this.redirect_uri()));
+        methods.add_method("Open", |_, _, url: String| {
+            open_url(&url);
+            Ok(())
+        });
+        // nil while waiting, then code, state (or nil, nil, error) once.
+        methods.add_method_mut("Poll", |lua, this, ()| match this.poll() {
+            None => ().into_lua_multi(lua),
+            Some(Ok(r)) => match (r.code, r.error) {
+                (Some(code), None) => (code, r.state).into_lua_multi(lua),
+                (_, error) => (
+                    LuaValue::Nil,
+                    r.state,
+                    error.unwrap_or_else(|| "no authorization code returned".into()),
+                )
+                    .into_lua_multi(lua),
+            },
+            Some(Err(e)) => (LuaValue::Nil, LuaValue::Nil, e).into_lua_multi(lua),
+        });
+        methods.add_method_mut("Close", |_, this, ()| {
+            this.close();
+            Ok(())
+        });
+    }
+}
+
+/// Registers `StartOAuthCapture([port], [path], [timeoutSecs])` and
+/// `GeneratePKCE()`, which returns a verifier, its S256 challenge and a
+/// random state value.
+pub fn register(lua: &Lua) -> LuaResult<()> {
+    let g = lua.globals();
+    g.set(
+        "StartOAuthCapture",
+        lua.create_function(
+            |lua, (port, path, timeout): (Option<u16>, Option<String>, Option<f64>)| {
+                // Negative, NaN and infinite timeouts get the default.
+                let timeout = timeout
+                    .and_then(|t| Duration::try_from_secs_f64(t).ok())
+                    .unwrap_or(DEFAULT_TIMEOUT);
+                match OAuthCapture::bind(port.unwrap_or(0), path.as_deref().unwrap_or("/"), timeout)
+                {
+                    Ok(capture) => capture.into_lua_multi(lua),
+                    Err(e) => (LuaValue::Nil, e.to_string()).into_lua_multi(lua),
+                }
+            },
+        )?,
+    )?;
+    g.set(
+        "GeneratePKCE",
+        lua.create_function(|_, ()| {
+            let verifier = random_token(32)?;
+            let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+            Ok((verifier, challenge, random_token(16)?))
+        })?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_code_from_redirect() {
+        let mut capture = OAuthCapture::bind(0, "auth/callback", DEFAULT_TIMEOUT).unwrap();
+        let uri = capture.redirect_uri();
+        assert!(uri.ends_with("/auth/callback"));
+        assert_eq!(capture.poll(), None);
+
+        let port = capture.port;
+        let get = |target: &str| {
+            let mut s = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(s, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+            s
+        };
+        let _favicon = get("/favicon.ico");
+        let _redirect = get("/auth/callback?code=abc%2F123&state=xyz");
+        let mut result = None;
+        for _ in 0..100 {
+            result = capture.poll();
+            if result.is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            result,
+            Some(Ok(Redirect {
+                code: Some("abc/123".into()),
+                state: Some("xyz".into()),
+                error: None,
+            }))
+        );
+        assert_eq!(capture.poll(), None);
+    }
+
+    #[test]
+    fn bad_timeouts_fall_back_to_the_default() {
+        let lua = Lua::new();
+        register(&lua).unwrap();
+        let started: bool = lua
+            .load(
+                r#"
+                local a = StartOAuthCapture(0, "/", -1)
+                local b = StartOAuthCapture(0, "/", math.huge)
+                local c = StartOAuthCapture(0, "/", 0/0)
+                local ok = a ~= nil and b ~= nil and c ~= nil
+                a:Close() b:Close() c:Close()
+                return ok
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(started);
+    }
+}
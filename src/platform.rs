@@ -0,0 +1,738 @@
+use std::{
+    io::{self, Read},
+    net::{SocketAddr, ToSocketAddrs},
+    panic,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use winit::{
+    dpi::{LogicalSize, PhysicalPosition},
+    event_loop::ActiveEventLoop,
+    monitor::MonitorHandle,
+    window::{Fullscreen, Window, WindowLevel},
+};
+
+use crate::gpu_timer::GpuTimer;
+use crate::graphics;
+use crate::post::PostProcess;
+use crate::profile;
+use crate::settings::WindowSettings;
+
+/// The window's size when it first opens, in logical pixels.
+const INITIAL_SIZE: LogicalSize<f64> = LogicalSize::new(1280.0, 720.0);
+
+/// Opens the main window, centred on `monitor` when one is named.
+pub fn create_window(event_loop: &ActiveEventLoop, monitor: Option<&str>) -> Arc<Window> {
+    let mut attributes = Window::default_attributes()
+        .with_title("Path Of Building")
+        // So window opacity can be lowered later.
+        .with_transparent(true)
+        .with_inner_size(INITIAL_SIZE);
+    // Wayland has no window icons; the compositor finds one by app ID.
+    #[cfg(target_os = "linux")]
+    {
+        use crate::window_icon::APP_ID;
+        use winit::platform::{wayland::WindowAttributesExtWayland, x11::WindowAttributesExtX11};
+        attributes = WindowAttributesExtWayland::with_name(attributes, APP_ID, APP_ID);
+        attributes = WindowAttributesExtX11::with_name(attributes, APP_ID, APP_ID);
+    }
+    // Set before the window opens, or the taskbar groups it by executable
+    // path, apart from pinned shortcuts.
+    #[cfg(windows)]
+    {
+        #[link(name = "shell32")]
+        unsafe extern "system" {
+            fn SetCurrentProcessExplicitAppUserModelID(app_id: *const u16) -> i32;
+        }
+        let id: Vec<u16> = crate::window_icon::APP_ID
+            .encode_utf16()
+            .chain([0])
+            .collect();
+        let hr = unsafe { SetCurrentProcessExplicitAppUserModelID(id.as_ptr()) };
+        if hr < 0 {
+            eprintln!("window: couldn't set the app ID ({:#x})", hr);
+        }
+    }
+    if let Some(wanted) = monitor {
+        let handles: Vec<_> = event_loop.available_monitors().collect();
+        let names: Vec<_> = handles.iter().enumerate().map(monitor_name).collect();
+        match find_monitor(&names, wanted) {
+            Some(i) => {
+                let m = &handles[i];
+                let size = INITIAL_SIZE.to_physical::<i32>(m.scale_factor());
+                let free = [
+                    m.size().width as i32 - size.width,
+                    m.size().height as i32 - size.height,
+                ];
+                attributes = attributes.with_position(PhysicalPosition::new(
+                    m.position().x + free[0].max(0) / 2,
+                    m.position().y + free[1].max(0) / 2,
+                ));
+            }
+            None => eprintln!("window: no monitor \"{}\"", wanted),
+        }
+    }
+    Arc::new(event_loop.create_window(attributes).unwrap())
+}
+
+/// A display as GetMonitors lists it, in physical pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorInfo {
+    pub name: String,
+    pub position: [i32; 2],
+    pub size: [u32; 2],
+    pub scale: f64,
+    pub primary: bool,
+    /// The window is on this one.
+    pub current: bool,
+}
+
+fn monitor_name((i, m): (usize, &MonitorHandle)) -> String {
+    m.name().unwrap_or_else(|| format!("Monitor {}", i + 1))
+}
+
+/// Finds a monitor by name, or by number counting from 1.
+fn find_monitor(names: &[String], wanted: &str) -> Option<usize> {
+    names
+        .iter()
+        .position(|n| n.eq_ignore_ascii_case(wanted))
+        .or_else(|| {
+            wanted
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| (1..=names.len()).contains(&n))
+                .map(|n| n - 1)
+        })
+}
+
+/// The displays attached, as the window sees them.
+pub fn monitors(window: &Window) -> Vec<MonitorInfo> {
+    let (primary, current) = (window.primary_monitor(), window.current_monitor());
+    window
+        .available_monitors()
+        .enumerate()
+        .map(|(i, m)| MonitorInfo {
+            name: monitor_name((i, &m)),
+            position: [m.position().x, m.position().y],
+            size: [m.size().width, m.size().height],
+            scale: m.scale_factor(),
+            primary: primary.as_ref() == Some(&m),
+            current: current.as_ref() == Some(&m),
+        })
+        .collect()
+}
+
+/// How the main window occupies the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    /// A window covering the monitor, without a mode switch.
+    Borderless,
+    /// The monitor switched to its largest video mode.
+    Fullscreen,
+}
+
+impl WindowMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "windowed" => Self::Windowed,
+            "borderless" => Self::Borderless,
+            "fullscreen" => Self::Fullscreen,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Windowed => "windowed",
+            Self::Borderless => "borderless",
+            Self::Fullscreen => "fullscreen",
+        }
+    }
+
+    /// Puts `window` in this mode on the monitor it is on. Exclusive
+    /// fullscreen falls back to borderless when the monitor lists no
+    /// video modes.
+    pub fn apply(self, window: &Window) {
+        let monitor = window.current_monitor();
+        let fullscreen = match self {
+            Self::Windowed => None,
+            Self::Borderless => Some(Fullscreen::Borderless(monitor)),
+            Self::Fullscreen => Some(
+                monitor
+                    .as_ref()
+                    .and_then(|m| {
+                        m.video_modes().max_by_key(|v| {
+                            let size = v.size();
+                            (size.width * size.height, v.refresh_rate_millihertz())
+                        })
+                    })
+                    .map_or(Fullscreen::Borderless(monitor), Fullscreen::Exclusive),
+            ),
+        };
+        window.set_fullscreen(fullscreen);
+    }
+}
+
+/// The lowest window opacity; below it the window is hard to find again.
+pub const MIN_OPACITY: f32 = 0.1;
+
+/// Window behaviour for overlaying PoB on the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowStyle {
+    pub always_on_top: bool,
+    pub opacity: f32,
+    pub click_through: bool,
+}
+
+impl Default for WindowStyle {
+    fn default() -> Self {
+        Self {
+            always_on_top: false,
+            opacity: 1.0,
+            click_through: false,
+        }
+    }
+}
+
+impl WindowStyle {
+    pub fn from_settings(settings: &WindowSettings) -> Self {
+        Self {
+            always_on_top: settings.always_on_top.unwrap_or(false),
+            opacity: settings.opacity.unwrap_or(1.0).clamp(MIN_OPACITY, 1.0),
+            click_through: settings.click_through.unwrap_or(false),
+        }
+    }
+
+    /// Applies the window level and hit testing; opacity is up to the
+    /// surface and the post pass.
+    pub fn apply(&self, window: &Window) {
+        window.set_window_level(if self.always_on_top {
+            WindowLevel::AlwaysOnTop
+        } else {
+            WindowLevel::Normal
+        });
+        if let Err(e) = window.set_cursor_hittest(!self.click_through) {
+            eprintln!("click-through: {}", e);
+        }
+    }
+}
+
+/// The GPU device and the window surface it presents to.
+pub struct GfxState {
+    pub surface: wgpu::Surface<'static>,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub config: wgpu::SurfaceConfiguration,
+    pub renderer: graphics::Renderer,
+    pub text_renderer: graphics::TextRenderer,
+    pub post: PostProcess,
+    /// Times the screen pass where the adapter has timestamp queries.
+    pub gpu_timer: Option<GpuTimer>,
+    /// How the compositor can blend the surface; the first is the default.
+    alpha_modes: Vec<wgpu::CompositeAlphaMode>,
+}
+
+impl GfxState {
+    /// With a `trace_dir`, the backend's validation is on and the device
+    /// records its API calls there for replaying.
+    pub fn new(window: Arc<Window>, trace_dir: Option<&Path>) -> Self {
+        let flags = match trace_dir {
+            Some(_) => wgpu::InstanceFlags::debugging(),
+            None => wgpu::InstanceFlags::from_build_config(),
+        };
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            flags,
+            ..Default::default()
+        });
+        println!("instance created");
+
+        let size = window.inner_size();
+        let surface = instance.create_surface(window).unwrap();
+        println!("surface created");
+
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: Some(&surface),
+            force_fallback_adapter: false,
+        }))
+        .expect("no adapter found");
+        println!("adapter: {}", adapter.get_info().name);
+
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                // Cached textures may be BC-compressed; the renderer decodes
+                // them itself when this is missing.
+                required_features: adapter.features()
+                    & (wgpu::Features::TEXTURE_COMPRESSION_BC | GpuTimer::FEATURES),
+                required_limits: wgpu::Limits::default(),
+            },
+            trace_dir,
+        ))
+        .expect("failed to create device");
+        println!("device created");
+        device.on_uncaptured_error(Box::new(|e| {
+            eprintln!("wgpu device error: {:?}", e);
+        }));
+
+        println!("screen size: {}x{}", size.width, size.height);
+        let caps = surface.get_capabilities(&adapter);
+        let format = caps
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .unwrap_or(caps.formats[0]);
+        println!("format: {:?}", format);
+
+        let config = wgpu::SurfaceConfiguration {
+            // Screenshots copy the presented frame back.
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | (caps.usages & wgpu::TextureUsages::COPY_SRC),
+            format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: caps.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(&device, &config);
+        let renderer = graphics::Renderer::new(&device, format, &queue);
+        let text_renderer = graphics::TextRenderer::new(&device, &queue, format);
+        let post = PostProcess::new(&device, format);
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        Self {
+            surface,
+            device,
+            queue,
+            config,
+            renderer,
+            text_renderer,
+            post,
+            gpu_timer,
+            alpha_modes: caps.alpha_modes,
+        }
+    }
+
+    /// Switches the surface between opaque and blended by the compositor.
+    /// Returns whether colours must then be premultiplied, or None when
+    /// the compositor can't blend it.
+    pub fn set_translucent(&mut self, on: bool) -> Option<bool> {
+        use wgpu::CompositeAlphaMode::{PostMultiplied, PreMultiplied};
+        let mode = match on {
+            true => [PreMultiplied, PostMultiplied]
+                .into_iter()
+                .find(|m| self.alpha_modes.contains(m))?,
+            false => self.alpha_modes[0],
+        };
+        if self.config.alpha_mode != mode {
+            self.config.alpha_mode = mode;
+            self.surface.configure(&self.device, &self.config);
+        }
+        Some(mode == PreMultiplied)
+    }
+
+    /// Rebuilds the pipelines from the shader sources in `dir`. A shader
+    /// that fails to compile is reported and the old pipeline kept.
+    pub fn reload_shaders(&mut self, dir: &Path) {
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).map_err(|e| e.to_string());
+        let main = read("shader.wgsl").and_then(|s| self.renderer.reload_shader(&self.device, &s));
+        let post = read("post.wgsl").and_then(|s| self.post.reload_shader(&self.device, &s));
+        for (name, result) in [("shader.wgsl", main), ("post.wgsl", post)] {
+            match result {
+                Ok(()) => println!("reloaded {}", name),
+                Err(e) => eprintln!("{}: {}", name, e),
+            }
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.config.width = width.max(1);
+        self.config.height = height.max(1);
+        self.surface.configure(&self.device, &self.config);
+    }
+}
+
+/// Opens `url` in the user's browser.
+pub fn open_url(url: &str) {
+    // Not `cmd /C start`: cmd splits the URL at `&` and runs the rest.
+    let mut cmd = if cfg!(windows) {
+        let mut c = std::process::Command::new("rundll32");
+        c.arg("url.dll,FileProtocolHandler");
+        c
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    cmd.arg(url).spawn().ok();
+}
+
+/// Shows a desktop notification from a background thread, since sending
+/// one can wait on the notification service.
+pub fn notify(title: &str, text: &str) {
+    let mut n = notify_rust::Notification::new();
+    n.appname("Path of Building").summary(title).body(text);
+    std::thread::spawn(move || {
+        if let Err(e) = n.show() {
+            eprintln!("notification: {}", e);
+        }
+    });
+}
+
+/// Whether there is a desktop to show a window or message box on; off
+/// Windows and macOS that takes an X11 or Wayland display.
+pub fn has_display() -> bool {
+    cfg!(any(windows, target_os = "macos"))
+        || ["DISPLAY", "WAYLAND_DISPLAY"]
+            .iter()
+            .any(|v| std::env::var_os(v).is_some_and(|d| !d.is_empty()))
+}
+
+/// Per-user data directory, created on first use. Each `--profile` gets
+/// its own directory under `profiles/`.
+pub fn user_path() -> PathBuf {
+    let mut path = dirs::data_dir().unwrap_or_default().join("PathOfBuilding");
+    if let Some(name) = profile::current() {
+        path = path.join("profiles").join(name);
+    }
+    std::fs::create_dir_all(&path).ok();
+    path
+}
+
+/// The OS clipboard's text; opening it fails where there is none.
+pub struct SystemClipboard(arboard::Clipboard);
+
+impl SystemClipboard {
+    pub fn open() -> Result<Self, String> {
+        match panic::catch_unwind(arboard::Clipboard::new) {
+            Ok(Ok(os)) => Ok(Self(os)),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("connecting to it panicked".into()),
+        }
+    }
+
+    pub fn set_text(&mut self, text: &str) -> Result<(), String> {
+        self.0.set_text(text).map_err(|e| e.to_string())
+    }
+
+    /// Empty when what is on the clipboard isn't text.
+    pub fn get_text(&mut self) -> Result<String, String> {
+        match self.0.get_text() {
+            Ok(text) => Ok(text),
+            Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear().ok();
+    }
+}
+
+/// How an HTTP client connects; the same for every request it sends.
+#[derive(Clone, Debug)]
+pub struct HttpOptions {
+    /// Redirects followed before giving up; 0 returns them as they are.
+    pub redirects: u32,
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub proxy: Option<String>,
+    pub verify_tls: bool,
+    /// PEM bundles trusted in addition to the system roots.
+    pub ca_bundles: Vec<PathBuf>,
+    /// Connect only over IPv4 (4) or IPv6 (6).
+    pub ip_version: Option<u8>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            redirects: 5,
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            verify_tls: true,
+            ca_bundles: Vec::new(),
+            ip_version: None,
+        }
+    }
+}
+
+/// Why a request got no response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HttpErrorKind {
+    BadUrl,
+    UnknownScheme,
+    Dns,
+    Connect,
+    TooManyRedirects,
+    Proxy,
+    TimedOut,
+    BadCaBundle,
+    Other,
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpError {
+    pub kind: HttpErrorKind,
+    pub message: String,
+}
+
+impl HttpError {
+    fn new(kind: HttpErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// A response, HTTP error statuses included, with the body still to read.
+pub struct HttpResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub version: String,
+    /// Where the response came from, after any redirects.
+    pub url: String,
+    /// In the order received, a name repeated for each of its values.
+    pub headers: Vec<(String, String)>,
+    pub body: Box<dyn Read + Send + Sync>,
+}
+
+impl HttpResponse {
+    /// The first value of header `name`.
+    pub fn header<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        self.all(name).next()
+    }
+
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// The network bindings' HTTP transport, backed by ureq with the system's
+/// TLS.
+pub struct HttpClient(ureq::Agent);
+
+impl HttpClient {
+    pub fn new(options: &HttpOptions) -> Result<Self, HttpError> {
+        use HttpErrorKind::*;
+        let mut builder = ureq::AgentBuilder::new().redirects(options.redirects);
+        if let Some(t) = options.timeout {
+            builder = builder.timeout(t);
+        }
+        if let Some(t) = options.connect_timeout {
+            builder = builder.timeout_connect(t);
+        }
+        if let Some(proxy) = &options.proxy {
+            let proxy =
+                ureq::Proxy::new(proxy).map_err(|e| HttpError::new(Proxy, e.to_string()))?;
+            builder = builder.proxy(proxy);
+        }
+        let mut tls = native_tls::TlsConnector::builder();
+        if !options.verify_tls {
+            tls.danger_accept_invalid_certs(true);
+        }
+        for path in &options.ca_bundles {
+            for cert in load_certificates(path)? {
+                tls.add_root_certificate(cert);
+            }
+        }
+        let tls = tls
+            .build()
+            .map_err(|e| HttpError::new(Connect, e.to_string()))?;
+        builder = builder.tls_connector(Arc::new(tls));
+        if let Some(version) = options.ip_version {
+            builder = builder.resolver(move |addr: &str| -> io::Result<Vec<SocketAddr>> {
+                Ok(addr
+                    .to_socket_addrs()?
+                    .filter(|a| a.is_ipv4() == (version == 4))
+                    .collect())
+            });
+        }
+        Ok(Self(builder.build()))
+    }
+
+    pub fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(String, String)],
+        body: Option<&[u8]>,
+    ) -> Result<HttpResponse, HttpError> {
+        let mut request = self.0.request(method, url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let result = match body {
+            Some(body) => request.send_bytes(body),
+            None => request.call(),
+        };
+        let response = match result {
+            Ok(r) | Err(ureq::Error::Status(_, r)) => r,
+            Err(ureq::Error::Transport(t)) => return Err(transport_error(&t)),
+        };
+        let mut headers = Vec::new();
+        for name in response.headers_names() {
+            for value in response.all(&name) {
+                headers.push((name.clone(), value.to_string()));
+            }
+        }
+        Ok(HttpResponse {
+            status: response.status(),
+            status_text: response.status_text().to_string(),
+            version: response.http_version().to_string(),
+            url: response.get_url().to_string(),
+            headers,
+            body: response.into_reader(),
+        })
+    }
+}
+
+fn transport_error(e: &ureq::Transport) -> HttpError {
+    use ureq::ErrorKind;
+    let kind = match e.kind() {
+        ErrorKind::InvalidUrl => HttpErrorKind::BadUrl,
+        ErrorKind::UnknownScheme => HttpErrorKind::UnknownScheme,
+        ErrorKind::Dns => HttpErrorKind::Dns,
+        ErrorKind::ConnectionFailed => HttpErrorKind::Connect,
+        ErrorKind::TooManyRedirects => HttpErrorKind::TooManyRedirects,
+        ErrorKind::InvalidProxyUrl | ErrorKind::ProxyConnect => HttpErrorKind::Proxy,
+        ErrorKind::Io if e.to_string().contains("timed out") => HttpErrorKind::TimedOut,
+        _ => HttpErrorKind::Other,
+    };
+    HttpError::new(kind, e.to_string())
+}
+
+/// Every certificate in a PEM bundle.
+fn load_certificates(path: &Path) -> Result<Vec<native_tls::Certificate>, HttpError> {
+    let bad = |e: String| {
+        HttpError::new(
+            HttpErrorKind::BadCaBundle,
+            format!("CA bundle {}: {}", path.display(), e),
+        )
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| bad(e.to_string()))?;
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut certs = Vec::new();
+    for block in text.split_inclusive(END) {
+        let Some(start) = block.find("-----BEGIN CERTIFICATE-----") else {
+            continue;
+        };
+        let cert = native_tls::Certificate::from_pem(&block.as_bytes()[start..])
+            .map_err(|e| bad(e.to_string()))?;
+        certs.push(cert);
+    }
+    if certs.is_empty() {
+        return Err(bad("no certificates found".into()));
+    }
+    Ok(certs)
+}
+
+/// The process's resident set size in bytes, where the OS says.
+#[cfg(target_os = "linux")]
+pub fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(windows)]
+pub fn resident_bytes() -> Option<u64> {
+    // PROCESS_MEMORY_COUNTERS; only the working set is read.
+    #[repr(C)]
+    #[derive(Default)]
+    struct Counters {
+        cb: u32,
+        page_faults: u32,
+        peak_working_set: usize,
+        working_set: usize,
+        quota: [usize; 4],
+        pagefile: usize,
+        peak_pagefile: usize,
+    }
+    unsafe extern "system" {
+        fn GetCurrentProcess() -> isize;
+        fn K32GetProcessMemoryInfo(process: isize, counters: *mut Counters, cb: u32) -> i32;
+    }
+    let mut counters = Counters {
+        cb: std::mem::size_of::<Counters>() as u32,
+        ..Default::default()
+    };
+    let ok = unsafe { K32GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, counters.cb) };
+    (ok != 0).then_some(counters.working_set as u64)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn resident_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_modes_round_trip_by_name() {
+        for mode in [
+            WindowMode::Windowed,
+            WindowMode::Borderless,
+            WindowMode::Fullscreen,
+        ] {
+            assert_eq!(WindowMode::parse(mode.name()), Some(mode));
+        }
+        assert_eq!(
+            WindowMode::parse("Borderless"),
+            Some(WindowMode::Borderless)
+        );
+        assert_eq!(WindowMode::parse("maximized"), None);
+    }
+
+    #[test]
+    fn response_headers_are_found_in_any_case() {
+        let response = HttpResponse {
+            status: 200,
+            status_text: "OK".into(),
+            version: "HTTP/1.1".into(),
+            url: "https://example.com/".into(),
+            headers: vec![
+                ("set-cookie".into(), "a=1".into()),
+                ("ETag".into(), "\"x\"".into()),
+                ("Set-Cookie".into(), "b=2".into()),
+            ],
+            body: Box::new(io::empty()),
+        };
+        assert_eq!(response.header("etag"), Some("\"x\""));
+        assert_eq!(
+            response.all("SET-COOKIE").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(response.header("Last-Modified"), None);
+    }
+
+    #[test]
+    fn monitors_are_found_by_name_or_number_from_one() {
+        let names = ["DELL U2720Q".to_string(), "LG HDR 4K".to_string()];
+        assert_eq!(find_monitor(&names, "lg hdr 4k"), Some(1));
+        assert_eq!(find_monitor(&names, "1"), Some(0));
+        assert_eq!(find_monitor(&names, "3"), None);
+        assert_eq!(find_monitor(&names, "0"), None);
+    }
+}
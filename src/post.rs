@@ -0,0 +1,423 @@
+use crate::graphics::try_pipeline;
+use crate::settings::DisplaySettings;
+
+/// Colour-vision-deficiency corrections: colours the viewer can't tell
+/// apart are shifted towards ones they can.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorFilter {
+    #[default]
+    None,
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl ColorFilter {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "none" | "" => Self::None,
+            "protanopia" => Self::Protanopia,
+            "deuteranopia" => Self::Deuteranopia,
+            "tritanopia" => Self::Tritanopia,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Protanopia => "protanopia",
+            Self::Deuteranopia => "deuteranopia",
+            Self::Tritanopia => "tritanopia",
+        }
+    }
+
+    /// How the deficiency sees linear RGB (Machado et al. 2009, full
+    /// severity).
+    fn simulation(self) -> Option<[[f32; 3]; 3]> {
+        Some(match self {
+            Self::None => return None,
+            Self::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Self::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Self::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        })
+    }
+
+    /// The correction as one matrix: the colour plus what the viewer
+    /// loses of it, moved into the channels they still see.
+    pub fn matrix(self) -> [[f32; 3]; 3] {
+        const SHIFT: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+        let identity = |r: usize, c: usize| if r == c { 1.0 } else { 0.0 };
+        let Some(sim) = self.simulation() else {
+            return std::array::from_fn(|r| std::array::from_fn(|c| identity(r, c)));
+        };
+        std::array::from_fn(|r| {
+            std::array::from_fn(|c| {
+                identity(r, c)
+                    + (0..3)
+                        .map(|k| SHIFT[r][k] * (identity(k, c) - sim[k][c]))
+                        .sum::<f32>()
+            })
+        })
+    }
+}
+
+/// The screen-space pass applied after everything else is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostParams {
+    pub gamma: f32,
+    pub brightness: f32,
+    pub filter: ColorFilter,
+    /// Window opacity, for surfaces the compositor blends.
+    pub opacity: f32,
+    /// The surface takes premultiplied colours.
+    pub premultiplied: bool,
+}
+
+impl Default for PostParams {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 1.0,
+            filter: ColorFilter::None,
+            opacity: 1.0,
+            premultiplied: false,
+        }
+    }
+}
+
+impl PostParams {
+    /// The `[display]` settings; an unknown colour filter is reported and
+    /// left off.
+    pub fn from_settings(settings: &DisplaySettings) -> Self {
+        let filter = settings
+            .color_filter
+            .as_deref()
+            .map_or(ColorFilter::None, |name| {
+                ColorFilter::parse(name).unwrap_or_else(|| {
+                    eprintln!("runtime.toml: unknown color_filter {:?}", name);
+                    ColorFilter::None
+                })
+            });
+        Self {
+            gamma: settings.gamma.unwrap_or(1.0),
+            brightness: settings.brightness.unwrap_or(1.0),
+            filter,
+            ..Self::default()
+        }
+        .clamped()
+    }
+
+    /// Limits gamma and brightness to values that keep the screen legible.
+    pub fn clamped(self) -> Self {
+        Self {
+            gamma: self.gamma.clamp(0.2, 5.0),
+            brightness: self.brightness.clamp(0.1, 4.0),
+            ..self
+        }
+    }
+
+    /// The pass changes nothing, so it can be skipped.
+    pub fn is_identity(&self) -> bool {
+        Self {
+            premultiplied: false,
+            ..*self
+        } == Self::default()
+    }
+
+    fn uniform(&self) -> PostUniform {
+        let m = self.filter.matrix();
+        let row = |r: [f32; 3]| [r[0], r[1], r[2], 0.0];
+        PostUniform {
+            rows: [row(m[0]), row(m[1]), row(m[2])],
+            params: [
+                self.gamma,
+                self.brightness,
+                self.opacity,
+                if self.premultiplied {
+                    self.opacity
+                } else {
+                    1.0
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    rows: [[f32; 4]; 3],
+    params: [f32; 4],
+}
+
+/// The offscreen copy of a frame the post pass reads.
+struct Scene {
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+/// Renders frames through `PostParams`: the frame is drawn into an
+/// offscreen texture, then copied to the screen by a fullscreen shader.
+pub struct PostProcess {
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    scene: Option<Scene>,
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("post"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("post.wgsl").into()),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::pipeline(device, &pipeline_layout, &shader, format);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("post"),
+            size: std::mem::size_of::<PostUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            pipeline,
+            pipeline_layout,
+            layout,
+            sampler,
+            uniform,
+            format,
+            scene: None,
+        }
+    }
+
+    fn pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("post"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// Swaps in a pipeline built from `source`, keeping the current one if
+    /// it doesn't compile.
+    pub fn reload_shader(&mut self, device: &wgpu::Device, source: &str) -> Result<(), String> {
+        self.pipeline = try_pipeline(device, source, |shader| {
+            Self::pipeline(device, &self.pipeline_layout, shader, self.format)
+        })?;
+        Ok(())
+    }
+
+    /// Where to draw a frame of `size` that goes through the pass.
+    pub fn scene_view(&mut self, device: &wgpu::Device, size: (u32, u32)) -> &wgpu::TextureView {
+        if self.scene.as_ref().is_none_or(|s| s.size != size) {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("post scene"),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&Default::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.uniform.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+            self.scene = Some(Scene {
+                view,
+                bind_group,
+                size,
+            });
+        }
+        &self.scene.as_ref().unwrap().view
+    }
+
+    /// Draws the scene, filtered through `params`, over `output`.
+    pub fn apply(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        params: &PostParams,
+        output: &wgpu::TextureView,
+    ) {
+        let Some(scene) = &self.scene else {
+            return;
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::cast_slice(&[params.uniform()]));
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("post"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &scene.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_keep_greys_and_separate_red_from_green() {
+        let apply = |m: [[f32; 3]; 3], c: [f32; 3]| -> [f32; 3] {
+            std::array::from_fn(|r| (0..3).map(|k| m[r][k] * c[k]).sum())
+        };
+        assert_eq!(
+            apply(ColorFilter::None.matrix(), [0.2, 0.4, 0.6]),
+            [0.2, 0.4, 0.6]
+        );
+        for filter in [
+            ColorFilter::Protanopia,
+            ColorFilter::Deuteranopia,
+            ColorFilter::Tritanopia,
+        ] {
+            let grey = apply(filter.matrix(), [0.5; 3]);
+            assert!(
+                grey.iter().all(|c| (c - 0.5).abs() < 0.01),
+                "{:?} {:?}",
+                filter,
+                grey
+            );
+            assert_eq!(ColorFilter::parse(filter.name()), Some(filter));
+        }
+        // Red loses its red to a protanope; the correction moves it to blue.
+        let red = apply(ColorFilter::Protanopia.matrix(), [1.0, 0.0, 0.0]);
+        assert!(red[2] > 0.5, "{:?}", red);
+
+        let params = PostParams::from_settings(&DisplaySettings {
+            gamma: Some(9.0),
+            color_filter: Some("Deuteranopia".into()),
+            ..DisplaySettings::default()
+        });
+        assert_eq!(
+            (params.gamma, params.filter),
+            (5.0, ColorFilter::Deuteranopia)
+        );
+        assert!(!params.is_identity());
+        assert!(PostParams::default().is_identity());
+
+        // A see-through window scales alpha, and colour too when the
+        // compositor expects premultiplied colours.
+        let faded = PostParams {
+            opacity: 0.5,
+            premultiplied: true,
+            ..PostParams::default()
+        };
+        assert!(!faded.is_identity());
+        assert_eq!(faded.uniform().params, [1.0, 1.0, 0.5, 0.5]);
+        assert!(
+            PostParams {
+                premultiplied: true,
+                ..PostParams::default()
+            }
+            .is_identity()
+        );
+    }
+}
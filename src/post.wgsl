@@ -0,0 +1,36 @@
+struct Post {
+    // Rows of the colour matrix.
+    m0: vec4<f32>,
+    m1: vec4<f32>,
+    m2: vec4<f32>,
+    // gamma, brightness, alpha and the factor colours get for it
+    params: vec4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> post: Post;
+@group(0) @binding(1) var t_scene: texture_2d<f32>;
+@group(0) @binding(2) var s_scene: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let c = textureSample(t_scene, s_scene, in.uv);
+    let rgb = vec3<f32>(dot(post.m0.xyz, c.rgb), dot(post.m1.xyz, c.rgb), dot(post.m2.xyz, c.rgb));
+    let lit = max(rgb * post.params.y, vec3<f32>(0.0));
+    let out = pow(lit, vec3<f32>(1.0 / post.params.x));
+    return vec4<f32>(out * post.params.w, c.a * post.params.z);
+}
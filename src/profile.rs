@@ -0,0 +1,69 @@
+use std::{
+    fs::{File, TryLockError},
+    path::Path,
+    sync::OnceLock,
+};
+
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Selects the profile the user path is derived from. Only the first call
+/// takes effect; it has to happen before anything reads the user path.
+pub fn set(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid profile name {:?}: use letters, digits, - and _",
+            name
+        ));
+    }
+    PROFILE.set(name.to_string()).ok();
+    Ok(())
+}
+
+/// The `--profile` name, or None for the default profile.
+pub fn current() -> Option<&'static str> {
+    PROFILE.get().map(String::as_str)
+}
+
+/// An exclusive lock on a user path, held for as long as the instance
+/// runs. The OS drops it when the process exits, however it exits.
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    pub fn acquire(dir: &Path) -> Result<Self, String> {
+        let path = dir.join("instance.lock");
+        let file = File::create(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(format!(
+                "{} is in use by another instance; start this one with --profile NAME",
+                dir.display()
+            )),
+            Err(TryLockError::Error(e)) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_lock_on_a_dir_fails() {
+        let dir = std::env::temp_dir().join(format!("pob-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lock = InstanceLock::acquire(&dir).unwrap();
+        assert!(InstanceLock::acquire(&dir).is_err());
+        drop(lock);
+        assert!(InstanceLock::acquire(&dir).is_ok());
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(set("../escape").is_err());
+        assert!(set("").is_err());
+    }
+}
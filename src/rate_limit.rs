@@ -0,0 +1,250 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// One window of a GGG rate-limit rule: at most `hits` requests per
+/// `period`, with `current` as last reported by the server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleWindow {
+    pub rule: String,
+    pub hits: u32,
+    pub period: Duration,
+    pub current: u32,
+    observed: Instant,
+}
+
+#[derive(Default)]
+struct HostState {
+    windows: Vec<RuleWindow>,
+    sent: VecDeque<Instant>,
+    restricted_until: Option<Instant>,
+}
+
+/// Quota for one host as Lua sees it.
+#[derive(Debug, PartialEq)]
+pub struct HostQuota {
+    pub windows: Vec<RuleWindow>,
+    /// Requests that can go out now without exceeding any known window.
+    pub remaining: Option<u32>,
+    pub retry_after: Duration,
+}
+
+/// Per-host limiter that learns each host's policy from the
+/// `X-Rate-Limit-*` headers GGG's APIs send, and holds requests back
+/// instead of letting the server time the user out.
+pub struct RateLimiter {
+    hosts: HashMap<String, HostState>,
+    /// Gap enforced between requests to the same host.
+    pub min_interval: Duration,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            hosts: HashMap::new(),
+            min_interval,
+        }
+    }
+
+    /// How long a request to `host` has to wait at `now`.
+    pub fn delay(&mut self, host: &str, now: Instant) -> Duration {
+        let Some(state) = self.hosts.get_mut(host) else {
+            return Duration::ZERO;
+        };
+        let longest = state.windows.iter().map(|w| w.period).max();
+        while let Some(&t) = state.sent.front() {
+            if longest.is_some_and(|p| now.duration_since(t) < p)
+                || now.duration_since(t) < self.min_interval
+            {
+                break;
+            }
+            state.sent.pop_front();
+        }
+
+        let mut wait = state
+            .restricted_until
+            .map_or(Duration::ZERO, |t| t.saturating_duration_since(now));
+        if let Some(&last) = state.sent.back() {
+            wait = wait.max((last + self.min_interval).saturating_duration_since(now));
+        }
+        // A 0-hit rule can't be met by waiting; the server's 429 says when.
+        for w in state.windows.iter().filter(|w| w.hits > 0) {
+            let in_window: Vec<Instant> = state
+                .sent
+                .iter()
+                .copied()
+                .filter(|&t| now.duration_since(t) < w.period)
+                .collect();
+            // The server also counts requests from other clients (browser
+            // trade site), so trust its count while it's current.
+            let server = if now.duration_since(w.observed) < w.period {
+                w.current
+            } else {
+                0
+            };
+            let oldest = in_window
+                .len()
+                .checked_sub(w.hits as usize)
+                .map(|i| in_window[i]);
+            if let Some(oldest) = oldest {
+                wait = wait.max((oldest + w.period).saturating_duration_since(now));
+            } else if server >= w.hits {
+                wait = wait.max((w.observed + w.period).saturating_duration_since(now));
+            }
+        }
+        wait
+    }
+
+    /// Notes a request to `host` going out at `now`.
+    pub fn record(&mut self, host: &str, now: Instant) {
+        self.hosts
+            .entry(host.to_string())
+            .or_default()
+            .sent
+            .push_back(now);
+    }
+
+    /// Updates the host's policy from a response. `header` looks up a
+    /// response header by name.
+    pub fn observe(
+        &mut self,
+        host: &str,
+        status: u16,
+        header: impl Fn(&str) -> Option<String>,
+        now: Instant,
+    ) {
+        let state = self.hosts.entry(host.to_string()).or_default();
+        if let Some(rules) = header("X-Rate-Limit-Rules") {
+            let mut windows = Vec::new();
+            let mut restricted = Duration::ZERO;
+            for rule in rules.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+                let limits = header(&format!("X-Rate-Limit-{}", rule)).unwrap_or_default();
+                let states = header(&format!("X-Rate-Limit-{}-State", rule)).unwrap_or_default();
+                let states: Vec<[u64; 3]> = states.split(',').filter_map(triple).collect();
+                for limit in limits.split(',').filter_map(triple) {
+                    let [hits, period, _] = limit;
+                    let current = states.iter().find(|s| s[1] == period);
+                    if let Some(s) = current {
+                        restricted = restricted.max(Duration::from_secs(s[2]));
+                    }
+                    windows.push(RuleWindow {
+                        rule: rule.to_string(),
+                        hits: hits as u32,
+                        period: Duration::from_secs(period),
+                        current: current.map_or(0, |s| s[0] as u32),
+                        observed: now,
+                    });
+                }
+            }
+            state.windows = windows;
+            if !restricted.is_zero() {
+                state.restricted_until = Some(now + restricted);
+            }
+        }
+        if status == 429 {
+            let retry = header("Retry-After")
+                .and_then(|v| v.trim().parse().ok())
+                .map_or(Duration::from_secs(60), Duration::from_secs);
+            let until = now + retry;
+            if state.restricted_until.is_none_or(|t| t < until) {
+                state.restricted_until = Some(until);
+            }
+        }
+    }
+
+    pub fn quota(&mut self, host: &str, now: Instant) -> Option<HostQuota> {
+        let retry_after = self.delay(host, now);
+        let state = self.hosts.get(host)?;
+        let remaining = state
+            .windows
+            .iter()
+            .map(|w| {
+                let local = state
+                    .sent
+                    .iter()
+                    .filter(|&&t| now.duration_since(t) < w.period)
+                    .count() as u32;
+                let server = if now.duration_since(w.observed) < w.period {
+                    w.current
+                } else {
+                    0
+                };
+                w.hits.saturating_sub(local.max(server))
+            })
+            .min();
+        Some(HostQuota {
+            windows: state.windows.clone(),
+            remaining,
+            retry_after,
+        })
+    }
+}
+
+fn triple(s: &str) -> Option<[u64; 3]> {
+    let mut parts = s.trim().split(':').map(|p| p.parse::<u64>());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(a)), Some(Ok(b)), Some(Ok(c))) => Some([a, b, c]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learns_policy_from_headers_and_waits() {
+        let mut limiter = RateLimiter::default();
+        let t0 = Instant::now();
+        let host = "www.pathofexile.com";
+        assert_eq!(limiter.delay(host, t0), Duration::ZERO);
+
+        let headers = |name: &str| match name {
+            "X-Rate-Limit-Rules" => Some("Ip".to_string()),
+            "X-Rate-Limit-Ip" => Some("2:10:60,30:300:300".to_string()),
+            "X-Rate-Limit-Ip-State" => Some("1:10:0,1:300:0".to_string()),
+            _ => None,
+        };
+        limiter.record(host, t0);
+        limiter.observe(host, 200, headers, t0);
+        let quota = limiter.quota(host, t0).unwrap();
+        assert_eq!(quota.windows.len(), 2);
+        assert_eq!(quota.remaining, Some(1));
+
+        let t1 = t0 + Duration::from_secs(1);
+        assert_eq!(limiter.delay(host, t1), Duration::ZERO);
+        limiter.record(host, t1);
+        // Two hits inside the 10s window: wait until the first one expires.
+        assert_eq!(
+            limiter.delay(host, t1 + Duration::from_secs(2)),
+            Duration::from_secs(7)
+        );
+
+        limiter.observe(
+            host,
+            429,
+            |name| (name == "Retry-After").then(|| "120".to_string()),
+            t1,
+        );
+        assert_eq!(limiter.delay(host, t1), Duration::from_secs(120));
+
+        // A rule allowing no hits is left to the server's 429s.
+        let zero = |name: &str| match name {
+            "X-Rate-Limit-Rules" => Some("Ip".to_string()),
+            "X-Rate-Limit-Ip" => Some("0:10:60".to_string()),
+            _ => None,
+        };
+        let other = "api.pathofexile.com";
+        limiter.observe(other, 200, zero, t0);
+        assert_eq!(limiter.delay(other, t0), Duration::ZERO);
+        limiter.record(other, t0);
+        assert_eq!(limiter.delay(other, t1), Duration::ZERO);
+    }
+}
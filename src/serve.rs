@@ -0,0 +1,309 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::{Value, json};
+
+use crate::build_stats;
+use crate::headless::{JobError, StatePool};
+use crate::layout::Layout;
+
+/// Where `--serve` listens unless `--listen` says otherwise.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8490";
+
+/// Headless states kept running by default; each holds all of PoB's data.
+pub const DEFAULT_WORKERS: usize = 2;
+
+/// Largest request body, well above any build code.
+const MAX_BODY: usize = 1 << 20;
+
+/// Longest request or header line, and most header lines.
+const MAX_LINE: usize = 8 << 10;
+const MAX_HEADERS: usize = 64;
+
+/// How long a client gets to send its whole request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections served at once; more are turned away with 503.
+const MAX_CLIENTS: usize = 64;
+
+/// Serves build stats over HTTP on `addr`:
+///
+/// - `POST /build` with a build code or `pob://` link as the body answers
+///   `{"stats": {...}}`, or `{"error": "..."}` with 422, or 504 once
+///   `timeout` passes.
+/// - `GET /health` answers `{"ok": true}`.
+pub fn run(layout: &Layout, addr: &str, workers: usize, timeout: Duration) -> io::Result<()> {
+    let pool = Arc::new(StatePool::spawn(layout, workers)?);
+    let listener = TcpListener::bind(addr)?;
+    println!("serving on http://{}", listener.local_addr()?);
+    let clients = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("serve: {}", e);
+                continue;
+            }
+        };
+        if clients.fetch_add(1, Ordering::AcqRel) >= MAX_CLIENTS {
+            clients.fetch_sub(1, Ordering::AcqRel);
+            write_response(&mut stream, 503, &json!({ "error": reason(503) })).ok();
+            continue;
+        }
+        let (pool, active) = (pool.clone(), clients.clone());
+        let spawned = std::thread::Builder::new()
+            .name("serve client".into())
+            .spawn(move || {
+                let reader = BufReader::new(DeadlineReader {
+                    stream: &stream,
+                    deadline: Instant::now() + READ_TIMEOUT,
+                });
+                if let Err(e) = handle(reader, &stream, &pool, timeout) {
+                    eprintln!("serve: {}", e);
+                }
+                active.fetch_sub(1, Ordering::AcqRel);
+            });
+        if let Err(e) = spawned {
+            clients.fetch_sub(1, Ordering::AcqRel);
+            eprintln!("serve: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Reads `stream` until `deadline` however the client paces its bytes,
+/// so a slow one can't hold its slot for longer.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        match (&mut &*self.stream).read(buf) {
+            // Unix reports a read timeout as WouldBlock.
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            read => read,
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Answers one request; every connection closes after its response.
+fn handle(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    pool: &StatePool,
+    timeout: Duration,
+) -> io::Result<()> {
+    let (status, body) = match read_request(&mut reader)? {
+        Ok(request) => respond(&request, pool, timeout),
+        Err(status) => (status, json!({ "error": reason(status) })),
+    };
+    write_response(&mut writer, status, &body)
+}
+
+fn write_response(writer: &mut impl Write, status: u16, body: &Value) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+/// The request, or the status to turn it away with.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Result<Request, u16>> {
+    let mut line = String::new();
+    if !read_line(reader, &mut line)? {
+        return Ok(Err(431));
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(Err(400));
+    };
+    let method = method.to_string();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let mut length = None;
+    for n in 0.. {
+        line.clear();
+        if n == MAX_HEADERS || !read_line(reader, &mut line)? {
+            return Ok(Err(431));
+        }
+        if line.is_empty() {
+            return Ok(Err(400));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            match value.trim().parse::<usize>() {
+                Ok(n) => length = Some(n),
+                Err(_) => return Ok(Err(400)),
+            }
+        }
+    }
+    let length = match length {
+        Some(n) if n > MAX_BODY => return Ok(Err(413)),
+        Some(n) => n,
+        None if method == "POST" => return Ok(Err(411)),
+        None => 0,
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Ok(Request { method, path, body }))
+}
+
+/// Reads one line of at most `MAX_LINE` bytes; false if it is longer.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<bool> {
+    let n = io::Read::take(reader, MAX_LINE as u64).read_line(line)?;
+    Ok(n < MAX_LINE || line.ends_with('\n'))
+}
+
+fn respond(request: &Request, pool: &StatePool, timeout: Duration) -> (u16, Value) {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => (200, json!({ "ok": true })),
+        ("POST", "/build") => {
+            let code = String::from_utf8_lossy(&request.body);
+            let code = code.trim();
+            if !is_build_code(code) {
+                return (
+                    400,
+                    json!({ "error": "the body must be a build code or pob:// link" }),
+                );
+            }
+            match pool.summarize(code, timeout) {
+                Ok(stats) => (200, json!({ "stats": build_stats::summary_json(&stats) })),
+                Err(JobError::TimedOut) => (504, json!({ "error": "timed out" })),
+                Err(JobError::Failed(e)) => (422, json!({ "error": e })),
+            }
+        }
+        (_, "/health" | "/build") => (405, json!({ "error": reason(405) })),
+        _ => (404, json!({ "error": reason(404) })),
+    }
+}
+
+/// Build codes and links only: a server must not open files by path.
+fn is_build_code(text: &str) -> bool {
+    text.starts_with("pob://")
+        || (!text.is_empty()
+            && text
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+/=_-".contains(c) || c.is_whitespace()))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_requests_and_reports_failures_as_json() {
+        // No scripts here, so every state fails to start.
+        let dir = std::env::temp_dir().join(format!("pob-serve-{}", std::process::id()));
+        let pool = StatePool::spawn(&Layout::standard(&dir), 1).unwrap();
+        let ask = |request: &str| {
+            let mut out = Vec::new();
+            handle(request.as_bytes(), &mut out, &pool, Duration::from_secs(5)).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            let (head, body) = out.split_once("\r\n\r\n").unwrap();
+            let status: u16 = head[9..12].parse().unwrap();
+            (status, serde_json::from_str::<Value>(body).unwrap())
+        };
+
+        assert_eq!(
+            ask("GET /health HTTP/1.1\r\n\r\n"),
+            (200, json!({ "ok": true }))
+        );
+        assert_eq!(ask("GET /nope HTTP/1.1\r\n\r\n").0, 404);
+        assert_eq!(ask("GET /build HTTP/1.1\r\n\r\n").0, 405);
+        assert_eq!(ask("POST /build HTTP/1.1\r\n\r\n").0, 411);
+        let post = |body: &str| {
+            format!(
+                "POST /build?x=1 HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        };
+        assert_eq!(ask(&post("/etc/Build.xml")).0, 400);
+        let long = format!("GET /health HTTP/1.1\r\nX: {}", "a".repeat(MAX_LINE));
+        assert_eq!(ask(&long).0, 431);
+        let many = format!(
+            "GET /health HTTP/1.1\r\n{}\r\n",
+            "X: a\r\n".repeat(MAX_HEADERS)
+        );
+        assert_eq!(ask(&many).0, 431);
+        let (status, body) = ask(&post("eNrtW1tz2zYS"));
+        assert_eq!(status, 422);
+        assert!(body["error"].is_string());
+    }
+
+    #[test]
+    fn slow_clients_run_out_of_time_for_the_whole_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut client = TcpStream::connect(addr).unwrap();
+            // One byte at a time, each well within a per-read timeout.
+            for b in b"GET /health HTTP/1.1\r\n".iter().cycle() {
+                if client.write_all(&[*b]).is_err() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let started = Instant::now();
+        let mut reader = BufReader::new(DeadlineReader {
+            stream: &stream,
+            deadline: started + Duration::from_millis(300),
+        });
+        let err = loop {
+            match read_request(&mut reader) {
+                Err(e) => break e,
+                Ok(_) => continue,
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
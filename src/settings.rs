@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::platform::user_path;
+
+/// Host settings read from `runtime.toml` in the user path. Every field is
+/// optional; anything missing falls back to the environment or built-in
+/// defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub network: NetworkSettings,
+    pub backup: BackupSettings,
+    pub textures: TextureSettings,
+    pub display: DisplaySettings,
+    pub window: WindowSettings,
+    pub tray: TraySettings,
+    pub sound: SoundSettings,
+    pub discord: DiscordSettings,
+    pub capture: CaptureSettings,
+    pub clipboard: ClipboardSettings,
+    pub text: TextSettings,
+    pub theme: ThemeSettings,
+    pub lua: LuaSettings,
+    pub ipc: IpcSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// Proxy URL for all requests, e.g. `http://proxy.corp:3128`.
+    pub proxy: Option<String>,
+    /// Hosts (or `.domain` suffixes) that bypass the proxy.
+    pub no_proxy: Option<Vec<String>>,
+    /// Extra PEM bundle trusted in addition to the system roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// Set to false to skip certificate verification entirely.
+    pub verify_tls: Option<bool>,
+    pub rate_limit: RateLimitSettings,
+    pub cache: CacheSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    pub enabled: Option<bool>,
+    /// Requests that would have to wait longer than this fail instead.
+    pub max_wait_secs: Option<u64>,
+    /// Minimum gap between requests to one host.
+    pub min_interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    pub enabled: Option<bool>,
+    pub max_entries: Option<usize>,
+    /// Responses larger than this aren't cached.
+    pub max_entry_bytes: Option<usize>,
+    /// What all cached responses may add up to; the oldest go first.
+    pub max_total_bytes: Option<usize>,
+    /// Lifetime of responses without Cache-Control.
+    pub default_ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    pub enabled: Option<bool>,
+    /// Minutes between snapshots of the open build.
+    pub interval_mins: Option<u64>,
+    /// Snapshots kept before the oldest are deleted.
+    pub keep: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TextureSettings {
+    /// Keep decoded images on disk between launches.
+    pub cache: Option<bool>,
+    /// Store cached images BC3-compressed: a quarter of the memory, at
+    /// some loss of quality.
+    pub compress: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    /// Above 1 brightens mid tones, below 1 darkens them.
+    pub gamma: Option<f32>,
+    /// Multiplies every colour.
+    pub brightness: Option<f32>,
+    /// `protanopia`, `deuteranopia` or `tritanopia` corrects colours for
+    /// that colour vision deficiency.
+    pub color_filter: Option<String>,
+    /// Enlarges (above 1) or shrinks the whole UI, independent of the
+    /// OS scale factor. Ctrl+= and Ctrl+- change it while running and
+    /// save the new value here.
+    pub ui_zoom: Option<f32>,
+    /// PoB's display scaling option, as a percentage; PoB sets it and it
+    /// replaces `ui_zoom` at launch.
+    pub dpi_override_percent: Option<u32>,
+    /// Frames per second while another window has focus, to save power
+    /// in the background, from 0.1 to 240; unset keeps the display rate.
+    pub unfocused_fps: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    /// Keep the window above others, e.g. the game.
+    pub always_on_top: Option<bool>,
+    /// Below 1 lets what is behind the window show through, where the
+    /// compositor supports it.
+    pub opacity: Option<f32>,
+    /// Pass mouse input through to the window below. Ctrl+Shift+O turns
+    /// it off again.
+    pub click_through: Option<bool>,
+    /// The monitor to open on, by name as GetMonitors lists it or by
+    /// number from 1.
+    pub monitor: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TraySettings {
+    /// Show a tray icon listing recent builds; needs the `tray` feature.
+    pub enabled: Option<bool>,
+    /// Closing the window hides it to the tray instead of quitting.
+    pub minimize_on_close: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SoundSettings {
+    /// Silences PlaySound; needs the `sound` feature to play at all.
+    pub muted: Option<bool>,
+    /// Master volume from 0 to 1, applied on top of each call's volume.
+    pub volume: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DiscordSettings {
+    /// Show SetRichPresence activity in Discord; off unless set.
+    pub enabled: Option<bool>,
+    /// The Discord application the activity is shown under.
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CaptureSettings {
+    /// Runs on regions from CaptureScreenRegion with the PNG on stdin, its
+    /// output passed along as the text, e.g. `["tesseract", "stdin", "stdout"]`.
+    pub ocr_command: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ClipboardSettings {
+    /// When copied items fire OnClipboardItem: "off" (the default),
+    /// "focused", or "global" to also catch Ctrl+C in the game.
+    pub watch: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TextSettings {
+    /// Colours for the `^0`-`^9` escapes in place of SimpleGraphic's, as
+    /// `RRGGBB` by digit, e.g. `{ 8 = "B0B0B0" }`.
+    pub digit_colors: Option<HashMap<String, String>>,
+    /// Named escapes like `^ORANGE`, as `RRGGBB` by name; scripts can add
+    /// more with RegisterColorEscape.
+    pub named_colors: Option<HashMap<String, String>>,
+    /// "basic" (the default) or "advanced", which joins and reorders
+    /// letters for scripts like Arabic and Hebrew at some cost per string.
+    pub shaping: Option<String>,
+    /// Start every font from SimpleGraphic's metrics (whole pixel sizes,
+    /// lines the size apart), which PoB's layouts were made for.
+    pub compat_metrics: Option<bool>,
+    /// Metrics by font name ("VAR", "VAR BOLD", "FIXED"), e.g.
+    /// `[text.fonts.VAR] width_scale = 1.05` where labels get cut off.
+    pub fonts: Option<HashMap<String, FontMetricSettings>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FontMetricSettings {
+    /// Line spacing as a multiple of the size, 1.2 by default.
+    pub line_height: Option<f32>,
+    /// What DrawStringWidth and the caret functions scale widths by.
+    pub width_scale: Option<f32>,
+    /// Round sizes to whole pixels.
+    pub round_size: Option<bool>,
+    /// Measure with each glyph's advance rounded to whole pixels, as
+    /// SimpleGraphic's hinted fonts advance.
+    pub hinting: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeSettings {
+    /// What shows behind the UI, as `RRGGBB`; SetClearColor overrides it.
+    pub clear_color: Option<String>,
+    /// An image drawn behind the UI, cropped to fill the window, relative
+    /// to the user path.
+    pub background: Option<PathBuf>,
+    /// How dark the window's edges get, 0 (the default) to 1.
+    pub vignette: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LuaSettings {
+    /// What a callback error does: "ignore", "log", "once" (log each
+    /// distinct error once), "prompt" or "crash". Unset prompts, except in
+    /// recorded and scripted runs, which crash.
+    pub on_error: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct IpcSettings {
+    /// Lets local tools drive this instance through `ipc.sock` in the user
+    /// path (on Windows, a named pipe only this user can open, its name
+    /// written to `ipc.pipe`).
+    pub enabled: Option<bool>,
+}
+
+impl Settings {
+    pub fn path() -> PathBuf {
+        user_path().join("runtime.toml")
+    }
+
+    /// Loads the settings file; a missing file is the defaults, a broken one
+    /// is reported and ignored.
+    pub fn load() -> Self {
+        let path = Self::path();
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_partial_files() {
+        let s = Settings::parse(
+            "[network]\nproxy = \"http://proxy:3128\"\nno_proxy = [\"localhost\", \".corp\"]\n",
+        )
+        .unwrap();
+        assert_eq!(s.network.proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(s.network.no_proxy.unwrap().len(), 2);
+        assert_eq!(s.network.verify_tls, None);
+        assert!(Settings::parse("").unwrap().network.proxy.is_none());
+        let s = Settings::parse("[network.cache]\nenabled = false\n").unwrap();
+        assert_eq!(s.network.cache.enabled, Some(false));
+    }
+}
@@ -33,5 +33,7 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.uv) * in.color;
+    // Textures are premultiplied; the draw colour is straight.
+    let color = vec4<f32>(in.color.rgb * in.color.a, in.color.a);
+    return textureSample(t_diffuse, s_diffuse, in.uv) * color;
 }
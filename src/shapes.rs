@@ -0,0 +1,189 @@
+use std::f32::consts::TAU;
+
+use crate::graphics::Vertex;
+
+/// Width of the alpha ramp along shape edges, in pixels.
+const FRINGE: f32 = 1.0;
+
+/// Maximum length of one circle or arc segment, in pixels.
+const SEGMENT_LEN: f32 = 3.0;
+
+fn vertex(p: [f32; 2], color: [f32; 4]) -> Vertex {
+    Vertex {
+        position: p,
+        uv: [0.0, 0.0],
+        color,
+    }
+}
+
+fn transparent(color: [f32; 4]) -> [f32; 4] {
+    [color[0], color[1], color[2], 0.0]
+}
+
+fn normalize(v: [f32; 2]) -> [f32; 2] {
+    let len = (v[0] * v[0] + v[1] * v[1]).sqrt();
+    if len > 0.0 {
+        [v[0] / len, v[1] / len]
+    } else {
+        [0.0, 0.0]
+    }
+}
+
+/// Points along an arc of `radius` around `center`, from `start` to `end`
+/// radians (clockwise on screen, 0 pointing right).
+pub fn arc_points(center: [f32; 2], radius: f32, start: f32, end: f32) -> Vec<[f32; 2]> {
+    let sweep = (end - start).clamp(-TAU, TAU);
+    let segments = ((sweep.abs() * radius / SEGMENT_LEN).ceil() as usize).clamp(4, 512);
+    (0..=segments)
+        .map(|i| {
+            let a = start + sweep * i as f32 / segments as f32;
+            [center[0] + radius * a.cos(), center[1] + radius * a.sin()]
+        })
+        .collect()
+}
+
+/// Triangles for a polyline of `width` pixels with anti-aliased edges. A
+/// `closed` line also joins the last point back to the first.
+pub fn stroke(points: &[[f32; 2]], closed: bool, width: f32, color: [f32; 4]) -> Vec<Vertex> {
+    let n = points.len();
+    if n < 2 || width <= 0.0 {
+        return Vec::new();
+    }
+    // Lines thinner than a pixel are drawn one pixel wide and fainter.
+    let color = if width < 1.0 {
+        [color[0], color[1], color[2], color[3] * width]
+    } else {
+        color
+    };
+    let core = ((width - FRINGE) / 2.0).max(0.0);
+    let outer = core + FRINGE;
+
+    let segment_normal = |a: [f32; 2], b: [f32; 2]| normalize([a[1] - b[1], b[0] - a[0]]);
+    let normals: Vec<[f32; 2]> = (0..n)
+        .map(|i| {
+            let prev = if i > 0 {
+                Some(segment_normal(points[i - 1], points[i]))
+            } else if closed {
+                Some(segment_normal(points[n - 1], points[0]))
+            } else {
+                None
+            };
+            let next = if i + 1 < n {
+                Some(segment_normal(points[i], points[i + 1]))
+            } else if closed {
+                Some(segment_normal(points[n - 1], points[0]))
+            } else {
+                None
+            };
+            match (prev, next) {
+                (Some(a), Some(b)) => {
+                    // Miter join, limited so sharp corners don't spike.
+                    let m = normalize([a[0] + b[0], a[1] + b[1]]);
+                    let scale = 1.0 / (m[0] * a[0] + m[1] * a[1]).max(0.5);
+                    [m[0] * scale, m[1] * scale]
+                }
+                (Some(a), None) | (None, Some(a)) => a,
+                (None, None) => [0.0, 0.0],
+            }
+        })
+        .collect();
+
+    // Four vertices across the line at each point: fringe, core, core, fringe.
+    let rings: Vec<[Vertex; 4]> = points
+        .iter()
+        .zip(&normals)
+        .map(|(p, nm)| {
+            let at = |d: f32| [p[0] + nm[0] * d, p[1] + nm[1] * d];
+            [
+                vertex(at(-outer), transparent(color)),
+                vertex(at(-core), color),
+                vertex(at(core), color),
+                vertex(at(outer), transparent(color)),
+            ]
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(n * 18);
+    let segments = if closed { n } else { n - 1 };
+    for i in 0..segments {
+        let (a, b) = (&rings[i], &rings[(i + 1) % n]);
+        for k in 0..3 {
+            out.extend_from_slice(&[a[k], a[k + 1], b[k + 1], a[k], b[k + 1], b[k]]);
+        }
+    }
+    out
+}
+
+/// Points around a full circle, without repeating the first one; for a
+/// closed `stroke`.
+pub fn circle_points(center: [f32; 2], radius: f32) -> Vec<[f32; 2]> {
+    let mut points = arc_points(center, radius, 0.0, TAU);
+    points.pop();
+    points
+}
+
+/// Triangles for a filled circle with an anti-aliased rim.
+pub fn disc(center: [f32; 2], radius: f32, color: [f32; 4]) -> Vec<Vertex> {
+    if radius <= 0.0 {
+        return Vec::new();
+    }
+    let inner = (radius - FRINGE / 2.0).max(0.0);
+    let outer = radius + FRINGE / 2.0;
+    let rim = arc_points(center, radius.max(1.0), 0.0, TAU).len();
+    let dir = |i: usize| {
+        let a = TAU * i as f32 / (rim - 1) as f32;
+        [a.cos(), a.sin()]
+    };
+    let at = |d: [f32; 2], r: f32| [center[0] + d[0] * r, center[1] + d[1] * r];
+    let mut out = Vec::with_capacity(rim * 9);
+    for i in 0..rim - 1 {
+        let (a, b) = (dir(i), dir(i + 1));
+        out.extend_from_slice(&[
+            vertex(center, color),
+            vertex(at(a, inner), color),
+            vertex(at(b, inner), color),
+            vertex(at(a, inner), color),
+            vertex(at(a, outer), transparent(color)),
+            vertex(at(b, outer), transparent(color)),
+            vertex(at(a, inner), color),
+            vertex(at(b, outer), transparent(color)),
+            vertex(at(b, inner), color),
+        ]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_feathered_geometry() {
+        let white = [1.0; 4];
+        let line = stroke(&[[0.0, 0.0], [10.0, 0.0]], false, 3.0, white);
+        assert_eq!(line.len(), 18);
+        let ys: Vec<f32> = line.iter().map(|v| v.position[1]).collect();
+        assert_eq!(ys.iter().cloned().fold(f32::MIN, f32::max), 2.0);
+        assert_eq!(ys.iter().cloned().fold(f32::MAX, f32::min), -2.0);
+        // edge vertices fade out, core vertices keep the colour
+        assert!(line.iter().all(|v| v.color[3] == 0.0 || v.color[3] == 1.0));
+
+        let hairline = stroke(&[[0.0, 0.0], [0.0, 10.0]], false, 0.5, white);
+        assert!(hairline.iter().all(|v| v.color[3] <= 0.5));
+
+        let arc = arc_points([0.0, 0.0], 100.0, 0.0, TAU / 4.0);
+        let last = arc[arc.len() - 1];
+        assert!(last[0].abs() < 1e-3 && (last[1] - 100.0).abs() < 1e-3);
+        let ring = stroke(&circle_points([0.0, 0.0], 10.0), true, 1.0, white);
+        assert!(
+            ring.iter()
+                .all(|v| v.position[0].hypot(v.position[1]) < 11.5)
+        );
+
+        let d = disc([5.0, 5.0], 4.0, white);
+        assert!(
+            d.iter()
+                .all(|v| (v.position[0] - 5.0).hypot(v.position[1] - 5.0) <= 4.5 + 1e-3)
+        );
+    }
+}
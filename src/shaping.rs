@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+
+use glyphon::cosmic_text::Align;
+use glyphon::{Attrs, Buffer, Family, FontSystem, Metrics, Shaping};
+
+use crate::font_metrics::SharedFontMetrics;
+use crate::settings::TextSettings;
+
+/// Shaped strings kept for measuring. Edit controls ask about the same text
+/// many times a frame (width, cursor, selection), so it is shaped once.
+const CAPACITY: usize = 256;
+
+/// One glyph: the bytes of the measured text it stands for, its x
+/// position and width, and whether it reads right to left.
+struct Glyph {
+    start: usize,
+    end: usize,
+    x: f32,
+    w: f32,
+    rtl: bool,
+}
+
+impl Glyph {
+    /// Where carets before and after the glyph are drawn.
+    fn edges(&self) -> (f32, f32) {
+        if self.rtl {
+            (self.x + self.w, self.x)
+        } else {
+            (self.x, self.x + self.w)
+        }
+    }
+}
+
+/// `[text] shaping`: "basic" (the default) gives each char its own glyph,
+/// which is quick but can't join or reorder letters; "advanced" shapes
+/// whole runs and lays out right-to-left text, for translations that need it.
+pub fn from_settings(settings: &TextSettings) -> Shaping {
+    match settings.shaping.as_deref() {
+        None | Some("basic") => Shaping::Basic,
+        Some("advanced") => Shaping::Advanced,
+        Some(other) => {
+            eprintln!("text: unknown shaping \"{}\"", other);
+            Shaping::Basic
+        }
+    }
+}
+
+struct Shaped {
+    glyphs: Vec<Glyph>,
+    width: f32,
+    used: u64,
+}
+
+/// How a string is shaped: at what size, in the fixed-width face or the
+/// proportional one, and with advances rounded to whole pixels or not.
+#[derive(Clone, Copy)]
+struct Face {
+    size: f32,
+    fixed: bool,
+    hinting: bool,
+}
+
+/// Glyph positions of strings shaped with the host font system, keyed by
+/// face and escape-stripped text, least recently used dropped first.
+/// Widths in and out are scaled by the font's metrics.
+pub struct ShapeCache {
+    font_system: FontSystem,
+    shaping: Shaping,
+    metrics: SharedFontMetrics,
+    shaped: HashMap<(u32, bool, bool, String), Shaped>,
+    tick: u64,
+}
+
+impl ShapeCache {
+    pub fn new(shaping: Shaping, metrics: SharedFontMetrics) -> Self {
+        Self {
+            font_system: FontSystem::new(),
+            shaping,
+            metrics,
+            shaped: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, face: Face, text: &str) -> &Shaped {
+        self.tick += 1;
+        let key = (
+            face.size.to_bits(),
+            face.fixed,
+            face.hinting,
+            text.to_string(),
+        );
+        if !self.shaped.contains_key(&key) {
+            if self.shaped.len() >= CAPACITY
+                && let Some(oldest) = self
+                    .shaped
+                    .iter()
+                    .min_by_key(|(_, s)| s.used)
+                    .map(|(k, _)| k.clone())
+            {
+                self.shaped.remove(&oldest);
+            }
+            let shaped = self.shape(face, text);
+            self.shaped.insert(key.clone(), shaped);
+        }
+        let shaped = self.shaped.get_mut(&key).unwrap();
+        shaped.used = self.tick;
+        shaped
+    }
+
+    fn shape(&mut self, face: Face, text: &str) -> Shaped {
+        let fs = &mut self.font_system;
+        let mut buf = Buffer::new(fs, Metrics::new(face.size, face.size * 1.2));
+        buf.set_size(fs, f32::MAX, f32::MAX);
+        // The families the text renderer draws with.
+        let family = match face.fixed {
+            true => Family::Monospace,
+            false => Family::SansSerif,
+        };
+        buf.set_text(fs, text, Attrs::new().family(family), self.shaping);
+        // Right-to-left lines would otherwise sit against the far edge.
+        for line in &mut buf.lines {
+            line.set_align(Some(Align::Left));
+        }
+        buf.shape_until_scroll(fs);
+        // Basic shaping gives one glyph per char, but numbers them from the
+        // start of each word; count through the text instead. Advanced
+        // shaping numbers clusters by byte from the start of their line.
+        let mut chars = text
+            .char_indices()
+            .filter(|&(_, c)| c != '\n' && c != '\r')
+            .map(|(i, c)| (i, i + c.len_utf8()));
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let mut glyphs = Vec::new();
+        let mut width = 0.0f32;
+        for run in buf.layout_runs() {
+            let first = glyphs.len();
+            for glyph in run.glyphs.iter() {
+                let (start, end) = match self.shaping {
+                    Shaping::Basic => chars.next().unwrap_or((text.len(), text.len())),
+                    Shaping::Advanced => {
+                        let line = line_starts[run.line_i];
+                        (line + glyph.start, line + glyph.end)
+                    }
+                };
+                glyphs.push(Glyph {
+                    start,
+                    end,
+                    x: glyph.x,
+                    w: glyph.w,
+                    rtl: glyph.level.is_rtl(),
+                });
+            }
+            // Right-to-left runs are laid out leftwards from the buffer's
+            // right edge, which an endless buffer puts at 0.
+            let run_glyphs = &mut glyphs[first..];
+            run_glyphs.sort_by(|a, b| a.x.total_cmp(&b.x));
+            if face.hinting {
+                let mut x = 0.0;
+                for g in run_glyphs.iter_mut() {
+                    g.w = g.w.round();
+                    g.x = x;
+                    x += g.w;
+                }
+                width = width.max(x);
+                continue;
+            }
+            if run.rtl
+                && let Some(left) = run_glyphs.first().map(|g| g.x)
+            {
+                run_glyphs.iter_mut().for_each(|g| g.x -= left);
+            }
+            width = width.max(run.line_w);
+        }
+        Shaped {
+            glyphs,
+            width,
+            used: 0,
+        }
+    }
+
+    /// How many strings are kept shaped.
+    pub fn len(&self) -> usize {
+        self.shaped.len()
+    }
+
+    /// What text in `font` at `size` is shaped with, and its width scale.
+    fn scaled(&self, font: &str, size: f32) -> (Face, f32) {
+        let metrics = self.metrics.get(font);
+        let face = Face {
+            size: metrics.size(size),
+            fixed: font == "FIXED",
+            hinting: metrics.hinting,
+        };
+        (face, metrics.width_scale)
+    }
+
+    pub fn width(&mut self, font: &str, size: f32, text: &str) -> f32 {
+        let (face, scale) = self.scaled(font, size);
+        self.get(face, text).width * scale
+    }
+
+    /// Byte offset of the glyph boundary nearest `x`. Glyphs are in screen
+    /// order, so the left edge of a right-to-left one is where it ends.
+    pub fn cursor_index(&mut self, font: &str, size: f32, text: &str, x: f32) -> usize {
+        let (face, scale) = self.scaled(font, size);
+        let x = x / scale;
+        let glyphs = &self.get(face, text).glyphs;
+        match glyphs.iter().find(|g| x < g.x + g.w * 0.5) {
+            Some(g) if g.rtl => g.end,
+            Some(g) => g.start,
+            None => match glyphs.last() {
+                Some(g) if g.rtl => g.start,
+                _ => text.len(),
+            },
+        }
+    }
+
+    /// X offsets of byte offsets `start` and `end`: where a selection
+    /// covering `text[start..end]` begins and ends.
+    pub fn span(
+        &mut self,
+        font: &str,
+        size: f32,
+        text: &str,
+        start: usize,
+        end: usize,
+    ) -> (f32, f32) {
+        let (face, scale) = self.scaled(font, size);
+        let shaped = self.get(face, text);
+        let at = |offset: usize| {
+            if let Some(g) = shaped.glyphs.iter().find(|g| g.start == offset) {
+                return g.edges().0;
+            }
+            if let Some(g) = shaped.glyphs.iter().find(|g| g.end == offset) {
+                return g.edges().1;
+            }
+            let mut right = 0.0f32;
+            for g in &shaped.glyphs {
+                if g.start >= offset {
+                    return g.x;
+                }
+                right = right.max(g.x + g.w);
+            }
+            right
+        };
+        (at(start) * scale, at(end) * scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_metrics::FontMetricsTable;
+    use std::sync::Arc;
+
+    #[test]
+    fn selection_offsets_match_prefix_widths() {
+        for shaping in [Shaping::Basic, Shaping::Advanced] {
+            let mut cache = ShapeCache::new(shaping, Arc::default());
+            let text = "Righteous Fire";
+            let (start, end) = cache.span("VAR", 16.0, text, 0, 9);
+            assert_eq!(start, 0.0);
+            let prefix = cache.width("VAR", 16.0, "Righteous");
+            assert!((end - prefix).abs() < 0.5, "{} vs {}", end, prefix);
+            let (_, last) = cache.span("VAR", 16.0, text, 10, text.len());
+            assert_eq!(last, cache.width("VAR", 16.0, text));
+            assert_eq!(cache.cursor_index("VAR", 16.0, text, end + 0.1), 9);
+            let (e, _) = cache.span("VAR", 16.0, text, 13, 14);
+            assert_eq!(cache.cursor_index("VAR", 16.0, text, e + 1.0), 13);
+            assert_eq!(cache.shaped.len(), 2);
+        }
+
+        // Hebrew reads from the right: its start is at the right edge.
+        let mut cache = ShapeCache::new(Shaping::Advanced, Arc::default());
+        let text = "שלום";
+        let width = cache.width("VAR", 16.0, text);
+        assert_eq!(cache.cursor_index("VAR", 16.0, text, -1.0), text.len());
+        assert_eq!(cache.cursor_index("VAR", 16.0, text, width + 1.0), 0);
+        assert_eq!(cache.span("VAR", 16.0, text, 0, text.len()), (width, 0.0));
+    }
+
+    /// The family `font` resolves to on this system.
+    fn family(cache: &mut ShapeCache, font: &str) -> String {
+        let (face, _) = cache.scaled(font, 16.0);
+        let fs = &mut cache.font_system;
+        let family = match face.fixed {
+            true => Family::Monospace,
+            false => Family::SansSerif,
+        };
+        let mut buf = Buffer::new(fs, Metrics::new(16.0, 16.0));
+        buf.set_size(fs, 100.0, 100.0);
+        buf.set_text(fs, "a", Attrs::new().family(family), Shaping::Basic);
+        buf.shape_until_scroll(fs);
+        let id = buf.layout_runs().next().unwrap().glyphs[0].font_id;
+        fs.db().face(id).unwrap().families[0].0.clone()
+    }
+
+    #[test]
+    fn compat_metrics_measure_labels_like_simplegraphic() {
+        // Labels PoB sizes its buttons and columns to, and their widths in
+        // VAR and FIXED at 14, 16 and 20 pixels: each glyph's advance in
+        // DejaVu Sans and DejaVu Sans Mono, from their hmtx tables, rounded
+        // to whole pixels as hinting rounds it.
+        const REFERENCE: &[(&str, [f32; 3], [f32; 3])] = &[
+            (
+                "Import/Export Build",
+                [138.0, 155.0, 197.0],
+                [152.0, 190.0, 228.0],
+            ),
+            (
+                "Manage Trees...",
+                [113.0, 129.0, 157.0],
+                [120.0, 150.0, 180.0],
+            ),
+            (
+                "Effective Hit Pool:",
+                [125.0, 141.0, 178.0],
+                [152.0, 190.0, 228.0],
+            ),
+            (
+                "Total DPS inc. Poison:",
+                [152.0, 172.0, 216.0],
+                [176.0, 220.0, 264.0],
+            ),
+            (
+                "Socket Group 1:",
+                [114.0, 128.0, 161.0],
+                [120.0, 150.0, 180.0],
+            ),
+            (
+                "Path of Building Community",
+                [197.0, 222.0, 282.0],
+                [208.0, 260.0, 312.0],
+            ),
+        ];
+        const SIZES: [f32; 3] = [14.0, 16.0, 20.0];
+        let compat_settings = |extra: &str| {
+            let settings: TextSettings =
+                toml::from_str(&format!("compat_metrics = true\n{}", extra)).unwrap();
+            Arc::new(FontMetricsTable::from_settings(&settings))
+        };
+        let mut plain = ShapeCache::new(Shaping::Basic, Arc::default());
+        let mut compat = ShapeCache::new(Shaping::Basic, compat_settings(""));
+        let mut fudged = ShapeCache::new(
+            Shaping::Basic,
+            compat_settings("[fonts.VAR]\nwidth_scale = 1.05"),
+        );
+        // The references hold for these fonts only.
+        if family(&mut compat, "VAR") != "DejaVu Sans"
+            || family(&mut compat, "FIXED") != "DejaVu Sans Mono"
+        {
+            return;
+        }
+        let mut unhinted_off = 0.0f32;
+        for &(label, var, fixed) in REFERENCE {
+            for (i, size) in SIZES.into_iter().enumerate() {
+                for (font, want) in [("VAR", var[i]), ("FIXED", fixed[i])] {
+                    let got = compat.width(font, size, label);
+                    assert!(
+                        (got - want).abs() <= 1.0,
+                        "{} {} {}: {} vs {}",
+                        font,
+                        size,
+                        label,
+                        got,
+                        want
+                    );
+                    unhinted_off = unhinted_off.max((plain.width(font, size, label) - want).abs());
+                }
+                let got = fudged.width("VAR", size, label);
+                assert!((got - var[i] * 1.05).abs() <= 1.05, "{}: {}", label, got);
+                assert_eq!(fudged.width("FIXED", size, label), fixed[i]);
+            }
+            // Fractional sizes measure as the whole size they're drawn at.
+            assert_eq!(compat.width("FIXED", 15.6, label), fixed[1]);
+            let end = compat.span("VAR", 20.0, label, 0, label.len()).1;
+            assert_eq!(end, compat.width("VAR", 20.0, label));
+            assert_eq!(
+                compat.cursor_index("VAR", 20.0, label, end + 1.0),
+                label.len()
+            );
+        }
+        // Without hinting the same labels come out pixels off.
+        assert!(unhinted_off > 4.0, "{}", unhinted_off);
+    }
+}
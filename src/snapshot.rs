@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use mlua::prelude::*;
+
+const MAGIC: &[u8; 4] = b"PSN1";
+
+const NIL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const FLOAT: u8 = 3;
+const INT: u8 = 4;
+const STRING: u8 = 5;
+const TABLE: u8 = 6;
+
+/// Encoded data tables by name, shared by the main state, subscripts and
+/// workers.
+pub type Snapshots = Arc<Mutex<HashMap<String, Arc<Vec<u8>>>>>;
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+struct Encoder<'lua> {
+    strings: HashMap<Vec<u8>, u64>,
+    string_data: Vec<u8>,
+    tables: HashMap<usize, u64>,
+    /// Tables in id order; their pairs are written after discovery.
+    pending: Vec<LuaTable<'lua>>,
+}
+
+impl<'lua> Encoder<'lua> {
+    /// Appends `value`, or returns false for types a snapshot can't hold.
+    fn value(&mut self, out: &mut Vec<u8>, value: &LuaValue<'lua>) -> bool {
+        match value {
+            LuaValue::Nil => out.push(NIL),
+            LuaValue::Boolean(false) => out.push(FALSE),
+            LuaValue::Boolean(true) => out.push(TRUE),
+            LuaValue::Integer(n) => {
+                out.push(INT);
+                put_varint(out, zigzag(*n));
+            }
+            // LuaJIT has no integer subtype; whole numbers are stored as
+            // varints, which is most of PoB's data.
+            LuaValue::Number(n) if n.fract() == 0.0 && n.abs() < (1u64 << 53) as f64 => {
+                out.push(INT);
+                put_varint(out, zigzag(*n as i64));
+            }
+            LuaValue::Number(n) => {
+                out.push(FLOAT);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            LuaValue::String(s) => {
+                let next = self.strings.len() as u64;
+                let id = *self
+                    .strings
+                    .entry(s.as_bytes().to_vec())
+                    .or_insert_with(|| {
+                        put_varint(&mut self.string_data, s.as_bytes().len() as u64);
+                        self.string_data.extend_from_slice(s.as_bytes());
+                        next
+                    });
+                out.push(STRING);
+                put_varint(out, id);
+            }
+            LuaValue::Table(t) => {
+                let next = self.pending.len() as u64;
+                let id = *self
+                    .tables
+                    .entry(t.to_pointer() as usize)
+                    .or_insert_with(|| {
+                        self.pending.push(t.clone());
+                        next
+                    });
+                out.push(TABLE);
+                put_varint(out, id);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Serializes `value` into a compact snapshot. Strings are stored once,
+/// tables referenced from several places (or cyclically) stay shared, and
+/// functions, userdata and metatables are left out.
+pub fn encode(value: &LuaValue) -> LuaResult<Vec<u8>> {
+    let mut enc = Encoder {
+        strings: HashMap::new(),
+        string_data: Vec::new(),
+        tables: HashMap::new(),
+        pending: Vec::new(),
+    };
+    let mut root = Vec::new();
+    if !enc.value(&mut root, value) {
+        return Err(LuaError::RuntimeError(format!(
+            "cannot snapshot a {}",
+            value.type_name()
+        )));
+    }
+    let mut tables = Vec::new();
+    let mut done = 0;
+    while done < enc.pending.len() {
+        let t = enc.pending[done].clone();
+        done += 1;
+        let mut pairs = Vec::new();
+        let mut count = 0u64;
+        for pair in t.pairs::<LuaValue, LuaValue>() {
+            let (k, v) = pair?;
+            let mark = pairs.len();
+            if enc.value(&mut pairs, &k) && enc.value(&mut pairs, &v) {
+                count += 1;
+            } else {
+                pairs.truncate(mark);
+            }
+        }
+        put_varint(&mut tables, count);
+        tables.extend_from_slice(&pairs);
+    }
+
+    let mut out = MAGIC.to_vec();
+    put_varint(&mut out, enc.strings.len() as u64);
+    out.extend_from_slice(&enc.string_data);
+    put_varint(&mut out, enc.pending.len() as u64);
+    out.extend_from_slice(&tables);
+    out.extend_from_slice(&root);
+    Ok(out)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn err() -> LuaError {
+        LuaError::RuntimeError("corrupt data snapshot".into())
+    }
+
+    fn byte(&mut self) -> LuaResult<u8> {
+        let b = *self.data.get(self.pos).ok_or_else(Self::err)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> LuaResult<&[u8]> {
+        let end = self.pos.checked_add(len).ok_or_else(Self::err)?;
+        let out = self.data.get(self.pos..end).ok_or_else(Self::err)?;
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn varint(&mut self) -> LuaResult<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= u64::from(b & 0x7f) << shift;
+            if b < 0x80 {
+                return Ok(n);
+            }
+        }
+        Err(Self::err())
+    }
+
+    fn len(&mut self) -> LuaResult<usize> {
+        let n = self.varint()? as usize;
+        // Every entry takes at least a byte, which bounds preallocation.
+        if n > self.data.len() {
+            return Err(Self::err());
+        }
+        Ok(n)
+    }
+
+    fn value<'lua>(
+        &mut self,
+        strings: &[LuaString<'lua>],
+        tables: &[LuaTable<'lua>],
+    ) -> LuaResult<LuaValue<'lua>> {
+        Ok(match self.byte()? {
+            NIL => LuaValue::Nil,
+            FALSE => LuaValue::Boolean(false),
+            TRUE => LuaValue::Boolean(true),
+            FLOAT => {
+                let raw: [u8; 8] = self.bytes(8)?.try_into().map_err(|_| Self::err())?;
+                LuaValue::Number(f64::from_le_bytes(raw))
+            }
+            INT => {
+                let z = self.varint()?;
+                LuaValue::Number(((z >> 1) as i64 ^ -((z & 1) as i64)) as f64)
+            }
+            STRING => {
+                let s = strings.get(self.varint()? as usize).ok_or_else(Self::err)?;
+                LuaValue::String(s.clone())
+            }
+            TABLE => {
+                let t = tables.get(self.varint()? as usize).ok_or_else(Self::err)?;
+                LuaValue::Table(t.clone())
+            }
+            _ => return Err(Self::err()),
+        })
+    }
+}
+
+/// Rebuilds the value `encode` produced in `lua`.
+pub fn decode<'lua>(lua: &'lua Lua, data: &[u8]) -> LuaResult<LuaValue<'lua>> {
+    let mut r = Reader { data, pos: 0 };
+    if r.bytes(4)? != MAGIC {
+        return Err(Reader::err());
+    }
+    let strings = (0..r.len()?)
+        .map(|_| {
+            let len = r.len()?;
+            lua.create_string(r.bytes(len)?)
+        })
+        .collect::<LuaResult<Vec<_>>>()?;
+    let count = r.len()?;
+    let tables = (0..count)
+        .map(|_| lua.create_table())
+        .collect::<LuaResult<Vec<_>>>()?;
+    for t in &tables {
+        for _ in 0..r.len()? {
+            let k = r.value(&strings, &tables)?;
+            let v = r.value(&strings, &tables)?;
+            t.raw_set(k, v)?;
+        }
+    }
+    r.value(&strings, &tables)
+}
+
+/// `LoadDataSnapshot(name)`: a fresh copy of a saved snapshot, or nil.
+pub fn load_function<'lua>(lua: &'lua Lua, snapshots: &Snapshots) -> LuaResult<LuaFunction<'lua>> {
+    let snapshots = snapshots.clone();
+    lua.create_function(move |lua, name: String| {
+        let data = snapshots.lock().unwrap().get(&name).cloned();
+        match data {
+            Some(data) => decode(lua, &data),
+            None => Ok(LuaValue::Nil),
+        }
+    })
+}
+
+/// Registers `SaveDataSnapshot(name, value)`, which returns the encoded
+/// size, and `LoadDataSnapshot(name)`. Data saved by the main state can be
+/// loaded by workers instead of running PoB's data files again.
+pub fn register(lua: &Lua, snapshots: Snapshots) -> LuaResult<()> {
+    let g = lua.globals();
+    g.set("LoadDataSnapshot", load_function(lua, &snapshots)?)?;
+    g.set(
+        "SaveDataSnapshot",
+        lua.create_function(move |_, (name, value): (String, LuaValue)| {
+            let data = encode(&value)?;
+            let size = data.len();
+            snapshots.lock().unwrap().insert(name, Arc::new(data));
+            Ok(size)
+        })?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_shared_and_cyclic_tables() {
+        let lua = Lua::new();
+        let snapshots = Snapshots::default();
+        register(&lua, snapshots.clone()).unwrap();
+        let size: usize = lua
+            .load(
+                r#"
+                local shared = { "fire", "cold" }
+                local data = {
+                    gems = { Fireball = { tags = shared, level = 20, q = 1.5 } },
+                    mods = { { tags = shared, neg = -3 }, { name = "fire" } },
+                    fn = print,
+                }
+                data.self = data
+                return SaveDataSnapshot("data", data)
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let data = snapshots.lock().unwrap()["data"].clone();
+        assert_eq!(data.len(), size);
+        // Repeated strings are stored once.
+        assert_eq!(data.windows(4).filter(|w| w == b"fire").count(), 1);
+
+        let other = Lua::new();
+        register(&other, snapshots).unwrap();
+        let ok: bool = other
+            .load(
+                r#"
+                local d = LoadDataSnapshot("data")
+                local g = d.gems.Fireball
+                return g.level == 20 and g.q == 1.5 and g.tags[2] == "cold"
+                    and g.tags == d.mods[1].tags and d.mods[1].neg == -3
+                    and d.self == d and d.fn == nil and LoadDataSnapshot("x") == nil
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(ok);
+        assert!(decode(&other, b"PSN1\x05").is_err());
+    }
+}
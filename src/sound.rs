@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+use std::path::{Component, Path};
+
+use mlua::prelude::*;
+
+use crate::platform::user_path;
+use crate::settings::SoundSettings;
+use crate::vfs::SharedVfs;
+
+/// How loud a sound plays: the call's volume (1 if not given) times the
+/// master volume, or not at all when muted.
+fn gain(volume: Option<f32>, settings: &SoundSettings) -> Option<f32> {
+    if settings.muted == Some(true) {
+        return None;
+    }
+    let master = settings.volume.unwrap_or(1.0).clamp(0.0, 1.0);
+    Some(volume.unwrap_or(1.0).clamp(0.0, 1.0) * master)
+}
+
+/// Reads a sound from the script tree, or else from the user path. Paths
+/// that would reach outside both are refused.
+fn read(files: &SharedVfs, path: &str) -> std::io::Result<Vec<u8>> {
+    if !Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "only paths relative to the script or user folder can be played",
+        ));
+    }
+    let mut files = files.lock().unwrap();
+    files.read(path).or_else(|e| {
+        let user = user_path().join(path);
+        std::fs::read(user).map_err(|_| e)
+    })
+}
+
+#[cfg(feature = "sound")]
+mod output {
+    use std::io::Cursor;
+
+    use rodio::{Decoder, OutputStream, OutputStreamHandle, Source};
+
+    /// The default audio device.
+    pub struct Output {
+        _stream: OutputStream,
+        handle: OutputStreamHandle,
+    }
+
+    impl Output {
+        pub fn open() -> Result<Self, String> {
+            let (stream, handle) = OutputStream::try_default().map_err(|e| e.to_string())?;
+            Ok(Self {
+                _stream: stream,
+                handle,
+            })
+        }
+
+        /// Decodes an OGG or WAV file and starts playing it.
+        pub fn play(&self, data: Vec<u8>, gain: f32) -> Result<(), String> {
+            let source = Decoder::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+            self.handle
+                .play_raw(source.convert_samples().amplify(gain))
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(not(feature = "sound"))]
+mod output {
+    /// Stands in for the audio device in builds without sound.
+    pub struct Output;
+
+    impl Output {
+        pub fn open() -> Result<Self, String> {
+            Err("this build has no sound support".into())
+        }
+
+        pub fn play(&self, _data: Vec<u8>, _gain: f32) -> Result<(), String> {
+            Ok(())
+        }
+    }
+}
+
+use output::Output;
+
+/// Registers `PlaySound(path, [volume]) -> started`. The audio device is
+/// opened on first use; if that fails sounds are skipped from then on.
+pub fn register(lua: &Lua, files: SharedVfs, settings: SoundSettings) -> LuaResult<()> {
+    let output: RefCell<Option<Option<Output>>> = RefCell::new(None);
+    lua.globals().set(
+        "PlaySound",
+        lua.create_function(move |_, (path, volume): (String, Option<f32>)| {
+            let Some(gain) = gain(volume, &settings) else {
+                return Ok(false);
+            };
+            let mut output = output.borrow_mut();
+            let device = output
+                .get_or_insert_with(|| Output::open().map_err(|e| eprintln!("sound: {}", e)).ok());
+            let Some(device) = device else {
+                return Ok(false);
+            };
+            let played = read(&files, &path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| device.play(data, gain));
+            if let Err(e) = &played {
+                eprintln!("PlaySound {}: {}", path, e);
+            }
+            Ok(played.is_ok())
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Archives;
+    use crate::vfs::Vfs;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn volume_scales_by_master_and_mute_silences() {
+        let settings = SoundSettings {
+            volume: Some(0.5),
+            ..SoundSettings::default()
+        };
+        assert_eq!(gain(None, &settings), Some(0.5));
+        assert_eq!(gain(Some(2.0), &settings), Some(0.5));
+        assert_eq!(gain(Some(0.5), &settings), Some(0.25));
+        let muted = SoundSettings {
+            muted: Some(true),
+            ..settings
+        };
+        assert_eq!(gain(Some(1.0), &muted), None);
+    }
+
+    #[test]
+    fn sounds_stay_inside_the_script_and_user_folders() {
+        let dir = std::env::temp_dir().join(format!("pob-sound-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Sounds")).unwrap();
+        std::fs::write(dir.join("Sounds/ding.ogg"), "ogg").unwrap();
+        let files: SharedVfs = Arc::new(Mutex::new(Vfs::new(
+            dir.clone(),
+            Archives::new(dir.clone()),
+        )));
+        assert_eq!(read(&files, "Sounds/ding.ogg").unwrap(), b"ogg");
+        let outside = dir.join("Sounds/ding.ogg");
+        for path in [outside.to_str().unwrap(), "../ding.ogg", "Sounds/../../x"] {
+            let e = read(&files, path).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput, "{}", path);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Named pixel rects inside one image, so Lua can draw sprites by name and
+/// leave the UV math to the host.
+#[derive(Default)]
+pub struct SpriteSheet {
+    rects: HashMap<String, [u32; 4]>,
+}
+
+/// Sprite sheets keyed by image handle id.
+pub type SpriteSheets = Arc<Mutex<HashMap<u32, SpriteSheet>>>;
+
+impl SpriteSheet {
+    /// Parses sheet metadata: one `name x y width height` entry per line,
+    /// blank lines and `#` comments ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut sheet = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, x, y, w, h] = fields[..] else {
+                return Err(format!("line {}: expected 'name x y width height'", n + 1));
+            };
+            let num = |s: &str| {
+                s.parse::<u32>()
+                    .map_err(|e| format!("line {}: {}: {}", n + 1, s, e))
+            };
+            sheet.set(name, [num(x)?, num(y)?, num(w)?, num(h)?]);
+        }
+        Ok(sheet)
+    }
+
+    pub fn set(&mut self, name: &str, rect: [u32; 4]) {
+        self.rects.insert(name.to_string(), rect);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+
+    /// Texture coordinates `[left, top, right, bottom]` of `name` within an
+    /// image of the given size. None for rects whose far edge overflows.
+    pub fn uv(&self, name: &str, width: u32, height: u32) -> Option<[f32; 4]> {
+        let [x, y, w, h] = *self.rects.get(name)?;
+        let (iw, ih) = (width.max(1) as f32, height.max(1) as f32);
+        Some([
+            x as f32 / iw,
+            y as f32 / ih,
+            x.checked_add(w)? as f32 / iw,
+            y.checked_add(h)? as f32 / ih,
+        ])
+    }
+}
+
+/// Splits a nine-patch draw into its `(dest, uv)` cells. `dest` is
+/// `[x, y, width, height]`; `margins` (left, top, right, bottom) are in
+/// source pixels of an image `size` pixels large and keep that size on
+/// screen, each clamped to half the span on screen and in the image when
+/// they don't fit. `uv` is the region of the
+/// image being drawn. Inner edges land on whole pixels so cells meet without
+/// seams; empty cells are left out.
+pub fn nine_patch(
+    dest: [f32; 4],
+    margins: [f32; 4],
+    uv: [f32; 4],
+    size: [f32; 2],
+) -> Vec<([f32; 4], [f32; 4])> {
+    let [x, y, w, h] = dest;
+    let [left, top, right, bottom] = margins.map(|m| m.max(0.0));
+    let half = |span: f32| span.max(0.0) / 2.0;
+    let (iw, ih) = (size[0].max(1.0), size[1].max(1.0));
+    let (du, dv) = (half(uv[2] - uv[0]), half(uv[3] - uv[1]));
+    let us = [
+        uv[0],
+        uv[0] + (left / iw).min(du),
+        uv[2] - (right / iw).min(du),
+        uv[2],
+    ];
+    let vs = [
+        uv[1],
+        uv[1] + (top / ih).min(dv),
+        uv[3] - (bottom / ih).min(dv),
+        uv[3],
+    ];
+    let (dx, dy) = (half(w), half(h));
+    let (left, right) = (left.min(dx), right.min(dx));
+    let (top, bottom) = (top.min(dy), bottom.min(dy));
+    let xs = [x, (x + left).round(), (x + w - right).round(), x + w];
+    let ys = [y, (y + top).round(), (y + h - bottom).round(), y + h];
+
+    let mut cells = Vec::with_capacity(9);
+    for row in 0..3 {
+        for col in 0..3 {
+            let (cw, ch) = (xs[col + 1] - xs[col], ys[row + 1] - ys[row]);
+            if cw <= 0.0 || ch <= 0.0 {
+                continue;
+            }
+            cells.push((
+                [xs[col], ys[row], cw, ch],
+                [us[col], vs[row], us[col + 1], vs[row + 1]],
+            ));
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sheet_and_maps_uvs() {
+        let sheet = SpriteSheet::parse(
+            "# skill frames\n\
+             frame0 0 0 32 32\n\
+             \n\
+             frame1 32 0 32 32 # second\n",
+        )
+        .unwrap();
+        assert_eq!(sheet.len(), 2);
+        assert_eq!(sheet.uv("frame1", 64, 32), Some([0.5, 0.0, 1.0, 1.0]));
+        assert_eq!(sheet.uv("missing", 64, 32), None);
+        assert!(SpriteSheet::parse("frame0 0 0 32").is_err());
+        let sheet = SpriteSheet::parse("huge 4294967295 0 1 1").unwrap();
+        assert_eq!(sheet.uv("huge", 64, 32), None);
+    }
+
+    #[test]
+    fn nine_patch_keeps_margins_and_drops_empty_cells() {
+        let cells = nine_patch(
+            [10.0, 20.0, 100.0, 50.0],
+            [8.0, 8.0, 8.0, 8.0],
+            [0.0, 0.0, 1.0, 1.0],
+            [32.0, 32.0],
+        );
+        assert_eq!(cells.len(), 9);
+        assert_eq!(cells[0], ([10.0, 20.0, 8.0, 8.0], [0.0, 0.0, 0.25, 0.25]));
+        assert_eq!(
+            cells[4],
+            ([18.0, 28.0, 84.0, 34.0], [0.25, 0.25, 0.75, 0.75])
+        );
+        assert_eq!(cells[8], ([102.0, 62.0, 8.0, 8.0], [0.75, 0.75, 1.0, 1.0]));
+
+        // Too narrow for both side margins: no middle column.
+        let cells = nine_patch(
+            [0.0, 0.0, 10.0, 40.0],
+            [8.0, 8.0, 8.0, 8.0],
+            [0.0, 0.0, 1.0, 1.0],
+            [32.0, 32.0],
+        );
+        assert_eq!(cells.len(), 6);
+        assert_eq!(cells[0].0, [0.0, 0.0, 5.0, 8.0]);
+    }
+
+    #[test]
+    fn nine_patch_clamps_oversized_margins_to_half_the_span() {
+        let cells = nine_patch(
+            [0.0, 0.0, 10.0, 10.0],
+            [40.0, 40.0, 40.0, 40.0],
+            [0.0, 0.0, 1.0, 1.0],
+            [32.0, 32.0],
+        );
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[0], ([0.0, 0.0, 5.0, 5.0], [0.0, 0.0, 0.5, 0.5]));
+        assert_eq!(cells[3], ([5.0, 5.0, 5.0, 5.0], [0.5, 0.5, 1.0, 1.0]));
+        for (dest, uv) in &cells {
+            assert!(dest[2] > 0.0 && dest[3] > 0.0);
+            assert!(uv[0] <= uv[2] && uv[1] <= uv[3]);
+        }
+        assert!(nine_patch([0.0; 4], [8.0; 4], [0.0, 0.0, 1.0, 1.0], [32.0, 32.0]).is_empty());
+    }
+}
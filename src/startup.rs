@@ -0,0 +1,59 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Phases timed so far; taken when the report is printed.
+static PHASES: Mutex<Option<Vec<(&'static str, Duration)>>> = Mutex::new(Some(Vec::new()));
+
+/// Starts the startup clock; call first thing in main.
+pub fn begin() {
+    START.get_or_init(Instant::now);
+}
+
+/// Runs `f` as the startup phase `name`.
+pub fn time<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let out = f();
+    if let Some(phases) = PHASES.lock().unwrap().as_mut() {
+        phases.push((name, started.elapsed()));
+    }
+    out
+}
+
+/// Prints the phase timings the first time a host frame is on screen.
+pub fn first_frame() {
+    let Some(phases) = PHASES.lock().unwrap().take() else {
+        return;
+    };
+    let total = START.get().map_or(Duration::ZERO, Instant::elapsed);
+    println!("{}", report(&phases, total));
+}
+
+fn report(phases: &[(&str, Duration)], total: Duration) -> String {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let mut out = String::from("startup:");
+    for (name, took) in phases {
+        out += &format!(" {} {:.0} ms,", name, ms(*took));
+    }
+    out + &format!(" first frame at {:.0} ms", ms(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_phases_in_order() {
+        let phases = [
+            ("Lua host", Duration::from_millis(12)),
+            ("Launch.lua", Duration::from_micros(40_400)),
+        ];
+        assert_eq!(
+            report(&phases, Duration::from_millis(350)),
+            "startup: Lua host 12 ms, Launch.lua 40 ms, first frame at 350 ms"
+        );
+    }
+}
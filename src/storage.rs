@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+
+use mlua::prelude::*;
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// Schema migrations, applied in order and tracked with `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &["CREATE TABLE kv (
+        bucket  TEXT NOT NULL,
+        key     TEXT NOT NULL,
+        value   BLOB NOT NULL,
+        updated INTEGER NOT NULL,
+        PRIMARY KEY (bucket, key)
+    )"];
+
+/// A small bucketed key/value store in one SQLite file, so forks can keep
+/// build history, price caches and update metadata without ad-hoc files.
+pub struct Storage {
+    conn: Connection,
+}
+
+impl Storage {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let version: usize = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+        for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(sql)?;
+            conn.pragma_update(None, "user_version", i + 1)?;
+        }
+        Ok(Self { conn })
+    }
+
+    pub fn put(&self, bucket: &str, key: &str, value: &[u8]) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO kv (bucket, key, value, updated)
+             VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+            params![bucket, key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, bucket: &str, key: &str) -> rusqlite::Result<Option<Vec<u8>>> {
+        self.conn
+            .query_row(
+                "SELECT value FROM kv WHERE bucket = ?1 AND key = ?2",
+                params![bucket, key],
+                |r| r.get(0),
+            )
+            .optional()
+    }
+
+    pub fn delete(&self, bucket: &str, key: &str) -> rusqlite::Result<bool> {
+        let n = self.conn.execute(
+            "DELETE FROM kv WHERE bucket = ?1 AND key = ?2",
+            params![bucket, key],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// Keys in `bucket` starting with `prefix`, sorted.
+    pub fn list(&self, bucket: &str, prefix: &str) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT key FROM kv WHERE bucket = ?1 AND substr(key, 1, length(?2)) = ?2
+             ORDER BY key",
+        )?;
+        let keys = stmt.query_map(params![bucket, prefix], |r| r.get(0))?;
+        keys.collect()
+    }
+
+    /// Migrates flat files under `dir` whose names end in `ext` into
+    /// `bucket`, keyed by path relative to `dir`. Keys already present are
+    /// left alone so repeated imports are harmless.
+    pub fn import_dir(&mut self, bucket: &str, dir: &Path, ext: &str) -> rusqlite::Result<usize> {
+        let mut files = Vec::new();
+        collect_files(dir, dir, ext, &mut files);
+        let tx = self.conn.transaction()?;
+        let mut imported = 0;
+        for (key, path) in files {
+            let Ok(data) = std::fs::read(&path) else {
+                continue;
+            };
+            imported += tx.execute(
+                "INSERT OR IGNORE INTO kv (bucket, key, value, updated)
+                 VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+                params![bucket, key, data],
+            )?;
+        }
+        tx.commit()?;
+        Ok(imported)
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, ext: &str, out: &mut Vec<(String, PathBuf)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(root, &path, ext, out);
+        } else if path.to_string_lossy().ends_with(ext)
+            && let Ok(rel) = path.strip_prefix(root)
+        {
+            out.push((rel.to_string_lossy().replace('\\', "/"), path));
+        }
+    }
+}
+
+impl LuaUserData for Storage {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "put",
+            |_, this, (bucket, key, value): (String, String, LuaString)| {
+                this.put(&bucket, &key, value.as_bytes())
+                    .map_err(LuaError::external)
+            },
+        );
+        methods.add_method(
+            "get",
+            |lua, this, (bucket, key): (String, String)| match this
+                .get(&bucket, &key)
+                .map_err(LuaError::external)?
+            {
+                Some(value) => Ok(LuaValue::String(lua.create_string(&value)?)),
+                None => Ok(LuaValue::Nil),
+            },
+        );
+        methods.add_method("delete", |_, this, (bucket, key): (String, String)| {
+            this.delete(&bucket, &key).map_err(LuaError::external)
+        });
+        methods.add_method(
+            "list",
+            |_, this, (bucket, prefix): (String, Option<String>)| {
+                this.list(&bucket, prefix.as_deref().unwrap_or(""))
+                    .map_err(LuaError::external)
+            },
+        );
+        methods.add_method_mut(
+            "import",
+            |_, this, (bucket, dir, ext): (String, String, Option<String>)| {
+                this.import_dir(&bucket, Path::new(&dir), ext.as_deref().unwrap_or(""))
+                    .map_err(LuaError::external)
+            },
+        );
+    }
+}
+
+/// Registers `native.storage`; `open(name)` opens `<user path>/<name>.db`.
+pub fn register(lua: &Lua, user_path: PathBuf) -> LuaResult<()> {
+    let module = lua.create_table()?;
+    module.set(
+        "open",
+        lua.create_function(move |lua, name: String| {
+            if name.is_empty() || name.contains(['/', '\\', '.']) {
+                return (LuaValue::Nil, format!("invalid storage name '{}'", name))
+                    .into_lua_multi(lua);
+            }
+            let path = user_path.join(format!("{}.db", name));
+            match Storage::open(&path) {
+                Ok(db) => db.into_lua_multi(lua),
+                Err(e) => (LuaValue::Nil, format!("{}: {}", path.display(), e)).into_lua_multi(lua),
+            }
+        })?,
+    )?;
+    lua.set_named_registry_value("native.storage", module)?;
+    let preload: LuaTable = lua
+        .globals()
+        .get::<_, LuaTable>("package")?
+        .get("preload")?;
+    preload.set(
+        "native.storage",
+        lua.create_function(|lua, _: LuaMultiValue| {
+            lua.named_registry_value::<LuaTable>("native.storage")
+        })?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_get_list_and_import() {
+        let dir = std::env::temp_dir().join(format!("pob-storage-{}", std::process::id()));
+        let builds = dir.join("Builds");
+        std::fs::create_dir_all(builds.join("League")).unwrap();
+        std::fs::write(builds.join("Witch.xml"), "<PathOfBuilding/>").unwrap();
+        std::fs::write(builds.join("League/Duelist.xml"), "<PathOfBuilding/>").unwrap();
+        std::fs::write(builds.join("notes.txt"), "skip").unwrap();
+
+        let mut db = Storage::open(&dir.join("test.db")).unwrap();
+        db.put("prices", "divine", b"180").unwrap();
+        assert_eq!(db.get("prices", "divine").unwrap(), Some(b"180".to_vec()));
+        assert_eq!(db.import_dir("builds", &builds, ".xml").unwrap(), 2);
+        assert_eq!(db.import_dir("builds", &builds, ".xml").unwrap(), 0);
+        assert_eq!(
+            db.list("builds", "").unwrap(),
+            vec!["League/Duelist.xml", "Witch.xml"]
+        );
+        assert!(db.delete("prices", "divine").unwrap());
+        assert_eq!(db.get("prices", "divine").unwrap(), None);
+        drop(db);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
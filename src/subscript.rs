@@ -0,0 +1,405 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+};
+
+use mlua::prelude::*;
+
+use crate::host_thread::Waker;
+use crate::net::{CancelFlag, SharedNet};
+use crate::snapshot::{self, Snapshots};
+use crate::vfs::SharedVfs;
+use crate::{codec, json, lua_host, net, platform, xml};
+
+/// Tables nested deeper than this are cut off when crossing threads.
+const MAX_DEPTH: usize = 32;
+
+/// A Lua value copied out of one state so it can move to another thread.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Vec<u8>),
+    Table(Vec<(SubValue, SubValue)>),
+}
+
+impl SubValue {
+    pub fn from_lua(value: &LuaValue, depth: usize) -> Self {
+        match value {
+            LuaValue::Boolean(b) => Self::Boolean(*b),
+            LuaValue::Integer(n) => Self::Number(*n as f64),
+            LuaValue::Number(n) => Self::Number(*n),
+            LuaValue::String(s) => Self::String(s.as_bytes().to_vec()),
+            LuaValue::Table(t) if depth < MAX_DEPTH => Self::Table(
+                t.clone()
+                    .pairs::<LuaValue, LuaValue>()
+                    .flatten()
+                    .map(|(k, v)| (Self::from_lua(&k, depth + 1), Self::from_lua(&v, depth + 1)))
+                    .collect(),
+            ),
+            _ => Self::Nil,
+        }
+    }
+
+    pub fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue<'_>> {
+        Ok(match self {
+            Self::Nil => LuaValue::Nil,
+            Self::Boolean(b) => LuaValue::Boolean(b),
+            Self::Number(n) => LuaValue::Number(n),
+            Self::String(s) => LuaValue::String(lua.create_string(&s)?),
+            Self::Table(pairs) => {
+                let t = lua.create_table()?;
+                for (k, v) in pairs {
+                    t.raw_set(k.into_lua(lua)?, v.into_lua(lua)?)?;
+                }
+                LuaValue::Table(t)
+            }
+        })
+    }
+}
+
+pub fn to_multi(lua: &Lua, values: Vec<SubValue>) -> LuaResult<LuaMultiValue<'_>> {
+    values
+        .into_iter()
+        .map(|v| v.into_lua(lua))
+        .collect::<LuaResult<Vec<_>>>()
+        .map(LuaMultiValue::from_vec)
+}
+
+/// Results a subscript thread sends back for the main state to dispatch.
+#[derive(Debug)]
+pub enum SubMessage {
+    /// Script returned; becomes `OnSubFinished(id, ...)`.
+    Finished(u32, Vec<SubValue>),
+    /// Script raised; becomes `OnSubError(id, message)`.
+    Error(u32, String),
+    /// Script called one of its sub functions; becomes `OnSubCall(name, ...)`.
+    Call(u32, String, Vec<SubValue>),
+}
+
+/// Paths a subscript's host functions report.
+#[derive(Clone)]
+pub struct SubEnv {
+    pub script_path: PathBuf,
+    pub runtime_path: PathBuf,
+    pub net: SharedNet,
+    pub snapshots: Snapshots,
+    pub vfs: SharedVfs,
+    /// Woken whenever a message is sent to the main state.
+    pub waker: Waker,
+}
+
+/// Runs LaunchSubScript code on worker threads, each with its own Lua state.
+#[derive(Clone)]
+pub struct SubScripts {
+    env: SubEnv,
+    next_id: Arc<AtomicU32>,
+    running: Arc<Mutex<HashMap<u32, Arc<AtomicBool>>>>,
+    tx: Sender<SubMessage>,
+    rx: Arc<Mutex<Receiver<SubMessage>>>,
+}
+
+impl SubScripts {
+    pub fn new(env: SubEnv) -> Self {
+        let (tx, rx) = channel();
+        Self {
+            env,
+            next_id: Arc::new(AtomicU32::new(1)),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+        }
+    }
+
+    /// Starts `script` with `args`. `funcs` and `subs` are PoB's
+    /// comma-separated lists of host functions to expose directly and of
+    /// functions forwarded to the main script's OnSubCall.
+    pub fn launch(&self, script: String, funcs: &str, subs: &str, args: Vec<SubValue>) -> u32 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.running.lock().unwrap().insert(id, cancel.clone());
+
+        let names = |list: &str| -> Vec<String> {
+            list.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        let (funcs, subs) = (names(funcs), names(subs));
+        let env = self.env.clone();
+        let tx = self.tx.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("subscript-{}", id))
+            .spawn(move || {
+                let msg = match run(id, &env, &script, &funcs, &subs, args, cancel, &tx) {
+                    Ok(values) => SubMessage::Finished(id, values),
+                    Err(e) => SubMessage::Error(id, e.to_string()),
+                };
+                tx.send(msg).ok();
+                env.waker.wake();
+            });
+        if let Err(e) = spawned {
+            self.tx
+                .send(SubMessage::Error(id, format!("failed to start: {}", e)))
+                .ok();
+        }
+        id
+    }
+
+    /// Flags the script to stop; its pending results are discarded.
+    pub fn abort(&self, id: u32) {
+        if let Some(cancel) = self.running.lock().unwrap().remove(&id) {
+            cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_running(&self, id: u32) -> bool {
+        self.running.lock().unwrap().contains_key(&id)
+    }
+
+    /// Messages ready for the main state, skipping those of aborted scripts.
+    /// A script counts as running until its result has been polled.
+    pub fn poll(&self) -> Vec<SubMessage> {
+        let mut running = self.running.lock().unwrap();
+        let mut out = Vec::new();
+        for msg in self.rx.lock().unwrap().try_iter() {
+            let id = match &msg {
+                SubMessage::Finished(id, _) | SubMessage::Error(id, _) => {
+                    if running.remove(id).is_none() {
+                        continue;
+                    }
+                    *id
+                }
+                SubMessage::Call(id, _, _) => *id,
+            };
+            if matches!(msg, SubMessage::Call(..)) && !running.contains_key(&id) {
+                continue;
+            }
+            out.push(msg);
+        }
+        out
+    }
+
+    /// Delivers polled messages to the main object's callbacks.
+    pub fn dispatch(&self, lua: &Lua, main_object: &LuaTable) -> LuaResult<()> {
+        for msg in self.poll() {
+            let (name, mut args) = match msg {
+                SubMessage::Finished(id, values) => ("OnSubFinished", {
+                    let mut args = vec![LuaValue::Integer(id as i64)];
+                    args.extend(to_multi(lua, values)?);
+                    args
+                }),
+                SubMessage::Error(id, err) => (
+                    "OnSubError",
+                    vec![
+                        LuaValue::Integer(id as i64),
+                        LuaValue::String(lua.create_string(&err)?),
+                    ],
+                ),
+                SubMessage::Call(_, func, values) => ("OnSubCall", {
+                    let mut args = vec![LuaValue::String(lua.create_string(&func)?)];
+                    args.extend(to_multi(lua, values)?);
+                    args
+                }),
+            };
+            if let Ok(func) = main_object.get::<_, LuaFunction>(name) {
+                args.insert(0, LuaValue::Table(main_object.clone()));
+                func.call::<_, ()>(LuaMultiValue::from_vec(args))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run(
+    id: u32,
+    env: &SubEnv,
+    script: &str,
+    funcs: &[String],
+    subs: &[String],
+    args: Vec<SubValue>,
+    cancel: Arc<AtomicBool>,
+    tx: &Sender<SubMessage>,
+) -> LuaResult<Vec<SubValue>> {
+    let lua = new_state(env, cancel)?;
+    let g = lua.globals();
+    for name in funcs {
+        match host_function(&lua, env, name)? {
+            Some(func) => g.set(name.as_str(), func)?,
+            None => eprintln!("subscript {}: {} is not available to subscripts", id, name),
+        }
+    }
+    for name in subs {
+        let tx = tx.clone();
+        let waker = env.waker.clone();
+        let func = name.clone();
+        g.set(
+            name.as_str(),
+            lua.create_function(move |_, args: LuaMultiValue| {
+                let values = args.iter().map(|v| SubValue::from_lua(v, 0)).collect();
+                tx.send(SubMessage::Call(id, func.clone(), values)).ok();
+                waker.wake();
+                Ok(())
+            })?,
+        )?;
+    }
+
+    let args = to_multi(&lua, args)?;
+    let results: LuaMultiValue = lua.load(script).set_name("subscript").call(args)?;
+    Ok(results.iter().map(|v| SubValue::from_lua(v, 0)).collect())
+}
+
+/// A Lua state for running off the main thread, with the runtime's
+/// library path and native modules. Setting `cancel` stops it at the next
+/// hook or network transfer.
+pub fn new_state(env: &SubEnv, cancel: Arc<AtomicBool>) -> LuaResult<Lua> {
+    let lua = unsafe { Lua::unsafe_new() };
+    lua.set_app_data(CancelFlag(cancel.clone()));
+    // Pure-Lua loops have no transfer to check the flag, so poll it from a
+    // count hook too.
+    lua.set_hook(
+        LuaHookTriggers::new().every_nth_instruction(10_000),
+        move |_, _| {
+            if cancel.load(Ordering::Relaxed) {
+                Err(LuaError::RuntimeError("script aborted".into()))
+            } else {
+                Ok(())
+            }
+        },
+    );
+
+    {
+        let package: LuaTable = lua.globals().get("package")?;
+        let path: String = package.get("path")?;
+        let lua_dir = env.runtime_path.join("lua");
+        package.set(
+            "path",
+            format!(
+                "{};{}/?.lua;{}/?/init.lua",
+                path,
+                lua_dir.display(),
+                lua_dir.display()
+            ),
+        )?;
+    }
+    json::register(&lua)?;
+    codec::register(&lua)?;
+    xml::register(&lua)?;
+    net::register(&lua, env.net.clone())?;
+    lua_host::install_require_overrides(&lua)?;
+    Ok(lua)
+}
+
+/// The host functions a state from `new_state` may be given, by name.
+pub fn host_function<'lua>(
+    lua: &'lua Lua,
+    env: &SubEnv,
+    name: &str,
+) -> LuaResult<Option<LuaFunction<'lua>>> {
+    let func = match name {
+        "GetScriptPath" => {
+            let p = env.script_path.to_string_lossy().into_owned();
+            lua.create_function(move |_, ()| Ok(p.clone()))?
+        }
+        "GetRuntimePath" => {
+            let p = env.runtime_path.to_string_lossy().into_owned();
+            lua.create_function(move |_, ()| Ok(p.clone()))?
+        }
+        "GetUserPath" => lua.create_function(|_, ()| {
+            Ok(platform::user_path().to_string_lossy().into_owned() + "/")
+        })?,
+        "GetWorkDir" => lua.create_function(|_, ()| Ok(String::new()))?,
+        "MakeDir" => {
+            let vfs = env.vfs.clone();
+            lua.create_function(move |_, path: String| {
+                vfs.lock()
+                    .unwrap()
+                    .create_dir(&path)
+                    .map_err(LuaError::external)
+            })?
+        }
+        "LoadDataSnapshot" => snapshot::load_function(lua, &env.snapshots)?,
+        "ConPrintf" => lua.create_function(|lua, args: LuaMultiValue| {
+            let fmt: LuaFunction = lua.globals().get::<_, LuaTable>("string")?.get("format")?;
+            println!("{}", fmt.call::<_, String>(args)?);
+            Ok(())
+        })?,
+        _ => return Ok(None),
+    };
+    Ok(Some(func))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Archives;
+    use crate::net::NetState;
+    use crate::vfs::Vfs;
+
+    fn wait(subs: &SubScripts) -> Vec<SubMessage> {
+        let mut out = Vec::new();
+        for _ in 0..500 {
+            out.extend(subs.poll());
+            if out
+                .iter()
+                .any(|m| matches!(m, SubMessage::Finished(..) | SubMessage::Error(..)))
+            {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        out
+    }
+
+    #[test]
+    fn runs_forwards_calls_and_aborts() {
+        let (wakes_tx, wakes) = std::sync::mpsc::channel();
+        let subs = SubScripts::new(SubEnv {
+            script_path: PathBuf::from("src"),
+            runtime_path: PathBuf::from("runtime"),
+            net: net::lazy(NetState::default),
+            snapshots: Snapshots::default(),
+            vfs: Arc::new(Mutex::new(Vfs::new(
+                PathBuf::from("src"),
+                Archives::new(PathBuf::from("src")),
+            ))),
+            waker: Waker::new(wakes_tx),
+        });
+        let id = subs.launch(
+            "local a, b = ... UpdateProgress('half') return a + b, { ok = true }".into(),
+            "",
+            "UpdateProgress",
+            vec![SubValue::Number(1.0), SubValue::Number(2.0)],
+        );
+        assert!(subs.is_running(id));
+        let msgs = wait(&subs);
+        assert!(matches!(&msgs[0], SubMessage::Call(_, name, _) if name == "UpdateProgress"));
+        match &msgs[1] {
+            SubMessage::Finished(fid, values) => {
+                assert_eq!(*fid, id);
+                assert_eq!(values[0], SubValue::Number(3.0));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(!subs.is_running(id));
+        // Once for the call, once for the result.
+        for _ in 0..2 {
+            let wake = wakes.recv_timeout(std::time::Duration::from_secs(5));
+            assert_eq!(wake, Ok(crate::host_thread::HostEvent::Wake));
+        }
+
+        let id = subs.launch("while true do end".into(), "", "", Vec::new());
+        subs.abort(id);
+        assert!(!subs.is_running(id));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(subs.poll().is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use resvg::{tiny_skia, usvg};
+
+use crate::graphics::{PixelFormat, TextureUploadCmd};
+
+/// Largest side an SVG is rasterized to, whatever the display scale.
+const MAX_RASTER_SIZE: u32 = 4096;
+
+/// A loaded SVG. The parsed tree is kept so the texture can be rendered
+/// again when the display scale changes.
+pub struct SvgImage {
+    tree: usvg::Tree,
+    /// Display scale the current texture was rendered at.
+    pub scale: f32,
+}
+
+/// SVG-backed image handles, keyed by texture id.
+pub type SvgImages = Arc<Mutex<HashMap<u32, SvgImage>>>;
+
+impl SvgImage {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let tree =
+            usvg::Tree::from_data(data, &usvg::Options::default()).map_err(|e| e.to_string())?;
+        Ok(Self { tree, scale: 0.0 })
+    }
+
+    /// Size in SVG user units, which Lua sees as the image size.
+    pub fn size(&self) -> (u32, u32) {
+        let size = self.tree.size();
+        (
+            size.width().ceil().max(1.0) as u32,
+            size.height().ceil().max(1.0) as u32,
+        )
+    }
+
+    /// Renders the image at `scale` times its size as the texture for `id`.
+    pub fn rasterize(&mut self, id: u32, scale: f32) -> TextureUploadCmd {
+        self.scale = scale;
+        let (w, h) = self.size();
+        let scale = scale.max(0.1).min(MAX_RASTER_SIZE as f32 / w.max(h) as f32);
+        let width = ((w as f32 * scale).round() as u32).max(1);
+        let height = ((h as f32 * scale).round() as u32).max(1);
+        let mut pixmap = tiny_skia::Pixmap::new(width, height).expect("non-empty pixmap");
+        let size = self.tree.size();
+        let transform = tiny_skia::Transform::from_scale(
+            width as f32 / size.width(),
+            height as f32 / size.height(),
+        );
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+        // tiny-skia renders premultiplied; textures are straight alpha.
+        let pixels: Vec<u8> = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|p| {
+                let c = p.demultiply();
+                [c.red(), c.green(), c.blue(), c.alpha()]
+            })
+            .collect();
+        TextureUploadCmd {
+            id,
+            pixels: pixels.into(),
+            format: PixelFormat::Rgba8,
+            width,
+            height,
+            premultiplied: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rasterizes_at_display_scale() {
+        let mut svg = SvgImage::parse(
+            br##"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2">
+                <rect width="4" height="2" fill="#ff0000" fill-opacity="0.5"/>
+            </svg>"##,
+        )
+        .unwrap();
+        assert_eq!(svg.size(), (4, 2));
+        let upload = svg.rasterize(7, 2.0);
+        assert_eq!((upload.id, upload.width, upload.height), (7, 8, 4));
+        assert_eq!(svg.scale, 2.0);
+        let px = &upload.pixels[..4];
+        assert_eq!(px[0], 255);
+        assert!((127..=128).contains(&px[3]));
+        assert!(SvgImage::parse(b"not svg").is_err());
+    }
+}
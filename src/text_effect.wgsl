@@ -0,0 +1,60 @@
+struct Sizes {
+    frame: vec2<f32>,
+    layer: vec2<f32>,
+}
+
+@group(0) @binding(0) var<uniform> sizes: Sizes;
+@group(0) @binding(1) var t_layer: texture_2d<f32>;
+@group(0) @binding(2) var s_layer: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) to_layer: vec2<f32>,
+    @location(2) offset: vec2<f32>,
+    @location(3) outline: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) offset: vec2<f32>,
+    @location(2) outline: f32,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let at = in.position / sizes.frame;
+    out.clip_position = vec4<f32>(at.x * 2.0 - 1.0, 1.0 - at.y * 2.0, 0.0, 1.0);
+    out.uv = (in.position + in.to_layer) / sizes.layer;
+    out.offset = in.offset / sizes.layer;
+    out.outline = in.outline;
+    return out;
+}
+
+// The layer holds each text once in its effect colour, premultiplied. A
+// shadow is one copy moved by `offset`; an outline is eight copies moved
+// `offset.x` out in each direction, laid over one another in order.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if in.outline < 0.5 {
+        return textureSampleLevel(t_layer, s_layer, in.uv - in.offset, 0.0);
+    }
+    let s = 0.70710678;
+    var around = array<vec2<f32>, 8>(
+        vec2<f32>(-1.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, -1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(-s, -s),
+        vec2<f32>(s, -s),
+        vec2<f32>(-s, s),
+        vec2<f32>(s, s),
+    );
+    var c = vec4<f32>(0.0);
+    for (var i = 0; i < 8; i++) {
+        let copy = textureSampleLevel(t_layer, s_layer, in.uv - around[i] * in.offset, 0.0);
+        c = copy + c * (1.0 - copy.a);
+    }
+    return c;
+}
@@ -0,0 +1,514 @@
+use crate::graphics::TextEffect;
+
+/// A corner of the area an effect covers, with what the shader needs to
+/// draw it. Positions are physical pixels in the pass.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct EffectVertex {
+    position: [f32; 2],
+    /// From the pass to where the text sits in the layer.
+    to_layer: [f32; 2],
+    /// A shadow's offset, or an outline's width in both components.
+    offset: [f32; 2],
+    /// 1 for an outline.
+    outline: f32,
+}
+
+impl EffectVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x2,
+        2 => Float32x2,
+        3 => Float32,
+    ];
+
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<EffectVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+impl TextEffect {
+    /// How far the copies reach from the text, in UI units.
+    pub fn extent(&self) -> f32 {
+        match *self {
+            Self::Shadow { offset, .. } => offset[0].abs().max(offset[1].abs()),
+            Self::Outline { width, .. } => width.abs(),
+        }
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        match *self {
+            Self::Shadow { color, .. } | Self::Outline { color, .. } => color,
+        }
+    }
+}
+
+/// Where one text's effect is drawn, physical pixels as left, top, right,
+/// bottom: `quad` in the pass, and `region`, the part of the pass its copy
+/// in the layer covers. The region holds everything the quad samples.
+#[derive(Debug, PartialEq)]
+struct Placement {
+    quad: [f32; 4],
+    region: [i32; 4],
+}
+
+/// Places an effect reaching `reach` pixels around `rect` (x, y, width,
+/// height), cut to `bounds` (left, top, right, bottom); None if none of
+/// it shows.
+fn place(rect: [f32; 4], reach: f32, bounds: [f32; 4]) -> Option<Placement> {
+    let quad = [
+        (rect[0] - reach).max(bounds[0]),
+        (rect[1] - reach).max(bounds[1]),
+        (rect[0] + rect[2] + reach).min(bounds[2]),
+        (rect[1] + rect[3] + reach).min(bounds[3]),
+    ];
+    if quad[0] >= quad[2] || quad[1] >= quad[3] {
+        return None;
+    }
+    let region = [
+        (quad[0] - reach).floor() as i32,
+        (quad[1] - reach).floor() as i32,
+        (quad[2] + reach).ceil() as i32,
+        (quad[3] + reach).ceil() as i32,
+    ];
+    Some(Placement { quad, region })
+}
+
+/// Lays boxes of `sizes` out in rows `width` wide, each apart from the
+/// others. Returns their top-left corners and the height used.
+fn pack(sizes: &[[u32; 2]], width: u32) -> (Vec<[u32; 2]>, u32) {
+    let (mut x, mut y, mut row) = (0, 0, 0);
+    let corners = sizes
+        .iter()
+        .map(|&[w, h]| {
+            if x + w > width {
+                (x, y, row) = (0, y + row, 0);
+            }
+            let corner = [x, y];
+            x += w;
+            row = row.max(h);
+            corner
+        })
+        .collect();
+    (corners, y + row)
+}
+
+/// The two triangles drawing `effect` on `quad` at `zoom`.
+fn vertices(
+    effect: &TextEffect,
+    quad: [f32; 4],
+    to_layer: [f32; 2],
+    zoom: f32,
+) -> [EffectVertex; 6] {
+    let (offset, outline) = match *effect {
+        TextEffect::Shadow { offset, .. } => ([offset[0] * zoom, offset[1] * zoom], 0.0),
+        TextEffect::Outline { width, .. } => ([width * zoom; 2], 1.0),
+    };
+    let v = |x, y| EffectVertex {
+        position: [x, y],
+        to_layer,
+        offset,
+        outline,
+    };
+    let [x0, y0, x1, y1] = quad;
+    [
+        v(x0, y0),
+        v(x1, y0),
+        v(x0, y1),
+        v(x0, y1),
+        v(x1, y0),
+        v(x1, y1),
+    ]
+}
+
+/// What `TextEffects::prepare` draws: the text in its effect colour and
+/// where its copies go.
+pub struct EffectText<'a> {
+    pub area: glyphon::TextArea<'a>,
+    pub effect: TextEffect,
+    /// The text's box, physical pixels.
+    pub rect: [f32; 4],
+}
+
+/// The texture one pass's effect texts are drawn into.
+struct LayerTexture {
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+/// One pass's effect texts, each drawn once into its own region of a
+/// layer texture. Glyphon keeps one resolution per atlas, so each layer
+/// has its own.
+struct Layer {
+    texture: Option<LayerTexture>,
+    uniform: wgpu::Buffer,
+    atlas: glyphon::TextAtlas,
+    text: glyphon::TextRenderer,
+    vertices: wgpu::Buffer,
+    /// Vertices to draw this frame; 0 when the pass has no effects.
+    count: u32,
+    /// The pass's size, physical pixels.
+    pass: (u32, u32),
+}
+
+/// Shadows and outlines for the text passes. Each pass's effect texts are
+/// rasterized once, in their effect colour, into a layer; one draw then
+/// lays all of their copies from it under the text, so an outline costs a
+/// glyph pass like a shadow does.
+pub struct TextEffects {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    format: wgpu::TextureFormat,
+    /// By text renderer slot.
+    layers: Vec<Layer>,
+}
+
+/// Layer textures grow in steps of this many pixels a side.
+const LAYER_STEP: u32 = 256;
+
+impl TextEffects {
+    /// Effects drawn in `format`, the glyph atlas's.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("text effect"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("text_effect.wgsl").into()),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("text effect"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("text effect"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("text effect"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[EffectVertex::layout()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            pipeline,
+            layout,
+            sampler,
+            format,
+            layers: Vec::new(),
+        }
+    }
+
+    /// The layer for `slot`, its texture at least `size`.
+    fn layer(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        slot: usize,
+        size: (u32, u32),
+    ) -> &mut Layer {
+        while self.layers.len() <= slot {
+            let mut atlas = glyphon::TextAtlas::new(device, queue, self.format);
+            let text = glyphon::TextRenderer::new(
+                &mut atlas,
+                device,
+                wgpu::MultisampleState::default(),
+                None,
+            );
+            self.layers.push(Layer {
+                texture: None,
+                uniform: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("text effect"),
+                    size: 16,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                atlas,
+                text,
+                vertices: vertex_buffer(device, 0),
+                count: 0,
+                pass: (0, 0),
+            });
+        }
+        let layer = &mut self.layers[slot];
+        let fits = |t: &LayerTexture| t.size.0 >= size.0 && t.size.1 >= size.1;
+        if !layer.texture.as_ref().is_some_and(fits) {
+            let old = layer.texture.as_ref().map_or((0, 0), |t| t.size);
+            let step = |n: u32| n.div_ceil(LAYER_STEP) * LAYER_STEP;
+            let max = device.limits().max_texture_dimension_2d;
+            let size = (
+                step(size.0.max(old.0)).min(max),
+                step(size.1.max(old.1)).min(max),
+            );
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("text effect layer"),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&Default::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("text effect"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: layer.uniform.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+            layer.texture = Some(LayerTexture {
+                view,
+                bind_group,
+                size,
+            });
+        }
+        layer
+    }
+
+    /// Readies the effects of pass `slot`, `size` physical pixels at
+    /// `zoom`, for `render_layer` and `draw`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        slot: usize,
+        size: (u32, u32),
+        zoom: f32,
+        font_system: &mut glyphon::FontSystem,
+        swash_cache: &mut glyphon::SwashCache,
+        texts: Vec<EffectText>,
+    ) -> Result<(), glyphon::PrepareError> {
+        if let Some(layer) = self.layers.get_mut(slot) {
+            layer.count = 0;
+        }
+        let placed: Vec<_> = texts
+            .into_iter()
+            .filter_map(|text| {
+                let reach = (text.effect.extent() * zoom).ceil() + 1.0;
+                let b = text.area.bounds;
+                let bounds = [b.left as f32, b.top as f32, b.right as f32, b.bottom as f32];
+                place(text.rect, reach, bounds).map(|p| (text, p))
+            })
+            .collect();
+        if placed.is_empty() {
+            return Ok(());
+        }
+        let sizes: Vec<[u32; 2]> = placed
+            .iter()
+            .map(|(_, p)| {
+                let r = p.region;
+                [(r[2] - r[0]) as u32, (r[3] - r[1]) as u32]
+            })
+            .collect();
+        let max = device.limits().max_texture_dimension_2d;
+        let width = sizes.iter().map(|s| s[0]).fold(size.0, u32::max).min(max);
+        let (corners, height) = pack(&sizes, width);
+        let layer = self.layer(device, queue, slot, (width, height.min(max)));
+        let layer_size = layer.texture.as_ref().unwrap().size;
+
+        let mut quads = Vec::with_capacity(placed.len() * 6);
+        let mut areas = Vec::with_capacity(placed.len());
+        for (((text, p), [x, y]), [w, h]) in placed.into_iter().zip(corners).zip(sizes) {
+            // Too big for the layer; its effect is left out.
+            if x + w > layer_size.0 || y + h > layer_size.1 {
+                continue;
+            }
+            let to_layer = [x as i32 - p.region[0], y as i32 - p.region[1]].map(|d| d as f32);
+            quads.extend(vertices(&text.effect, p.quad, to_layer, zoom));
+            let mut area = text.area;
+            area.left += to_layer[0];
+            area.top += to_layer[1];
+            area.bounds = glyphon::TextBounds {
+                left: x as i32,
+                top: y as i32,
+                right: (x + w) as i32,
+                bottom: (y + h) as i32,
+            };
+            areas.push(area);
+        }
+        let bytes: &[u8] = bytemuck::cast_slice(&quads);
+        if layer.vertices.size() < bytes.len() as u64 {
+            layer.vertices = vertex_buffer(device, bytes.len() as u64);
+        }
+        queue.write_buffer(&layer.vertices, 0, bytes);
+        let sizes = [
+            size.0 as f32,
+            size.1 as f32,
+            layer_size.0 as f32,
+            layer_size.1 as f32,
+        ];
+        queue.write_buffer(&layer.uniform, 0, bytemuck::cast_slice(&sizes));
+        layer.count = quads.len() as u32;
+        layer.pass = size;
+        layer.text.prepare(
+            device,
+            queue,
+            font_system,
+            &mut layer.atlas,
+            glyphon::Resolution {
+                width: layer_size.0,
+                height: layer_size.1,
+            },
+            areas,
+            swash_cache,
+        )
+    }
+
+    /// Draws pass `slot`'s effect texts into its layer; this goes before
+    /// the pass itself.
+    pub fn render_layer(
+        &self,
+        slot: usize,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), glyphon::RenderError> {
+        let Some(layer) = self.layers.get(slot).filter(|l| l.count > 0) else {
+            return Ok(());
+        };
+        let Some(texture) = &layer.texture else {
+            return Ok(());
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("text effect layer"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        layer.text.render(&layer.atlas, &mut pass)
+    }
+
+    /// Lays pass `slot`'s copies from its layer into `pass`.
+    pub fn draw<'pass>(&'pass self, slot: usize, pass: &mut wgpu::RenderPass<'pass>) {
+        let Some(layer) = self.layers.get(slot).filter(|l| l.count > 0) else {
+            return;
+        };
+        let Some(texture) = &layer.texture else {
+            return;
+        };
+        pass.set_scissor_rect(0, 0, layer.pass.0, layer.pass.1);
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &texture.bind_group, &[]);
+        pass.set_vertex_buffer(0, layer.vertices.slice(..));
+        pass.draw(0..layer.count, 0..1);
+    }
+}
+
+fn vertex_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("text effect"),
+        // Room for a few dozen effect texts before it has to grow.
+        size: size.max(std::mem::size_of::<EffectVertex>() as u64 * 6 * 64),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effects_reach_past_the_text_within_its_clip_from_apart_regions() {
+        let clip = [0.0, 0.0, 25.0, 100.0];
+        let p = place([10.0, 10.0, 20.0, 8.0], 3.0, clip).unwrap();
+        assert_eq!(p.quad, [7.0, 7.0, 25.0, 21.0]);
+        assert_eq!(p.region, [4, 4, 28, 24]);
+        assert_eq!(place([30.0, 10.0, 5.0, 5.0], 1.0, clip), None);
+
+        let (corners, height) = pack(&[[30, 10], [40, 20], [50, 5], [10, 10]], 80);
+        assert_eq!(corners, [[0, 0], [30, 0], [0, 20], [50, 20]]);
+        assert_eq!(height, 30);
+
+        let outline = TextEffect::Outline {
+            width: 2.0,
+            color: [0.0; 4],
+        };
+        let v = vertices(&outline, p.quad, [1.0, 2.0], 1.5);
+        assert!(v.iter().all(|v| v.offset == [3.0, 3.0] && v.outline == 1.0));
+        let shadow = TextEffect::Shadow {
+            offset: [1.0, -2.0],
+            color: [0.0; 4],
+        };
+        assert_eq!(shadow.extent(), 2.0);
+        let v = vertices(&shadow, p.quad, [1.0, 2.0], 2.0);
+        assert_eq!((v[0].offset, v[0].outline), ([2.0, -4.0], 0.0));
+    }
+}
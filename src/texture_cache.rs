@@ -0,0 +1,298 @@
+use std::{fs::File, io, path::PathBuf, sync::Arc};
+
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+
+use crate::graphics::{PixelFormat, Pixels, TextureUploadCmd, premultiply_alpha};
+
+/// Leads every cache file, followed by format, width and height as
+/// little-endian u32 and then the pixels, premultiplied.
+const MAGIC: &[u8; 8] = b"PoBTEX3\0";
+const HEADER_LEN: usize = 20;
+
+/// Smaller images decode faster than a cache lookup is worth.
+const MIN_PIXELS: u64 = 256 * 256;
+
+/// Decoded images on disk, keyed by a hash of the source file and the
+/// settings they were processed with, so later launches skip decoding.
+pub struct TextureCache {
+    dir: PathBuf,
+    /// Store BC3 blocks instead of RGBA where the size allows.
+    compress: bool,
+}
+
+impl TextureCache {
+    pub fn new(dir: PathBuf, compress: bool) -> Self {
+        Self { dir, compress }
+    }
+
+    fn file(&self, source: &[u8]) -> PathBuf {
+        let mut hash = Sha256::new();
+        hash.update(source);
+        hash.update([self.compress as u8]);
+        self.dir.join(format!("{:x}.tex", hash.finalize()))
+    }
+
+    /// The processed copy of `source`, if one was stored.
+    pub fn load(&self, id: u32, source: &[u8]) -> Option<TextureUploadCmd> {
+        let file = File::open(self.file(source)).ok()?;
+        // SAFETY: cache files are only ever replaced by rename, never
+        // written in place.
+        let map = unsafe { Mmap::map(&file) }.ok()?;
+        if map.len() < HEADER_LEN {
+            return None;
+        }
+        let rest = map.strip_prefix(MAGIC)?;
+        let word = |i: usize| u32::from_le_bytes(rest[i * 4..i * 4 + 4].try_into().unwrap());
+        let format = match word(0) {
+            0 => PixelFormat::Rgba8,
+            1 => PixelFormat::Bc3,
+            _ => return None,
+        };
+        let (width, height) = (word(1), word(2));
+        (Some(map.len() - HEADER_LEN) == format.size(width, height)).then(|| TextureUploadCmd {
+            id,
+            pixels: Pixels::Mapped(Arc::new(map), HEADER_LEN),
+            format,
+            width,
+            height,
+            premultiplied: true,
+        })
+    }
+
+    /// Keeps a freshly decoded `upload` of `source`, compressing it first
+    /// when enabled. Returns what should be uploaded.
+    pub fn store(&self, source: &[u8], upload: TextureUploadCmd) -> TextureUploadCmd {
+        if u64::from(upload.width) * u64::from(upload.height) < MIN_PIXELS {
+            return upload;
+        }
+        let mut upload = upload;
+        if upload.format == PixelFormat::Rgba8 && !upload.premultiplied {
+            premultiply_alpha(upload.pixels.to_mut());
+            upload.premultiplied = true;
+        }
+        let upload =
+            if self.compress && upload.width.is_multiple_of(4) && upload.height.is_multiple_of(4) {
+                TextureUploadCmd {
+                    pixels: encode_bc3(&upload.pixels, upload.width, upload.height).into(),
+                    format: PixelFormat::Bc3,
+                    ..upload
+                }
+            } else {
+                upload
+            };
+        if let Err(e) = self.write(source, &upload) {
+            eprintln!("texture cache {}: {}", self.dir.display(), e);
+        }
+        upload
+    }
+
+    fn write(&self, source: &[u8], upload: &TextureUploadCmd) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let format: u32 = match upload.format {
+            PixelFormat::Rgba8 => 0,
+            PixelFormat::Bc3 => 1,
+        };
+        let mut data = Vec::with_capacity(HEADER_LEN + upload.pixels.len());
+        data.extend_from_slice(MAGIC);
+        for word in [format, upload.width, upload.height] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&upload.pixels);
+        let path = self.file(source);
+        let part = path.with_extension("part");
+        std::fs::write(&part, data)?;
+        std::fs::rename(&part, &path)
+    }
+}
+
+fn to_565(c: [u8; 3]) -> u16 {
+    let scale = |v: u8, max: u32| ((v as u32 * max + 127) / 255) as u16;
+    (scale(c[0], 31) << 11) | (scale(c[1], 63) << 5) | scale(c[2], 31)
+}
+
+fn from_565(c: u16) -> [u8; 3] {
+    let (r, g, b) = ((c >> 11) & 31, (c >> 5) & 63, c & 31);
+    [
+        (r << 3 | r >> 2) as u8,
+        (g << 2 | g >> 4) as u8,
+        (b << 3 | b >> 2) as u8,
+    ]
+}
+
+fn color_palette(c0: u16, c1: u16) -> [[u8; 3]; 4] {
+    let (a, b) = (from_565(c0), from_565(c1));
+    let mix = |x: u8, y: u8| ((2 * x as u16 + y as u16) / 3) as u8;
+    [
+        a,
+        b,
+        [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])],
+        [mix(b[0], a[0]), mix(b[1], a[1]), mix(b[2], a[2])],
+    ]
+}
+
+fn alpha_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a, b) = (a0 as u16, a1 as u16);
+    let mut out = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            out[i + 1] = (((7 - i as u16) * a + i as u16 * b) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            out[i + 1] = (((5 - i as u16) * a + i as u16 * b) / 5) as u8;
+        }
+    }
+    out
+}
+
+/// Compresses RGBA pixels to BC3 (DXT5) blocks with a bounding-box fit.
+/// Width and height must be multiples of 4.
+pub fn encode_bc3(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let mut out = Vec::with_capacity(w * h);
+    for by in (0..h).step_by(4) {
+        for bx in (0..w).step_by(4) {
+            let texel = |i: usize| {
+                let at = ((by + i / 4) * w + bx + i % 4) * 4;
+                [rgba[at], rgba[at + 1], rgba[at + 2], rgba[at + 3]]
+            };
+            let block: Vec<[u8; 4]> = (0..16).map(texel).collect();
+
+            let a0 = block.iter().map(|p| p[3]).max().unwrap();
+            let a1 = block.iter().map(|p| p[3]).min().unwrap();
+            let alphas = alpha_palette(a0, a1);
+            let mut bits = 0u64;
+            for (i, p) in block.iter().enumerate() {
+                let best = (0..8)
+                    .min_by_key(|&k| (alphas[k] as i32 - p[3] as i32).abs())
+                    .unwrap();
+                bits |= (best as u64) << (3 * i);
+            }
+            out.extend_from_slice(&[a0, a1]);
+            out.extend_from_slice(&bits.to_le_bytes()[..6]);
+
+            let mut lo = [255u8; 3];
+            let mut hi = [0u8; 3];
+            for p in &block {
+                for c in 0..3 {
+                    lo[c] = lo[c].min(p[c]);
+                    hi[c] = hi[c].max(p[c]);
+                }
+            }
+            let (mut c0, mut c1) = (to_565(hi), to_565(lo));
+            if c0 < c1 {
+                std::mem::swap(&mut c0, &mut c1);
+            }
+            let colors = color_palette(c0, c1);
+            let mut bits = 0u32;
+            for (i, p) in block.iter().enumerate() {
+                let best = (0..4)
+                    .min_by_key(|&k| {
+                        (0..3)
+                            .map(|c| (colors[k][c] as i32 - p[c] as i32).pow(2))
+                            .sum::<i32>()
+                    })
+                    .unwrap();
+                bits |= (best as u32) << (2 * i);
+            }
+            out.extend_from_slice(&c0.to_le_bytes());
+            out.extend_from_slice(&c1.to_le_bytes());
+            out.extend_from_slice(&bits.to_le_bytes());
+        }
+    }
+    out
+}
+
+/// Expands BC3 blocks back to RGBA, for GPUs without BC support.
+pub fn decode_bc3(blocks: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let mut out = vec![0; w * h * 4];
+    for (n, block) in blocks.chunks_exact(16).enumerate() {
+        let (bx, by) = (n % w.div_ceil(4) * 4, n / w.div_ceil(4) * 4);
+        let alphas = alpha_palette(block[0], block[1]);
+        let mut abits = [0u8; 8];
+        abits[..6].copy_from_slice(&block[2..8]);
+        let abits = u64::from_le_bytes(abits);
+        let c0 = u16::from_le_bytes([block[8], block[9]]);
+        let c1 = u16::from_le_bytes([block[10], block[11]]);
+        let colors = color_palette(c0, c1);
+        let cbits = u32::from_le_bytes(block[12..16].try_into().unwrap());
+        for i in 0..16 {
+            let (x, y) = (bx + i % 4, by + i / 4);
+            if x >= w || y >= h {
+                continue;
+            }
+            let at = (y * w + x) * 4;
+            let color = colors[(cbits >> (2 * i)) as usize & 3];
+            out[at..at + 3].copy_from_slice(&color);
+            out[at + 3] = alphas[(abits >> (3 * i)) as usize & 7];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caches_compressed_textures_by_source_and_settings() {
+        let (width, height) = (256, 256);
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = ((i % width) as u8, (i / width) as u8);
+                [x, y, 128, x / 2 + y / 2]
+            })
+            .collect();
+        let source = b"png bytes".to_vec();
+        let upload = TextureUploadCmd {
+            id: 1,
+            pixels: rgba.clone().into(),
+            format: PixelFormat::Rgba8,
+            width,
+            height,
+            premultiplied: false,
+        };
+
+        let dir = std::env::temp_dir().join(format!("pob-textures-{}", std::process::id()));
+        let cache = TextureCache::new(dir.clone(), true);
+        assert!(cache.load(1, &source).is_none());
+        let stored = cache.store(&source, upload.clone());
+        assert_eq!(stored.format, PixelFormat::Bc3);
+        assert_eq!(stored.pixels.len(), (width * height) as usize);
+
+        let loaded = cache.load(7, &source).unwrap();
+        assert_eq!((loaded.id, loaded.format), (7, PixelFormat::Bc3));
+        assert!(matches!(loaded.pixels, Pixels::Mapped(..)));
+        assert_eq!(*loaded.pixels, *stored.pixels);
+        // Blocks hold the premultiplied pixels the pipeline blends.
+        let decoded = decode_bc3(&loaded.pixels, width, height);
+        let mut premultiplied = rgba.clone();
+        premultiply_alpha(&mut premultiplied);
+        let worst = decoded
+            .iter()
+            .zip(&premultiplied)
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(worst <= 8, "error {}", worst);
+
+        // Other settings miss the compressed copy.
+        let plain = TextureCache::new(dir.clone(), false);
+        assert!(plain.load(1, &source).is_none());
+        assert_eq!(plain.store(&source, upload).format, PixelFormat::Rgba8);
+        let loaded = plain.load(1, &source).unwrap();
+        assert!(loaded.premultiplied);
+        assert_eq!(*loaded.pixels, premultiplied[..]);
+
+        // A header claiming more pixels than fit is a miss, not a panic.
+        let mut data = MAGIC.to_vec();
+        for word in [0, u32::MAX, u32::MAX] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        std::fs::write(plain.file(b"hostile"), data).unwrap();
+        assert!(plain.load(1, b"hostile").is_none());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
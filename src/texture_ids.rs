@@ -0,0 +1,136 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use mlua::prelude::*;
+
+use crate::graphics::{TextureCmd, TextureQueue};
+
+/// Frames that may still be queued or drawing while Lua builds the next:
+/// the one being sent and the one the window thread is presenting.
+const FRAMES_IN_FLIGHT: u64 = 2;
+
+/// Texture ids shared by image handles and render targets. Released ids
+/// are handed out again lowest first, so ids stay small and a session that
+/// reloads the same images gets the same ids back. They wait out the
+/// frames in flight first, since draws queued in those may still name them.
+#[derive(Default)]
+pub struct TextureIds {
+    next: u32,
+    free: BTreeSet<u32>,
+    frame: u64,
+    /// Released ids and the frame they were released in.
+    quarantine: VecDeque<(u64, u32)>,
+}
+
+pub type SharedTextureIds = Arc<Mutex<TextureIds>>;
+
+impl TextureIds {
+    pub fn alloc(&mut self) -> u32 {
+        if let Some(id) = self.free.pop_first() {
+            return id;
+        }
+        self.next += 1;
+        self.next
+    }
+
+    pub fn release(&mut self, id: u32) {
+        if id != 0 && id <= self.next {
+            self.quarantine.push_back((self.frame, id));
+        }
+    }
+
+    /// Starts the next frame, freeing ids no frame in flight can use.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+        while let Some(&(frame, id)) = self.quarantine.front()
+            && frame + FRAMES_IN_FLIGHT <= self.frame
+        {
+            self.quarantine.pop_front();
+            self.free.insert(id);
+        }
+    }
+}
+
+/// Ties a texture id to the Lua handle holding it: when the handle is
+/// collected the texture is unloaded, `cleanup` forgets anything else kept
+/// under the id and the id goes back to the pool.
+pub struct TextureLease {
+    id: u32,
+    ids: SharedTextureIds,
+    queue: TextureQueue,
+    cleanup: Option<Box<dyn FnOnce(u32)>>,
+}
+
+impl TextureLease {
+    pub fn new(
+        ids: SharedTextureIds,
+        queue: TextureQueue,
+        cleanup: impl FnOnce(u32) + 'static,
+    ) -> Self {
+        let id = ids.lock().unwrap().alloc();
+        Self {
+            id,
+            ids,
+            queue,
+            cleanup: Some(Box::new(cleanup)),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for TextureLease {
+    fn drop(&mut self) {
+        self.queue.lock().unwrap().push(TextureCmd::Unload(self.id));
+        if let Some(cleanup) = self.cleanup.take() {
+            cleanup(self.id);
+        }
+        self.ids.lock().unwrap().release(self.id);
+    }
+}
+
+impl LuaUserData for TextureLease {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collected_handles_unload_and_return_their_ids() {
+        let ids = SharedTextureIds::default();
+        let queue = TextureQueue::default();
+        let lua = Lua::new();
+        let lease = || {
+            let t = lua.create_table().unwrap();
+            let lease = TextureLease::new(ids.clone(), queue.clone(), |_| {});
+            t.set("id", lease.id()).unwrap();
+            t.set("lease", lua.create_userdata(lease).unwrap()).unwrap();
+            t
+        };
+        let (a, b) = (lease(), lease());
+        assert_eq!(
+            (
+                a.get::<_, u32>("id").unwrap(),
+                b.get::<_, u32>("id").unwrap()
+            ),
+            (1, 2)
+        );
+        drop(a);
+        lua.gc_collect().unwrap();
+        lua.gc_collect().unwrap();
+        assert!(matches!(queue.lock().unwrap()[..], [TextureCmd::Unload(1)]));
+        // Draws in this frame and the one before may still use id 1.
+        assert_eq!(lease().get::<_, u32>("id").unwrap(), 3);
+        ids.lock().unwrap().next_frame();
+        assert!(ids.lock().unwrap().free.is_empty());
+        ids.lock().unwrap().next_frame();
+        assert_eq!(ids.lock().unwrap().free, BTreeSet::from([1]));
+        let (c, d) = (lease(), lease());
+        assert_eq!(c.get::<_, u32>("id").unwrap(), 1);
+        assert_eq!(d.get::<_, u32>("id").unwrap(), 4);
+    }
+}
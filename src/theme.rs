@@ -0,0 +1,257 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+
+use crate::escapes::hex_color;
+use crate::graphics::{
+    DrawCmd, DrawItem, DrawQueue, MeshCmd, PixelFormat, TextureCmd, TextureQueue, TextureUploadCmd,
+    Vertex,
+};
+use crate::platform::user_path;
+use crate::settings::ThemeSettings;
+use crate::texture_ids::{SharedTextureIds, TextureLease};
+
+/// The part of the vignette, from each edge, that darkens towards it.
+const VIGNETTE_REACH: f32 = 0.3;
+
+/// What the window shows behind PoB's UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// Straight alpha; below 1 shows through translucent windows.
+    pub clear: [f32; 4],
+    /// How dark the vignette gets at the edges, 0 for none.
+    pub vignette: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            clear: [0.05, 0.05, 0.05, 1.0],
+            vignette: 0.0,
+        }
+    }
+}
+
+/// Read by the window thread for the clear colour.
+pub type SharedTheme = Arc<Mutex<Theme>>;
+
+impl Theme {
+    /// The `[theme]` settings; a bad colour is reported and left default.
+    pub fn from_settings(settings: &ThemeSettings) -> Self {
+        let mut theme = Self::default();
+        if let Some(hex) = &settings.clear_color {
+            match hex_color(hex) {
+                Some([r, g, b]) => theme.clear = [r, g, b, 1.0],
+                None => eprintln!("theme: clear_color {:?} isn't RRGGBB", hex),
+            }
+        }
+        theme.vignette = settings.vignette.unwrap_or(0.0).clamp(0.0, 1.0);
+        theme
+    }
+
+    /// The clear colour, premultiplied like everything else drawn.
+    pub fn clear_color(&self) -> wgpu::Color {
+        let [r, g, b, a] = self.clear.map(f64::from);
+        wgpu::Color {
+            r: r * a,
+            g: g * a,
+            b: b * a,
+            a,
+        }
+    }
+}
+
+/// A screen-filling draw of an `image`-sized texture, cropped to the window's shape.
+fn cover(id: u32, image: [u32; 2], screen: [f32; 2]) -> DrawItem {
+    let [w, h] = screen;
+    let scale = (w / image[0] as f32).max(h / image[1] as f32);
+    let u = w / (image[0] as f32 * scale);
+    let v = h / (image[1] as f32 * scale);
+    DrawItem::Rect(DrawCmd {
+        x: 0.0,
+        y: 0.0,
+        w,
+        h,
+        color: [1.0; 4],
+        texture_id: id,
+        uv: [
+            (1.0 - u) / 2.0,
+            (1.0 - v) / 2.0,
+            (1.0 + u) / 2.0,
+            (1.0 + v) / 2.0,
+        ],
+        clip: None,
+        corners: None,
+    })
+}
+
+/// A frame of gradients between the screen edges, at `strength` black,
+/// and a clear rectangle inside them.
+fn vignette(strength: f32, screen: [f32; 2]) -> DrawItem {
+    let [w, h] = screen;
+    let (dx, dy) = (w * VIGNETTE_REACH, h * VIGNETTE_REACH);
+    let outer = [[0.0, 0.0], [w, 0.0], [w, h], [0.0, h]];
+    let inner = [[dx, dy], [w - dx, dy], [w - dx, h - dy], [dx, h - dy]];
+    let vertex = |position, alpha| Vertex {
+        position,
+        uv: [0.0; 2],
+        color: [0.0, 0.0, 0.0, alpha],
+    };
+    let mut vertices = Vec::with_capacity(24);
+    for i in 0..4 {
+        let j = (i + 1) % 4;
+        let (o0, o1) = (vertex(outer[i], strength), vertex(outer[j], strength));
+        let (i0, i1) = (vertex(inner[i], 0.0), vertex(inner[j], 0.0));
+        vertices.extend([o0, o1, i1, o0, i1, i0]);
+    }
+    DrawItem::Mesh(MeshCmd {
+        vertices,
+        clip: None,
+    })
+}
+
+/// The uploaded background image and its size in pixels.
+type BackgroundImage = Arc<Mutex<Option<(TextureLease, [u32; 2])>>>;
+
+/// The background image and vignette, queued ahead of each frame's draws.
+#[derive(Clone)]
+pub struct Backdrop {
+    theme: SharedTheme,
+    ids: SharedTextureIds,
+    queue: TextureQueue,
+    image: BackgroundImage,
+}
+
+impl Backdrop {
+    /// Also loads the settings' background image.
+    pub fn new(
+        theme: SharedTheme,
+        ids: SharedTextureIds,
+        queue: TextureQueue,
+        settings: &ThemeSettings,
+    ) -> Self {
+        let backdrop = Self {
+            theme,
+            ids,
+            queue,
+            image: Arc::default(),
+        };
+        if let Some(path) = &settings.background
+            && let Err(e) = backdrop.set_image(Some(path))
+        {
+            eprintln!("theme: background {}: {}", path.display(), e);
+        }
+        backdrop
+    }
+
+    /// Shows the image at `path`, relative to the user path, or none.
+    fn set_image(&self, path: Option<&Path>) -> Result<(), String> {
+        let Some(path) = path else {
+            *self.image.lock().unwrap() = None;
+            return Ok(());
+        };
+        let pixels = image::open(user_path().join(path))
+            .map_err(|e| e.to_string())?
+            .to_rgba8();
+        let size = [pixels.width(), pixels.height()];
+        let lease = TextureLease::new(self.ids.clone(), self.queue.clone(), |_| {});
+        self.queue
+            .lock()
+            .unwrap()
+            .push(TextureCmd::Upload(TextureUploadCmd {
+                id: lease.id(),
+                pixels: pixels.into_raw().into(),
+                format: PixelFormat::Rgba8,
+                width: size[0],
+                height: size[1],
+                premultiplied: false,
+            }));
+        *self.image.lock().unwrap() = Some((lease, size));
+        Ok(())
+    }
+
+    /// Queues the backdrop for a screen of `size` UI units.
+    pub fn draw(&self, queue: &DrawQueue, size: [u32; 2]) {
+        let screen = size.map(|v| v as f32);
+        let mut items = Vec::new();
+        if let Some((lease, image)) = &*self.image.lock().unwrap() {
+            items.push(cover(lease.id(), *image, screen));
+        }
+        let strength = self.theme.lock().unwrap().vignette;
+        if strength > 0.0 {
+            items.push(vignette(strength, screen));
+        }
+        queue.lock().unwrap().extend(items);
+    }
+
+    /// Registers `SetClearColor(r, g, b, [a])`, `SetBackgroundImage([path])
+    /// -> ok, err` and `SetVignette(strength)`. Background paths are
+    /// relative to the user path, as in the settings file.
+    pub fn register(&self, lua: &Lua) -> LuaResult<()> {
+        let g = lua.globals();
+        let theme = self.theme.clone();
+        g.set(
+            "SetClearColor",
+            lua.create_function(move |_, (r, g, b, a): (f32, f32, f32, Option<f32>)| {
+                let clear = [r, g, b, a.unwrap_or(1.0)].map(|c| c.clamp(0.0, 1.0));
+                theme.lock().unwrap().clear = clear;
+                Ok(())
+            })?,
+        )?;
+        let backdrop = self.clone();
+        g.set(
+            "SetBackgroundImage",
+            lua.create_function(move |_, path: Option<String>| {
+                Ok(match backdrop.set_image(path.as_deref().map(Path::new)) {
+                    Ok(()) => (true, None),
+                    Err(e) => (false, Some(e)),
+                })
+            })?,
+        )?;
+        let theme = self.theme.clone();
+        g.set(
+            "SetVignette",
+            lua.create_function(move |_, strength: f32| {
+                theme.lock().unwrap().vignette = strength.clamp(0.0, 1.0);
+                Ok(())
+            })?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_covers_the_screen_and_vignette_fades_inwards() {
+        // A square image on a wide screen loses its top and bottom.
+        let DrawItem::Rect(bg) = cover(5, [100, 100], [200.0, 100.0]) else {
+            panic!("not a rect");
+        };
+        assert_eq!((bg.w, bg.h, bg.texture_id), (200.0, 100.0, 5));
+        assert_eq!(bg.uv, [0.0, 0.25, 1.0, 0.75]);
+
+        let DrawItem::Mesh(mesh) = vignette(0.6, [100.0, 50.0]) else {
+            panic!("not a mesh");
+        };
+        assert_eq!(mesh.vertices.len(), 24);
+        for v in &mesh.vertices {
+            let edge = v.position[0] == 0.0
+                || v.position[0] == 100.0
+                || v.position[1] == 0.0
+                || v.position[1] == 50.0;
+            assert_eq!(v.color[3], if edge { 0.6 } else { 0.0 });
+        }
+
+        let settings = ThemeSettings {
+            clear_color: Some("#336699".into()),
+            vignette: Some(4.0),
+            background: None,
+        };
+        let theme = Theme::from_settings(&settings);
+        assert_eq!(theme.clear, [0.2, 0.4, 0.6, 1.0]);
+        assert_eq!(theme.vignette, 1.0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::path::Path;
+
+use crate::dir_watch::snapshot;
+
+/// Builds listed in the tray menu.
+pub const MAX_RECENT: usize = 10;
+
+/// A build file listed in the tray menu.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecentBuild {
+    pub name: String,
+    pub path: String,
+}
+
+/// The most recently saved builds under `dir`, newest first.
+pub fn recent_builds(dir: &Path) -> Vec<RecentBuild> {
+    let mut files: Vec<_> = snapshot(dir)
+        .into_iter()
+        .filter(|(path, _)| {
+            path.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("xml"))
+        })
+        .map(|(path, (_, modified))| (modified, path))
+        .collect();
+    files.sort_by(|a, b| b.cmp(a));
+    files
+        .into_iter()
+        .take(MAX_RECENT)
+        .map(|(_, path)| RecentBuild {
+            name: path
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned()),
+            path: path.to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+
+/// What a tray menu entry does. Entries carry it in their menu id, so the
+/// menu's event handler can forward clicks without looking anything up.
+/// Only the tray itself constructs these.
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrayAction {
+    Show,
+    Open(String),
+    Quit,
+}
+
+#[allow(dead_code)]
+impl TrayAction {
+    pub fn id(&self) -> String {
+        match self {
+            TrayAction::Show => "show".into(),
+            TrayAction::Open(path) => format!("open:{}", path),
+            TrayAction::Quit => "quit".into(),
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        match id {
+            "show" => Some(TrayAction::Show),
+            "quit" => Some(TrayAction::Quit),
+            _ => id
+                .strip_prefix("open:")
+                .map(|path| TrayAction::Open(path.into())),
+        }
+    }
+}
+
+#[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+pub use icon::Tray;
+
+#[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+mod icon {
+    use std::sync::Mutex;
+
+    use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+    use tray_icon::{
+        Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent,
+    };
+    use winit::event_loop::EventLoopProxy;
+
+    use super::{RecentBuild, TrayAction};
+    use crate::host_thread::UserEvent;
+    use crate::window_icon::IconImage;
+
+    /// The tray icon and its menu of recent builds. Create it on the
+    /// event loop thread.
+    pub struct Tray {
+        icon: TrayIcon,
+        listed: Option<Vec<RecentBuild>>,
+    }
+
+    /// Forwards a clicked entry to the window thread.
+    fn send(action: TrayAction, proxy: &EventLoopProxy<UserEvent>) {
+        proxy.send_event(UserEvent::Tray(action)).ok();
+    }
+
+    impl Tray {
+        pub fn new(proxy: EventLoopProxy<UserEvent>) -> Result<Self, String> {
+            let icon = TrayIconBuilder::new()
+                .with_icon(app_icon()?)
+                .with_tooltip("Path of Building")
+                .with_menu_on_left_click(false)
+                .build()
+                .map_err(|e| e.to_string())?;
+            // Both handlers run on the event loop thread, but must be Sync.
+            let proxy = Mutex::new(proxy);
+            let menu_proxy = Mutex::new(proxy.lock().unwrap().clone());
+            MenuEvent::set_event_handler(Some(move |e: MenuEvent| {
+                if let Some(action) = TrayAction::parse(&e.id.0) {
+                    send(action, &menu_proxy.lock().unwrap());
+                }
+            }));
+            TrayIconEvent::set_event_handler(Some(move |e: TrayIconEvent| {
+                if let TrayIconEvent::Click {
+                    button: MouseButton::Left,
+                    button_state: MouseButtonState::Up,
+                    ..
+                } = e
+                {
+                    send(TrayAction::Show, &proxy.lock().unwrap());
+                }
+            }));
+            Ok(Self { icon, listed: None })
+        }
+
+        /// Rebuilds the menu when the recent builds changed.
+        pub fn update(&mut self, builds: &[RecentBuild]) {
+            if self.listed.as_deref() == Some(builds) {
+                return;
+            }
+            let menu = Menu::new();
+            let entry =
+                |text: &str, action: TrayAction| MenuItem::with_id(action.id(), text, true, None);
+            let mut items = vec![entry("Show Path of Building", TrayAction::Show)];
+            items.extend(
+                builds
+                    .iter()
+                    .map(|b| entry(&b.name, TrayAction::Open(b.path.clone()))),
+            );
+            for (i, item) in items.iter().enumerate() {
+                menu.append(item).ok();
+                if i == 0 || i == items.len() - 1 {
+                    menu.append(&PredefinedMenuItem::separator()).ok();
+                }
+            }
+            menu.append(&entry("Quit", TrayAction::Quit)).ok();
+            self.icon.set_menu(Some(Box::new(menu)));
+            self.listed = Some(builds.to_vec());
+        }
+    }
+
+    /// The built-in window icon, as the tray is made before the host has
+    /// looked for the fork's own.
+    fn app_icon() -> Result<Icon, String> {
+        let image = IconImage::embedded();
+        Icon::from_rgba(image.rgba, image.width, image.height).map_err(|e| e.to_string())
+    }
+}
+
+/// Stands in for the tray where it isn't built.
+#[cfg(not(all(feature = "tray", any(windows, target_os = "macos"))))]
+pub struct Tray;
+
+#[cfg(not(all(feature = "tray", any(windows, target_os = "macos"))))]
+impl Tray {
+    pub fn new(
+        _proxy: winit::event_loop::EventLoopProxy<crate::host_thread::UserEvent>,
+    ) -> Result<Self, String> {
+        Err("this build has no tray support".into())
+    }
+
+    pub fn update(&mut self, _builds: &[RecentBuild]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_newest_builds_and_round_trips_actions() {
+        let dir = std::env::temp_dir().join(format!("pob-tray-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("League")).unwrap();
+        std::fs::write(dir.join("Witch.xml"), "<PathOfBuilding/>").unwrap();
+        std::fs::write(dir.join("notes.txt"), "skip").unwrap();
+        let newer = dir.join("League/Duelist.xml");
+        std::fs::write(&newer, "<PathOfBuilding/>").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&newer)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+
+        let builds = recent_builds(&dir);
+        let names: Vec<_> = builds.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["Duelist", "Witch"]);
+        assert_eq!(builds[0].path, newer.to_string_lossy());
+
+        let open = TrayAction::Open(builds[0].path.clone());
+        for action in [TrayAction::Show, open, TrayAction::Quit] {
+            assert_eq!(TrayAction::parse(&action.id()), Some(action));
+        }
+        assert_eq!(TrayAction::parse("other"), None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
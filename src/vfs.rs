@@ -0,0 +1,521 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use mlua::prelude::*;
+
+use crate::archive::{self, ArchiveIndex, Archives};
+use crate::embed;
+use crate::layout::Layout;
+
+/// One result of a file search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Wildcards match case-insensitively and never across a `/`.
+pub fn search_options() -> glob::MatchOptions {
+    glob::MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    }
+}
+
+/// A tree of files that can be mounted into a `Vfs`. Paths are relative to
+/// the provider's root.
+pub trait Provider: Send {
+    fn read(&mut self, path: &str) -> io::Result<Vec<u8>>;
+
+    /// Files (or directories, with `dirs`) matching `spec`, such as
+    /// `Builds/*.xml`. Only the last component may contain wildcards.
+    fn search(&mut self, spec: &str, dirs: bool) -> Vec<FoundFile>;
+
+    /// The file on disk behind `path`, for providers that can be written.
+    fn host_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Files held in memory, matched case-insensitively like zip entries. The
+/// desktop build doesn't mount one; embedders and tests do.
+#[derive(Default)]
+pub struct Memory {
+    /// Normalized name to (name as given, contents).
+    files: HashMap<String, (String, Vec<u8>)>,
+}
+
+#[allow(dead_code)]
+impl Memory {
+    pub fn insert(&mut self, path: &str, data: impl Into<Vec<u8>>) {
+        let name = path.replace('\\', "/").trim_matches('/').to_string();
+        self.files
+            .insert(archive::normalize(&name), (name, data.into()));
+    }
+}
+
+impl Provider for Memory {
+    fn read(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        self.files
+            .get(&archive::normalize(path))
+            .map(|(_, data)| data.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.to_string()))
+    }
+
+    fn search(&mut self, spec: &str, dirs: bool) -> Vec<FoundFile> {
+        let spec = spec.replace('\\', "/");
+        let (dir, pattern) = spec.rsplit_once('/').unwrap_or(("", &spec));
+        let Ok(pattern) = glob::Pattern::new(pattern) else {
+            return Vec::new();
+        };
+        let dir = archive::normalize(dir);
+        let mut found = BTreeSet::new();
+        for (key, (name, data)) in &self.files {
+            // Keys are lowercased names, so offsets into one fit the other.
+            let skip = if dir.is_empty() { 0 } else { dir.len() + 1 };
+            if !key.starts_with(&dir) || (skip > 0 && key.as_bytes().get(dir.len()) != Some(&b'/'))
+            {
+                continue;
+            }
+            let rest = &name[skip..];
+            let (leaf, size) = match rest.split_once('/') {
+                Some((sub, _)) if dirs => (sub, 0),
+                None if !dirs => (rest, data.len() as u64),
+                _ => continue,
+            };
+            if pattern.matches_with(leaf, search_options()) {
+                found.insert((leaf.to_string(), size));
+            }
+        }
+        found
+            .into_iter()
+            .map(|(name, size)| FoundFile {
+                name,
+                size,
+                modified: None,
+            })
+            .collect()
+    }
+}
+
+/// Serves `upper` with `lower` underneath: reads fall through to `lower`
+/// for files `upper` lacks, and writes go to `upper`.
+pub struct Overlay {
+    upper: Box<dyn Provider>,
+    lower: Box<dyn Provider>,
+}
+
+impl Overlay {
+    pub fn new(upper: impl Provider + 'static, lower: impl Provider + 'static) -> Self {
+        Self {
+            upper: Box::new(upper),
+            lower: Box::new(lower),
+        }
+    }
+}
+
+impl Provider for Overlay {
+    fn read(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        self.upper.read(path).or_else(|_| self.lower.read(path))
+    }
+
+    fn search(&mut self, spec: &str, dirs: bool) -> Vec<FoundFile> {
+        let mut found = self.upper.search(spec, dirs);
+        let lower: Vec<FoundFile> = self
+            .lower
+            .search(spec, dirs)
+            .into_iter()
+            .filter(|f| !found.iter().any(|u| u.name.eq_ignore_ascii_case(&f.name)))
+            .collect();
+        found.extend(lower);
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        found
+    }
+
+    fn host_path(&self, path: &str) -> Option<PathBuf> {
+        self.upper.host_path(path)
+    }
+}
+
+/// Everything host file access sees: a root provider plus providers mounted
+/// at top-level directory names or absolute paths. Paths under `base` are
+/// looked up relative to it, so the script directory can be served by any
+/// provider.
+pub struct Vfs {
+    base: PathBuf,
+    root: Box<dyn Provider>,
+    mounts: Vec<(PathBuf, Box<dyn Provider>)>,
+}
+
+pub type SharedVfs = Arc<Mutex<Vfs>>;
+
+impl Vfs {
+    pub fn new(base: PathBuf, root: impl Provider + 'static) -> Self {
+        Self {
+            base,
+            root: Box::new(root),
+            mounts: Vec::new(),
+        }
+    }
+
+    /// The script directory on disk, with the tree directory mounted as
+    /// `TreeData` when the layout keeps it elsewhere. A tree directory
+    /// ending in `.zip` is served from inside the archive.
+    ///
+    /// A standalone executable serves its embedded `src` and `runtime`
+    /// trees, with the layout's directories on disk as overrides.
+    pub fn standard(layout: &Layout) -> Self {
+        let mut scripts = Archives::new(layout.script_dir.clone());
+        scripts.scan();
+        let mut vfs = match embed::subtree("src") {
+            Some(embedded) => {
+                let runtime = Archives::new(layout.runtime_dir.clone());
+                let mut vfs = Self::new(layout.script_dir.clone(), Overlay::new(scripts, embedded));
+                if let Some(embedded) = embed::subtree("runtime") {
+                    vfs.mount(layout.runtime_dir.clone(), Overlay::new(runtime, embedded));
+                }
+                vfs
+            }
+            None => Self::new(layout.script_dir.clone(), scripts),
+        };
+        let tree = &layout.tree_dir;
+        if tree
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+        {
+            match ArchiveIndex::open(tree) {
+                Ok(zip) => vfs.mount("TreeData", zip),
+                Err(e) => eprintln!("tree archive {}: {}", tree.display(), e),
+            }
+        } else if *tree != layout.script_dir.join("TreeData") {
+            let mut dir = Archives::new(tree.clone());
+            dir.scan();
+            vfs.mount("TreeData", dir);
+        }
+        vfs
+    }
+
+    /// Serves paths under `prefix/` from `provider`. A relative prefix is a
+    /// directory name under `base`, matched case-insensitively; an absolute
+    /// one is matched as is.
+    pub fn mount(&mut self, prefix: impl Into<PathBuf>, provider: impl Provider + 'static) {
+        self.mounts.push((prefix.into(), Box::new(provider)));
+    }
+
+    /// The mount serving `path` (None for the root) and the path inside it.
+    fn route(&self, path: &str) -> (Option<usize>, String) {
+        for (i, (prefix, _)) in self.mounts.iter().enumerate() {
+            if prefix.is_absolute()
+                && let Ok(rest) = Path::new(path).strip_prefix(prefix)
+            {
+                return (Some(i), rest.to_string_lossy().into_owned());
+            }
+        }
+        let path = match Path::new(path).strip_prefix(&self.base) {
+            Ok(rest) => rest.to_string_lossy().into_owned(),
+            Err(_) => path.to_string(),
+        };
+        for (i, (prefix, _)) in self.mounts.iter().enumerate() {
+            let prefix = prefix.to_string_lossy();
+            if let (Some(head), Some(rest)) = (path.get(..prefix.len()), path.get(prefix.len()..))
+                && head.eq_ignore_ascii_case(&prefix)
+                && let Some(rest) = rest.strip_prefix(['/', '\\'])
+            {
+                return (Some(i), rest.to_string());
+            }
+        }
+        (None, path)
+    }
+
+    fn provider(&mut self, mount: Option<usize>) -> &mut dyn Provider {
+        match mount {
+            Some(i) => self.mounts[i].1.as_mut(),
+            None => self.root.as_mut(),
+        }
+    }
+
+    pub fn read(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        let (mount, path) = self.route(path);
+        self.provider(mount).read(&path)
+    }
+
+    pub fn read_to_string(&mut self, path: &str) -> io::Result<String> {
+        String::from_utf8(self.read(path)?).map_err(io::Error::other)
+    }
+
+    pub fn search(&mut self, spec: &str, dirs: bool) -> Vec<FoundFile> {
+        let (mount, spec) = self.route(spec);
+        self.provider(mount).search(&spec, dirs)
+    }
+
+    pub fn host_path(&self, path: &str) -> Option<PathBuf> {
+        let (mount, path) = self.route(path);
+        match mount {
+            Some(i) => self.mounts[i].1.host_path(&path),
+            None => self.root.host_path(&path),
+        }
+    }
+
+    pub fn create_dir(&self, path: &str) -> io::Result<()> {
+        let dir = self.host_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{}: read-only", path),
+            )
+        })?;
+        std::fs::create_dir_all(dir)
+    }
+}
+
+/// A file read whole through the VFS, standing in for an `io.open` handle
+/// when there is no file on disk behind the path.
+struct MemFile {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl MemFile {
+    fn read_format<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        format: &LuaValue,
+    ) -> LuaResult<LuaValue<'lua>> {
+        let rest = &self.data[self.pos..];
+        let (out, used) = match format {
+            LuaValue::Integer(_) | LuaValue::Number(_) => {
+                if rest.is_empty() {
+                    return Ok(LuaValue::Nil);
+                }
+                let n = lua.unpack::<usize>(format.clone())?.min(rest.len());
+                (&rest[..n], n)
+            }
+            LuaValue::String(s) => match s.to_str()?.trim_start_matches('*').get(..1) {
+                Some("a") => (rest, rest.len()),
+                Some(f @ ("l" | "L")) => {
+                    if rest.is_empty() {
+                        return Ok(LuaValue::Nil);
+                    }
+                    let end = rest.iter().position(|&b| b == b'\n');
+                    let used = end.map_or(rest.len(), |e| e + 1);
+                    let keep = if f == "L" { used } else { end.unwrap_or(used) };
+                    (&rest[..keep], used)
+                }
+                Some("n") => {
+                    let text = String::from_utf8_lossy(rest);
+                    let trimmed = text.trim_start();
+                    let len = trimmed
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c)))
+                        .unwrap_or(trimmed.len());
+                    let Ok(n) = trimmed[..len].parse::<f64>() else {
+                        return Ok(LuaValue::Nil);
+                    };
+                    self.pos += text.len() - trimmed.len() + len;
+                    return Ok(LuaValue::Number(n));
+                }
+                _ => return Err(LuaError::RuntimeError("bad read format".into())),
+            },
+            _ => return Err(LuaError::RuntimeError("bad read format".into())),
+        };
+        let s = lua.create_string(out)?;
+        self.pos += used;
+        Ok(LuaValue::String(s))
+    }
+}
+
+impl LuaUserData for MemFile {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("read", |lua, this, formats: LuaMultiValue| {
+            let mut formats = formats.into_vec();
+            if formats.is_empty() {
+                formats.push(LuaValue::String(lua.create_string("*l")?));
+            }
+            let mut out = Vec::new();
+            for format in &formats {
+                let value = this.read_format(lua, format)?;
+                let done = value.is_nil();
+                out.push(value);
+                if done {
+                    break;
+                }
+            }
+            Ok(LuaMultiValue::from_vec(out))
+        });
+        methods.add_function("lines", |lua, ud: LuaAnyUserData| {
+            let key = lua.create_registry_value(ud)?;
+            lua.create_function(move |lua, ()| {
+                let ud: LuaAnyUserData = lua.registry_value(&key)?;
+                let mut file = ud.borrow_mut::<MemFile>()?;
+                file.read_format(lua, &LuaValue::String(lua.create_string("*l")?))
+            })
+        });
+        methods.add_method_mut(
+            "seek",
+            |_, this, (whence, offset): (Option<String>, Option<i64>)| {
+                let from = match whence.as_deref().unwrap_or("cur") {
+                    "set" => 0,
+                    "end" => this.data.len() as i64,
+                    _ => this.pos as i64,
+                };
+                let pos = from + offset.unwrap_or(0);
+                if pos < 0 {
+                    return Ok((None, Some("invalid offset")));
+                }
+                this.pos = (pos as usize).min(this.data.len());
+                Ok((Some(this.pos), None))
+            },
+        );
+        methods.add_method("write", |_, _, _: LuaMultiValue| -> LuaResult<()> {
+            Err(LuaError::RuntimeError("file is read-only".into()))
+        });
+        methods.add_method("close", |_, _, ()| Ok(true));
+        methods.add_method("flush", |_, _, ()| Ok(true));
+        methods.add_method("setvbuf", |_, _, _: LuaMultiValue| Ok(true));
+    }
+}
+
+/// Routes `io.open`, `require` and `MakeDir` through `vfs`. Paths with a
+/// file on disk behind them open natively; others are read whole into a
+/// read-only handle.
+pub fn register(lua: &Lua, vfs: SharedVfs) -> LuaResult<()> {
+    let v = vfs.clone();
+    let open = lua.create_function(move |lua, path: String| {
+        let data = v.lock().unwrap().read(&path);
+        match data {
+            Ok(data) => Ok((Some(lua.create_userdata(MemFile { data, pos: 0 })?), None)),
+            Err(e) => Ok((None, Some(format!("{}: {}", path, e)))),
+        }
+    })?;
+    let v = vfs.clone();
+    let read = lua.create_function(move |lua, path: String| {
+        let data = v.lock().unwrap().read(&path);
+        data.ok().map(|d| lua.create_string(d)).transpose()
+    })?;
+    let v = vfs.clone();
+    let host_path = lua.create_function(move |_, path: String| {
+        Ok(v.lock()
+            .unwrap()
+            .host_path(&path)
+            .map(|p| p.to_string_lossy().into_owned()))
+    })?;
+    lua.load(
+        r#"
+        local openVfs, hostPath, readVfs = ...
+        local _open = io.open
+        function io.open(path, mode)
+            if type(path) ~= "string" then
+                return _open(path, mode)
+            end
+            local write = mode and mode:find("[wa+]")
+            local real = hostPath(path)
+            if real then
+                local file, err = _open(real, mode)
+                if file or write then
+                    return file, err
+                end
+            end
+            if write then
+                return nil, path .. ": read-only"
+            end
+            return openVfs(path)
+        end
+
+        -- After the file searchers: modules only the VFS has, such as
+        -- embedded runtime libraries.
+        table.insert(package.loaders, function(name)
+            local file = name:gsub("%.", "/")
+            local tried = {}
+            for template in package.path:gmatch("[^;]+") do
+                local path = template:gsub("%?", file)
+                local code = readVfs(path)
+                if code then
+                    return assert(loadstring(code, "@" .. path))
+                end
+                tried[#tried + 1] = "\n\tno file '" .. path .. "' in the vfs"
+            end
+            return table.concat(tried)
+        end)
+        "#,
+    )
+    .set_name("vfs")
+    .call::<_, ()>((open, host_path, read))?;
+    lua.globals().set(
+        "MakeDir",
+        lua.create_function(move |_, path: String| {
+            vfs.lock()
+                .unwrap()
+                .create_dir(&path)
+                .map_err(LuaError::external)
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mounts_route_reads_searches_and_io_open() {
+        let dir = std::env::temp_dir().join(format!("pob-vfs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Launch.lua"), "return 1").unwrap();
+        let mut assets = Memory::default();
+        assets.insert("Tree/data.lua", "line one\nline two\n42");
+        assets.insert("Tree/Sub/skip.png", "");
+        assets.insert("Tree/Icons.png", vec![1, 2, 3]);
+
+        let mut vfs = Vfs::new(dir.clone(), Archives::new(dir.clone()));
+        vfs.mount("Assets", assets);
+        assert_eq!(vfs.read_to_string("Launch.lua").unwrap(), "return 1");
+        let abs = dir.join("assets/tree/DATA.lua");
+        assert!(
+            vfs.read(&abs.to_string_lossy())
+                .unwrap()
+                .starts_with(b"line one")
+        );
+        let names =
+            |found: Vec<FoundFile>| -> Vec<String> { found.into_iter().map(|f| f.name).collect() };
+        assert_eq!(
+            names(vfs.search("Assets/Tree/*", false)),
+            ["Icons.png", "data.lua"]
+        );
+        assert_eq!(names(vfs.search("assets\\tree\\*", true)), ["Sub"]);
+        assert_eq!(vfs.host_path("Launch.lua"), Some(dir.join("Launch.lua")));
+        assert_eq!(vfs.host_path("Assets/Tree/data.lua"), None);
+        assert!(vfs.create_dir("Assets/New").is_err());
+
+        let lua = Lua::new();
+        register(&lua, Arc::new(Mutex::new(vfs))).unwrap();
+        let ok: bool = lua
+            .load(
+                r#"
+                local f = io.open("Assets/Tree/data.lua", "r")
+                local first = f:read("*l")
+                local rest = {}
+                for line in f:lines() do rest[#rest + 1] = line end
+                f:seek("set", 0)
+                local all, eof = f:read("*a", "*l")
+                f:close()
+                local native = io.open("Launch.lua")
+                local native_ok = native:read("*a") == "return 1"
+                native:close()
+                MakeDir("Builds/League")
+                return first == "line one" and rest[2] == "42" and #all == 20
+                    and eof == nil and native_ok
+                    and io.open("Assets/Tree/data.lua", "w") == nil
+                    and io.open("Assets/missing.lua") == nil
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(ok);
+        assert!(dir.join("Builds/League").is_dir());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+
+use crate::layout::Layout;
+use crate::vfs::SharedVfs;
+
+/// The app's name to the window system: the Wayland app ID and X11 class,
+/// which taskbars group windows by and match to a `.desktop` file, and the
+/// Windows AppUserModelID, which the taskbar groups by and pins. macOS
+/// takes the app's identity from its bundle instead.
+#[cfg(not(target_os = "macos"))]
+pub const APP_ID: &str = "path-of-building";
+
+/// Where a fork's own icon is looked for, in order: in the runtime
+/// directory, then in the script directory.
+const ICON_NAMES: &[&str] = &["PathOfBuilding.ico", "icon.png"];
+
+/// Window icons are shown at most this big, so larger images are shrunk.
+const MAX_SIZE: u32 = 256;
+
+/// The icon used when the fork ships none, as in packed builds.
+const EMBEDDED: &[u8] = include_bytes!("icon.png");
+
+/// Straight-alpha RGBA pixels of a window icon.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IconImage {
+    pub rgba: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// An icon for the window thread to set, taken once applied.
+pub type SharedWindowIcon = Arc<Mutex<Option<IconImage>>>;
+
+impl IconImage {
+    /// Decodes a PNG, ICO (its largest image) or any other format image reads.
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let mut image = image::load_from_memory(data).map_err(|e| e.to_string())?;
+        if image.width() > MAX_SIZE || image.height() > MAX_SIZE {
+            image = image.thumbnail(MAX_SIZE, MAX_SIZE);
+        }
+        let rgba = image.to_rgba8();
+        Ok(Self {
+            width: rgba.width(),
+            height: rgba.height(),
+            rgba: rgba.into_raw(),
+        })
+    }
+
+    /// The built-in icon, for installs without one of their own.
+    pub fn embedded() -> Self {
+        Self::decode(EMBEDDED).expect("embedded icon decodes")
+    }
+
+    /// The fork's icon if it ships one, otherwise the built-in one.
+    pub fn standard(layout: &Layout, vfs: &SharedVfs) -> Self {
+        let mut vfs = vfs.lock().unwrap();
+        let dirs = [&layout.runtime_dir, &layout.script_dir];
+        dirs.iter()
+            .flat_map(|dir| ICON_NAMES.iter().map(move |name| dir.join(name)))
+            .find_map(|path| {
+                let data = vfs.read(&path.to_string_lossy()).ok()?;
+                IconImage::decode(&data)
+                    .map_err(|e| eprintln!("window icon {}: {}", path.display(), e))
+                    .ok()
+            })
+            .unwrap_or_else(Self::embedded)
+    }
+
+    pub fn to_winit(&self) -> Option<winit::window::Icon> {
+        winit::window::Icon::from_rgba(self.rgba.clone(), self.width, self.height)
+            .map_err(|e| eprintln!("window icon: {}", e))
+            .ok()
+    }
+}
+
+/// Registers `SetWindowIcon([path]) -> ok, err`. The path goes through the
+/// script file system like any other; nil goes back to the standard icon.
+pub fn register(
+    lua: &Lua,
+    pending: SharedWindowIcon,
+    layout: &Layout,
+    vfs: SharedVfs,
+) -> LuaResult<()> {
+    let layout = layout.clone();
+    lua.globals().set(
+        "SetWindowIcon",
+        lua.create_function(move |_, path: Option<String>| {
+            let icon = match path {
+                Some(path) => {
+                    let data = vfs.lock().unwrap().read(&path);
+                    match data
+                        .map_err(|e| e.to_string())
+                        .and_then(|d| IconImage::decode(&d))
+                    {
+                        Ok(icon) => icon,
+                        Err(e) => return Ok((false, Some(format!("{}: {}", path, e)))),
+                    }
+                }
+                None => IconImage::standard(&layout, &vfs),
+            };
+            *pending.lock().unwrap() = Some(icon);
+            Ok((true, None))
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::Vfs;
+
+    #[test]
+    fn forks_icon_is_found_and_lua_can_swap_it() {
+        let dir = std::env::temp_dir().join(format!("pob-icon-{}", std::process::id()));
+        let layout = Layout::standard(&dir);
+        std::fs::create_dir_all(&layout.script_dir).unwrap();
+        let vfs: SharedVfs = Arc::new(Mutex::new(Vfs::standard(&layout)));
+        assert_eq!(IconImage::standard(&layout, &vfs), IconImage::embedded());
+
+        image::RgbaImage::from_pixel(512, 256, image::Rgba([255, 0, 0, 255]))
+            .save(layout.script_dir.join("icon.png"))
+            .unwrap();
+        let icon = IconImage::standard(&layout, &vfs);
+        assert_eq!((icon.width, icon.height), (256, 128));
+        assert_eq!(&icon.rgba[..4], [255, 0, 0, 255]);
+
+        let lua = Lua::new();
+        let pending = SharedWindowIcon::default();
+        register(&lua, pending.clone(), &layout, vfs).unwrap();
+        let (ok, err): (bool, Option<String>) = lua
+            .load(r#"return SetWindowIcon("missing.png")"#)
+            .eval()
+            .unwrap();
+        assert!(!ok && err.unwrap().starts_with("missing.png"));
+        assert!(pending.lock().unwrap().is_none());
+        lua.load("SetWindowIcon()").exec().unwrap();
+        assert_eq!(pending.lock().unwrap().take(), Some(icon));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn embedded_icon_is_a_square_with_a_transparent_corner() {
+        let icon = IconImage::embedded();
+        assert_eq!((icon.width, icon.height), (128, 128));
+        assert_eq!(icon.rgba.len(), 128 * 128 * 4);
+        assert_eq!(icon.rgba[3], 0);
+        assert!(icon.to_winit().is_some());
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::available_parallelism,
+};
+
+use mlua::prelude::*;
+
+use crate::subscript::{self, SubEnv, SubValue, to_multi};
+
+/// Upper bound on workers in one pool.
+const MAX_WORKERS: usize = 64;
+
+/// Host functions every worker state gets.
+const HOST_FUNCTIONS: &[&str] = &[
+    "GetScriptPath",
+    "GetRuntimePath",
+    "GetUserPath",
+    "GetWorkDir",
+    "MakeDir",
+    "ConPrintf",
+    "LoadDataSnapshot",
+];
+
+struct Job {
+    id: u32,
+    func: String,
+    args: Vec<SubValue>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JobResult {
+    Done(u32, Vec<SubValue>),
+    Failed(u32, String),
+}
+
+/// Worker Lua states that run jobs in parallel. Each worker loads the same
+/// modules up front, then calls the global function a job names with the
+/// job's arguments; arguments and results cross threads as `SubValue`s.
+pub struct WorkerPool {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<JobResult>,
+    cancel: Arc<AtomicBool>,
+    next_id: u32,
+    pending: usize,
+}
+
+impl WorkerPool {
+    pub fn spawn(env: &SubEnv, workers: usize, modules: Vec<String>) -> std::io::Result<Self> {
+        let (job_tx, job_rx) = channel::<Job>();
+        let (result_tx, results) = channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let cancel = Arc::new(AtomicBool::new(false));
+        for n in 0..workers.clamp(1, MAX_WORKERS) {
+            let (env, modules, jobs, tx, cancel) = (
+                env.clone(),
+                modules.clone(),
+                job_rx.clone(),
+                result_tx.clone(),
+                cancel.clone(),
+            );
+            std::thread::Builder::new()
+                .name(format!("worker-{}", n))
+                .spawn(move || work(&env, &modules, &jobs, &tx, cancel))?;
+        }
+        Ok(Self {
+            jobs: Some(job_tx),
+            results,
+            cancel,
+            next_id: 1,
+            pending: 0,
+        })
+    }
+
+    /// Queues a call to the global `func`; returns the job id.
+    pub fn submit(&mut self, func: String, args: Vec<SubValue>) -> LuaResult<u32> {
+        let id = self.next_id;
+        let job = Job { id, func, args };
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or_else(|| LuaError::RuntimeError("worker pool is closed".into()))?;
+        self.next_id += 1;
+        self.pending += 1;
+        Ok(id)
+    }
+
+    /// Results finished since the last call, in completion order.
+    pub fn poll(&mut self) -> Vec<JobResult> {
+        let out: Vec<JobResult> = self.results.try_iter().collect();
+        self.pending -= out.len().min(self.pending);
+        out
+    }
+
+    /// Jobs submitted but not yet returned by `poll`.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Stops the workers; running jobs are aborted and queued ones dropped.
+    pub fn close(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.jobs = None;
+        self.pending = 0;
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn work(
+    env: &SubEnv,
+    modules: &[String],
+    jobs: &Mutex<Receiver<Job>>,
+    tx: &Sender<JobResult>,
+    cancel: Arc<AtomicBool>,
+) {
+    let state = setup(env, modules, cancel.clone()).map_err(|e| e.to_string());
+    loop {
+        let Ok(job) = jobs.lock().unwrap().recv() else {
+            return;
+        };
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let result = match &state {
+            Ok(lua) => run_job(lua, &job).map_err(|e| e.to_string()),
+            Err(e) => Err(format!("worker failed to start: {}", e)),
+        };
+        let msg = match result {
+            Ok(values) => JobResult::Done(job.id, values),
+            Err(e) => JobResult::Failed(job.id, e),
+        };
+        if tx.send(msg).is_err() {
+            return;
+        }
+        env.waker.wake();
+    }
+}
+
+fn setup(env: &SubEnv, modules: &[String], cancel: Arc<AtomicBool>) -> LuaResult<Lua> {
+    let lua = subscript::new_state(env, cancel)?;
+    {
+        let g = lua.globals();
+        for name in HOST_FUNCTIONS {
+            if let Some(func) = subscript::host_function(&lua, env, name)? {
+                g.set(*name, func)?;
+            }
+        }
+        let (script_path, vfs) = (env.script_path.clone(), env.vfs.clone());
+        g.set(
+            "LoadModule",
+            lua.create_function(move |lua, (name, args): (String, LuaMultiValue)| {
+                let file = if name.ends_with(".lua") {
+                    name.clone()
+                } else {
+                    format!("{}.lua", name)
+                };
+                let code = vfs
+                    .lock()
+                    .unwrap()
+                    .read_to_string(&script_path.join(file).to_string_lossy())
+                    .map_err(|e| LuaError::RuntimeError(format!("{}: {}", name, e)))?;
+                lua.load(&code)
+                    .set_name(name)
+                    .call::<_, LuaMultiValue>(args)
+            })?,
+        )?;
+        let load: LuaFunction = g.get("LoadModule")?;
+        for module in modules {
+            load.call::<_, ()>(module.as_str())?;
+        }
+    }
+    Ok(lua)
+}
+
+fn run_job(lua: &Lua, job: &Job) -> LuaResult<Vec<SubValue>> {
+    let func: LuaFunction = lua
+        .globals()
+        .get(job.func.as_str())
+        .map_err(|_| LuaError::RuntimeError(format!("no function {}", job.func)))?;
+    let results: LuaMultiValue = func.call(to_multi(lua, job.args.clone())?)?;
+    Ok(results.iter().map(|v| SubValue::from_lua(v, 0)).collect())
+}
+
+impl LuaUserData for WorkerPool {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut(
+            "Submit",
+            |_, this, (func, args): (String, LuaMultiValue)| {
+                let args = args.iter().map(|v| SubValue::from_lua(v, 0)).collect();
+                this.submit(func, args)
+            },
+        );
+        // A list of { id = n, results = { ... } } or { id = n, error = msg }.
+        methods.add_method_mut("Poll", |lua, this, ()| {
+            let list = lua.create_table()?;
+            for result in this.poll() {
+                let t = lua.create_table()?;
+                match result {
+                    JobResult::Done(id, values) => {
+                        t.set("id", id)?;
+                        let results = lua.create_sequence_from(to_multi(lua, values)?)?;
+                        t.set("results", results)?;
+                    }
+                    JobResult::Failed(id, error) => {
+                        t.set("id", id)?;
+                        t.set("error", error)?;
+                    }
+                }
+                list.push(t)?;
+            }
+            Ok(list)
+        });
+        methods.add_method("Pending", |_, this, ()| Ok(this.pending()));
+        methods.add_method_mut("Close", |_, this, ()| {
+            this.close();
+            Ok(())
+        });
+    }
+}
+
+/// Registers `NewWorkerPool([count], [modules])`: `count` workers (one per
+/// core by default), each having run `LoadModule` on every name in
+/// `modules`.
+pub fn register(lua: &Lua, env: SubEnv) -> LuaResult<()> {
+    lua.globals().set(
+        "NewWorkerPool",
+        lua.create_function(
+            move |_, (count, modules): (Option<usize>, Option<Vec<String>>)| {
+                let count = count.unwrap_or_else(|| available_parallelism().map_or(4, |n| n.get()));
+                WorkerPool::spawn(&env, count, modules.unwrap_or_default())
+                    .map_err(LuaError::external)
+            },
+        )?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Archives;
+    use crate::host_thread::Waker;
+    use crate::net::{self, NetState};
+    use crate::snapshot::Snapshots;
+    use crate::vfs::Vfs;
+
+    #[test]
+    fn runs_jobs_across_workers() {
+        let dir = std::env::temp_dir().join(format!("pob-workers-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Modules")).unwrap();
+        std::fs::write(
+            dir.join("Modules/Square.lua"),
+            "function Square(t) return t.x * t.x, GetScriptPath() ~= nil end",
+        )
+        .unwrap();
+        let env = SubEnv {
+            script_path: dir.clone(),
+            runtime_path: dir.join("runtime"),
+            net: net::lazy(NetState::default),
+            snapshots: Snapshots::default(),
+            vfs: Arc::new(Mutex::new(Vfs::new(
+                dir.clone(),
+                Archives::new(dir.clone()),
+            ))),
+            waker: Waker::default(),
+        };
+        let mut pool = WorkerPool::spawn(&env, 3, vec!["Modules/Square".into()]).unwrap();
+        for x in 1..=10 {
+            let arg = SubValue::Table(vec![(
+                SubValue::String(b"x".to_vec()),
+                SubValue::Number(x as f64),
+            )]);
+            pool.submit("Square".into(), vec![arg]).unwrap();
+        }
+        let bad = pool.submit("Missing".into(), Vec::new()).unwrap();
+
+        let mut results = Vec::new();
+        for _ in 0..500 {
+            results.extend(pool.poll());
+            if pool.pending() == 0 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(results.len(), 11);
+        let sum: f64 = results
+            .iter()
+            .filter_map(|r| match r {
+                JobResult::Done(_, v) if v[1] == SubValue::Boolean(true) => match v[0] {
+                    SubValue::Number(n) => Some(n),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .sum();
+        assert_eq!(sum, 385.0);
+        assert!(results.iter().any(|r| matches!(r, JobResult::Failed(id, e)
+            if *id == bad && e.contains("no function Missing"))));
+
+        pool.close();
+        assert!(pool.submit("Square".into(), Vec::new()).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,250 @@
+use mlua::prelude::*;
+use quick_xml::{Reader, events::Event};
+
+/// Registers `native.xml`, a quick-xml replacement for PoB's pure-Lua
+/// xml.lua. Nodes have the same shape: `{ elem = name, attrib = {..}, ... }`
+/// with child nodes and text strings in the array part.
+pub fn register(lua: &Lua) -> LuaResult<()> {
+    let module = lua.create_table()?;
+    module.set(
+        "ParseXML",
+        lua.create_function(|lua, text: LuaString| {
+            let text = text.to_string_lossy();
+            match parse(lua, &text) {
+                Ok(nodes) => nodes.into_lua_multi(lua),
+                Err(e) => (LuaValue::Nil, e).into_lua_multi(lua),
+            }
+        })?,
+    )?;
+    module.set(
+        "LoadXMLFile",
+        lua.create_function(|lua, path: String| {
+            let text = match std::fs::read(&path) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => return (LuaValue::Nil, format!("{}: {}", path, e)).into_lua_multi(lua),
+            };
+            match parse(lua, &text) {
+                Ok(nodes) => nodes.into_lua_multi(lua),
+                Err(e) => (LuaValue::Nil, format!("{}: {}", path, e)).into_lua_multi(lua),
+            }
+        })?,
+    )?;
+    module.set(
+        "ComposeXML",
+        lua.create_function(|_, node: LuaTable| {
+            let mut out = String::new();
+            compose(&node, 0, &mut out)?;
+            Ok(out)
+        })?,
+    )?;
+    module.set(
+        "SaveXMLFile",
+        lua.create_function(|lua, (node, path): (LuaTable, String)| {
+            let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            compose(&node, 0, &mut out)?;
+            match std::fs::write(&path, out) {
+                Ok(()) => Ok(LuaValue::Nil),
+                Err(e) => format!("{}: {}", path, e).into_lua(lua),
+            }
+        })?,
+    )?;
+
+    lua.set_named_registry_value("native.xml", module)?;
+    let preload: LuaTable = lua
+        .globals()
+        .get::<_, LuaTable>("package")?
+        .get("preload")?;
+    preload.set(
+        "native.xml",
+        lua.create_function(|lua, _: LuaMultiValue| {
+            lua.named_registry_value::<LuaTable>("native.xml")
+        })?,
+    )?;
+    Ok(())
+}
+
+/// Parses a document into a list of top-level nodes.
+fn parse<'lua>(lua: &'lua Lua, text: &str) -> Result<LuaTable<'lua>, String> {
+    let lua_err = |e: LuaError| e.to_string();
+    let mut reader = Reader::from_str(text);
+    let root = lua.create_table().map_err(lua_err)?;
+    let mut stack: Vec<(Vec<u8>, LuaTable)> = Vec::new();
+
+    let new_node = |e: &quick_xml::events::BytesStart| -> Result<LuaTable<'lua>, String> {
+        let node = lua.create_table().map_err(lua_err)?;
+        let attrib = lua.create_table().map_err(lua_err)?;
+        for attr in e.attributes() {
+            let attr = attr.map_err(|e| e.to_string())?;
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr.unescape_value().map_err(|e| e.to_string())?;
+            attrib.set(key, value.as_ref()).map_err(lua_err)?;
+        }
+        node.set("elem", String::from_utf8_lossy(e.name().as_ref()).as_ref())
+            .map_err(lua_err)?;
+        node.set("attrib", attrib).map_err(lua_err)?;
+        Ok(node)
+    };
+    let append = |stack: &[(Vec<u8>, LuaTable<'lua>)], value: LuaValue<'lua>| {
+        let parent = stack.last().map_or(&root, |(_, t)| t);
+        parent.raw_set(parent.raw_len() + 1, value).map_err(lua_err)
+    };
+
+    loop {
+        let pos = reader.buffer_position();
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("{} at byte {}", e, pos))?;
+        match event {
+            Event::Start(e) => {
+                let node = new_node(&e)?;
+                stack.push((e.name().as_ref().to_vec(), node));
+            }
+            Event::Empty(e) => {
+                let node = new_node(&e)?;
+                append(&stack, LuaValue::Table(node))?;
+            }
+            Event::End(e) => match stack.pop() {
+                Some((name, node)) if name == e.name().as_ref() => {
+                    append(&stack, LuaValue::Table(node))?;
+                }
+                _ => {
+                    return Err(format!(
+                        "unexpected </{}> at byte {}",
+                        String::from_utf8_lossy(e.name().as_ref()),
+                        pos
+                    ));
+                }
+            },
+            Event::Text(e) => {
+                let text = e.unescape().map_err(|e| e.to_string())?;
+                // Indentation between elements isn't content.
+                if !stack.is_empty() && !text.trim().is_empty() {
+                    let s = lua.create_string(text.as_ref()).map_err(lua_err)?;
+                    append(&stack, LuaValue::String(s))?;
+                }
+            }
+            Event::CData(e) => {
+                let s = lua.create_string(&*e).map_err(lua_err)?;
+                append(&stack, LuaValue::String(s))?;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if let Some((name, _)) = stack.last() {
+        return Err(format!("unclosed <{}>", String::from_utf8_lossy(name)));
+    }
+    Ok(root)
+}
+
+/// Writes `node` (or a list of nodes) tab-indented the way xml.lua does.
+/// Attributes are sorted so saved builds diff cleanly.
+fn compose(node: &LuaTable, depth: usize, out: &mut String) -> LuaResult<()> {
+    let Some(elem) = node.get::<_, Option<String>>("elem")? else {
+        for child in node.clone().sequence_values::<LuaTable>() {
+            compose(&child?, depth, out)?;
+        }
+        return Ok(());
+    };
+    let indent = "\t".repeat(depth);
+    out.push_str(&indent);
+    out.push('<');
+    out.push_str(&elem);
+    if let Some(attrib) = node.get::<_, Option<LuaTable>>("attrib")? {
+        let mut attrs: Vec<(String, String)> = Vec::new();
+        for pair in attrib.pairs::<String, LuaValue>() {
+            let (k, v) = pair?;
+            let v = match v {
+                LuaValue::String(s) => s.to_string_lossy().into_owned(),
+                LuaValue::Integer(n) => n.to_string(),
+                LuaValue::Number(n) => n.to_string(),
+                LuaValue::Boolean(b) => b.to_string(),
+                _ => continue,
+            };
+            attrs.push((k, v));
+        }
+        attrs.sort();
+        for (k, v) in attrs {
+            out.push_str(&format!(" {}=\"{}\"", k, escape(&v, true)));
+        }
+    }
+
+    let children: Vec<LuaValue> = node
+        .clone()
+        .sequence_values::<LuaValue>()
+        .collect::<LuaResult<_>>()?;
+    if children.is_empty() {
+        out.push_str("/>\n");
+        return Ok(());
+    }
+    out.push('>');
+    if let [LuaValue::String(s)] = &children[..] {
+        out.push_str(&escape(&s.to_string_lossy(), false));
+    } else {
+        out.push('\n');
+        for child in &children {
+            match child {
+                LuaValue::Table(t) => compose(t, depth + 1, out)?,
+                LuaValue::String(s) => {
+                    out.push_str(&indent);
+                    out.push('\t');
+                    out.push_str(&escape(&s.to_string_lossy(), false));
+                    out.push('\n');
+                }
+                _ => {}
+            }
+        }
+        out.push_str(&indent);
+    }
+    out.push_str(&format!("</{}>\n", elem));
+    Ok(())
+}
+
+fn escape(s: &str, attr: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attr => out.push_str("&quot;"),
+            '\n' if attr => out.push_str("&#10;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_composes_build_xml() {
+        let lua = Lua::new();
+        register(&lua).unwrap();
+        let (class, notes, out, err): (String, String, String, String) = lua
+            .load(
+                r#"
+                local xml = require("native.xml")
+                local nodes = xml.ParseXML([[<?xml version="1.0" encoding="UTF-8"?>
+<PathOfBuilding>
+	<Build level="90" className="Witch"/>
+	<Notes>Tips &amp; tricks</Notes>
+</PathOfBuilding>]])
+                local root = nodes[1]
+                local _, err = xml.ParseXML("<a><b></a>")
+                return root[1].attrib.className, root[2][1], xml.ComposeXML(root), err
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(class, "Witch");
+        assert_eq!(notes, "Tips & tricks");
+        assert_eq!(
+            out,
+            "<PathOfBuilding>\n\t<Build className=\"Witch\" level=\"90\"/>\n\t<Notes>Tips &amp; tricks</Notes>\n</PathOfBuilding>\n"
+        );
+        assert!(err.contains("</a>"));
+    }
+}